@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Embeds build information in the binary: the git commit it was built from, the build time
+// (seconds since the epoch, SOURCE_DATE_EPOCH if set for reproducible builds) and the version of
+// rustc. Values may be provided by the environment if built out of a git checkout (e.g. in CI).
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// the first line of the output of a command, if it succeeds
+fn output(cmd: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(cmd).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.lines().next()?.trim().to_string())
+}
+
+fn main() {
+    let git_sha = std::env::var("FRR_AGENT_GIT_SHA")
+        .ok()
+        .or_else(|| output("git", &["rev-parse", "HEAD"]))
+        .unwrap_or("unknown".to_string());
+    let dirty = output("git", &["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    let git_sha = if dirty {
+        format!("{git_sha}-dirty")
+    } else {
+        git_sha
+    };
+
+    let build_time = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string()
+    });
+
+    let rustc = std::env::var("RUSTC").unwrap_or("rustc".to_string());
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or("unknown".to_string());
+
+    println!("cargo:rustc-env=FRR_AGENT_GIT_SHA={git_sha}");
+    println!("cargo:rustc-env=FRR_AGENT_BUILD_TIME={build_time}");
+    println!("cargo:rustc-env=FRR_AGENT_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rerun-if-env-changed=FRR_AGENT_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+}
@@ -0,0 +1,2290 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Agent-wide state and processing of requests
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::alerting::{AlertEvent, Alerter};
+use crate::applyqueue::ApplyQueue;
+use crate::archive::{export_archive, import_archive};
+use crate::audit::{AuditEntry, AuditLog};
+use crate::backup::ObjectStore;
+use crate::bgpgr::{self, Drain, GrMode};
+use crate::breaker::Breaker;
+use crate::buildinfo::build_info;
+use crate::cancel::TESTS;
+use crate::canonical::canonicalize;
+use crate::complexity::Complexity;
+use crate::connection::Client;
+use crate::convergence::Convergence;
+use crate::diff::diff_configs;
+use crate::dualwrite::DualWriter;
+use crate::facts;
+use crate::framing::{Compression, FRAMING_STATS, Framing};
+use crate::frrdown::{FrrDownPolicy, Held};
+use crate::frrprobe::probe;
+use crate::githistory::GitHistory;
+use crate::history::{blame, changes, history, record};
+use crate::hooks::{HookStage, run_hook};
+use crate::impact::{self, Disruption};
+use crate::journal::{Journal, JournalRecord, Stage};
+use crate::json::Json;
+use crate::monitor::{FrrMonitor, missing_statements};
+use crate::node;
+use crate::passthrough::show;
+use crate::pending::{InFlight, Pending};
+use crate::persist::persist;
+use crate::policy::Policy;
+use crate::privileged;
+use crate::quota::Quotas;
+use crate::rawtext;
+use crate::recent::RecentLogs;
+use crate::reload::{
+    FrrErr, Reloader, Transcript, Validation, config_file_path, frr_preview, frr_reload,
+    frr_reload_validated, frr_validate, metadata_file_path, stored_generations, write_config_file,
+};
+use crate::request::Request;
+use crate::retention::Retention;
+use crate::service::FrrAction;
+use crate::session::{Keepalive, Sessions};
+use crate::sha256::sha256_hex;
+use crate::shipping::TranscriptShipper;
+use crate::slo::ReloadSlo;
+use crate::staticroutes;
+use crate::tempfiles;
+use crate::verify::{Verification, Verifier};
+use crate::vtysh::{interface_status, running_config, vtysh_config, watchfrr_status};
+use crate::vtysh_conf::{install_vtysh_conf, split_vtysh_conf};
+use crate::{Args, GenId, SEND_STATS};
+
+// response to requests whose apply stage is skipped in shadow mode
+pub(crate) const SHADOWED: &str = "SHADOWED";
+
+// the response to a request, along with the transcript of the reloader runs it took
+pub(crate) struct Outcome {
+    pub(crate) response: String,
+    pub(crate) transcript: Transcript,
+}
+impl From<String> for Outcome {
+    fn from(response: String) -> Self {
+        Self {
+            response,
+            transcript: Transcript::new(),
+        }
+    }
+}
+
+// file in outdir the last-known-good generation is persisted to
+const LAST_KNOWN_GOOD_FILE: &str = "last-known-good";
+
+// file in outdir the last generation applied and the checksum of its config are persisted to
+const LAST_APPLIED_FILE: &str = "last-applied";
+
+// file in outdir the highest generation applied is persisted to (see --monotonic-genid)
+const HIGHEST_APPLIED_FILE: &str = "highest-applied";
+
+// time between probes of FRR while requests are held
+const HELD_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// a config that was uploaded and validated
+#[derive(Clone)]
+struct Staged {
+    config_file: PathBuf,
+    vtysh_conf: Option<String>,
+}
+
+// configs that were uploaded and validated, waiting to be promoted
+#[derive(Default)]
+pub(crate) struct Staging {
+    validated: Mutex<BTreeMap<GenId, Staged>>,
+}
+impl Staging {
+    fn stage(&self, genid: GenId, staged: Staged) {
+        if let Ok(mut validated) = self.validated.lock() {
+            validated.insert(genid, staged);
+        }
+    }
+    fn get(&self, genid: GenId) -> Option<Staged> {
+        self.validated.lock().ok()?.get(&genid).cloned()
+    }
+    fn unstage(&self, genid: GenId) {
+        if let Ok(mut validated) = self.validated.lock() {
+            validated.remove(&genid);
+        }
+    }
+}
+
+// a config prepared for a two-phase apply. While prepared, other changes are refused.
+struct Prepared {
+    genid: GenId,
+    token: String,
+    staged: Staged,
+    expires: Instant,
+}
+
+// serializes the requests that change state, telling whether one is being processed
+#[derive(Default)]
+struct Executor {
+    lock: Mutex<()>,
+    busy: AtomicBool,
+}
+
+// a request serialized with the others changing state, being processed until dropped
+struct Serialized<'a> {
+    _guard: MutexGuard<'a, ()>,
+    busy: &'a AtomicBool,
+}
+impl Drop for Serialized<'_> {
+    fn drop(&mut self) {
+        self.busy.store(false, Ordering::Release);
+    }
+}
+
+impl Executor {
+    fn serialized<'a>(&'a self, guard: MutexGuard<'a, ()>) -> Serialized<'a> {
+        self.busy.store(true, Ordering::Release);
+        Serialized {
+            _guard: guard,
+            busy: &self.busy,
+        }
+    }
+
+    // wait for the request being processed, if any, to be done
+    fn lock(&self) -> Option<Serialized<'_>> {
+        self.lock.lock().ok().map(|guard| self.serialized(guard))
+    }
+
+    // serialize a request unless another is being processed
+    fn try_lock(&self) -> Option<Serialized<'_>> {
+        self.lock
+            .try_lock()
+            .ok()
+            .map(|guard| self.serialized(guard))
+    }
+
+    // whether a request changing state is being processed
+    fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Acquire)
+    }
+}
+
+// the preview of the apply of a stored generation (APPLY), waiting for its confirmation
+struct ApplyPreview {
+    nonce: String,
+    current: Option<GenId>, /* the generation applied when previewed */
+}
+
+// a token that can not be predicted, from the random source of the kernel
+fn random_token() -> Result<String, String> {
+    let mut random = [0u8; 16];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut urandom| urandom.read_exact(&mut random))
+        .map_err(|e| format!("Could not read /dev/urandom: {e}"))?;
+    Ok(random
+        .iter()
+        .fold(String::with_capacity(32), |mut hex, octet| {
+            let _ = write!(hex, "{octet:02x}");
+            hex
+        }))
+}
+
+// the last-known-good generation persisted in outdir, if any
+fn read_last_known_good(outdir: &str) -> Option<GenId> {
+    let path = Path::new(outdir).join(LAST_KNOWN_GOOD_FILE);
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+// the last generation applied and the checksum of its config persisted in outdir, if any
+fn read_last_applied(outdir: &str) -> Option<(GenId, String)> {
+    let path = Path::new(outdir).join(LAST_APPLIED_FILE);
+    let last_applied = std::fs::read_to_string(path).ok()?;
+    let (genid, checksum) = last_applied.trim().split_once(' ')?;
+    Some((genid.parse().ok()?, checksum.to_string()))
+}
+
+// the highest generation applied persisted in outdir, if any, else the last one applied
+fn read_highest_applied(outdir: &str) -> Option<GenId> {
+    let path = Path::new(outdir).join(HIGHEST_APPLIED_FILE);
+    match std::fs::read_to_string(path) {
+        Ok(highest) => highest.trim().parse().ok(),
+        Err(_) => read_last_applied(outdir).map(|(genid, _)| genid),
+    }
+}
+
+pub(crate) struct Agent<'a> {
+    pub(crate) args: &'a Args,
+    pub(crate) reloader: Reloader<'a>,
+    staging: Staging,
+    applied: Mutex<Option<GenId>>,
+    last_known_good: Mutex<Option<GenId>>,
+    applied_checksum: Mutex<Option<(GenId, String)>>, /* of the config last applied */
+    highest_applied: Mutex<Option<GenId>>,            /* with --monotonic-genid */
+    pub(crate) sessions: Sessions,
+    pub(crate) monitor: FrrMonitor,
+    pub(crate) convergence: Option<Convergence>,
+    shipper: TranscriptShipper,
+    backup: Option<ObjectStore>,
+    pub(crate) history: Option<GitHistory>,
+    pending: Pending,
+    prepared: Mutex<Option<Prepared>>,
+    previews: Mutex<BTreeMap<GenId, ApplyPreview>>, /* of APPLY, by generation */
+    breaker: Breaker,
+    alerter: Alerter,
+    recent: RecentLogs,
+    slo: ReloadSlo,
+    last_result: Mutex<Option<Json>>, /* of the last request that ran the reloader */
+    executor: Executor,
+    started: Instant,
+    secondaries: DualWriter,
+    pub(crate) quotas: Quotas,
+    policy: Policy,
+    frr_down: Option<FrrDownPolicy>,
+    gr: Option<GrMode>,
+    held: Held,
+    apply_queue: Option<ApplyQueue>,
+    journal: Journal,
+    retention: Option<Retention>,
+    verifier: Option<Verifier>,
+    verification: Mutex<Option<(GenId, Verification)>>, /* of the last generation verified */
+    interrupted: Vec<JournalRecord>,                    /* reloads found interrupted at startup */
+    instances: BTreeMap<String, Agent<'a>>, /* agents of the FRR instances (--instance) */
+    audit: Option<AuditLog>,
+}
+
+impl<'a> Agent<'a> {
+    pub(crate) fn new(
+        args: &'a Args,
+        reloader: Reloader<'a>,
+        shipper: TranscriptShipper,
+        alerter: Alerter,
+        policy: Policy,
+    ) -> Self {
+        let journal = Journal::open(args.outdir());
+        let interrupted = journal.interrupted();
+        let last_applied = read_last_applied(args.outdir());
+        Self {
+            args,
+            reloader,
+            staging: Staging::default(),
+            applied: Mutex::new(last_applied.as_ref().map(|(genid, _)| *genid)),
+            last_known_good: Mutex::new(read_last_known_good(args.outdir())),
+            applied_checksum: Mutex::new(last_applied),
+            highest_applied: Mutex::new(
+                args.monotonic_genid
+                    .then(|| read_highest_applied(args.outdir()))
+                    .flatten(),
+            ),
+            sessions: Sessions::default(),
+            monitor: FrrMonitor::default(),
+            convergence: args.convergence(),
+            shipper,
+            backup: args.object_store(),
+            history: args.git_history(),
+            pending: Pending::default(),
+            prepared: Mutex::new(None),
+            previews: Mutex::new(BTreeMap::new()),
+            breaker: Breaker::new(args.breaker_threshold, args.breaker_cooldown()),
+            alerter,
+            recent: RecentLogs::new(args.logs_size()),
+            slo: ReloadSlo::new(args.reload_slo()),
+            last_result: Mutex::new(None),
+            executor: Executor::default(),
+            started: Instant::now(),
+            secondaries: DualWriter::new(
+                args.secondary_sock.as_deref(),
+                args.secondary_dir.as_deref(),
+            ),
+            quotas: Quotas::new(
+                args.peer_rate,
+                args.peer_daily_applies,
+                args.max_applies_per_minute,
+                args.max_peer_connections,
+            ),
+            policy,
+            frr_down: args.frr_down_policy(),
+            gr: args.bgp_gr_mode(),
+            held: Held::default(),
+            apply_queue: args.apply_queue.map(ApplyQueue::new),
+            journal,
+            retention: args.retention(),
+            verifier: args.verifier().ok().flatten(),
+            verification: Mutex::new(None),
+            interrupted,
+            instances: BTreeMap::new(),
+            audit: args.audit_log.then(|| AuditLog::open(args.outdir())),
+        }
+    }
+
+    // serve the requests targeting an FRR instance with the agent given
+    pub(crate) fn add_instance(&mut self, name: &str, agent: Agent<'a>) {
+        self.instances.insert(name.to_string(), agent);
+    }
+
+    // the agent of an FRR instance
+    pub(crate) fn instance(&self, name: &str) -> Option<&Self> {
+        self.instances.get(name)
+    }
+
+    // the agents of the FRR instances, by name
+    pub(crate) fn instances(&self) -> impl Iterator<Item = (&str, &Self)> {
+        self.instances
+            .iter()
+            .map(|(name, agent)| (name.as_str(), agent))
+    }
+
+    // this agent and those of the FRR instances
+    pub(crate) fn all(&self) -> impl Iterator<Item = &Self> {
+        std::iter::once(self).chain(self.instances.values())
+    }
+
+    // the request in a message, as rendered, along with the agent of the FRR instance it targets:
+    // requests prefixed with "INSTANCE\n<name>\n" target the instance named, others this agent
+    pub(crate) fn route(&self, genid: GenId, message: String) -> (&Self, Request) {
+        let Some(rest) = message.strip_prefix("INSTANCE\n") else {
+            return (self, self.render(genid, Request::parse(message, self.args)));
+        };
+        let (name, request) = rest.split_once('\n').unwrap_or((rest, ""));
+        let name = name.trim();
+        match self.instance(name) {
+            Some(instance) => {
+                let request = Request::parse(request.to_string(), instance.args);
+                (instance, instance.render(genid, request))
+            }
+            None => (
+                self,
+                Request::Invalid(format!("Unknown FRR instance '{name}'")),
+            ),
+        }
+    }
+
+    // render the config of a request as a template, with the facts of the node, if templating
+    // is enabled
+    pub(crate) fn render(&self, genid: GenId, request: Request) -> Request {
+        if !self.args.templating {
+            return request;
+        }
+        let (Request::Config(template, _)
+        | Request::Upload(template)
+        | Request::Preview(template)
+        | Request::Prepare(template)) = &request
+        else {
+            return request;
+        };
+        let rendered = facts::gather(self.args.facts_file.as_deref())
+            .and_then(|facts| facts::render(template, &facts));
+        let config = match rendered {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Failed to render {}: {e}", request.describe(genid));
+                return Request::Invalid(format!("Template error: {e}"));
+            }
+        };
+        match request {
+            Request::Config(_, force) => Request::Config(config, force),
+            Request::Upload(_) => Request::Upload(config),
+            Request::Preview(_) => Request::Preview(config),
+            _ => Request::Prepare(config),
+        }
+    }
+
+    // the config request a config received in chunks stands for, once loaded from its spool file
+    // (and rendered)
+    fn load(&self, genid: GenId, request: &Request) -> Option<Request> {
+        let Request::Spooled(spooled, force) = request else {
+            return None;
+        };
+        Some(match spooled.load() {
+            Ok(config) => self.render(genid, Request::Config(config, *force)),
+            Err(e) => Request::Invalid(format!(
+                "Chunked transfer of generation {genid} failed: {e}"
+            )),
+        })
+    }
+
+    // account for a request received until it is responded to (or dropped)
+    pub(crate) fn received(&self, genid: GenId, request: &Request) -> InFlight<'_> {
+        self.pending.received(genid, request)
+    }
+
+    // whether a config received while another request changing state is processed (whatever the
+    // connection it came from) is to be validated in the background. Configs that would be
+    // refused are not, so that they run no side effect (e.g. the pre-hook): the checks run when
+    // they are processed are run first, but those that are not cheap (e.g. probing FRR).
+    pub(crate) fn validates_ahead(&self, genid: GenId, request: &Request, client: &Client) -> bool {
+        let Request::Config(config, _) = request else {
+            return false;
+        };
+        if self.args.always_ok || !self.executor.is_busy() {
+            return false;
+        }
+        let refused = self
+            .refusal(genid, request)
+            .or_else(|| self.stale(genid, request))
+            .or_else(|| self.quotas.would_admit(client.cred.as_ref(), true).err())
+            .or_else(|| self.policy_violation(genid, request))
+            .or_else(|| self.lacking_gr(genid, request))
+            .or_else(|| {
+                let disruptions = if self.args.require_force {
+                    self.impact(config)
+                } else {
+                    vec![]
+                };
+                self.unforced(genid, request, &disruptions)
+            });
+        if let Some(refused) = refused {
+            debug!(
+                "Not validating generation {genid} ahead: it would be refused ({})",
+                refused.lines().next().unwrap_or_default()
+            );
+            return false;
+        }
+        true
+    }
+
+    // compact status for keepalives: last generation applied, whether the FRR daemons are
+    // alive (as last seen by the monitor) and the number of other requests in flight
+    fn keepalive(&self) -> String {
+        let in_flight = self.pending.len().saturating_sub(1);
+        let blob = Json::object([
+            ("last_applied", self.last_applied().into()),
+            ("healthy", self.monitor.healthy().into()),
+            (
+                "in_flight",
+                u64::try_from(in_flight).unwrap_or(u64::MAX).into(),
+            ),
+        ]);
+        node::identity().tag(blob).to_string()
+    }
+
+    // the last generation that was successfully applied
+    pub(crate) fn last_applied(&self) -> Option<GenId> {
+        self.applied.lock().ok().and_then(|applied| *applied)
+    }
+
+    // the highest generation that was successfully applied, if generations must be monotonic
+    pub(crate) fn highest_applied(&self) -> Option<GenId> {
+        self.highest_applied
+            .lock()
+            .ok()
+            .and_then(|highest| *highest)
+    }
+
+    // the last generation that applied successfully and passed the post-apply health check
+    pub(crate) fn last_known_good(&self) -> Option<GenId> {
+        self.last_known_good.lock().ok().and_then(|lkg| *lkg)
+    }
+
+    // for how long the longest running apply has been running, if any
+    pub(crate) fn longest_apply(&self) -> Option<Duration> {
+        self.pending.longest_apply()
+    }
+
+    // whether the circuit breaker is open, refusing applies
+    pub(crate) fn breaker_open(&self) -> bool {
+        self.breaker.is_open()
+    }
+
+    // after a generation applied successfully, make it the last-known-good generation if the FRR
+    // daemons are all alive. If no daemon is found, the check passes.
+    fn check_health(&self, genid: GenId) {
+        if FrrMonitor::alive_now(self.args.rundir()) == Some(false) {
+            warn!("Generation {genid} applied but FRR daemons are down: not last-known-good");
+            return;
+        }
+        self.set_last_known_good(genid);
+    }
+
+    // verify a generation applied, if told so (--verify). If the verification fails, the
+    // generation is not last-known-good and, if told so (--verify-rollback), the last-known-good
+    // generation is re-applied. Returns the failure, if any.
+    fn verify(&self, genid: GenId) -> Option<String> {
+        let verifier = self.verifier.as_ref()?;
+        info!("Verifying generation {genid}...");
+        let verification = verifier.verify(self.args);
+        if let Ok(mut last) = self.verification.lock() {
+            *last = Some((genid, verification.clone()));
+        }
+        if verification.passed {
+            info!("Generation {genid} verified in {:?}", verification.duration);
+            return None;
+        }
+        let mut failure = format!(
+            "Verification failed after {:?}: {}",
+            verification.duration,
+            verification.failures.join("; ")
+        );
+        error!("Generation {genid}: {failure}");
+        if self.args.verify_rollback
+            && let Some(lkg) = self.last_known_good().filter(|lkg| *lkg != genid)
+        {
+            warn!("Rolling back to last-known-good generation {lkg}...");
+            let mut transcript = vec![];
+            let rollback = self.rollback(lkg, &mut transcript);
+            let message = if rollback == "Ok" {
+                format!("rolled back to generation {lkg}")
+            } else {
+                format!("rollback to generation {lkg} failed: {rollback}")
+            };
+            self.notify(
+                AlertEvent::Rollback,
+                Some(lkg),
+                &format!("Generation {genid} failed verification: {message}"),
+            );
+            let _ = write!(failure, ". {message}");
+        }
+        Some(failure)
+    }
+
+    // the outcome of the verification of a generation, if it was the last one verified
+    fn verification_of(&self, genid: GenId) -> Option<Verification> {
+        let last = self.verification.lock().ok()?;
+        last.as_ref()
+            .filter(|(verified, _)| *verified == genid)
+            .map(|(_, verification)| verification.clone())
+    }
+
+    fn set_last_known_good(&self, genid: GenId) {
+        if let Ok(mut lkg) = self.last_known_good.lock() {
+            *lkg = Some(genid);
+        }
+        let path = Path::new(self.args.outdir()).join(LAST_KNOWN_GOOD_FILE);
+        if let Err(e) = std::fs::write(&path, genid.to_string()) {
+            warn!("Could not persist last-known-good generation {genid} at {path:?}: {e}");
+        }
+        debug!("Generation {genid} is the last-known-good generation");
+    }
+
+    // get BGP ready for a config to be applied, if it changes BGP: report the peers that would
+    // not retain routes through graceful restart and drain the peers, if told so, until the
+    // drain returned is dropped
+    fn prepare_bgp(&self, genid: GenId, config: &str) -> Option<Drain<'_>> {
+        if self.gr.is_none() && self.args.bgp_graceful_shutdown.is_none() {
+            return None;
+        }
+        if !bgpgr::touches_bgp(&self.applied_config().unwrap_or_default(), config) {
+            return None;
+        }
+        match bgpgr::non_helpers(self.args) {
+            Ok(peers) if peers.is_empty() => {}
+            Ok(peers) => warn!(
+                "Generation {genid} changes BGP. Peers not retaining routes on reset: {}",
+                peers.join(", ")
+            ),
+            Err(e) => warn!("Could not get the graceful restart state of BGP peers: {e}"),
+        }
+        let wait = Duration::from_secs(self.args.bgp_graceful_shutdown?);
+        Drain::start(self.args, config, wait)
+            .inspect_err(|e| {
+                warn!("Failed to drain BGP peers before applying generation {genid}: {e}");
+            })
+            .ok()
+    }
+
+    // the config of the last generation applied, if any
+    fn applied_config(&self) -> Option<String> {
+        let applied = self.last_applied()?;
+        rawtext::read(config_file_path(applied, self.args.outdir())).ok()
+    }
+
+    // the disruptions predicted from applying a config, with respect to the config applied
+    fn impact(&self, config: &str) -> Vec<Disruption> {
+        let Some(applied) = self.applied_config() else {
+            return vec![];
+        };
+        split_vtysh_conf(config)
+            .map(|(_, config)| impact::predict(&applied, config))
+            .unwrap_or_default()
+    }
+
+    // a config in canonical form, if canonicalization is enabled
+    fn canonical<'c>(&self, config: &'c str) -> Cow<'c, str> {
+        if self.args.canonicalize {
+            Cow::Owned(canonicalize(config))
+        } else {
+            Cow::Borrowed(config)
+        }
+    }
+
+    // the FRR config as it is to be written: in canonical form if canonicalization is enabled,
+    // with BGP graceful restart if it is to be enabled
+    fn normalize<'c>(&self, config: &'c str) -> Cow<'c, str> {
+        let config = self.canonical(config);
+        if self.gr == Some(GrMode::Enable)
+            && let Cow::Owned(enabled) = bgpgr::enable_gr(&config, self.args.bgp_llgr_stale_time)
+        {
+            return Cow::Owned(enabled);
+        }
+        config
+    }
+
+    // run the --test stage for a config ahead of time
+    pub(crate) fn validate(&self, genid: GenId, config: &str) -> Validation {
+        let mut transcript = Transcript::new();
+        let _in_progress = self.journal.begin(genid, Stage::Validate);
+        let validation = split_vtysh_conf(config)
+            .map_err(FrrErr::BadConfig)
+            .and_then(|(_, config)| {
+                frr_validate(
+                    &self.reloader,
+                    genid,
+                    &self.normalize(config),
+                    self.args.outdir(),
+                    &mut transcript,
+                )
+            });
+        (validation, transcript)
+    }
+
+    // remember the checksum of the config last applied, across restarts
+    fn record_applied(&self, genid: GenId, config: &str) {
+        let checksum = sha256_hex(&rawtext::encode(config));
+        let path = Path::new(self.args.outdir()).join(LAST_APPLIED_FILE);
+        if let Err(e) = std::fs::write(&path, format!("{genid} {checksum}\n")) {
+            warn!("Could not persist the checksum of generation {genid} at {path:?}: {e}");
+        }
+        if let Ok(mut applied_checksum) = self.applied_checksum.lock() {
+            *applied_checksum = Some((genid, checksum));
+        }
+    }
+
+    // remember the highest generation applied, across restarts, if generations must be monotonic
+    fn record_highest(&self, genid: GenId) {
+        if !self.args.monotonic_genid {
+            return;
+        }
+        let Ok(mut highest) = self.highest_applied.lock() else {
+            return;
+        };
+        if highest.is_some_and(|highest| highest >= genid) {
+            return;
+        }
+        *highest = Some(genid);
+        let path = Path::new(self.args.outdir()).join(HIGHEST_APPLIED_FILE);
+        if let Err(e) = std::fs::write(&path, format!("{genid}\n")) {
+            warn!("Could not persist the highest generation applied ({genid}) at {path:?}: {e}");
+        }
+    }
+
+    // the response to a request for a generation that is not newer than the highest one applied,
+    // if generations must be monotonic (e.g. from a lagging controller replica after a failover).
+    // Stored generations may still be rolled back to and applied.
+    fn stale(&self, genid: GenId, request: &Request) -> Option<String> {
+        if !self.args.monotonic_genid
+            || !matches!(
+                request,
+                Request::Config(..) | Request::Upload(_) | Request::Prepare(_) | Request::Promote
+            )
+        {
+            return None;
+        }
+        let highest = self.highest_applied()?;
+        if genid > highest {
+            return None;
+        }
+        warn!(
+            "Refusing {}: generation {highest} was applied",
+            request.describe(genid)
+        );
+        Some(format!(
+            "STALE_GENERATION\nthe highest generation applied is {highest}"
+        ))
+    }
+
+    // the generation applied last, if a config (as normalized) is identical to its config and
+    // configs identical to the one applied are not reloaded (--skip-unchanged or --canonicalize)
+    fn identical_to_applied(&self, config: &str) -> Option<GenId> {
+        if !self.args.skip_unchanged && !self.args.canonicalize {
+            return None;
+        }
+        let applied_checksum = self.applied_checksum.lock().ok()?;
+        let (current, checksum) = applied_checksum.as_ref()?;
+        (sha256_hex(&rawtext::encode(config)) == *checksum).then_some(*current)
+    }
+
+    // the generation applied last, if a config is identical to its config and is hence not
+    // reloaded (configs with a vtysh.conf always are)
+    fn unchanged(&self, config: &str) -> Option<GenId> {
+        match split_vtysh_conf(config) {
+            Ok((None, config)) => self.identical_to_applied(&self.normalize(config)),
+            _ => None,
+        }
+    }
+
+    // apply a config along with its vtysh.conf, if any. The vtysh.conf is restored if
+    // the config fails to apply.
+    fn apply(
+        &self,
+        genid: GenId,
+        config: &str,
+        vtysh_conf: Option<&str>,
+        apply: impl FnOnce() -> String,
+    ) -> String {
+        if let Some(deferred) = self.convergence.as_ref().and_then(Convergence::await_quiet) {
+            info!("Generation {genid} was deferred for {deferred:?} for routing to be quiet");
+        }
+        let _in_progress = self.journal.begin(genid, Stage::Apply);
+        let _drain = self.prepare_bgp(genid, config);
+        let backup = match vtysh_conf
+            .map(|vtysh_conf| install_vtysh_conf(self.args.confdir(), vtysh_conf))
+            .transpose()
+        {
+            Ok(backup) => backup,
+            Err(e) => return e,
+        };
+        let mut response = apply();
+        if response == "Ok" {
+            if let Ok(mut applied) = self.applied.lock() {
+                *applied = Some(genid);
+            }
+            self.record_applied(genid, config);
+            self.record_highest(genid);
+            if let Some(e) = self.verify(genid) {
+                response = e;
+            } else {
+                self.check_health(genid);
+                if let Some(retention) = &self.retention {
+                    let protected: Vec<GenId> = self.last_known_good().into_iter().collect();
+                    retention.prune(self.args.outdir(), genid, &protected);
+                }
+            }
+        } else if let Some(backup) = backup {
+            backup.restore();
+        }
+        self.post_hook(genid, &response);
+        response
+    }
+
+    // run the post-hook, if any, once a generation is applied (or failed to)
+    fn post_hook(&self, genid: GenId, response: &str) {
+        let Some(hook) = &self.args.post_hook else {
+            return;
+        };
+        let config = config_file_path(genid, self.args.outdir());
+        if let Err(e) = run_hook(hook, HookStage::Post, genid, &config, Some(response)) {
+            warn!("Post-hook failed for generation {genid}: {e}");
+        }
+    }
+
+    // in shadow mode, configs go through the --test stage but are never applied
+    fn shadow(
+        &self,
+        genid: GenId,
+        config: &str,
+        validation: Option<Validation>,
+        transcript: &mut Transcript,
+    ) -> String {
+        let (validation, validation_transcript) =
+            validation.unwrap_or_else(|| self.validate(genid, config));
+        transcript.extend(validation_transcript);
+        match validation {
+            Ok(_) => {
+                info!("Generation {genid} validated. Not applying it (shadow mode)");
+                SHADOWED.to_string()
+            }
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn apply_config(
+        &self,
+        genid: GenId,
+        config: &str,
+        validation: Option<Validation>,
+        transcript: &mut Transcript,
+    ) -> String {
+        if self.args.shadow {
+            return self.shadow(genid, config, validation, transcript);
+        }
+        let (vtysh_conf, config) = match split_vtysh_conf(config) {
+            Ok(split) => split,
+            Err(e) => return FrrErr::BadConfig(e).to_string(),
+        };
+        let config = self.normalize(config);
+
+        /* a config identical to the one applied is a no-op */
+        if vtysh_conf.is_none()
+            && let Some(current) = self.identical_to_applied(&config)
+        {
+            info!("Generation {genid} is identical to applied generation {current}: not reloading");
+            return self.record_unchanged(genid, &config);
+        }
+
+        /* configs changing only static routes are applied with vtysh, bypassing the reloader */
+        let fast_path = if self.args.static_fast_path && vtysh_conf.is_none() {
+            self.applied_config()
+                .and_then(|applied| staticroutes::delta(&applied, &config))
+        } else {
+            None
+        };
+        self.apply(genid, &config, vtysh_conf, || {
+            if let Some(commands) = fast_path {
+                match self.apply_static_routes(genid, &config, &commands) {
+                    Ok(()) => return "Ok".to_string(),
+                    Err(e) => warn!("Fast path failed for generation {genid}: {e}. Reloading"),
+                }
+            }
+            if let Some(validation) = validation {
+                frr_reload_validated(&self.reloader, validation, transcript)
+            } else {
+                frr_reload(
+                    &self.reloader,
+                    genid,
+                    &config,
+                    self.args.outdir(),
+                    transcript,
+                )
+            }
+        })
+    }
+
+    // store a config identical to the one applied as the generation applied, without going
+    // through any stage of an apply (FRR is not touched)
+    fn record_unchanged(&self, genid: GenId, config: &str) -> String {
+        if let Err(e) = write_config_file(genid, config, self.args.outdir()) {
+            return e.to_string();
+        }
+        if let Ok(mut applied) = self.applied.lock() {
+            *applied = Some(genid);
+        }
+        self.record_applied(genid, config);
+        self.record_highest(genid);
+        "Ok".to_string()
+    }
+
+    // apply a config that only changes static routes with the vtysh commands given, and store it
+    fn apply_static_routes(
+        &self,
+        genid: GenId,
+        config: &str,
+        commands: &[String],
+    ) -> Result<(), String> {
+        let started = Instant::now();
+        if !commands.is_empty() {
+            let commands: Vec<&str> = commands.iter().map(String::as_str).collect();
+            vtysh_config(self.args, &commands)?;
+        }
+        write_config_file(genid, config, self.args.outdir()).map_err(|e| e.to_string())?;
+        info!(
+            "Generation {genid} only changes static routes: applied with {} vtysh commands in {:?}",
+            commands.len(),
+            started.elapsed()
+        );
+        Ok(())
+    }
+
+    // store and validate a config so that it can be applied later on
+    fn stage(
+        &self,
+        genid: GenId,
+        config: &str,
+        transcript: &mut Transcript,
+    ) -> Result<Staged, FrrErr> {
+        let (validation, validation_transcript) = self.validate(genid, config);
+        transcript.extend(validation_transcript);
+        validation.and_then(|config_file| {
+            let (vtysh_conf, _) = split_vtysh_conf(config).map_err(FrrErr::BadConfig)?;
+            Ok(Staged {
+                config_file,
+                vtysh_conf: vtysh_conf.map(str::to_string),
+            })
+        })
+    }
+
+    // apply a config that was staged, running only the apply stage
+    fn apply_staged(&self, genid: GenId, staged: &Staged, transcript: &mut Transcript) -> String {
+        if self.args.shadow {
+            info!("Generation {genid} was validated. Not applying it (shadow mode)");
+            return SHADOWED.to_string();
+        }
+        let config = rawtext::read(&staged.config_file).unwrap_or_default();
+        self.apply(genid, &config, staged.vtysh_conf.as_deref(), || {
+            frr_reload_validated(
+                &self.reloader,
+                (Ok(staged.config_file.clone()), Transcript::new()),
+                transcript,
+            )
+        })
+    }
+
+    fn upload(&self, genid: GenId, config: &str, transcript: &mut Transcript) -> String {
+        match self.stage(genid, config, transcript) {
+            Ok(staged) => {
+                info!("Generation {genid} uploaded and validated. Ready to be promoted");
+                self.staging.stage(genid, staged);
+                "Ok".to_string()
+            }
+            Err(e) => e.to_string(),
+        }
+    }
+
+    // the changes the reloader would make to apply a config, as a JSON object
+    fn preview(&self, genid: GenId, config: &str, transcript: &mut Transcript) -> String {
+        let config = match split_vtysh_conf(config) {
+            Ok((_, config)) => self.normalize(config),
+            Err(e) => return FrrErr::BadConfig(e).to_string(),
+        };
+        let result = frr_preview(
+            &self.reloader,
+            genid,
+            &config,
+            self.args.outdir(),
+            transcript,
+        );
+        let run = transcript.first();
+        match result {
+            Ok(()) => Json::object([
+                ("changes", run.map_or("", |run| run.stdout.as_str()).into()),
+                ("impact", impact::to_json(&self.impact(&config))),
+            ])
+            .to_string(),
+            Err(e) => match run {
+                Some(run) => format!("{e}: {}", run.stderr.trim()),
+                None => e.to_string(),
+            },
+        }
+    }
+
+    // the generation prepared, if any. A prepared generation that was not committed in time
+    // is released.
+    fn prepared(&self) -> Option<GenId> {
+        let mut prepared = self.prepared.lock().ok()?;
+        if prepared
+            .as_ref()
+            .is_some_and(|prepared| prepared.expires <= Instant::now())
+        {
+            let expired = prepared.take().map(|prepared| prepared.genid);
+            warn!("Prepared generation {expired:?} was not committed in time: released");
+        }
+        prepared.as_ref().map(|prepared| prepared.genid)
+    }
+
+    // first phase of a two-phase apply: validate a config and lock it in. The token returned
+    // is required to commit (or abort) it.
+    fn prepare(&self, genid: GenId, config: &str, transcript: &mut Transcript) -> String {
+        if let Some(prepared) = self.prepared() {
+            return format!("Generation {prepared} is already prepared");
+        }
+        let staged = match self.stage(genid, config, transcript) {
+            Ok(staged) => staged,
+            Err(e) => return e.to_string(),
+        };
+        let Ok(written) = std::fs::read(&staged.config_file) else {
+            return format!("Generation {genid} could not be read back");
+        };
+        let checksum = sha256_hex(&written);
+        let token = match random_token() {
+            Ok(token) => token,
+            Err(e) => return e,
+        };
+        let Ok(mut prepared) = self.prepared.lock() else {
+            return "Internal failure: poisoned lock".to_string();
+        };
+        *prepared = Some(Prepared {
+            genid,
+            token: token.clone(),
+            staged,
+            expires: Instant::now() + self.args.prepare_timeout(),
+        });
+        info!("Generation {genid} prepared. Waiting for commit");
+        Json::object([("token", token.into()), ("sha256", checksum.into())]).to_string()
+    }
+
+    // take the prepared generation, provided that the genid and token match
+    fn take_prepared(&self, genid: GenId, token: &str) -> Result<Prepared, String> {
+        self.prepared();
+        let mut prepared = self
+            .prepared
+            .lock()
+            .map_err(|_| "Internal failure: poisoned lock".to_string())?;
+        match prepared.as_ref() {
+            None => Err("No generation is prepared".to_string()),
+            Some(p) if p.genid != genid => Err(format!("Generation {} is prepared", p.genid)),
+            Some(p) if p.token != token => Err("Bad token".to_string()),
+            Some(_) => prepared
+                .take()
+                .ok_or("No generation is prepared".to_string()),
+        }
+    }
+
+    // second phase of a two-phase apply: apply the prepared generation. The lock is released
+    // whatever the outcome.
+    fn commit(&self, genid: GenId, token: &str, transcript: &mut Transcript) -> String {
+        let prepared = match self.take_prepared(genid, token) {
+            Ok(prepared) => prepared,
+            Err(e) => return e,
+        };
+        let response = self.apply_staged(genid, &prepared.staged, transcript);
+        if response == "Ok" {
+            info!("Generation {genid} was committed");
+        }
+        response
+    }
+
+    fn abort(&self, genid: GenId, token: &str) -> String {
+        match self.take_prepared(genid, token) {
+            Ok(_) => {
+                info!("Prepared generation {genid} was aborted");
+                "Ok".to_string()
+            }
+            Err(e) => e,
+        }
+    }
+
+    // cancel the apply of a generation queued, or whose --test stage is running. The apply of a
+    // generation whose --reload stage is running is never interrupted.
+    fn cancel(&self, genid: GenId) -> String {
+        /* a config waiting in the queue of its connection may be validated ahead meanwhile */
+        let validating = TESTS.cancel(genid);
+        if self.pending.cancel(genid) {
+            return format!("Ok\ngeneration {genid} was dropped from the queue of its connection");
+        }
+        if let Some(queue) = &self.apply_queue
+            && queue.cancel(genid)
+        {
+            return format!("Ok\ngeneration {genid} was dropped from the apply queue");
+        }
+        if validating {
+            return format!("Ok\nthe --test stage of generation {genid} is being cancelled");
+        }
+        if self.pending.applying() == Some(genid) {
+            return format!("Generation {genid} is being reloaded and can no longer be cancelled");
+        }
+        format!("Generation {genid} is neither queued nor being validated")
+    }
+
+    fn promote(&self, genid: GenId, transcript: &mut Transcript) -> String {
+        let Some(staged) = self.staging.get(genid) else {
+            warn!("Can't promote generation {genid}: it has not been uploaded");
+            return format!("Generation {genid} has not been uploaded");
+        };
+        let response = self.apply_staged(genid, &staged, transcript);
+        if response == "Ok" {
+            info!("Generation {genid} was promoted");
+            self.staging.unstage(genid);
+        }
+        response
+    }
+
+    // the config of a stored generation
+    fn stored_config(&self, genid: GenId) -> Result<String, String> {
+        rawtext::read(config_file_path(genid, self.args.outdir())).map_err(|e| {
+            warn!("Generation {genid} is not available: {e}");
+            format!("Generation {genid} is not available: {e}")
+        })
+    }
+
+    // re-apply a generation that was previously applied
+    fn rollback(&self, genid: GenId, transcript: &mut Transcript) -> String {
+        let config = match self.stored_config(genid) {
+            Ok(config) => config,
+            Err(e) => return e,
+        };
+        info!("Rolling back to generation {genid}...");
+        self.apply_config(genid, &config, None, transcript)
+    }
+
+    // apply any stored generation. Without a confirmation, the changes from the last generation
+    // applied are returned along with a token to confirm them with: a nonce generated for the
+    // preview, so that a generation can not be applied without being previewed. The token only
+    // confirms the changes previewed, once: it is stale once another generation is applied.
+    fn apply_generation(
+        &self,
+        genid: GenId,
+        confirm: Option<&str>,
+        transcript: &mut Transcript,
+    ) -> String {
+        let config = match self.stored_config(genid) {
+            Ok(config) => config,
+            Err(e) => return e,
+        };
+        let current = self.last_applied();
+        let Some(confirm) = confirm else {
+            let nonce = match random_token() {
+                Ok(nonce) => nonce,
+                Err(e) => return e,
+            };
+            let Ok(mut previews) = self.previews.lock() else {
+                return "Internal failure: poisoned lock".to_string();
+            };
+            previews.insert(
+                genid,
+                ApplyPreview {
+                    nonce: nonce.clone(),
+                    current,
+                },
+            );
+            let current_config = current
+                .and_then(|current| self.stored_config(current).ok())
+                .unwrap_or_default();
+            return Json::object([
+                ("genid", genid.into()),
+                ("current", current.into()),
+                ("diff", diff_configs(&current_config, &config).into()),
+                ("impact", impact::to_json(&self.impact(&config))),
+                ("confirm", nonce.into()),
+            ])
+            .to_string();
+        };
+        let preview = self
+            .previews
+            .lock()
+            .ok()
+            .and_then(|mut previews| previews.remove(&genid));
+        match preview {
+            Some(preview) if preview.nonce == confirm && preview.current == current => {
+                info!("Applying generation {genid} (confirmed)...");
+                self.apply_config(genid, &config, None, transcript)
+            }
+            Some(preview) if preview.nonce == confirm => {
+                warn!("Refusing to apply generation {genid}: stale confirmation");
+                "Stale confirmation: the generation applied changed since the preview".to_string()
+            }
+            _ => {
+                warn!("Refusing to apply generation {genid}: bad confirmation");
+                format!("Bad confirmation: preview generation {genid} with APPLY first")
+            }
+        }
+    }
+
+    fn status(&self) -> String {
+        /* the agent is healthy if watchfrr reports all the FRR daemons up */
+        let (watchfrr, healthy) = match watchfrr_status(self.args) {
+            Ok(daemons) => {
+                let healthy = !daemons.is_empty() && daemons.values().all(|state| state == "up");
+                let daemons = daemons
+                    .into_iter()
+                    .map(|(daemon, state)| (daemon, Json::from(state)));
+                (Json::Object(daemons.collect()), healthy)
+            }
+            Err(e) => {
+                warn!("Could not get watchfrr status: {e}");
+                (Json::Null, false)
+            }
+        };
+        let staged = self
+            .staging
+            .validated
+            .lock()
+            .map(|validated| validated.keys().map(|genid| Json::from(*genid)).collect())
+            .unwrap_or_default();
+        let last_result = self.last_result.lock().ok().and_then(|last| last.clone());
+        let binary = self.reloader.binary();
+        let reloader = Json::object([
+            ("path", binary.path.as_str().into()),
+            ("sha256", binary.sha256.as_deref().into()),
+            (
+                "timeout",
+                self.reloader
+                    .timeout
+                    .map(|timeout| timeout.as_secs())
+                    .into(),
+            ),
+        ]);
+        let status = Json::object([
+            ("last_applied", self.last_applied().into()),
+            ("last_known_good", self.last_known_good().into()),
+            ("highest_applied", self.highest_applied().into()),
+            ("last_result", last_result.into()),
+            ("reloading", self.longest_apply().is_some().into()),
+            ("reloader", reloader),
+            (
+                "instances",
+                Json::Array(
+                    self.instances
+                        .keys()
+                        .map(|name| name.as_str().into())
+                        .collect(),
+                ),
+            ),
+            ("staged", Json::Array(staged)),
+            (
+                "verification",
+                self.verification
+                    .lock()
+                    .ok()
+                    .and_then(|last| {
+                        last.as_ref().map(|(genid, verification)| {
+                            Json::object([
+                                ("genid", (*genid).into()),
+                                ("outcome", verification.to_json()),
+                            ])
+                        })
+                    })
+                    .into(),
+            ),
+            ("sessions", self.sessions.to_json()),
+            ("frr_daemons", self.monitor.to_json()),
+            ("watchfrr", watchfrr),
+            ("healthy", healthy.into()),
+            ("breaker", self.breaker.to_json()),
+            ("slo", self.slo.to_json()),
+            (
+                "convergence",
+                self.convergence
+                    .as_ref()
+                    .map_or(Json::Null, Convergence::to_json),
+            ),
+            ("build", build_info(self.started)),
+            ("quotas", self.quotas.to_json()),
+            ("queue", self.pending.to_json()),
+            ("sends", SEND_STATS.to_json()),
+            ("framing", FRAMING_STATS.to_json()),
+            ("held", self.held.to_json()),
+            (
+                "apply_queue",
+                self.apply_queue
+                    .as_ref()
+                    .map_or(Json::Null, ApplyQueue::to_json),
+            ),
+            (
+                "interrupted",
+                Json::Array(self.interrupted.iter().map(|r| r.to_json()).collect()),
+            ),
+        ]);
+        node::identity().tag(status).to_string()
+    }
+
+    // the state of the agent written to the state file: the last generation applied, the last
+    // result, timestamps and health flags
+    pub(crate) fn state(&self) -> Json {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let last_result = self.last_result.lock().ok().and_then(|last| last.clone());
+        let state = Json::object([
+            ("time", now.into()),
+            ("pid", u64::from(std::process::id()).into()),
+            ("uptime", self.started.elapsed().as_secs().into()),
+            ("last_applied", self.last_applied().into()),
+            ("last_known_good", self.last_known_good().into()),
+            ("last_result", last_result.into()),
+            ("healthy", self.monitor.healthy().into()),
+            ("breaker_open", self.breaker.is_open().into()),
+            (
+                "pending",
+                u64::try_from(self.pending.len()).unwrap_or(u64::MAX).into(),
+            ),
+        ]);
+        node::identity().tag(state)
+    }
+
+    // the keepalive parameters agreed for what a client requested
+    pub(crate) fn negotiate_keepalive(&self, requested: &Keepalive) -> Keepalive {
+        requested.negotiate(self.args.keepalive_max())
+    }
+
+    fn hello(
+        &self,
+        requested: &Keepalive,
+        framing: Option<Framing>,
+        compression: Option<Compression>,
+        client: &Client,
+    ) -> String {
+        let agreed = self.negotiate_keepalive(requested);
+        info!("Agreed keepalives with client: {agreed}");
+        self.sessions.set_keepalive(client.session, agreed);
+        let mut response = vec![agreed.to_string()];
+        if let Some(framing) = framing {
+            info!("Agreed framing v{} with client", framing.version());
+            response.push(format!("framing={}", framing.version()));
+        }
+        if let Some(compression) = compression {
+            info!("Agreed compression {} with client", compression.name());
+            response.push(format!("compression={}", compression.name()));
+        }
+        response.join(" ")
+    }
+
+    // check that a client may perform a privileged operation (i.e. that its uid is authorized)
+    fn authorize(&self, client: &Client, operation: &str) -> Result<(), String> {
+        let Some(peer) = client.cred else {
+            warn!("Refusing to {operation}: peer credentials are unknown");
+            return Err("Not authorized".to_string());
+        };
+        if !self.args.service_uids().contains(&peer.uid) {
+            warn!("Refusing to {operation}: peer ({peer}) is not authorized");
+            return Err("Not authorized".to_string());
+        }
+        Ok(())
+    }
+
+    // start, stop or restart FRR. Only peers with an authorized uid may do this.
+    fn control_service(&self, action: FrrAction, client: &Client) -> String {
+        if let Err(e) = self.authorize(client, &format!("{action} FRR")) {
+            return e;
+        }
+        match privileged::control_frr(self.args, action) {
+            Ok(()) => "Ok".to_string(),
+            Err(e) => e,
+        }
+    }
+
+    // close the circuit breaker. Only peers with an authorized uid may do this.
+    fn reset_breaker(&self, client: &Client) -> String {
+        if let Err(e) = self.authorize(client, "reset the circuit breaker") {
+            return e;
+        }
+        self.breaker.reset();
+        "Ok".to_string()
+    }
+
+    // open a vtysh passthrough session. Only peers with an authorized uid may do this.
+    fn open_passthrough(&self, client: &Client) -> String {
+        if self.args.vtysh_allow.is_empty() {
+            return "vtysh passthrough is disabled".to_string();
+        }
+        if let Err(e) = self.authorize(client, "open a vtysh session") {
+            return e;
+        }
+        "Ok".to_string()
+    }
+
+    // export or import the generation history. Only peers with an authorized uid may do this,
+    // since archives are read from or written to the filesystem of the agent.
+    fn export(&self, archive: &str, client: &Client) -> String {
+        if let Err(e) = self.authorize(client, "export history") {
+            return e;
+        }
+        match export_archive(self.args.outdir(), archive, self.last_applied()) {
+            Ok(()) => "Ok".to_string(),
+            Err(e) => e,
+        }
+    }
+    fn import(&self, archive: &str, client: &Client) -> String {
+        if let Err(e) = self.authorize(client, "import history") {
+            return e;
+        }
+        match import_archive(self.args.outdir(), archive) {
+            Ok(_) => "Ok".to_string(),
+            Err(e) => e,
+        }
+    }
+
+    // store the metadata of a request that ran the reloader along with its generation
+    fn record_metadata(&self, genid: GenId, request: &str, peer: &str, result: &str) -> Json {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let complexity = rawtext::read(config_file_path(genid, self.args.outdir()))
+            .map_or(Json::Null, |config| Complexity::of(&config).to_json());
+        let metadata = Json::object([
+            ("genid", genid.into()),
+            ("request", request.into()),
+            ("peer", peer.into()),
+            ("result", result.into()),
+            ("time", time.as_secs().into()),
+            ("complexity", complexity),
+        ]);
+        let path = metadata_file_path(genid, self.args.outdir());
+        if let Err(e) = std::fs::write(&path, metadata.to_string()) {
+            warn!("Could not store metadata of generation {genid} at {path:?}: {e}");
+        }
+        metadata
+    }
+
+    // the generation that introduced a line in the config of a generation (of the last one
+    // applied if genid is 0)
+    fn blame(&self, genid: GenId, line: &str) -> String {
+        let genid = match genid {
+            0 => self.last_applied(),
+            genid => Some(genid),
+        };
+        match genid.map(|genid| blame(self.args.outdir(), genid, line)) {
+            Some(Ok(blame)) => blame.to_string(),
+            Some(Err(e)) => e,
+            None => "No generation was applied".to_string(),
+        }
+    }
+
+    // the change log of a time window, which ends now if to is not given
+    fn changes(&self, from: u64, to: Option<u64>) -> String {
+        let to = to.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        });
+        changes(self.args.outdir(), from, to).to_string()
+    }
+
+    // fire the alerts due after a request applying a generation
+    fn alert(&self, genid: GenId, request: &Request, response: &str) {
+        /* cancellations are not failures */
+        if response.starts_with("CANCELLED\n") {
+            return;
+        }
+        let success = response == "Ok" || response == SHADOWED;
+        if !success {
+            let message = format!("{} failed: {response}", request.describe(genid));
+            self.notify(AlertEvent::ApplyFailed, Some(genid), &message);
+        } else if matches!(request, Request::Rollback) {
+            let message = format!("Rolled back to generation {genid}");
+            self.notify(AlertEvent::Rollback, Some(genid), &message);
+        }
+        if self.breaker.record(success) {
+            let message = "Circuit breaker tripped: config requests are refused";
+            self.notify(AlertEvent::BreakerTripped, Some(genid), message);
+        }
+    }
+
+    // keep a significant event in the recent history and fire an alert for it
+    pub(crate) fn notify(&self, event: AlertEvent, genid: Option<GenId>, message: &str) {
+        self.recent.event(event, genid, message);
+        self.alerter.fire(event, genid, message);
+    }
+
+    // the response to a request that must not be processed, if so
+    fn refusal(&self, genid: GenId, request: &Request) -> Option<String> {
+        /* a prepared generation locks out any other change */
+        if matches!(
+            request,
+            Request::Config(..)
+                | Request::Promote
+                | Request::Rollback
+                | Request::Apply(Some(_))
+                | Request::Daemons(_)
+                | Request::Service(_)
+        ) && let Some(prepared) = self.prepared()
+        {
+            warn!(
+                "Refusing {}: generation {prepared} is prepared",
+                request.describe(genid)
+            );
+            return Some(format!("Locked: generation {prepared} is prepared"));
+        }
+        /* while the circuit breaker is open, no generation is written */
+        if request.writes_generation() && self.breaker.is_open() {
+            warn!(
+                "Refusing {}: circuit breaker is open",
+                request.describe(genid)
+            );
+            return Some("BREAKER_OPEN".to_string());
+        }
+        /* the baseline is always generation 0 */
+        if matches!(request, Request::Baseline) && genid != 0 {
+            return Some(format!("The baseline is generation 0, not {genid}"));
+        }
+        /* in shadow mode, FRR is never touched */
+        if self.args.shadow && matches!(request, Request::Daemons(_) | Request::Service(_)) {
+            info!("Not processing {} (shadow mode)", request.describe(genid));
+            return Some(SHADOWED.to_string());
+        }
+        None
+    }
+
+    // the response to a request exceeding the quotas of the peer, if so
+    fn throttled(&self, genid: GenId, request: &Request, client: &Client) -> Option<String> {
+        /* keepalives are never throttled, so that sessions stay alive */
+        if matches!(request, Request::Keepalive) {
+            return None;
+        }
+        let Err(e) = self.quotas.admit(client.cred.as_ref(), request.applies()) else {
+            return None;
+        };
+        warn!("Throttling {}: {e}", request.describe(genid));
+        Some(format!("THROTTLED\n{e}"))
+    }
+
+    // the response to an apply exceeding the applies allowed per minute, if so
+    fn rate_limited(&self, genid: GenId, request: &Request) -> Option<String> {
+        if !request.applies() {
+            return None;
+        }
+        let Err(e) = self.quotas.admit_apply() else {
+            return None;
+        };
+        warn!("Rate-limiting {}: {e}", request.describe(genid));
+        Some(format!("RATE_LIMITED\n{e}"))
+    }
+
+    // the config a request is to validate or apply, if any
+    fn candidate<'r>(&self, genid: GenId, request: &'r Request) -> Option<Cow<'r, str>> {
+        match request {
+            Request::Config(config, _)
+            | Request::Upload(config)
+            | Request::Preview(config)
+            | Request::Prepare(config) => Some(Cow::Borrowed(config.as_str())),
+            Request::Rollback | Request::Apply(Some(_)) => {
+                Some(Cow::Owned(self.stored_config(genid).ok()?))
+            }
+            _ => None,
+        }
+    }
+
+    // the response to a request with a config changing BGP without graceful restart, if it
+    // is to be verified
+    fn lacking_gr(&self, genid: GenId, request: &Request) -> Option<String> {
+        if self.gr != Some(GrMode::Verify) {
+            return None;
+        }
+        let config = self.candidate(genid, request)?;
+        let (_, config) = split_vtysh_conf(&config).ok()?;
+        if !bgpgr::touches_bgp(&self.applied_config().unwrap_or_default(), config) {
+            return None;
+        }
+        let lacking = bgpgr::lacking_gr(config);
+        if lacking.is_empty() {
+            return None;
+        }
+        warn!(
+            "Refusing {}: BGP graceful restart is not enabled in {}",
+            request.describe(genid),
+            lacking.join(", ")
+        );
+        Some(format!("GR_REQUIRED\n{}", lacking.join("\n")))
+    }
+
+    // the response to a request with a config violating the policy, if so. Configs are checked
+    // against the policy before they are validated or applied, including stored generations
+    // rolled back to (whose config may predate the policy).
+    fn policy_violation(&self, genid: GenId, request: &Request) -> Option<String> {
+        if self.policy.is_empty() {
+            return None;
+        }
+        let config = self.candidate(genid, request)?;
+        let (_, config) = split_vtysh_conf(&config).ok()?;
+        let violations = self
+            .policy
+            .violations(config, self.applied_config().as_deref());
+        if violations.is_empty() {
+            return None;
+        }
+        warn!(
+            "Refusing {}: policy violated: {}",
+            request.describe(genid),
+            violations.join("; ")
+        );
+        Some(format!("POLICY_VIOLATION\n{}", violations.join("\n")))
+    }
+
+    // the response to a config predicted to be disruptive that is not forced, if --require-force
+    // is set
+    fn unforced(
+        &self,
+        genid: GenId,
+        request: &Request,
+        disruptions: &[Disruption],
+    ) -> Option<String> {
+        if disruptions.is_empty() {
+            return None;
+        }
+        let disruptions: Vec<String> = disruptions.iter().map(ToString::to_string).collect();
+        match request {
+            Request::Config(_, false) if self.args.require_force => {
+                warn!(
+                    "Refusing {}: disruptions predicted: {}",
+                    request.describe(genid),
+                    disruptions.join("; ")
+                );
+                Some(format!("DISRUPTIVE\n{}", disruptions.join("\n")))
+            }
+            _ => {
+                warn!(
+                    "Applying generation {genid} is predicted to disrupt: {}",
+                    disruptions.join("; ")
+                );
+                None
+            }
+        }
+    }
+
+    // the response to a request applying a config while FRR is down, if it is rejected or held
+    fn frr_down(&self, genid: GenId, request: &Request, client: &Client) -> Option<String> {
+        let policy = self.frr_down?;
+        if !request.applies() {
+            return None;
+        }
+        match policy {
+            FrrDownPolicy::Reject => {
+                let e = probe(self.args).err()?;
+                warn!("Rejecting {}: FRR is down: {e}", request.describe(genid));
+                Some(format!("FRR_DOWN\n{e}"))
+            }
+            FrrDownPolicy::Hold => self
+                .held
+                .hold(genid, request, client.cred, || probe(self.args)),
+        }
+    }
+
+    // process the requests queued while others were processed (--apply-queue), in order
+    pub(crate) fn run_apply_queue(&self) {
+        let Some(queue) = &self.apply_queue else {
+            return;
+        };
+        loop {
+            queue.wait();
+            /* requests are dequeued once serialized, so that no other is processed before them */
+            let _serialized = self.executor.lock();
+            let Some(queued) = queue.pop() else {
+                continue;
+            };
+            let (genid, request) = (queued.genid, &queued.request);
+            let client = Client {
+                session: 0,
+                cred: queued.cred,
+            };
+            let in_flight = self.pending.received(genid, request);
+            in_flight.start();
+            info!("Processing queued {}...", request.describe(genid));
+            let response = self
+                .audited(genid, request, &client, || {
+                    self.handle_serialized(genid, request, &client, None, false)
+                })
+                .response;
+            info!("Queued {} got: {response}", request.describe(genid));
+        }
+    }
+
+    // apply the requests held while FRR was down, once it is reachable again
+    pub(crate) fn release_held(&self) {
+        if self.frr_down != Some(FrrDownPolicy::Hold) {
+            return;
+        }
+        loop {
+            /* serialized before the held requests are locked, as requests being processed are */
+            if !self.held.is_empty() {
+                let _serialized = self.executor.lock();
+                self.held.release(
+                    || probe(self.args),
+                    |genid, request, cred| {
+                        let client = Client { session: 0, cred };
+                        info!(
+                            "FRR is reachable. Processing held {}...",
+                            request.describe(genid)
+                        );
+                        let response = self
+                            .audited(genid, request, &client, || {
+                                self.handle_serialized(genid, request, &client, None, true)
+                            })
+                            .response;
+                        info!("Held {} got: {response}", request.describe(genid));
+                    },
+                );
+            }
+            sleep(HELD_PROBE_INTERVAL);
+        }
+    }
+
+    // process a request from a client. If the request is a config whose --test stage was
+    // already run, its outcome is provided in validation. The transcript of the reloader runs,
+    // if any, is shipped to the remote collectors.
+    // Successful responses are "Ok", followed by "\nsha256=<checksum>\ncomplexity=<metrics>" for
+    // generations written, "\ndiff=<changes>" (the output of the --test stage, as a JSON string) if
+    // the reloader was run, and "\nattempts=<n>", the most attempts a stage of the reloader took, if
+    // transient failures are retried.
+    pub(crate) fn handle(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+        validation: Option<Validation>,
+    ) -> String {
+        self.handle_outcome(genid, request, client, validation)
+            .response
+    }
+
+    // process a request from a client, as handle(), keeping the transcript of the reloader runs.
+    // Apply attempts are recorded in the audit log, if kept.
+    pub(crate) fn handle_outcome(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+        validation: Option<Validation>,
+    ) -> Outcome {
+        self.audited(genid, request, client, || {
+            self.outcome(genid, request, client, validation)
+        })
+    }
+
+    // the outcome of a request, recorded in the audit log (if kept) if it applies a generation,
+    // whether it is processed right away, queued or held
+    fn audited(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+        outcome: impl FnOnce() -> Outcome,
+    ) -> Outcome {
+        let started = Instant::now();
+        let outcome = outcome();
+        if request.applies()
+            && let Some(audit) = &self.audit
+        {
+            /* the checksum of the config requested, else of the generation applied */
+            let sha256 = match request {
+                Request::Config(config, _) => Some(sha256_hex(&rawtext::encode(config))),
+                Request::Spooled(spooled, _) => Some(spooled.sha256().to_string()),
+                _ => std::fs::read(config_file_path(genid, self.args.outdir()))
+                    .ok()
+                    .map(|config| sha256_hex(&config)),
+            };
+            let entry = AuditEntry {
+                genid,
+                request: request.describe(genid),
+                cred: client.cred,
+                sha256,
+                result: outcome.response.lines().next().unwrap_or_default(),
+                duration: started.elapsed(),
+            };
+            if let Err(e) = audit.append(&entry) {
+                error!("{e}");
+            }
+        }
+        outcome
+    }
+
+    fn outcome(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+        validation: Option<Validation>,
+    ) -> Outcome {
+        if self.args.always_ok
+            && !matches!(
+                request,
+                Request::Keepalive
+                    | Request::Status
+                    | Request::Version
+                    | Request::Hello(..)
+                    | Request::Interfaces(_)
+                    | Request::Subscribe(_)
+                    | Request::Vtysh
+                    | Request::Show(_)
+                    | Request::Logs(_)
+                    | Request::History(_)
+                    | Request::Format(_)
+                    | Request::Blame(_)
+                    | Request::Changes(..)
+                    | Request::Apply(None)
+            )
+        {
+            warn!("This agent is running in always-ok mode and will always report SUCCESS");
+            return "Ok".to_string().into();
+        }
+        /* requests changing state are processed one at a time, whatever the client */
+        let _serialized = match self.serialize(genid, request, client) {
+            Ok(serialized) => serialized,
+            Err(response) => return response.into(),
+        };
+        self.handle_serialized(genid, request, client, validation, false)
+    }
+
+    // serialize a request with the other requests changing state. With --apply-queue, requests
+    // applying configs are queued instead of waiting while others are processed: the response
+    // to them is returned then.
+    fn serialize(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+    ) -> Result<Option<Serialized<'_>>, String> {
+        if !request.is_serialized() {
+            return Ok(None);
+        }
+        if let Some(queue) = &self.apply_queue
+            && request.applies()
+        {
+            if queue.is_empty()
+                && let Some(serialized) = self.executor.try_lock()
+            {
+                return Ok(Some(serialized));
+            }
+            return Err(queue.push(genid, request, client.cred, self.pending.applying()));
+        }
+        Ok(self.executor.lock())
+    }
+
+    // process a request from a client, once serialized with the other requests changing state.
+    // Requests released once FRR is reachable again (held) are checked again, but are not held
+    // again.
+    fn handle_serialized(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+        validation: Option<Validation>,
+        held: bool,
+    ) -> Outcome {
+        /* rolling back to generation 0 means rolling back to the last-known-good generation */
+        let genid = match (request, genid) {
+            (Request::Rollback, 0) => match self.last_known_good() {
+                Some(lkg) => lkg,
+                None => {
+                    return "No last-known-good generation to roll back to"
+                        .to_string()
+                        .into();
+                }
+            },
+            _ => genid,
+        };
+        /* configs received in chunks are loaded only once processed */
+        if let Some(loaded) = self.load(genid, request) {
+            return self.handle_serialized(genid, &loaded, client, validation, held);
+        }
+        /* requests held were accounted for against the quotas when received, and are not held
+         * again once released */
+        let over_quota = || {
+            self.throttled(genid, request, client)
+                .or_else(|| self.rate_limited(genid, request))
+        };
+        if let Some(response) = self
+            .refusal(genid, request)
+            .or_else(|| self.stale(genid, request))
+            .or_else(|| (!held).then(over_quota).flatten())
+            .or_else(|| self.policy_violation(genid, request))
+            .or_else(|| self.lacking_gr(genid, request))
+        {
+            return response.into();
+        }
+        let disruptions = match request {
+            Request::Config(config, _) => self.impact(config),
+            _ => vec![],
+        };
+        let down = || self.frr_down(genid, request, client);
+        if let Some(response) = self
+            .unforced(genid, request, &disruptions)
+            .or_else(|| (!held).then(down).flatten())
+        {
+            return response.into();
+        }
+        let unchanged = match request {
+            Request::Config(config, _) => self.unchanged(config),
+            _ => None,
+        };
+        let mut outcome = self.process(genid, request, client, validation);
+
+        /* configs applied report the disruptions predicted, if any */
+        let response = &outcome.response;
+        if !disruptions.is_empty()
+            && (response.starts_with("Ok\n") || response.starts_with(SHADOWED))
+        {
+            outcome.response = format!("{response}\nimpact={}", impact::to_json(&disruptions));
+        }
+
+        /* configs identical to the one applied report the generation they are identical to */
+        if let Some(current) = unchanged
+            && outcome.response.starts_with("Ok\n")
+        {
+            outcome.response = format!("{}\nunchanged={current}", outcome.response);
+        }
+
+        /* rollbacks report the generation now active, which the client may not know (genid 0) */
+        if matches!(request, Request::Rollback) && outcome.response.starts_with("Ok\n") {
+            outcome.response = format!("{}\nactive={genid}", outcome.response);
+        }
+        outcome
+    }
+
+    // process a request that was admitted
+    fn process(
+        &self,
+        genid: GenId,
+        request: &Request,
+        client: &Client,
+        validation: Option<Validation>,
+    ) -> Outcome {
+        let mut transcript = Transcript::new();
+        let response = match request {
+            Request::Keepalive => self.keepalive(),
+            Request::Subscribe(_) | Request::Format(_) => "Ok".to_string(),
+            Request::Config(config, _) => {
+                self.apply_config(genid, config, validation, &mut transcript)
+            }
+            Request::Upload(config) => self.upload(genid, config, &mut transcript),
+            Request::Preview(config) => self.preview(genid, config, &mut transcript),
+            Request::Prepare(config) => self.prepare(genid, config, &mut transcript),
+            Request::Commit(token) => self.commit(genid, token, &mut transcript),
+            Request::Abort(token) => self.abort(genid, token),
+            Request::Promote => self.promote(genid, &mut transcript),
+            Request::Daemons(daemons) => match privileged::set_frr_daemons(self.args, daemons) {
+                Ok(()) => "Ok".to_string(),
+                Err(e) => e,
+            },
+            Request::Service(action) => self.control_service(*action, client),
+            Request::Hello(requested, framing, compression) => {
+                self.hello(requested, *framing, *compression, client)
+            }
+            Request::Interfaces(names) => match interface_status(self.args, names) {
+                Ok(interfaces) => interfaces.to_string(),
+                Err(e) => e,
+            },
+            Request::Rollback => self.rollback(genid, &mut transcript),
+            Request::Apply(confirm) => {
+                self.apply_generation(genid, confirm.as_deref(), &mut transcript)
+            }
+            Request::Export(archive) => self.export(archive, client),
+            Request::Import(archive) => self.import(archive, client),
+            Request::Blame(line) => self.blame(genid, line),
+            Request::Changes(from, to) => self.changes(*from, *to),
+            Request::Status => self.status(),
+            Request::Baseline => match self.snapshot_baseline() {
+                Ok(()) => "Ok".to_string(),
+                Err(e) => e,
+            },
+            Request::Version => node::identity().tag(build_info(self.started)).to_string(),
+            Request::Reset => self.reset_breaker(client),
+            Request::Vtysh => self.open_passthrough(client),
+            Request::Show(command) => show(self.args, command),
+            Request::Logs(count) => self.recent.to_json(*count).to_string(),
+            Request::History(count) => history(self.args.outdir(), *count).to_string(),
+            Request::Chunked(_) => {
+                "Chunked transfers are only supported by the binary framing".to_string()
+            }
+            Request::Spooled(..) => "Internal failure: config received in chunks not loaded".into(),
+            Request::Cancel => self.cancel(genid),
+            Request::Invalid(e) => e.clone(),
+        };
+        let writes_generation = request.writes_generation();
+        if request.applies() {
+            self.alert(genid, request, &response);
+        }
+        let config_file = config_file_path(genid, self.args.outdir());
+        if writes_generation && config_file.exists() {
+            let peer = client
+                .cred
+                .map_or("unknown".to_string(), |cred| cred.to_string());
+            let applied =
+                response == "Ok" && (request.applies() || matches!(request, Request::Baseline));
+            let request = request.describe(genid);
+            self.report(genid, request, peer, &response, &transcript, applied);
+        }
+
+        let persist_error = self.persist(genid, request, &response);
+
+        /* successful responses to requests writing a generation carry the checksum and the
+         * complexity metrics of the file */
+        if (response == "Ok" || response == SHADOWED)
+            && writes_generation
+            && let Ok(config) = std::fs::read(&config_file)
+        {
+            let mut response = format!(
+                "{response}\nsha256={}\ncomplexity={}",
+                sha256_hex(&config),
+                Complexity::of(&String::from_utf8_lossy(&config)).to_json()
+            );
+            if let Some(e) = persist_error {
+                let _ = write!(response, "\npersist_error={}", e.replace('\n', " "));
+            }
+            /* the changes computed by the --test stage, so that callers can log what changed */
+            if let Some(run) = transcript.iter().rev().find(|run| run.test) {
+                let _ = write!(response, "\ndiff={}", Json::from(run.stdout.as_str()));
+            }
+            if self.reloader.retry.enabled() {
+                let attempts = transcript.iter().map(|run| run.attempt).max().unwrap_or(1);
+                let _ = write!(response, "\nattempts={attempts}");
+            }
+            if request.applies()
+                && let Some(verification) = self.verification_of(genid)
+            {
+                let _ = write!(response, "\nverification={}", verification.to_json());
+            }
+            return Outcome {
+                response,
+                transcript,
+            };
+        }
+        Outcome {
+            response,
+            transcript,
+        }
+    }
+
+    // persist a generation applied, if told so (--persist). Failing to persist it does not fail
+    // the request, since the config is applied, but is reported apart. Returns the failure, if any.
+    fn persist(&self, genid: GenId, request: &Request, response: &str) -> Option<String> {
+        let mode = self.args.persist_mode()?;
+        if response != "Ok" || !request.applies() {
+            return None;
+        }
+        let e = persist(self.args, mode, genid).err()?;
+        error!("Failed to persist generation {genid}: {e}");
+        Some(e)
+    }
+
+    // record the outcome of a request that wrote a generation and ship its transcript. Generations
+    // applied are committed to the git history and backed up.
+    fn report(
+        &self,
+        genid: GenId,
+        request: String,
+        peer: String,
+        response: &str,
+        transcript: &Transcript,
+        applied: bool,
+    ) {
+        let metadata = self.record_metadata(genid, &request, &peer, response);
+        if let Ok(mut last_result) = self.last_result.lock() {
+            *last_result = Some(Json::object([
+                ("genid", genid.into()),
+                ("request", request.as_str().into()),
+                ("result", response.lines().next().unwrap_or_default().into()),
+                ("time", metadata.get("time").cloned().unwrap_or(Json::Null)),
+            ]));
+        }
+        let checksum = std::fs::read(config_file_path(genid, self.args.outdir()))
+            .ok()
+            .map(|config| sha256_hex(&config));
+        let entry = Json::object([
+            ("genid", genid.into()),
+            ("time", metadata.get("time").cloned().unwrap_or(Json::Null)),
+            ("request", request.as_str().into()),
+            ("peer", peer.as_str().into()),
+            ("sha256", checksum.into()),
+            ("result", response.lines().next().unwrap_or_default().into()),
+            ("applied", applied.into()),
+        ]);
+        if let Err(e) = record(self.args.outdir(), &entry) {
+            warn!("{e}");
+        }
+        if applied
+            && let Some(history) = &self.history
+            && let Err(e) = history.commit(
+                genid,
+                &config_file_path(genid, self.args.outdir()),
+                &metadata,
+            )
+        {
+            warn!("Failed to commit generation {genid} to git history: {e}");
+        }
+        if applied && let Some(backup) = &self.backup {
+            backup.mirror(genid, self.args.outdir());
+        }
+        if applied && let Ok(config) = rawtext::read(config_file_path(genid, self.args.outdir())) {
+            self.secondaries.forward(genid, config);
+        }
+        if let Some(breach) = self.slo.check(genid, transcript) {
+            self.notify(AlertEvent::SlowReload, Some(genid), &breach);
+        }
+        self.recent
+            .transcript(genid, &request, &peer, response, transcript);
+        self.shipper
+            .ship(genid, request, peer, response, transcript.clone());
+    }
+
+    // store the running config of FRR as generation 0, so that the first rollback and the first
+    // diff have something meaningful to compare against. Generation 0 is the last generation
+    // applied (and the last-known-good one) if none was.
+    fn snapshot_baseline(&self) -> Result<(), String> {
+        let config = running_config(self.args)?;
+        write_config_file(0, &self.canonical(&config), self.args.outdir())
+            .map_err(|e| e.to_string())?;
+        if let Ok(mut applied) = self.applied.lock() {
+            applied.get_or_insert(0);
+        }
+        if self.last_known_good().is_none() {
+            self.set_last_known_good(0);
+        }
+        info!("Stored the running config as generation 0");
+        Ok(())
+    }
+
+    // on first boot (no generation stored in outdir), snapshot the running config as generation 0
+    pub(crate) fn baseline(&self) {
+        let outdir = self.args.outdir();
+        if !stored_generations(outdir).is_empty() {
+            debug!("Generation history found at {outdir}. Not taking a baseline");
+            return;
+        }
+        let response = match self.snapshot_baseline() {
+            Ok(()) => "Ok".to_string(),
+            Err(e) => {
+                warn!("Failed to take a baseline of the running config: {e}");
+                return;
+            }
+        };
+        let request = Request::Baseline.describe(0);
+        self.report(0, request, "agent".to_string(), &response, &vec![], true);
+    }
+
+    // on first boot (no generation stored in outdir), apply the day-0 config in a file (or stdin
+    // if the path is "-") as generation 0, through the same pipeline as the configs requested.
+    // Failing to apply it is not fatal, since the controller may still push a config.
+    pub(crate) fn apply_initial(&self, path: &str) -> Result<(), String> {
+        let outdir = self.args.outdir();
+        if !stored_generations(outdir).is_empty() {
+            info!("Generation history found at {outdir}. Not applying initial config {path}");
+            return Ok(());
+        }
+        let config = if path == "-" {
+            let mut config = vec![];
+            std::io::stdin()
+                .read_to_end(&mut config)
+                .map_err(|e| format!("Could not read initial config from stdin: {e}"))?;
+            rawtext::decode(config)
+        } else {
+            rawtext::read(path).map_err(|e| format!("Could not read initial config {path}: {e}"))?
+        };
+        info!("Applying initial config {path} as generation 0...");
+        let request = self.render(0, Request::Config(config, true));
+        let client = Client {
+            session: 0,
+            cred: None,
+        };
+        let response = self.handle(0, &request, &client, None);
+        if response.starts_with("Ok") {
+            info!("Applied initial config {path}");
+        } else {
+            error!("Failed to apply initial config {path}: {response}");
+        }
+        Ok(())
+    }
+
+    // report the reloads that a previous run of the agent left interrupted, as found in the
+    // journal, re-applying the generations interrupted mid-apply if told so. The temp files the
+    // interrupted runs of the reloader left in rundir are removed, along with stale ones.
+    pub(crate) fn recover(&self) {
+        let interrupted = self.interrupted.iter().map(|record| record.started).min();
+        tempfiles::clean_stale(self.args.rundir(), interrupted);
+        for record in &self.interrupted {
+            let genid = record.genid;
+            let message = format!(
+                "Reload of generation {genid} was interrupted in the {} stage (started at {})",
+                record.stage, record.started
+            );
+            error!("{message}");
+            self.notify(AlertEvent::ReloadInterrupted, Some(genid), &message);
+            if record.stage != Stage::Apply || !self.args.reapply_interrupted {
+                continue;
+            }
+            let config = match rawtext::read(config_file_path(genid, self.args.outdir())) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Could not re-apply interrupted generation {genid}: {e}");
+                    continue;
+                }
+            };
+            info!("Re-applying interrupted generation {genid}...");
+            let mut transcript = Transcript::new();
+            let response = self.apply_config(genid, &config, None, &mut transcript);
+            info!("Re-apply of interrupted generation {genid} got: {response}");
+            let request = format!("re-apply of interrupted generation {genid}");
+            let applied = response == "Ok";
+            self.report(
+                genid,
+                request,
+                "agent".to_string(),
+                &response,
+                &transcript,
+                applied,
+            );
+        }
+        self.journal.resolve();
+    }
+
+    // re-apply the last generation applied if FRR lost (part of) its config when daemons were
+    // restarted, e.g. by watchfrr after zebra crashed
+    pub(crate) fn reapply_lost(&self, restarted: &[String]) {
+        let restarted = restarted.join(", ");
+        if self.args.shadow {
+            return;
+        }
+        let (Some(genid), Some(config)) = (self.last_applied(), self.applied_config()) else {
+            return;
+        };
+        /* serialized with the requests changing state, which may be applying a config */
+        let _serialized = self.executor.lock();
+        if self.last_applied() != Some(genid) {
+            return;
+        }
+        let running = match running_config(self.args) {
+            Ok(running) => running,
+            Err(e) => {
+                warn!("Could not tell if FRR kept its config after {restarted} restarted: {e}");
+                return;
+            }
+        };
+        let Ok((_, frr_config)) = split_vtysh_conf(&config) else {
+            return;
+        };
+        let frr_config = self.normalize(frr_config);
+        let missing = missing_statements(&frr_config, &running);
+        let Some(first) = missing.first() else {
+            info!("FRR kept the config of generation {genid} after {restarted} restarted");
+            return;
+        };
+        let message = format!(
+            "FRR lost the config of generation {genid} after {restarted} restarted ({} statements missing, e.g. '{first}')",
+            missing.len()
+        );
+        error!("EVENT: {message}");
+        self.notify(AlertEvent::ConfigLost, Some(genid), &message);
+        /* the config is reloaded in full, FRR no longer running the config applied */
+        if let Ok(mut applied_checksum) = self.applied_checksum.lock() {
+            *applied_checksum = None;
+        }
+        info!("Re-applying generation {genid}...");
+        let mut transcript = Transcript::new();
+        let response = self.apply(genid, &frr_config, None, || {
+            frr_reload(
+                &self.reloader,
+                genid,
+                &frr_config,
+                self.args.outdir(),
+                &mut transcript,
+            )
+        });
+        info!("Re-apply of generation {genid} got: {response}");
+        let request = format!("re-apply of generation {genid} lost by FRR");
+        let applied = response == "Ok";
+        self.report(
+            genid,
+            request,
+            "agent".to_string(),
+            &response,
+            &transcript,
+            applied,
+        );
+    }
+
+    // on first boot (no generation stored in outdir), import the history from an archive and
+    // apply its newest generation. Failing to apply it is not fatal, since the controller may
+    // still push a config.
+    pub(crate) fn restore(&self, archive: &str) -> Result<(), String> {
+        let outdir = self.args.outdir();
+        if !stored_generations(outdir).is_empty() {
+            info!("Generation history found at {outdir}. Not restoring from {archive}");
+            return Ok(());
+        }
+        let generations = import_archive(outdir, archive)?;
+        let Some(&genid) = generations.last() else {
+            warn!("Archive {archive} has no generation to restore");
+            return Ok(());
+        };
+        let config = rawtext::read(config_file_path(genid, outdir))
+            .map_err(|e| format!("Could not read restored generation {genid}: {e}"))?;
+        info!("Applying generation {genid} restored from {archive}...");
+        let mut transcript = Transcript::new();
+        let response = self.apply_config(genid, &config, None, &mut transcript);
+        if response == "Ok" {
+            info!("Restored generation {genid} from {archive}");
+        } else {
+            error!("Failed to apply generation {genid} restored from {archive}: {response}");
+        }
+        let request = format!("restore of generation {genid} from {archive}");
+        let applied = response == "Ok";
+        self.report(
+            genid,
+            request,
+            "agent".to_string(),
+            &response,
+            &transcript,
+            applied,
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Alerting on critical events (apply failures, rollbacks, circuit breaker trips, FRR daemons
+// dying and reloads interrupted), so that they page without relying on log scraping. Alerts are fired in the background
+// to the sinks configured: an http webhook (POST), SNMP traps (sent with snmptrap) and/or a
+// script, which gets the payload on stdin. The payload is a JSON object unless a template is
+// given, in which {event}, {genid}, {host}, {node_id}, {fabric}, {time} and {message} are
+// replaced.
+
+use std::fmt::Display;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::node;
+use crate::shipping::{HttpUrl, post};
+
+// OID of the traps, under the net-snmp experimental arc
+const TRAP_OID: &str = "1.3.6.1.4.1.8072.9999.9999.1";
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum AlertEvent {
+    ApplyFailed,
+    Rollback,
+    BreakerTripped,
+    DaemonDied,
+    ReloadInterrupted,
+    SlowReload,
+    ConfigLost,
+}
+impl Display for AlertEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertEvent::ApplyFailed => write!(f, "apply_failed"),
+            AlertEvent::Rollback => write!(f, "rollback"),
+            AlertEvent::BreakerTripped => write!(f, "breaker_tripped"),
+            AlertEvent::DaemonDied => write!(f, "daemon_died"),
+            AlertEvent::ReloadInterrupted => write!(f, "reload_interrupted"),
+            AlertEvent::SlowReload => write!(f, "slow_reload"),
+            AlertEvent::ConfigLost => write!(f, "config_lost"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum AlertSink {
+    Webhook(HttpUrl),
+    Snmp { target: String, community: String },
+    Exec(String),
+}
+
+// an alert to fire
+struct Alert {
+    event: AlertEvent,
+    genid: Option<GenId>,
+    host: String,
+    time: u64, /* seconds since the epoch */
+    message: String,
+}
+
+impl Alert {
+    fn render(&self, template: Option<&str>) -> String {
+        let genid = self
+            .genid
+            .map_or("null".to_string(), |genid| genid.to_string());
+        let identity = node::identity();
+        match template {
+            Some(template) => template
+                .replace("{event}", &self.event.to_string())
+                .replace("{genid}", &genid)
+                .replace("{host}", &self.host)
+                .replace("{node_id}", identity.node_id.as_deref().unwrap_or(""))
+                .replace("{fabric}", identity.fabric.as_deref().unwrap_or(""))
+                .replace("{time}", &self.time.to_string())
+                .replace("{message}", &self.message),
+            None => Json::object([
+                ("event", self.event.to_string().into()),
+                ("genid", self.genid.into()),
+                ("host", self.host.as_str().into()),
+                ("node_id", identity.node_id.as_deref().into()),
+                ("fabric", identity.fabric.as_deref().into()),
+                ("time", self.time.into()),
+                ("message", self.message.as_str().into()),
+            ])
+            .to_string(),
+        }
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map_or("unknown".to_string(), |host| host.trim().to_string())
+}
+
+// run a command feeding the payload on stdin
+fn run(cmd: &mut Command, payload: &str) -> Result<(), String> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to spawn: {e}"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(payload.as_bytes())
+            .map_err(|e| format!("Failed to write payload: {e}"))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to wait: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+impl AlertSink {
+    fn fire(&self, alert: &Alert, payload: &str) -> Result<(), String> {
+        match self {
+            AlertSink::Webhook(url) => post(url, payload),
+            AlertSink::Snmp { target, community } => {
+                /* SNMPv2c trap with the payload as a string varbind */
+                run(
+                    Command::new("snmptrap")
+                        .args(["-v", "2c", "-c", community, target, ""])
+                        .arg(TRAP_OID)
+                        .arg(format!("{TRAP_OID}.1"))
+                        .args(["s", payload]),
+                    "",
+                )
+            }
+            AlertSink::Exec(script) => run(
+                Command::new(script)
+                    .env("ALERT_EVENT", alert.event.to_string())
+                    .env(
+                        "ALERT_GENID",
+                        alert.genid.map(|g| g.to_string()).unwrap_or_default(),
+                    ),
+                payload,
+            ),
+        }
+    }
+}
+
+// fires alerts to the sinks configured
+#[derive(Default)]
+pub(crate) struct Alerter {
+    sinks: Vec<AlertSink>,
+    template: Option<String>,
+}
+
+impl Alerter {
+    pub(crate) fn new(
+        webhook: Option<&str>,
+        snmp_target: Option<&str>,
+        snmp_community: &str,
+        exec: Option<&str>,
+        template: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut sinks = vec![];
+        if let Some(url) = webhook {
+            sinks.push(AlertSink::Webhook(HttpUrl::parse(url)?));
+        }
+        if let Some(target) = snmp_target {
+            sinks.push(AlertSink::Snmp {
+                target: target.to_string(),
+                community: snmp_community.to_string(),
+            });
+        }
+        if let Some(script) = exec {
+            sinks.push(AlertSink::Exec(script.to_string()));
+        }
+        Ok(Self {
+            sinks,
+            template: template.map(str::to_string),
+        })
+    }
+
+    // fire an alert in the background
+    pub(crate) fn fire(&self, event: AlertEvent, genid: Option<GenId>, message: &str) {
+        if self.sinks.is_empty() {
+            return;
+        }
+        let alert = Alert {
+            event,
+            genid,
+            host: hostname(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            message: message.to_string(),
+        };
+        let payload = alert.render(self.template.as_deref());
+        let sinks = self.sinks.clone();
+        thread::spawn(move || {
+            for sink in &sinks {
+                match sink.fire(&alert, &payload) {
+                    Ok(()) => debug!("Fired {event} alert to {sink:?}"),
+                    Err(e) => warn!("Failed to fire {event} alert to {sink:?}: {e}"),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Bounded queue of the requests applying configs received while another request is being
+// processed (--apply-queue), so that clients are not left waiting on the socket for reloads they
+// do not control: such requests are queued and acknowledged with "QUEUED", or refused with "BUSY"
+// if the queue is full. Queued requests are processed in order in the background; a config
+// queued supersedes the configs of older generations queued, so that only the newest generation
+// is applied. While requests are queued, every other request applying a config is queued too, so
+// that generations are never applied out of order. Queued requests may be cancelled (CANCEL).
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::peer::PeerCred;
+use crate::request::Request;
+
+pub(crate) struct QueuedApply {
+    pub(crate) genid: GenId,
+    pub(crate) request: Request,
+    pub(crate) cred: Option<PeerCred>,
+    since: u64,
+}
+
+pub(crate) struct ApplyQueue {
+    capacity: usize,
+    queue: Mutex<VecDeque<QueuedApply>>,
+    ready: Condvar, /* notified when requests are queued */
+}
+
+impl ApplyQueue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.lock().map_or(true, |queue| queue.is_empty())
+    }
+
+    // queue a request while the generation applying (if known) is processed, returning the
+    // response to it
+    pub(crate) fn push(
+        &self,
+        genid: GenId,
+        request: &Request,
+        cred: Option<PeerCred>,
+        applying: Option<GenId>,
+    ) -> String {
+        let busy = applying.map_or(
+            "another request is being processed".to_string(),
+            |applying| format!("generation {applying} is being applied"),
+        );
+        let Ok(mut queue) = self.queue.lock() else {
+            return format!("BUSY\n{busy}");
+        };
+        /* configs supersede the configs of older generations queued */
+        if request.is_config() {
+            queue.retain(|queued| {
+                let superseded = queued.request.is_config() && queued.genid < genid;
+                if superseded {
+                    info!(
+                        "Queued generation {} is superseded by generation {genid}: dropping it",
+                        queued.genid
+                    );
+                }
+                !superseded
+            });
+        }
+        if queue.len() >= self.capacity {
+            warn!(
+                "Refusing {}: {busy} and the queue is full",
+                request.describe(genid)
+            );
+            return format!("BUSY\n{busy}");
+        }
+        info!("Queueing {}: {busy}", request.describe(genid));
+        queue.push_back(QueuedApply {
+            genid,
+            request: request.clone(),
+            cred,
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+        self.ready.notify_one();
+        format!("QUEUED\ngeneration {genid} is queued: {busy}")
+    }
+
+    // wait until requests are queued
+    pub(crate) fn wait(&self) {
+        let Ok(queue) = self.queue.lock() else {
+            return;
+        };
+        let _queue = self.ready.wait_while(queue, |queue| queue.is_empty());
+    }
+
+    // drop the requests queued for a generation (CANCEL), returning whether any was
+    pub(crate) fn cancel(&self, genid: GenId) -> bool {
+        let Ok(mut queue) = self.queue.lock() else {
+            return false;
+        };
+        let queued = queue.len();
+        queue.retain(|queued| queued.genid != genid);
+        let cancelled = queue.len() < queued;
+        if cancelled {
+            info!("Queued generation {genid} is cancelled: dropping it");
+        }
+        cancelled
+    }
+
+    // the request queued first, if any
+    pub(crate) fn pop(&self) -> Option<QueuedApply> {
+        self.queue.lock().ok()?.pop_front()
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        let Ok(queue) = self.queue.lock() else {
+            return Json::Null;
+        };
+        let queued = queue.iter().map(|queued| {
+            Json::object([
+                ("genid", queued.genid.into()),
+                ("request", queued.request.describe(queued.genid).into()),
+                (
+                    "peer",
+                    queued
+                        .cred
+                        .map_or("unknown".to_string(), |cred| cred.to_string())
+                        .into(),
+                ),
+                ("since", queued.since.into()),
+            ])
+        });
+        Json::Array(queued.collect())
+    }
+}
@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Export and import of the generation history. An archive is a gzipped tarball with the config
+// and metadata files of every generation stored in outdir, along with a manifest. Archives allow
+// the history of a node to move with its role (e.g. when the node is replaced).
+
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::reload::{
+    config_dir, config_file_path, generation_of, metadata_file_path, stored_generations,
+};
+
+const MANIFEST: &str = "manifest.json";
+
+// exports run so far, to name the directory of each
+static EXPORTS: AtomicU64 = AtomicU64::new(0);
+
+// a directory of its own for an export, private to the agent: it must not exist yet
+fn export_dir(outdir: &str) -> Result<PathBuf, String> {
+    let export = EXPORTS.fetch_add(1, Ordering::Relaxed);
+    let dir = Path::new(outdir).join(format!(".export-{}-{export}", std::process::id()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+    Ok(dir)
+}
+
+fn tar(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("tar")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run tar: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// export the generations stored in outdir to an archive
+pub(crate) fn export_archive(
+    outdir: &str,
+    archive: &str,
+    last_applied: Option<GenId>,
+) -> Result<(), String> {
+    let generations = stored_generations(outdir);
+    /* configs may be kept apart from their metadata (see --volatile-dir) */
+    let mut files = vec![];
+    for genid in &generations {
+        for path in [
+            config_file_path(*genid, outdir),
+            metadata_file_path(*genid, outdir),
+        ] {
+            if path.exists()
+                && let (Some(dir), Some(name)) = (
+                    path.parent().and_then(|dir| dir.to_str()),
+                    path.file_name().and_then(|name| name.to_str()),
+                )
+            {
+                files.extend(["-C".to_string(), dir.to_string(), name.to_string()]);
+            }
+        }
+    }
+
+    /* the manifest is written in a directory of its own, so that concurrent exports do not mix */
+    let manifest = Json::object([
+        ("last_applied", last_applied.into()),
+        (
+            "generations",
+            Json::Array(generations.iter().map(|genid| Json::from(*genid)).collect()),
+        ),
+    ]);
+    let tmpdir = export_dir(outdir)?;
+    let result = fs::write(tmpdir.join(MANIFEST), manifest.to_string())
+        .map_err(|e| format!("Could not write manifest: {e}"))
+        .and_then(|()| {
+            let tmpdir = tmpdir.to_str().ok_or("Bad temp dir")?;
+            let mut args = vec!["-czf", archive, "-C", tmpdir, MANIFEST];
+            args.extend(files.iter().map(String::as_str));
+            tar(&args)
+        });
+    let _ = fs::remove_dir_all(&tmpdir);
+    result?;
+    info!(
+        "Exported {} generations to archive {archive}",
+        generations.len()
+    );
+    Ok(())
+}
+
+// import the generations in an archive into outdir. Returns the generations imported.
+pub(crate) fn import_archive(outdir: &str, archive: &str) -> Result<Vec<GenId>, String> {
+    if !Path::new(archive).is_file() {
+        return Err(format!("Archive {archive} does not exist"));
+    }
+
+    /* only regular files that belong to a generation are extracted (no links, which could
+     * point anywhere) */
+    let listing = tar(&["-tvzf", archive])?;
+    let mut members = vec![];
+    for line in listing.lines() {
+        let name = line.split_whitespace().last().unwrap_or_default();
+        if !line.starts_with('-') {
+            return Err(format!(
+                "Archive {archive} has a member that is not a regular file: {line}"
+            ));
+        }
+        if name == MANIFEST {
+            continue;
+        }
+        if generation_of(name).is_none() {
+            return Err(format!("Unexpected file '{name}' in archive {archive}"));
+        }
+        members.push(name);
+    }
+    fs::create_dir_all(outdir).map_err(|e| format!("Could not create {outdir}: {e}"))?;
+    if !members.is_empty() {
+        let mut args = vec!["-xzf", archive, "-C", outdir];
+        args.extend(members.iter());
+        tar(&args)?;
+    }
+
+    let mut generations: Vec<GenId> = members
+        .iter()
+        .filter(|name| Path::new(name).extension().is_some_and(|ext| ext == "conf"))
+        .filter_map(|name| generation_of(name))
+        .collect();
+    generations.sort_unstable();
+
+    /* configs kept apart from their metadata are moved where they belong */
+    if config_dir(outdir) != outdir {
+        for genid in &generations {
+            let extracted = Path::new(outdir).join(format!("frr-config-gen-{genid}.conf"));
+            let config_file = config_file_path(*genid, outdir);
+            if !fs::symlink_metadata(&extracted).is_ok_and(|meta| meta.file_type().is_file()) {
+                return Err(format!("{} is not a regular file", extracted.display()));
+            }
+            if let Some(dir) = config_file.parent() {
+                fs::create_dir_all(dir)
+                    .map_err(|e| format!("Could not create {}: {e}", dir.display()))?;
+            }
+            fs::copy(&extracted, &config_file)
+                .and_then(|_| fs::remove_file(&extracted))
+                .map_err(|e| format!("Could not move {}: {e}", extracted.display()))?;
+        }
+    }
+    info!(
+        "Imported {} generations from archive {archive}",
+        generations.len()
+    );
+    Ok(generations)
+}
@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Append-only audit log of the changes of configuration (--audit-log), independent of logging: a
+// record is appended to audit.jsonl in outdir for every request attempting to apply a config,
+// whatever its outcome, with its time, generation, request, peer credentials, the checksum of the
+// config, the result and the duration. Records are chained: each carries the hash of the record
+// before it ("prev") and its own ("hash", the SHA-256 of the record without it), so that records
+// altered, removed or inserted break the chain, as told by frr-agent verify-audit.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::peer::PeerCred;
+use crate::sha256::sha256_hex;
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+// the hash the first record is chained to
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// an apply attempt to record
+pub(crate) struct AuditEntry<'a> {
+    pub(crate) genid: GenId,
+    pub(crate) request: String,
+    pub(crate) cred: Option<PeerCred>,
+    pub(crate) sha256: Option<String>, /* of the config */
+    pub(crate) result: &'a str,
+    pub(crate) duration: Duration,
+}
+
+// the hash of a record, without its own hash
+fn hash_of(record: &BTreeMap<String, Json>) -> String {
+    sha256_hex(Json::Object(record.clone()).to_string().as_bytes())
+}
+
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    last: Mutex<String>, /* hash of the last record */
+}
+
+impl AuditLog {
+    // open the audit log in outdir, to append records chained to the last one
+    pub(crate) fn open(outdir: &str) -> Self {
+        let path = Path::new(outdir).join(AUDIT_FILE);
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let last = match contents.lines().rev().find(|line| !line.trim().is_empty()) {
+            None => GENESIS.to_string(),
+            Some(line) => {
+                let hash = Json::parse(line)
+                    .ok()
+                    .and_then(|record| record.get("hash")?.as_str().map(str::to_string));
+                hash.unwrap_or_else(|| {
+                    error!(
+                        "The last record of the audit log {} is bad: the chain is broken",
+                        path.display()
+                    );
+                    sha256_hex(line.as_bytes())
+                })
+            }
+        };
+        Self {
+            path,
+            last: Mutex::new(last),
+        }
+    }
+
+    // append the record of an apply attempt
+    pub(crate) fn append(&self, entry: &AuditEntry) -> Result<(), String> {
+        let mut last = self
+            .last
+            .lock()
+            .map_err(|_| "Audit log lock poisoned".to_string())?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut record: BTreeMap<String, Json> = [
+            ("time", time.as_secs().into()),
+            ("genid", entry.genid.into()),
+            ("request", entry.request.as_str().into()),
+            ("peer", entry.cred.map(|cred| cred.to_string()).into()),
+            ("uid", entry.cred.map(|cred| u64::from(cred.uid)).into()),
+            ("sha256", entry.sha256.as_deref().into()),
+            ("result", entry.result.into()),
+            (
+                "duration_ms",
+                u64::try_from(entry.duration.as_millis())
+                    .unwrap_or(u64::MAX)
+                    .into(),
+            ),
+            ("prev", last.as_str().into()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        let hash = hash_of(&record);
+        record.insert("hash".to_string(), hash.as_str().into());
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o640)
+            .open(&self.path)
+            .map_err(|e| format!("Could not open audit log {}: {e}", self.path.display()))?;
+        writeln!(file, "{}", Json::Object(record))
+            .and_then(|()| file.sync_data())
+            .map_err(|e| format!("Could not append to audit log {}: {e}", self.path.display()))?;
+        *last = hash;
+        Ok(())
+    }
+}
+
+// verify the chain of the audit log in outdir, returning the number of records
+pub(crate) fn verify(outdir: &str) -> Result<usize, String> {
+    let path = Path::new(outdir).join(AUDIT_FILE);
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read audit log {}: {e}", path.display()))?;
+    let mut last = GENESIS.to_string();
+    let mut records = 0;
+    for (n, line) in contents.lines().enumerate() {
+        let n = n + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(Json::Object(mut record)) = Json::parse(line) else {
+            return Err(format!("Record at line {n} is not a JSON object"));
+        };
+        let hash = record
+            .remove("hash")
+            .and_then(|hash| hash.as_str().map(str::to_string))
+            .ok_or(format!("Record at line {n} has no hash"))?;
+        if record.get("prev").and_then(Json::as_str) != Some(last.as_str()) {
+            return Err(format!(
+                "Record at line {n} is not chained to the record before it"
+            ));
+        }
+        if hash_of(&record) != hash {
+            return Err(format!("Record at line {n} was altered"));
+        }
+        last = hash;
+        records += 1;
+    }
+    Ok(records)
+}
@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Backup of the applied generations to an S3-compatible bucket. Every generation applied is
+// mirrored (config and metadata) in the background, so that the config history survives the loss
+// of the node and can be reviewed without touching routers. Objects are uploaded with curl, which
+// signs the requests (AWS SigV4) with the credentials in AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::reload::{config_file_path, metadata_file_path};
+
+#[derive(Clone, Debug)]
+pub(crate) struct ObjectStore {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+}
+
+impl ObjectStore {
+    pub(crate) fn new(endpoint: &str, bucket: &str, region: &str, prefix: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    // url of the object a file is stored as (path-style, as understood by all S3 implementations)
+    fn object_url(&self, file: &Path) -> Option<String> {
+        let name = file.file_name()?.to_str()?;
+        Some(format!(
+            "{}/{}/{}{name}",
+            self.endpoint, self.bucket, self.prefix
+        ))
+    }
+
+    // upload a file to the bucket
+    fn put(&self, file: &Path) -> Result<(), String> {
+        let url = self.object_url(file).ok_or("Bad file name")?;
+        let file = file.to_str().ok_or("Bad file name")?;
+        let (Ok(key), Ok(secret)) = (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) else {
+            return Err("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set".to_string());
+        };
+
+        /* the credentials are passed via stdin so that they do not show up in the process list */
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--max-time", "30"])
+            .args(["--config", "-"])
+            .arg("--aws-sigv4")
+            .arg(format!("aws:amz:{}:s3", self.region))
+            .args(["--upload-file", file, &url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to spawn curl: {e}"))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            writeln!(stdin, "user = \"{key}:{secret}\"")
+                .map_err(|e| format!("Failed to pass credentials to curl: {e}"))?;
+        }
+        let output = child
+            .wait_with_output()
+            .map_err(|e| format!("Failed to wait for curl: {e}"))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Upload to {url} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    // mirror a generation (config and metadata) to the bucket in the background
+    pub(crate) fn mirror(&self, genid: GenId, outdir: &str) {
+        let files: Vec<PathBuf> = [
+            config_file_path(genid, outdir),
+            metadata_file_path(genid, outdir),
+        ]
+        .into_iter()
+        .filter(|file| file.exists())
+        .collect();
+        let store = self.clone();
+        thread::spawn(move || {
+            for file in &files {
+                if let Err(e) = store.put(file) {
+                    warn!("Failed to back up generation {genid}: {e}");
+                    return;
+                }
+            }
+            debug!("Backed up generation {genid} to bucket {}", store.bucket);
+        });
+    }
+}
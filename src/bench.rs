@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Load/benchmark mode. Drives an agent with synthetic config pushes of configurable size and rate
+// and reports the throughput and latency percentiles, so that changes to the protocol or the
+// pipeline can be evaluated objectively. The agent driven is the one at --sock-path or, with
+// --embedded, an instance run in-process whose reloader always succeeds (mock backend).
+
+use std::fmt::Write;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::agent::{Agent, SHADOWED};
+use crate::alerting::Alerter;
+use crate::framing::Framing;
+use crate::policy::Policy;
+use crate::shipping::TranscriptShipper;
+use crate::{
+    Args, GenId, Listeners, build_reloader, create_unix_listener, send_response, sock_perms,
+};
+
+// reloader of embedded instances: accepts any config without touching FRR
+const MOCK_RELOADER: &str = "true";
+
+#[derive(Clone, Debug, PartialEq, clap::Args)]
+pub(crate) struct BenchArgs {
+    #[arg(long, value_name = "Number of configs to push. Defaults to 100")]
+    count: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Size of the configs pushed, in octets. Defaults to 4096"
+    )]
+    size: Option<usize>,
+    #[arg(
+        long,
+        value_name = "Configs pushed per second. Defaults to as fast as possible"
+    )]
+    rate: Option<f64>,
+    #[arg(
+        long,
+        value_name = "Generation id of the first config pushed. Defaults to 1"
+    )]
+    first_genid: Option<GenId>,
+    #[arg(long)]
+    embedded: bool,
+}
+
+// the outcome of a benchmark run
+pub(crate) struct BenchReport {
+    size: usize,
+    failures: u64,
+    elapsed: Duration,
+    latencies: Vec<Duration>, /* sorted */
+}
+
+impl BenchReport {
+    fn percentile(&self, p: usize) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (self.latencies.len() * p).div_ceil(100).max(1);
+        self.latencies[rank - 1]
+    }
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        #[allow(clippy::cast_precision_loss)]
+        let pushed = self.latencies.len() as f64;
+        writeln!(
+            f,
+            "configs: {} (failed: {}), size: {} octets",
+            self.latencies.len(),
+            self.failures,
+            self.size
+        )?;
+        writeln!(
+            f,
+            "throughput: {:.1} configs/s",
+            pushed / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )?;
+        write!(
+            f,
+            "latency (ms): p50 {:.2} p90 {:.2} p99 {:.2} max {:.2}",
+            ms(self.percentile(50)),
+            ms(self.percentile(90)),
+            ms(self.percentile(99)),
+            ms(self.percentile(100))
+        )
+    }
+}
+
+// a synthetic FRR config of (about) some size. The seq varies the contents across pushes.
+fn synthetic_config(size: usize, seq: u64) -> String {
+    let mut config = format!("frr defaults datacenter\nhostname bench-{seq}\n!\n");
+    let mut n: u64 = 0;
+    while config.len() < size {
+        let _ = writeln!(
+            config,
+            "ip prefix-list BENCH seq {} permit 10.{}.{}.0/24",
+            (n + 1) * 5,
+            (n / 256) % 256,
+            n % 256
+        );
+        n += 1;
+    }
+    config
+}
+
+// serve an in-process agent with the mock backend at the socket path, in the background
+fn start_embedded(args: &Args) -> Result<(), String> {
+    let mut embedded = args.clone();
+    embedded.reloader = Some(MOCK_RELOADER.to_string());
+    if embedded.outdir.is_none() {
+        let outdir = std::env::temp_dir().join(format!("frr-agent-bench-{}", std::process::id()));
+        embedded.outdir = Some(outdir.to_string_lossy().into_owned());
+    }
+    let listener = create_unix_listener(&embedded.sock_path, &sock_perms(args)?)?;
+
+    /* the instance lives as long as the process */
+    let embedded: &'static Args = Box::leak(Box::new(embedded));
+    let agent: &'static Agent = Box::leak(Box::new(Agent::new(
+        embedded,
+        build_reloader(embedded)?,
+        TranscriptShipper::default(),
+        Alerter::default(),
+        Policy::default(),
+    )));
+    info!(
+        "Running embedded agent at '{}' (outdir '{}')",
+        embedded.sock_path,
+        embedded.outdir()
+    );
+    thread::spawn(move || crate::serve(embedded, &Listeners::unix(listener), agent));
+    Ok(())
+}
+
+// push synthetic configs to the agent and measure the latency of the responses
+pub(crate) fn bench(args: &Args, bench: &BenchArgs) -> Result<BenchReport, String> {
+    if bench.embedded {
+        start_embedded(args)?;
+    }
+    let count = bench.count.unwrap_or(100);
+    let size = bench.size.unwrap_or(4096);
+    let first_genid = bench.first_genid.unwrap_or(1);
+    let interval = match bench.rate {
+        Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(1.0 / rate)),
+        Some(rate) => return Err(format!("Bad rate {rate}")),
+        None => None,
+    };
+
+    let mut sock = UnixStream::connect(&args.sock_path)
+        .map_err(|e| format!("Could not connect to {}: {e}", args.sock_path))?;
+    info!(
+        "Pushing {count} configs of {size} octets to '{}'",
+        args.sock_path
+    );
+
+    let mut latencies = Vec::with_capacity(usize::try_from(count).unwrap_or_default());
+    let mut failures = 0;
+    let started = Instant::now();
+    let mut next = started;
+    for seq in 0..count {
+        if let Some(interval) = interval {
+            sleep(next.saturating_duration_since(Instant::now()));
+            next += interval;
+        }
+        let genid = first_genid.saturating_add(GenId::try_from(seq).unwrap_or(GenId::MAX));
+        let config = synthetic_config(size, seq);
+        let sent = Instant::now();
+        send_response(&mut sock, genid, config.as_bytes())?;
+        let (_, response) = Framing::V1.receive(&mut sock)?;
+        latencies.push(sent.elapsed());
+        if !response.starts_with("Ok") && !response.starts_with(SHADOWED) {
+            debug!("Push of generation {genid} failed: {response}");
+            failures += 1;
+        }
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+    Ok(BenchReport {
+        size,
+        failures,
+        elapsed,
+        latencies,
+    })
+}
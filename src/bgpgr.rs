@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// BGP graceful restart around reloads, so that the sessions reset by a reload do not flush the
+// routes learnt from (and advertised to) peers:
+//   * with GR enabled, graceful restart (along with the notification N-bit of RFC 8538, so that
+//     sessions reset with a NOTIFICATION are graceful too, and long-lived GR if a stale time is
+//     set) is added to the BGP instances of every config,
+//   * with GR verified, configs changing BGP whose BGP instances lack graceful restart are
+//     refused,
+//   * before a config changing BGP is applied, the peers that would not retain routes (not GR
+//     helpers) are reported and, if told so, peers are drained with graceful shutdown (RFC 8326)
+//     until the reload is done.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::json::Json;
+use crate::vtysh::{vtysh, vtysh_config};
+
+const GRACEFUL_RESTART: &str = "bgp graceful-restart";
+const GR_NOTIFICATION: &str = "bgp graceful-restart notification";
+const LLGR_STALE_TIME: &str = "bgp long-lived-graceful-restart stale-time";
+const GRACEFUL_SHUTDOWN: &str = "bgp graceful-shutdown";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GrMode {
+    Enable,
+    Verify,
+}
+impl GrMode {
+    pub(crate) fn parse(mode: &str) -> Option<Self> {
+        match mode.trim() {
+            "enable" => Some(GrMode::Enable),
+            "verify" => Some(GrMode::Verify),
+            _ => None,
+        }
+    }
+}
+
+// the BGP instances of a config (top-level "router bgp" line along with the lines nested under
+// it, whitespace collapsed), by top-level line
+fn bgp_instances(config: &str) -> BTreeMap<String, Vec<String>> {
+    let mut instances: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut current = None;
+    for line in config.lines() {
+        let content = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if content.is_empty() || content.starts_with('!') {
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            current = content
+                .starts_with("router bgp ")
+                .then_some(content.clone());
+            if let Some(header) = &current {
+                instances.entry(header.clone()).or_default();
+            }
+        } else if let Some(header) = &current {
+            instances.entry(header.clone()).or_default().push(content);
+        }
+    }
+    instances
+}
+
+// whether applying a config changes BGP, with respect to the config applied
+pub(crate) fn touches_bgp(applied: &str, config: &str) -> bool {
+    bgp_instances(applied) != bgp_instances(config)
+}
+
+// the BGP instances of a config that lack graceful restart
+pub(crate) fn lacking_gr(config: &str) -> Vec<String> {
+    bgp_instances(config)
+        .into_iter()
+        .filter(|(_, lines)| {
+            !lines.iter().any(|line| line == GRACEFUL_RESTART)
+                || lines
+                    .iter()
+                    .any(|line| line.starts_with("bgp graceful-restart-disable"))
+        })
+        .map(|(header, _)| header)
+        .collect()
+}
+
+// add graceful restart to the BGP instances of a config lacking it
+pub(crate) fn enable_gr(config: &str, llgr_stale_time: Option<u64>) -> Cow<'_, str> {
+    let instances = bgp_instances(config);
+    /* the stale time of long-lived GR is not changed if set */
+    let missing = |header: &str, setting: &str| {
+        let key = if setting.starts_with(LLGR_STALE_TIME) {
+            LLGR_STALE_TIME
+        } else {
+            setting
+        };
+        instances.get(header).is_some_and(|lines| {
+            !lines
+                .iter()
+                .any(|line| line == key || line.starts_with(&format!("{key} ")))
+        })
+    };
+    let mut settings = vec![GRACEFUL_RESTART.to_string(), GR_NOTIFICATION.to_string()];
+    if let Some(stale_time) = llgr_stale_time {
+        settings.push(format!("{LLGR_STALE_TIME} {stale_time}"));
+    }
+    let mut enabled = String::with_capacity(config.len());
+    let mut added = false;
+    for line in config.lines() {
+        enabled.push_str(line);
+        enabled.push('\n');
+        let header = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.starts_with(char::is_whitespace) || !header.starts_with("router bgp ") {
+            continue;
+        }
+        for setting in settings.iter().filter(|setting| missing(&header, setting)) {
+            enabled.push(' ');
+            enabled.push_str(setting);
+            enabled.push('\n');
+            added = true;
+        }
+    }
+    if added {
+        Cow::Owned(enabled)
+    } else {
+        Cow::Borrowed(config)
+    }
+}
+
+// the peers that would not retain the routes of a session reset gracefully, as reported by
+// "show bgp vrf all neighbors json": those whose GR mode is neither restart nor helper
+pub(crate) fn non_helpers(args: &Args) -> Result<Vec<String>, String> {
+    let output = vtysh(args, "show bgp vrf all neighbors json")?;
+    let vrfs = Json::parse(&output).map_err(|e| format!("Bad neighbors json: {e}"))?;
+    let mut peers = vec![];
+    let Json::Object(vrfs) = vrfs else {
+        return Ok(peers);
+    };
+    for (vrf, neighbors) in &vrfs {
+        let Json::Object(neighbors) = neighbors else {
+            continue;
+        };
+        for (peer, neighbor) in neighbors {
+            let Some(info) = neighbor.get("gracefulRestartInfo") else {
+                continue;
+            };
+            let mode = info.get("remoteGrMode").and_then(Json::as_str);
+            if !matches!(mode, Some("Restart" | "Helper")) {
+                peers.push(format!("{peer} (vrf {vrf})"));
+            }
+        }
+    }
+    Ok(peers)
+}
+
+// peers drained with graceful shutdown until dropped, when graceful shutdown is lifted unless
+// the config applied sets it
+pub(crate) struct Drain<'a> {
+    args: &'a Args,
+    keep: bool,
+}
+
+impl<'a> Drain<'a> {
+    // drain the peers and wait for traffic to move away
+    pub(crate) fn start(args: &'a Args, config: &str, wait: Duration) -> Result<Self, String> {
+        vtysh_config(args, &[GRACEFUL_SHUTDOWN])?;
+        info!("Draining BGP peers with graceful shutdown for {wait:?}...");
+        sleep(wait);
+        let keep = config.lines().any(|line| line.trim() == GRACEFUL_SHUTDOWN);
+        Ok(Self { args, keep })
+    }
+}
+
+impl Drop for Drain<'_> {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        match vtysh_config(self.args, &[&format!("no {GRACEFUL_SHUTDOWN}")]) {
+            Ok(_) => info!("Lifted BGP graceful shutdown"),
+            Err(e) => error!("Failed to lift BGP graceful shutdown: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WITHOUT_GR: &str = "frr defaults datacenter\n!\nrouter bgp 65000\n \
+                              neighbor 10.0.0.1 remote-as 65001\n!\nrouter bgp 65000 vrf red\n \
+                              neighbor 10.1.0.1 remote-as 65002\nexit\n";
+
+    #[test]
+    fn lacking() {
+        assert_eq!(
+            lacking_gr(WITHOUT_GR),
+            vec!["router bgp 65000", "router bgp 65000 vrf red"]
+        );
+        let with_gr = "router bgp 65000\n bgp graceful-restart\n!\nrouter bgp 65000 vrf red\n  \
+                       bgp   graceful-restart\n";
+        assert!(lacking_gr(with_gr).is_empty());
+        let disabled = "router bgp 65000\n bgp graceful-restart\n bgp graceful-restart-disable\n";
+        assert_eq!(lacking_gr(disabled), vec!["router bgp 65000"]);
+        assert!(lacking_gr("interface eth0\n bgp graceful-restart\n").is_empty());
+    }
+
+    #[test]
+    fn enabled() {
+        let enabled = enable_gr(WITHOUT_GR, None);
+        assert_eq!(
+            enabled,
+            "frr defaults datacenter\n!\nrouter bgp 65000\n bgp graceful-restart\n \
+             bgp graceful-restart notification\n neighbor 10.0.0.1 remote-as 65001\n!\n\
+             router bgp 65000 vrf red\n bgp graceful-restart\n bgp graceful-restart notification\n \
+             neighbor 10.1.0.1 remote-as 65002\nexit\n"
+        );
+        assert!(lacking_gr(&enabled).is_empty());
+        /* configs with GR already are left as they are */
+        assert!(matches!(enable_gr(&enabled, None), Cow::Borrowed(_)));
+        assert!(matches!(
+            enable_gr("interface eth0\n", Some(3600)),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn enabled_long_lived() {
+        let config = "router bgp 65000\n bgp graceful-restart\n";
+        assert_eq!(
+            enable_gr(config, Some(3600)),
+            "router bgp 65000\n bgp graceful-restart notification\n \
+             bgp long-lived-graceful-restart stale-time 3600\n bgp graceful-restart\n"
+        );
+        /* the stale time set is kept */
+        let config = "router bgp 65000\n bgp graceful-restart\n bgp graceful-restart notification\n \
+                      bgp long-lived-graceful-restart stale-time 60\n";
+        assert!(matches!(enable_gr(config, Some(3600)), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn disabled_is_respected() {
+        let config = "router bgp 65000\n bgp graceful-restart-disable\n";
+        assert_eq!(
+            lacking_gr(&enable_gr(config, None)),
+            vec!["router bgp 65000"]
+        );
+    }
+
+    #[test]
+    fn touching() {
+        let applied = "router bgp 65000\n neighbor 10.0.0.1 remote-as 65001\n!\ninterface eth0\n";
+        /* whitespace, comments and other stanzas aside */
+        assert!(!touches_bgp(
+            applied,
+            "interface eth1\n!\nrouter bgp 65000\n  neighbor  10.0.0.1 remote-as 65001\n"
+        ));
+        assert!(touches_bgp(
+            applied,
+            "router bgp 65000\n neighbor 10.0.0.1 remote-as 65002\n"
+        ));
+        assert!(touches_bgp(
+            applied,
+            &format!("{applied}router bgp 65000 vrf red\n")
+        ));
+        assert!(touches_bgp(applied, "interface eth0\n"));
+        assert!(!touches_bgp("", "interface eth0\n"));
+    }
+}
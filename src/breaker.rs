@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Circuit breaker protecting FRR from a controller stuck in a loop of failing pushes. After a
+// number of consecutive failed applies, the breaker trips and config requests are refused until
+// an operator resets it or its cool-down passes.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::json::Json;
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u64,
+    opened: Option<Instant>,
+    trips: u64,
+}
+
+pub(crate) struct Breaker {
+    threshold: Option<u64>,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl Breaker {
+    // a breaker tripping after threshold consecutive failures (never if None)
+    pub(crate) fn new(threshold: Option<u64>, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    // whether the breaker is open. A breaker whose cool-down passed is closed.
+    pub(crate) fn is_open(&self) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        if state
+            .opened
+            .is_some_and(|opened| opened.elapsed() >= self.cooldown)
+        {
+            info!("Circuit breaker cool-down passed: closing it");
+            state.opened = None;
+            state.failures = 0;
+        }
+        state.opened.is_some()
+    }
+
+    // account for the outcome of an apply. Returns true if the breaker tripped.
+    pub(crate) fn record(&self, success: bool) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        if success {
+            state.failures = 0;
+            return false;
+        }
+        state.failures += 1;
+        if state.opened.is_none() && self.threshold.is_some_and(|t| state.failures >= t) {
+            error!(
+                "Circuit breaker tripped after {} consecutive failed applies",
+                state.failures
+            );
+            state.opened = Some(Instant::now());
+            state.trips += 1;
+            return true;
+        }
+        false
+    }
+
+    // close the breaker
+    pub(crate) fn reset(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if state.opened.take().is_some() {
+                info!("Circuit breaker was reset");
+            }
+            state.failures = 0;
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        let open = self.is_open();
+        let Ok(state) = self.state.lock() else {
+            return Json::Null;
+        };
+        Json::object([
+            ("open", open.into()),
+            ("failures", state.failures.into()),
+            ("trips", state.trips.into()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_mins(5);
+
+    #[test]
+    fn trips_at_threshold() {
+        let breaker = Breaker::new(Some(3), COOLDOWN);
+        assert!(!breaker.record(false));
+        assert!(!breaker.record(false));
+        assert!(!breaker.is_open());
+        assert!(breaker.record(false));
+        assert!(breaker.is_open());
+        /* failures while open do not trip it again */
+        assert!(!breaker.record(false));
+        assert!(breaker.is_open());
+        let status = breaker.to_json();
+        assert_eq!(status.get("open"), Some(&Json::Bool(true)));
+        assert_eq!(status.get("failures").and_then(Json::as_i64), Some(4));
+        assert_eq!(status.get("trips").and_then(Json::as_i64), Some(1));
+    }
+
+    #[test]
+    fn never_trips_without_threshold() {
+        let breaker = Breaker::new(None, COOLDOWN);
+        for _ in 0..100 {
+            assert!(!breaker.record(false));
+        }
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let breaker = Breaker::new(Some(2), COOLDOWN);
+        assert!(!breaker.record(false));
+        assert!(!breaker.record(true));
+        assert!(!breaker.record(false));
+        assert!(!breaker.is_open());
+        assert!(breaker.record(false));
+        assert!(breaker.is_open());
+    }
+
+    #[test]
+    fn closes_after_cooldown() {
+        let breaker = Breaker::new(Some(1), Duration::ZERO);
+        assert!(breaker.record(false));
+        assert!(!breaker.is_open());
+        /* the failures are forgotten along with it */
+        assert_eq!(
+            breaker.to_json().get("failures").and_then(Json::as_i64),
+            Some(0)
+        );
+        assert!(breaker.record(false));
+        assert_eq!(
+            breaker.to_json().get("trips").and_then(Json::as_i64),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn reset() {
+        let breaker = Breaker::new(Some(1), COOLDOWN);
+        assert!(breaker.record(false));
+        assert!(breaker.is_open());
+        breaker.reset();
+        assert!(!breaker.is_open());
+        assert_eq!(
+            breaker.to_json().get("failures").and_then(Json::as_i64),
+            Some(0)
+        );
+        assert!(breaker.record(false));
+        assert!(breaker.is_open());
+    }
+}
@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Information about the build of the agent (embedded by build.rs), so that fleet inventory can
+// tell exactly which build runs where.
+
+use std::time::Instant;
+
+use crate::json::Json;
+
+pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub(crate) const GIT_SHA: &str = env!("FRR_AGENT_GIT_SHA");
+pub(crate) const BUILD_TIME: &str = env!("FRR_AGENT_BUILD_TIME");
+pub(crate) const RUSTC_VERSION: &str = env!("FRR_AGENT_RUSTC_VERSION");
+
+// the build information, along with the uptime of an agent started at some instant
+pub(crate) fn build_info(started: Instant) -> Json {
+    Json::object([
+        ("version", VERSION.into()),
+        ("git_sha", GIT_SHA.into()),
+        ("build_time", BUILD_TIME.parse::<u64>().ok().into()),
+        ("rustc", RUSTC_VERSION.into()),
+        ("uptime", started.elapsed().as_secs().into()),
+    ])
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Cancellation of the --test stage of generations (CANCEL), for controllers superseding a
+// generation while a slow reload is still validating it. The --test stages running are
+// registered here by generation; cancelling a generation flags its stages, and the reloader
+// running one of them is killed (with its children) at its next check. The --reload stage is
+// never registered, so that FRR is never left half-way through a reload.
+
+use std::sync::Mutex;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+
+struct Entry {
+    id: u64,
+    genid: GenId,
+    cancelled: bool,
+}
+
+struct Entries {
+    next: u64,
+    entries: Vec<Entry>,
+}
+
+// the --test stages running
+pub(crate) struct Tests {
+    entries: Mutex<Entries>,
+}
+
+pub(crate) static TESTS: Tests = Tests {
+    entries: Mutex::new(Entries {
+        next: 0,
+        entries: Vec::new(),
+    }),
+};
+
+// a --test stage running, until dropped
+pub(crate) struct Test<'a> {
+    tests: &'a Tests,
+    id: u64,
+    pub(crate) genid: GenId,
+}
+impl Test<'_> {
+    // whether the stage was cancelled
+    pub(crate) fn cancelled(&self) -> bool {
+        self.tests.entries.lock().is_ok_and(|entries| {
+            entries
+                .entries
+                .iter()
+                .any(|entry| entry.id == self.id && entry.cancelled)
+        })
+    }
+}
+impl Drop for Test<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut entries) = self.tests.entries.lock() {
+            entries.entries.retain(|entry| entry.id != self.id);
+        }
+    }
+}
+
+impl Tests {
+    // register the --test stage of a generation until it completes
+    pub(crate) fn begin(&self, genid: GenId) -> Test<'_> {
+        let mut id = 0;
+        if let Ok(mut entries) = self.entries.lock() {
+            id = entries.next;
+            entries.next += 1;
+            entries.entries.push(Entry {
+                id,
+                genid,
+                cancelled: false,
+            });
+        }
+        Test {
+            tests: self,
+            id,
+            genid,
+        }
+    }
+
+    // cancel the --test stages of a generation, returning whether any is running
+    pub(crate) fn cancel(&self, genid: GenId) -> bool {
+        let Ok(mut entries) = self.entries.lock() else {
+            return false;
+        };
+        let mut running = false;
+        for entry in entries
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.genid == genid)
+        {
+            info!("Cancelling the --test stage of generation {genid}...");
+            entry.cancelled = true;
+            running = true;
+        }
+        running
+    }
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Canonicalization of FRR configs, so that semantically identical configs have the same bytes
+// (and checksum) and diffs between configs are minimal:
+//   * comments and blank lines are stripped,
+//   * lines are indented by one space per nesting level,
+//   * runs of prefix-list entries with explicit sequence numbers and of route-map entries are
+//     sorted by name and sequence number, since their order in the config is irrelevant.
+
+// a top-level line along with the lines nested under it
+struct Block {
+    lines: Vec<String>,
+}
+
+impl Block {
+    // the sort key of blocks whose order is irrelevant: (kind, name, sequence number)
+    fn key(&self) -> Option<(&str, &str, u64)> {
+        let words: Vec<&str> = self.lines.first()?.split_whitespace().collect();
+        match words.as_slice() {
+            [afi @ ("ip" | "ipv6"), "prefix-list", name, "seq", seq, ..] => {
+                Some((afi, name, seq.parse().ok()?))
+            }
+            ["route-map", name, "permit" | "deny", seq] => {
+                Some(("route-map", name, seq.parse().ok()?))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with('!') || line.starts_with('#')
+}
+
+// normalize the lines of a config and group them into top-level blocks
+fn blocks(config: &str) -> Vec<Block> {
+    let mut blocks: Vec<Block> = vec![];
+    let mut indents: Vec<usize> = vec![]; /* indentation of the enclosing levels */
+    for line in config.lines() {
+        let content = line.trim();
+        if content.is_empty() || is_comment(content) {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        while indents.last().is_some_and(|last| indent <= *last) {
+            indents.pop();
+        }
+        let level = indents.len();
+        indents.push(indent);
+        let line = format!("{}{content}", " ".repeat(level));
+
+        /* a top-level "exit" closes the block it follows */
+        match blocks.last_mut() {
+            Some(block) if level > 0 || content == "exit" => block.lines.push(line),
+            _ => blocks.push(Block { lines: vec![line] }),
+        }
+    }
+    blocks
+}
+
+// the canonical form of an FRR config
+pub(crate) fn canonicalize(config: &str) -> String {
+    let mut blocks = blocks(config);
+
+    /* sort the runs of consecutive blocks whose order is irrelevant */
+    let mut start = 0;
+    while start < blocks.len() {
+        let kind = blocks[start].key().map(|(kind, _, _)| kind.to_string());
+        let mut end = start + 1;
+        if let Some(kind) = &kind {
+            while end < blocks.len()
+                && blocks[end]
+                    .key()
+                    .is_some_and(|(other, _, _)| other == kind.as_str())
+            {
+                end += 1;
+            }
+            blocks[start..end].sort_by(|a, b| a.key().cmp(&b.key()));
+        }
+        start = end;
+    }
+
+    let mut canonical = String::new();
+    for line in blocks.iter().flat_map(|block| &block.lines) {
+        canonical.push_str(line);
+        canonical.push('\n');
+    }
+    canonical
+}
@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Chunked transfer of configs, for the multi-MB configs of large fabrics. A transfer is opened
+// with a CHUNKED request, whose params describe the config:
+//
+//      CHUNKED\n[size=<octets>] [sha256=<hex>] [force]
+//
+// and followed by messages carrying the chunks of the config in order, with the genid of the
+// CHUNKED request. An empty message commits the transfer: the config is then processed as if it
+// had been sent in a single message (or with FORCE). Chunks are written to a spool file in outdir
+// as they are received rather than buffered, and the config is read back from it only once
+// processed. Chunks may be compressed as any message, in v4 messages flagged as such (see
+// framing.rs): they are decompressed as they are received, so the size and checksum given, if
+// any, are those of the config once decompressed. A transfer that fails (e.g. bad params, or a
+// config larger than --max-transfer-size) is still read up to its commit, so that the stream
+// stays in sync, and the commit is responded to with the failure.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::agent::Agent;
+use crate::framing::Framing;
+use crate::rawtext;
+use crate::request::Request;
+use crate::sha256::Sha256;
+use crate::{Args, GenId};
+
+// the params of a chunked transfer
+#[derive(Clone, Debug)]
+pub(crate) struct Transfer {
+    size: Option<u64>,      /* of the config, in octets */
+    sha256: Option<String>, /* of the config */
+    force: bool,            /* whether the config is forced despite predicted disruptions */
+}
+
+impl Transfer {
+    // parse the params of a CHUNKED request (e.g. "size=4194304 force")
+    pub(crate) fn parse(params: &str) -> Result<Self, String> {
+        let mut transfer = Transfer {
+            size: None,
+            sha256: None,
+            force: false,
+        };
+        for param in params.split_whitespace() {
+            match param.split_once('=') {
+                Some(("compression", _)) => {
+                    return Err(format!(
+                        "Unsupported transfer param '{param}': chunks are compressed in v4 messages"
+                    ));
+                }
+                Some(("size", size)) => {
+                    let size = size
+                        .parse()
+                        .map_err(|e| format!("Bad size '{size}': {e}"))?;
+                    transfer.size = Some(size);
+                }
+                Some(("sha256", sha256)) => transfer.sha256 = Some(sha256.to_lowercase()),
+                None if param == "force" => transfer.force = true,
+                _ => return Err(format!("Unknown transfer param '{param}'")),
+            }
+        }
+        Ok(transfer)
+    }
+}
+
+// a config spooled to outdir by a chunked transfer. It is loaded only once the request it stands
+// for is processed (not while queued or held), and the spool file is removed once done with.
+#[derive(Debug)]
+pub(crate) struct Spooled {
+    path: PathBuf,
+    sha256: String, /* of the config, computed as its chunks were written */
+}
+
+impl Spooled {
+    pub(crate) fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    // the config spooled
+    pub(crate) fn load(&self) -> Result<String, String> {
+        rawtext::read(&self.path)
+            .map_err(|e| format!("Could not read spool file {}: {e}", self.path.display()))
+    }
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// the spool file a config is written to as its chunks are received
+struct Spool {
+    spooled: Spooled,
+    file: File,
+    transfer: Transfer,
+    size: u64,      /* spooled so far */
+    sha256: Sha256, /* of the chunks spooled so far */
+}
+
+impl Spool {
+    fn create(path: PathBuf, transfer: Transfer) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {e}", parent.display()))?;
+        }
+        let file = File::create(&path)
+            .map_err(|e| format!("Could not create spool file {}: {e}", path.display()))?;
+        Ok(Self {
+            spooled: Spooled {
+                path,
+                sha256: String::new(),
+            },
+            file,
+            transfer,
+            size: 0,
+            sha256: Sha256::new(),
+        })
+    }
+
+    // write a chunk
+    fn write(&mut self, chunk: &[u8], max_size: u64) -> Result<(), String> {
+        self.size += chunk.len() as u64;
+        if self.size > max_size {
+            return Err(format!("Config is larger than {max_size} octets"));
+        }
+        self.file
+            .write_all(chunk)
+            .map_err(|e| format!("Could not write to spool file: {e}"))?;
+        self.sha256.update(chunk);
+        Ok(())
+    }
+
+    // the config spooled, once all its chunks were written
+    fn finish(mut self) -> Result<Spooled, String> {
+        self.file
+            .flush()
+            .map_err(|e| format!("Could not write to spool file: {e}"))?;
+        if let Some(expected) = self.transfer.size
+            && self.size != expected
+        {
+            return Err(format!("Config has {} octets, not {expected}", self.size));
+        }
+        self.spooled.sha256 = self.sha256.hex();
+        if let Some(expected) = &self.transfer.sha256
+            && self.spooled.sha256 != *expected
+        {
+            return Err("Config does not match its checksum".to_string());
+        }
+        Ok(self.spooled)
+    }
+}
+
+// receive the chunks of a transfer (or of one whose params are bad) up to its commit, into the
+// config request it stands for. Only failures to receive messages are errors, those of the
+// transfer making an invalid request.
+pub(crate) fn receive(
+    sock: &mut impl Read,
+    framing: Framing,
+    genid: GenId,
+    transfer: Result<Transfer, String>,
+    session: u64,
+    agent: &Agent,
+) -> Result<Request, String> {
+    let args: &Args = agent.args;
+    let max_size = args.max_transfer_size();
+    let path = Path::new(args.outdir()).join(format!(".chunked-{session}-{genid}"));
+    let mut spool = transfer.and_then(|transfer| Spool::create(path, transfer));
+    let mut chunks = 0u64;
+    let mut received = 0u64;
+    loop {
+        let (chunk_genid, chunk) = framing.receive(sock)?;
+        if chunk.is_empty() {
+            break;
+        }
+        chunks += 1;
+        let chunk = rawtext::encode(&chunk);
+        received += chunk.len() as u64;
+        let Ok(spooled) = &mut spool else {
+            continue; /* the transfer failed: the chunks left are discarded */
+        };
+        let written = if chunk_genid == genid {
+            spooled.write(&chunk, max_size)
+        } else {
+            Err(format!("Chunk {chunks} is for generation {chunk_genid}"))
+        };
+        if let Err(e) = written {
+            warn!(
+                "Chunked transfer of generation {genid} failed: {e}. Discarding the chunks left..."
+            );
+            spool = Err(e);
+        }
+    }
+    debug!("Received {chunks} chunks ({received} octets) of generation {genid}");
+    let finished = spool.and_then(|spool| {
+        let force = spool.transfer.force;
+        Ok((spool.finish()?, force))
+    });
+    match finished {
+        Ok((spooled, force)) => Ok(Request::Spooled(Arc::new(spooled), force)),
+        Err(e) => Ok(Request::Invalid(format!(
+            "Chunked transfer of generation {genid} failed: {e}"
+        ))),
+    }
+}
@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Client of the agent, speaking its protocol over the unix socket, for programs (e.g. controllers)
+// to embed. In client mode (send), the binary sends a config (from a file or stdin) or a request to
+// the agent at --sock-path with a genid and prints the response, so that the agent can be operated
+// and debugged without hand-writing the framing. Responses are requested in the structured format
+// (see response.rs) so that the exit status tells whether the request succeeded.
+
+use std::fs;
+use std::io::{Read, stdin};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::framing::Framing;
+use crate::json::Json;
+use crate::{Args, GenId};
+
+// a connection to the agent
+pub struct Client {
+    sock: UnixStream,
+    framing: Framing,
+}
+
+impl Client {
+    // connect to the agent at a socket path, with the original framing (v1)
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let sock = UnixStream::connect(path)
+            .map_err(|e| format!("Could not connect to {}: {e}", path.display()))?;
+        Ok(Self {
+            sock,
+            framing: Framing::V1,
+        })
+    }
+
+    // use a framing for the messages sent and received from now on
+    #[must_use]
+    pub fn with_framing(self, framing: Framing) -> Self {
+        Self { framing, ..self }
+    }
+
+    // send a message (a config or a request, e.g. "STATUS") with a genid and wait for the
+    // response, returned along with its genid
+    pub fn request(&mut self, genid: GenId, message: &[u8]) -> Result<(GenId, String), String> {
+        self.framing.send(&mut self.sock, genid, message)?;
+        self.framing.receive(&mut self.sock)
+    }
+
+    // apply a config as a generation, returning the response (e.g. "Ok\nsha256=...")
+    pub fn apply(&mut self, genid: GenId, config: &str) -> Result<String, String> {
+        self.request(genid, config.as_bytes())
+            .map(|(_, response)| response)
+    }
+
+    // the status of the agent (JSON)
+    pub fn status(&mut self) -> Result<String, String> {
+        self.request(0, b"STATUS").map(|(_, response)| response)
+    }
+
+    // request structured (JSON) responses from now on
+    pub fn structured(&mut self) -> Result<(), String> {
+        let (_, response) = self.request(0, b"FORMAT\njson")?;
+        match Json::parse(&response) {
+            Ok(response) if response.get("code").and_then(Json::as_i64) == Some(0) => Ok(()),
+            _ => Err(format!(
+                "Could not request structured responses: {response}"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, clap::Args)]
+pub(crate) struct SendArgs {
+    #[arg(long, value_name = "Generation id to send with. Defaults to 0")]
+    genid: Option<GenId>,
+    #[arg(
+        long,
+        value_name = "Request to send instead of a config (e.g. STATUS, or ROLLBACK with --genid)"
+    )]
+    request: Option<String>,
+    #[arg(long)]
+    json: bool,
+    #[arg(value_name = "File to read the config from. Defaults to stdin")]
+    file: Option<String>,
+}
+
+// the message to send: the request given or the config read
+fn message(send_args: &SendArgs) -> Result<Vec<u8>, String> {
+    if let Some(request) = &send_args.request {
+        return Ok(request.as_bytes().to_vec());
+    }
+    match send_args.file.as_deref() {
+        None | Some("-") => {
+            let mut config = vec![];
+            stdin()
+                .read_to_end(&mut config)
+                .map_err(|e| format!("Could not read config from stdin: {e}"))?;
+            Ok(config)
+        }
+        Some(file) => fs::read(file).map_err(|e| format!("Could not read config {file}: {e}")),
+    }
+}
+
+// send a request to the agent at sock-path and print the response. Returns whether the request
+// succeeded.
+pub(crate) fn send(args: &Args, send_args: &SendArgs) -> Result<bool, String> {
+    let message = message(send_args)?;
+    let genid = send_args.genid.unwrap_or(0);
+    let mut client = Client::connect(&args.sock_path)?;
+    client.structured()?;
+    let (_, response) = client.request(genid, &message)?;
+    let response = Json::parse(&response).map_err(|e| format!("Bad response '{response}': {e}"))?;
+    if send_args.json {
+        println!("{response}");
+    } else {
+        println!(
+            "{}",
+            response
+                .get("message")
+                .and_then(Json::as_str)
+                .unwrap_or_default()
+        );
+    }
+    Ok(response.get("code").and_then(Json::as_i64) == Some(0))
+}
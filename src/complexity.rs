@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Simple complexity metrics of FRR configs (lines, BGP neighbors, route-maps and prefix-lists),
+// reported per generation so that reload duration regressions can be correlated with the growth
+// of configs.
+
+use std::collections::BTreeSet;
+
+use crate::json::Json;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Complexity {
+    lines: u64,
+    neighbors: u64,
+    route_maps: u64,
+    prefix_lists: u64,
+}
+
+impl Complexity {
+    pub(crate) fn of(config: &str) -> Self {
+        let mut lines = 0;
+        let mut block = ""; /* the top-level line the current line is nested under */
+        let mut neighbors = BTreeSet::new();
+        let mut peer_groups = BTreeSet::new();
+        let mut route_maps = BTreeSet::new();
+        let mut prefix_lists = BTreeSet::new();
+        for line in config.lines() {
+            let content = line.trim();
+            if content.is_empty() || content.starts_with('!') || content.starts_with('#') {
+                continue;
+            }
+            lines += 1;
+            if !line.starts_with(' ') {
+                block = content;
+            }
+            let words: Vec<&str> = content.split_whitespace().collect();
+            match words.as_slice() {
+                ["neighbor", name, "peer-group"] => {
+                    peer_groups.insert((block, *name));
+                }
+                ["neighbor", name, ..] => {
+                    neighbors.insert((block, *name));
+                }
+                ["route-map", name, "permit" | "deny", ..] => {
+                    route_maps.insert(*name);
+                }
+                [afi @ ("ip" | "ipv6"), "prefix-list", name, ..] => {
+                    prefix_lists.insert((*afi, *name));
+                }
+                _ => {}
+            }
+        }
+        let count = |n: usize| u64::try_from(n).unwrap_or(u64::MAX);
+        Self {
+            lines,
+            neighbors: count(neighbors.difference(&peer_groups).count()),
+            route_maps: count(route_maps.len()),
+            prefix_lists: count(prefix_lists.len()),
+        }
+    }
+
+    pub(crate) fn to_json(self) -> Json {
+        Json::object([
+            ("lines", self.lines.into()),
+            ("neighbors", self.neighbors.into()),
+            ("route_maps", self.route_maps.into()),
+            ("prefix_lists", self.prefix_lists.into()),
+        ])
+    }
+}
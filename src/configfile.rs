@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Configuration file of the agent (--config), covering everything that can be set on the command
+// line. The file is in TOML, limited to top-level key/value pairs, with the long names of the
+// command line options as keys (e.g. reload-timeout = 60, shadow = true, allowed-uid = [0, 990]).
+// Options given on the command line take precedence over those in the file (repeatable options
+// add up). The file is re-read on SIGHUP: changes of loglevel and of the reloader (reloader and
+// reloader-sha256, of every FRR instance) are applied without dropping the connections of
+// clients, while other changes are reported as requiring a restart.
+
+use std::fs;
+
+use clap::Parser;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::agent::Agent;
+use crate::{Args, reloader_binary, set_loglevel};
+
+// the config file given on the command line, if any
+fn config_path(cmdline: &[String]) -> Option<String> {
+    let mut args = cmdline.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().cloned();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+// a TOML string, with its escapes resolved
+fn string(value: &str) -> Result<String, String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .or_else(|| {
+            value
+                .strip_prefix('\'')
+                .and_then(|value| value.strip_suffix('\''))
+        })
+        .ok_or(format!("Bad string {value}"))?;
+    if value.starts_with('\'') {
+        return Ok(inner.to_string());
+    }
+    let mut string = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => string.push('"'),
+            Some('\\') => string.push('\\'),
+            Some('n') => string.push('\n'),
+            Some('t') => string.push('\t'),
+            _ => return Err(format!("Bad escape in string {value}")),
+        }
+    }
+    Ok(string)
+}
+
+// a scalar value (string, integer or bare word) as an argument
+fn scalar(value: &str) -> Result<String, String> {
+    if value.starts_with('"') || value.starts_with('\'') {
+        string(value)
+    } else if value.is_empty() || value.contains(char::is_whitespace) {
+        Err(format!("Bad value '{value}'"))
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+// split the items of an array, outside of strings
+fn items(array: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in array.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ',') => {
+                items.push(array[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(array[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+// strip a comment from a line, outside of strings
+fn uncommented(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// the command line arguments equivalent to the contents of a config file
+fn parse(contents: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    for (number, line) in contents.lines().enumerate() {
+        let line = uncommented(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad = |e: String| format!("line {}: {e}", number + 1);
+        if line.starts_with('[') {
+            return Err(bad("tables are not supported".to_string()));
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| bad(format!("expected key = value, got '{line}'")))?;
+        let key = key.trim().trim_matches('"').replace('_', "-");
+        if key.is_empty() || key == "config" {
+            return Err(bad(format!("bad key '{key}'")));
+        }
+        let option = format!("--{key}");
+        match value.trim() {
+            "true" => args.push(option),
+            "false" => {}
+            value => {
+                if let Some(array) = value
+                    .strip_prefix('[')
+                    .and_then(|value| value.strip_suffix(']'))
+                {
+                    for item in items(array) {
+                        args.push(option.clone());
+                        args.push(scalar(item).map_err(bad)?);
+                    }
+                } else {
+                    args.push(option);
+                    args.push(scalar(value).map_err(bad)?);
+                }
+            }
+        }
+    }
+    Ok(args)
+}
+
+// the command line, with the arguments from the config file given (if any) inserted ahead of
+// those given, so that the latter take precedence
+pub(crate) fn merged(cmdline: &[String]) -> Result<Vec<String>, String> {
+    let Some(path) = config_path(cmdline) else {
+        return Ok(cmdline.to_vec());
+    };
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Could not read config file {path}: {e}"))?;
+    let from_file = parse(&contents).map_err(|e| format!("Bad config file {path}: {e}"))?;
+    let mut merged = cmdline.to_vec();
+    let at = usize::from(!merged.is_empty());
+    merged.splice(at..at, from_file);
+    Ok(merged)
+}
+
+// re-read the config file and apply the changes that can be applied while running
+fn reload(agent: &Agent, cmdline: &[String]) -> Result<(), String> {
+    let args = Args::try_parse_from(merged(cmdline)?).map_err(|e| e.to_string())?;
+    let current = agent.args;
+
+    let loglevel = args.loglevel().map_err(|()| "Bad loglevel".to_string())?;
+    /* the reloader of each FRR instance, derived from the args as at startup */
+    let mut binaries = vec![(String::new(), agent, reloader_binary(&args)?)];
+    for (name, instance) in agent.instances() {
+        let binary = reloader_binary(&args.for_instance(name)?)?;
+        binaries.push((format!(" of FRR instance {name}"), instance, binary));
+    }
+
+    set_loglevel(loglevel)?;
+    info!("Loglevel is {loglevel}");
+    for (of, agent, binary) in binaries {
+        if binary != agent.reloader.binary() {
+            info!(
+                "Reloader{of} is now {} (sha256 {:?})",
+                binary.path, binary.sha256
+            );
+            agent.reloader.set_binary(binary);
+        }
+    }
+
+    /* the other settings are only read at startup */
+    let mut unchanged = args.clone();
+    unchanged.loglevel.clone_from(&current.loglevel);
+    unchanged.reloader.clone_from(&current.reloader);
+    unchanged
+        .reloader_sha256
+        .clone_from(&current.reloader_sha256);
+    if unchanged != *current {
+        warn!("Changes other than loglevel and reloader require a restart to take effect");
+    }
+    Ok(())
+}
+
+// re-read the config file on SIGHUP, forever
+pub(crate) fn watch(agent: &Agent) {
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!("Could not handle SIGHUP: {e}");
+            return;
+        }
+    };
+    let cmdline: Vec<String> = std::env::args().collect();
+    for _ in signals.forever() {
+        let Some(path) = config_path(&cmdline) else {
+            info!("Got SIGHUP, but no config file is set: ignoring");
+            continue;
+        };
+        info!("Got SIGHUP: reloading config file {path}...");
+        match reload(agent, &cmdline) {
+            Ok(()) => info!("Reloaded config file {path}"),
+            Err(e) => error!("Could not reload config file {path}: {e}. Keeping current settings"),
+        }
+    }
+}
@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Handling of a client connection: a reader thread decodes requests and queues them
+// while the main thread processes them in order. Configs received while another request
+// changing state is processed (from any connection) get their --test stage run in the
+// background so that only the apply stage remains by the time they reach the front of
+// the queue. CANCEL is handled by the reader as soon as it is received, so that it does not
+// wait for the request it cancels.
+
+use std::fmt::Debug;
+use std::io::{Cursor, Read};
+use std::net::Shutdown;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, channel};
+use std::thread::{self, ScopedJoinHandle};
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{Span, debug, error, info, info_span, warn};
+
+use crate::GenId;
+use crate::agent::{Agent, Outcome};
+use crate::chunked;
+use crate::framing::Framing;
+use crate::jsonrpc::{JSONRPC_MAGIC, serve_jsonrpc};
+use crate::logstream::stream_logs;
+use crate::metrics::METRICS;
+use crate::passthrough::serve_vtysh;
+use crate::peer::{PeerCred, allowed, peer_cred, process_name};
+use crate::pending::InFlight;
+use crate::reload::{FrrErr, Validation};
+use crate::request::Request;
+use crate::response::ResponseFormat;
+use crate::tls::TlsAddr;
+use crate::vsock::{self, VsockAddr};
+
+// a request sitting in the connection queue
+struct Queued<'scope> {
+    agent: &'scope Agent<'scope>, /* of the FRR instance the request targets */
+    genid: GenId,
+    request: Request,
+    validation: Option<ScopedJoinHandle<'scope, Validation>>,
+    in_flight: InFlight<'scope>,
+    handled: Option<Outcome>, /* the outcome of a CANCEL handled on receipt */
+}
+
+// how responses are sent on a connection, shared by the reader which responds to CANCEL
+struct Responder {
+    framing: Framing,
+    format: ResponseFormat,
+    switching: usize, /* HELLO and FORMAT requests queued, the responses after which may change */
+}
+
+// the outcome of the --test stage run ahead for a queued config
+fn joined(validation: Option<ScopedJoinHandle<'_, Validation>>) -> Option<Validation> {
+    validation.map(|validation| {
+        validation
+            .join()
+            .unwrap_or((Err(FrrErr::Failure("Validation thread panicked")), vec![]))
+    })
+}
+
+// the address of a peer, over the unix socket, vsock or TCP
+pub(crate) enum Peer {
+    Unix(SocketAddr),
+    Vsock(VsockAddr),
+    Tls(TlsAddr),
+}
+impl Debug for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Peer::Unix(addr) => addr.fmt(f),
+            Peer::Vsock(addr) => addr.fmt(f),
+            Peer::Tls(addr) => addr.fmt(f),
+        }
+    }
+}
+
+// a client connected to the agent
+pub(crate) struct Client {
+    pub(crate) session: u64,
+    pub(crate) cred: Option<PeerCred>,
+}
+
+// how a peer is named in logs: by its credentials if it has any, else by its address
+fn peer_name(peer: &Peer, client: &Client) -> String {
+    client
+        .cred
+        .map_or_else(|| format!("{peer:?}"), |cred| cred.to_string())
+}
+
+// the span annotating every log line of a connection with the identity of the peer
+fn peer_span(peer: &Peer, cred: Option<PeerCred>) -> Span {
+    let Some(cred) = cred else {
+        return info_span!("peer", addr = ?peer);
+    };
+    let comm = process_name(cred.pid).unwrap_or_else(|| "?".to_string());
+    info_span!("peer", uid = cred.uid, pid = cred.pid, comm = %comm)
+}
+
+pub(crate) fn handle_connection(stream: UnixStream, peer: &Peer, agent: &Agent) {
+    let _connection = METRICS.connection();
+    /* vsock and TLS peers are not local processes: they have no credentials */
+    let cred = match peer {
+        Peer::Unix(_) => match peer_cred(&stream) {
+            Ok(cred) => {
+                debug!("Peer credentials are {cred}");
+                Some(cred)
+            }
+            Err(e) => {
+                warn!("{e}");
+                None
+            }
+        },
+        Peer::Vsock(_) | Peer::Tls(_) => None,
+    };
+    let span = peer_span(peer, cred);
+    let _entered = span.enter();
+    /* unauthorized peers are rejected before anything is read from them */
+    let authorized = match peer {
+        Peer::Unix(_) => allowed(
+            cred.as_ref(),
+            &agent.args.allowed_uid,
+            &agent.args.allowed_gid,
+        ),
+        Peer::Vsock(addr) => vsock::allowed(*addr, &agent.args.vsock_allowed_cid),
+        /* the certificate of TLS peers was verified during the handshake */
+        Peer::Tls(_) => Ok(()),
+    };
+    if let Err(e) = authorized {
+        warn!("Rejecting connection: {e}");
+        METRICS.rejected_connection();
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    /* peers may only have so many connections open at once */
+    let _slot = match agent.quotas.connect(cred.as_ref()) {
+        Ok(slot) => slot,
+        Err(e) => {
+            warn!("Refusing connection: {e}");
+            METRICS.rejected_connection();
+            refuse(
+                stream,
+                &format!("RATE_LIMITED\n{e}"),
+                agent.args.send_timeout(),
+            );
+            return;
+        }
+    };
+    let peer_name = cred.map_or_else(|| format!("{peer:?}"), |cred| cred.to_string());
+    let client = Client {
+        session: agent.sessions.register(peer_name),
+        cred,
+    };
+    /* sending responses is bounded in time, so that stuck clients are told apart */
+    if let Err(e) = stream.set_write_timeout(Some(agent.args.send_timeout())) {
+        warn!("Failed to set send timeout: {e}");
+    }
+    serve(stream, peer, agent, &client);
+    agent.sessions.unregister(client.session);
+}
+
+// respond to the first request on a connection that is refused, so that the client is told why,
+// then close it. JSON-RPC clients are not responded to.
+fn refuse(stream: UnixStream, response: &str, timeout: Duration) {
+    let mut stream = stream;
+    let _ = stream.set_read_timeout(Some(timeout));
+    let mut first = [0u8; 8];
+    if stream.read_exact(&mut first).is_ok() && &first != JSONRPC_MAGIC {
+        let framing = Framing::detect(&first);
+        if let Ok((genid, _)) = framing.receive(&mut Cursor::new(first).chain(&stream)) {
+            let _ = framing.send(&mut stream, genid, response.as_bytes());
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+// receive the next request and route it to the FRR instance it targets, with the chunks of the
+// config it opens a chunked transfer of, if it does
+fn next_request<'s>(
+    rx_stream: &mut impl Read,
+    framing: Framing,
+    agent: &'s Agent<'s>,
+    client: &Client,
+) -> Result<(GenId, &'s Agent<'s>, Request), String> {
+    let (genid, request) = framing.receive(rx_stream)?;
+    agent.sessions.seen(client.session);
+    let (target, request) = agent.route(genid, request);
+    let Request::Chunked(transfer) = request else {
+        return Ok((genid, target, request));
+    };
+    let request = chunked::receive(rx_stream, framing, genid, transfer, client.session, target)?;
+    Ok((genid, target, request))
+}
+
+// hand the connection over once the response to a request was sent, if the request turns it into
+// an event channel or a vtysh session. Returns the connection if it keeps serving requests.
+fn hand_over(
+    request: &Request,
+    response: &str,
+    stream: UnixStream,
+    agent: &Agent,
+    framing: Framing,
+) -> Option<UnixStream> {
+    let mut stream = stream;
+    match request {
+        Request::Subscribe(source) if response == "Ok" => {
+            stream_logs(source.clone(), stream, move |stream, line| {
+                framing.send(stream, 0, line.as_bytes())
+            });
+        }
+        /* the connection is closed when the session ends, or if it is refused */
+        Request::Vtysh => {
+            if response == "Ok" {
+                serve_vtysh(agent.args, &mut stream, framing);
+            }
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        _ => return Some(stream),
+    }
+    None
+}
+
+// serve queued requests in order, in the format requested (FORMAT)
+fn process(
+    rx: &Receiver<Queued>,
+    mut stream: UnixStream,
+    peer: &Peer,
+    client: &Client,
+    responder: &Mutex<Responder>,
+) {
+    let peer_name = peer_name(peer, client);
+    while let Ok(queued) = rx.recv() {
+        let genid = queued.genid;
+        debug!("Got {} from {peer:?}", queued.request.describe(genid));
+        queued.in_flight.start();
+        let validation = joined(queued.validation);
+        let agent = queued.agent;
+        let started = Instant::now();
+        let outcome = if let Some(handled) = queued.handled {
+            handled
+        } else if queued.in_flight.cancelled() {
+            info!("Generation {genid} was cancelled while queued on its connection");
+            format!("CANCELLED\ngeneration {genid} was cancelled while queued on its connection")
+                .into()
+        } else {
+            agent.args.proc_time();
+            agent.handle_outcome(genid, &queued.request, client, validation)
+        };
+        let duration = started.elapsed();
+        let Ok(mut responder) = responder.lock() else {
+            break;
+        };
+        responder.format = responder.format.after(&queued.request, &outcome);
+        let response = responder.format.encode(&queued.request, genid, &outcome);
+        if let Err(e) = responder
+            .framing
+            .send(&mut stream, genid, response.as_bytes())
+        {
+            error!("Error sending response: {e:?}. Shutting down connection...");
+            break;
+        }
+        debug!("Successfully sent response");
+        served(
+            genid,
+            &queued.request,
+            &peer_name,
+            duration,
+            &outcome.response,
+        );
+
+        /* the responses following that to a HELLO are sent in the framing negotiated, if any */
+        if let Request::Hello(_, Some(negotiated), _) = &queued.request {
+            responder.framing = *negotiated;
+        }
+        if switches(&queued.request) {
+            responder.switching -= 1;
+        }
+        let framing = responder.framing;
+        drop(responder);
+
+        /* the connection may be handed over for good */
+        match hand_over(&queued.request, &outcome.response, stream, agent, framing) {
+            Some(kept) => stream = kept,
+            None => return,
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+// whether the responses following that to a request may be sent in another framing or format
+fn switches(request: &Request) -> bool {
+    matches!(request, Request::Hello(..) | Request::Format(_))
+}
+
+// handle a CANCEL on receipt, and respond to it right away unless the framing or format of its
+// response depends on requests queued before it. Returns its outcome if it is to be queued.
+fn cancel(
+    stream: &mut UnixStream,
+    genid: GenId,
+    target: &Agent,
+    client: &Client,
+    peer_name: &str,
+    responder: &Mutex<Responder>,
+) -> Result<Option<Outcome>, String> {
+    let started = Instant::now();
+    let request = Request::Cancel;
+    let outcome = target.handle_outcome(genid, &request, client, None);
+    let responder = responder
+        .lock()
+        .map_err(|_| "Responder lock is poisoned".to_string())?;
+    if responder.switching > 0 {
+        return Ok(Some(outcome));
+    }
+    let response = responder.format.encode(&request, genid, &outcome);
+    responder
+        .framing
+        .send(stream, genid, response.as_bytes())
+        .map_err(|e| format!("Error sending response: {e:?}"))?;
+    served(
+        genid,
+        &request,
+        peer_name,
+        started.elapsed(),
+        &outcome.response,
+    );
+    Ok(None)
+}
+
+// log a summary of a request served (but keepalives), with structured fields
+fn served(genid: GenId, request: &Request, peer: &str, duration: Duration, response: &str) {
+    if matches!(request, Request::Keepalive) {
+        return;
+    }
+    info!(
+        genid,
+        peer,
+        duration_ms = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+        result = response.lines().next().unwrap_or_default(),
+        "Served {}",
+        request.describe(genid)
+    );
+}
+
+fn serve(stream: UnixStream, peer: &Peer, agent: &Agent, client: &Client) {
+    /* the first 8 octets tell if the client wants JSON-RPC or the binary framing */
+    let mut stream = stream;
+    let mut first = [0u8; 8];
+    if let Err(e) = stream.read_exact(&mut first) {
+        error!("Could not receive data: {e}. Shutting down connection...");
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    if &first == JSONRPC_MAGIC {
+        serve_jsonrpc(stream, agent, client);
+        return;
+    }
+
+    let (Ok(rx_stream), Ok(mut cancel_stream)) = (stream.try_clone(), stream.try_clone()) else {
+        error!("Could not clone connection stream. Shutting down connection...");
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    };
+    let framing = Framing::detect(&first);
+    debug!("Client uses the {framing:?} framing");
+    let mut rx_stream = Cursor::new(first).chain(rx_stream);
+    let responder = &Mutex::new(Responder {
+        framing,
+        format: ResponseFormat::Text,
+        switching: 0,
+    });
+    let peer_name = peer_name(peer, client);
+
+    /* threads do not inherit the span of the connection */
+    let span = &Span::current();
+    thread::scope(|scope| {
+        let (tx, rx) = channel::<Queued>();
+
+        /* reader: decode requests and queue them */
+        scope.spawn(move || {
+            let _entered = span.enter();
+            let mut keepalive = None;
+            let mut framing = framing;
+            loop {
+                let (genid, target, request) =
+                    match next_request(&mut rx_stream, framing, agent, client) {
+                        Ok(received) => received,
+                        Err(e) => {
+                            METRICS.receive_error(&e);
+                            if let Some(keepalive) = keepalive {
+                                warn!("No request received within {keepalive}: {e}");
+                            }
+                            error!("An error occurred: {e}. Shutting down connection...");
+                            let _ = rx_stream.get_ref().1.shutdown(Shutdown::Read);
+                            break; /* pending requests are still served */
+                        }
+                    };
+
+                /* enforce the keepalive interval the client negotiated, and read the messages
+                 * following a HELLO in the framing negotiated, if any */
+                if let Request::Hello(requested, negotiated, _) = &request {
+                    let agreed = agent.negotiate_keepalive(requested);
+                    let deadline = Some(agreed.deadline());
+                    if let Err(e) = rx_stream.get_ref().1.set_read_timeout(deadline) {
+                        error!("Failed to set keepalive timeout: {e}");
+                    }
+                    keepalive = Some(agreed);
+                    framing = negotiated.unwrap_or(framing);
+                }
+                let mut handled = None;
+                if matches!(request, Request::Cancel) {
+                    match cancel(&mut cancel_stream, genid, target, client, &peer_name, responder) {
+                        Ok(None) => continue,
+                        Ok(queued) => handled = queued,
+                        Err(e) => {
+                            error!("{e}. Shutting down connection...");
+                            let _ = cancel_stream.shutdown(Shutdown::Both);
+                            break;
+                        }
+                    }
+                }
+                if switches(&request)
+                    && let Ok(mut responder) = responder.lock()
+                {
+                    responder.switching += 1;
+                }
+                let validation = match &request {
+                    Request::Config(config, _) if target.validates_ahead(genid, &request, client) => {
+                        debug!(
+                            "Generation {genid} queued behind an in-flight request. Validating it..."
+                        );
+                        let config = config.clone();
+                        Some(scope.spawn(move || span.in_scope(|| target.validate(genid, &config))))
+                    }
+                    _ => None,
+                };
+                /* no more requests are read once the client subscribes to events or opens a
+                 * vtysh session */
+                let subscribed = matches!(request, Request::Subscribe(_) | Request::Vtysh);
+                let queued = Queued {
+                    agent: target,
+                    in_flight: target.received(genid, &request),
+                    genid,
+                    request,
+                    validation,
+                    handled,
+                };
+                if tx.send(queued).is_err() || subscribed {
+                    break;
+                }
+            }
+        });
+
+        process(&rx, stream, peer, client, responder);
+        drop(rx);
+    });
+}
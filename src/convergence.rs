@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Convergence-aware deferral of applies: reloading while routing has not converged compounds the
+// instability, so applies may be deferred until the box has been quiet for a window. Routing is
+// sampled every second: it is quiet if, between samples, no BGP peer changed state (e.g. cycling
+// through Active/Connect) or prefix count, and the RIB changed by no more than a number of routes.
+// Routing is watched in the background, so that applies are only deferred if it has not been
+// quiet for the window already. Applies are never deferred beyond a max wait, nor if routing can
+// not be sampled.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::json::Json;
+use crate::vtysh::vtysh;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+// the state of routing at some point in time
+struct Sample {
+    peers: BTreeMap<String, (String, i64)>, /* state and prefixes received, by vrf/afi/peer */
+    routes: i64,
+}
+
+impl Sample {
+    fn take(args: &Args) -> Result<Self, String> {
+        let summary = vtysh(args, "show bgp vrf all summary json")?;
+        let summary = Json::parse(&summary).map_err(|e| format!("Bad BGP summary json: {e}"))?;
+        let mut peers = BTreeMap::new();
+        if let Json::Object(vrfs) = &summary {
+            for (vrf, afis) in vrfs {
+                let Json::Object(afis) = afis else {
+                    continue;
+                };
+                for (afi, afi_summary) in afis {
+                    let Some(Json::Object(afi_peers)) = afi_summary.get("peers") else {
+                        continue;
+                    };
+                    for (peer, state) in afi_peers {
+                        let status = state.get("state").and_then(Json::as_str).unwrap_or("");
+                        let prefixes = state.get("pfxRcd").and_then(Json::as_i64).unwrap_or(0);
+                        peers.insert(
+                            format!("{vrf}/{afi}/{peer}"),
+                            (status.to_string(), prefixes),
+                        );
+                    }
+                }
+            }
+        }
+        let mut routes = 0;
+        for afi in ["ip", "ipv6"] {
+            let summary = vtysh(args, &format!("show {afi} route summary json"))?;
+            let summary =
+                Json::parse(&summary).map_err(|e| format!("Bad route summary json: {e}"))?;
+            routes += summary
+                .get("routesTotal")
+                .and_then(Json::as_i64)
+                .unwrap_or(0);
+        }
+        Ok(Self { peers, routes })
+    }
+
+    // why routing is not quiet since an earlier sample, if so
+    fn churn(&self, earlier: &Self, rib_churn: u64) -> Option<String> {
+        for (peer, (state, prefixes)) in &self.peers {
+            match earlier.peers.get(peer) {
+                Some((earlier_state, _)) if earlier_state != state => {
+                    return Some(format!("BGP peer {peer} went {earlier_state} -> {state}"));
+                }
+                Some((_, earlier_prefixes)) if earlier_prefixes != prefixes => {
+                    return Some(format!(
+                        "BGP peer {peer} prefixes changed {earlier_prefixes} -> {prefixes}"
+                    ));
+                }
+                Some(_) => {}
+                None => return Some(format!("BGP peer {peer} appeared ({state})")),
+            }
+        }
+        if let Some(peer) = earlier
+            .peers
+            .keys()
+            .find(|peer| !self.peers.contains_key(*peer))
+        {
+            return Some(format!("BGP peer {peer} disappeared"));
+        }
+        let changed = self.routes.abs_diff(earlier.routes);
+        (changed > rib_churn).then(|| format!("RIB changed by {changed} routes"))
+    }
+}
+
+// the convergence of routing, as watched in the background
+#[derive(Default)]
+struct Watched {
+    quiet_since: Option<Instant>, /* None if routing could not be sampled */
+    churn: Option<String>,        /* the last churn seen */
+}
+
+pub(crate) struct Convergence {
+    window: Duration,
+    max_wait: Duration,
+    rib_churn: u64,
+    watched: Mutex<Watched>,
+}
+
+impl Convergence {
+    pub(crate) fn new(window: Duration, max_wait: Duration, rib_churn: u64) -> Self {
+        Self {
+            window,
+            max_wait,
+            rib_churn,
+            watched: Mutex::new(Watched::default()),
+        }
+    }
+
+    // sample routing forever, keeping track of how long it has been quiet
+    pub(crate) fn watch(&self, args: &Args) {
+        let mut last: Option<Sample> = None;
+        loop {
+            let sample = Sample::take(args)
+                .inspect_err(|e| debug!("Could not sample routing convergence: {e}"))
+                .ok();
+            if let Ok(mut watched) = self.watched.lock() {
+                let churn = match (&sample, &last) {
+                    (Some(sample), Some(last)) => sample.churn(last, self.rib_churn),
+                    _ => None,
+                };
+                match (&sample, churn) {
+                    (None, _) => watched.quiet_since = None,
+                    (Some(_), Some(churn)) => {
+                        debug!("Routing is not quiet: {churn}");
+                        watched.quiet_since = Some(Instant::now());
+                        watched.churn = Some(churn);
+                    }
+                    (Some(_), None) => {
+                        watched.quiet_since.get_or_insert_with(Instant::now);
+                    }
+                }
+            }
+            last = sample;
+            sleep(SAMPLE_INTERVAL);
+        }
+    }
+
+    // how long routing has been quiet for, if known
+    fn quiet_for(&self) -> Option<Duration> {
+        let watched = self.watched.lock().ok()?;
+        watched.quiet_since.map(|since| since.elapsed())
+    }
+
+    // wait for routing to have been quiet for the window, for up to the max wait. Returns how
+    // long the apply was deferred for, if it was.
+    pub(crate) fn await_quiet(&self) -> Option<Duration> {
+        let started = Instant::now();
+        let mut deferred = false;
+        loop {
+            let Some(quiet_for) = self.quiet_for() else {
+                if deferred {
+                    warn!("Routing convergence is unknown. Not deferring any longer");
+                }
+                break;
+            };
+            if quiet_for >= self.window {
+                break;
+            }
+            if started.elapsed() >= self.max_wait {
+                warn!(
+                    "Routing did not settle in {:?}. Applying anyway",
+                    self.max_wait
+                );
+                break;
+            }
+            if !deferred {
+                let churn = self
+                    .churn()
+                    .map_or(String::new(), |churn| format!(", last churn: {churn}"));
+                info!(
+                    "Deferring apply until routing is quiet for {:?} (quiet for {quiet_for:?}{churn})",
+                    self.window
+                );
+                deferred = true;
+            }
+            sleep(SAMPLE_INTERVAL.min(self.window.saturating_sub(quiet_for)));
+        }
+        deferred.then(|| started.elapsed())
+    }
+
+    fn churn(&self) -> Option<String> {
+        self.watched.lock().ok()?.churn.clone()
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            (
+                "quiet_ms",
+                self.quiet_for()
+                    .map(|quiet_for| u64::try_from(quiet_for.as_millis()).unwrap_or(u64::MAX))
+                    .into(),
+            ),
+            ("last_churn", self.churn().into()),
+        ])
+    }
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Management of the FRR daemons file, which tells which FRR daemons are to be started,
+// with lines like "bgpd=yes" or "bfdd=no".
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::service::{FrrAction, FrrService};
+
+fn daemons_file(confdir: &str) -> PathBuf {
+    Path::new(confdir).join("daemons")
+}
+
+// parse a line of the daemons file that enables or disables a daemon
+fn daemon_setting(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.trim().split_once('=')?;
+    if !name.ends_with('d') || name.contains(char::is_whitespace) {
+        return None;
+    }
+    match value.trim() {
+        v @ ("yes" | "no") => Some((name, v)),
+        _ => None,
+    }
+}
+
+// rewrite the contents of the daemons file so that exactly the requested daemons are enabled.
+// Returns the new contents, or None if nothing changes.
+fn update_daemons(contents: &str, enabled: &[String]) -> Result<Option<String>, String> {
+    let known: Vec<&str> = contents
+        .lines()
+        .filter_map(daemon_setting)
+        .map(|(name, _)| name)
+        .collect();
+    if let Some(unknown) = enabled.iter().find(|d| !known.contains(&d.as_str())) {
+        return Err(format!("Unknown daemon '{unknown}'"));
+    }
+
+    let mut changed = false;
+    let mut updated = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        match daemon_setting(line) {
+            Some((name, value)) => {
+                let wanted = if enabled.iter().any(|d| d == name) {
+                    "yes"
+                } else {
+                    "no"
+                };
+                if wanted != value {
+                    info!("Setting {name}={wanted}");
+                    changed = true;
+                }
+                updated.push_str(name);
+                updated.push('=');
+                updated.push_str(wanted);
+            }
+            None => updated.push_str(line),
+        }
+        updated.push('\n');
+    }
+    Ok(changed.then_some(updated))
+}
+
+// set the FRR daemons to be enabled and restart FRR if that changed the daemons file
+pub fn set_frr_daemons(
+    confdir: &str,
+    service: &FrrService,
+    enabled: &[String],
+) -> Result<(), String> {
+    let path = daemons_file(confdir);
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read daemons file {}: {e}", path.display()))?;
+
+    let Some(updated) = update_daemons(&contents, enabled)? else {
+        debug!("Daemons file is up to date. Enabled daemons: {enabled:?}");
+        return Ok(());
+    };
+
+    /* replace the daemons file atomically */
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, updated).map_err(|e| format!("Could not write {}: {e}", tmp.display()))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("Could not update {}: {e}", path.display()))?;
+    info!("Updated daemons file {}", path.display());
+
+    /* the set of daemons is only read by FRR on start */
+    service.control(FrrAction::Restart)
+}
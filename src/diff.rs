@@ -0,0 +1,312 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Line diff of configs (Myers' algorithm, in its linear space variant so that diffing large or
+// unrelated configs does not take memory quadratic in their size)
+
+use std::collections::HashSet;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Edit<'a> {
+    Keep(&'a str),
+    Insert(&'a str),
+    Delete(&'a str),
+}
+
+// the middle snake of the shortest edit script turning old into new, i.e. the run of common lines
+// (possibly empty) halfway through it, from (x, y) to (u, v). The furthest reaching paths are
+// searched from both ends at once, keeping only the last one on every diagonal: memory is linear.
+// Names follow the notation of Myers' paper.
+#[allow(
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss,
+    clippy::many_single_char_names
+)]
+fn middle_snake(old: &[&str], new: &[&str]) -> (usize, usize, usize, usize) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m + 1) / 2;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let at = |k: isize| (k + max + 1) as usize;
+
+    /* furthest reaching x on every diagonal k, from the start (vf) and from the end (vb) */
+    let mut vf = vec![0isize; 2 * max as usize + 3];
+    let mut vb = vec![0isize; 2 * max as usize + 3];
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && vf[at(k - 1)] < vf[at(k + 1)]) {
+                vf[at(k + 1)]
+            } else {
+                vf[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            vf[at(k)] = x;
+            /* the paths from the end took d - 1 edits so far */
+            let kb = delta - k;
+            if odd && (1 - d..d).contains(&kb) && x + vb[at(kb)] >= n {
+                return (x0 as usize, y0 as usize, x as usize, y as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && vb[at(k - 1)] < vb[at(k + 1)]) {
+                vb[at(k + 1)]
+            } else {
+                vb[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            vb[at(k)] = x;
+            /* the paths from the start took d edits */
+            let kf = delta - k;
+            if !odd && (-d..=d).contains(&kf) && vf[at(kf)] + x >= n {
+                return (
+                    (n - x) as usize,
+                    (m - y) as usize,
+                    (n - x0) as usize,
+                    (m - y0) as usize,
+                );
+            }
+        }
+    }
+    /* not reached: the paths meet after (n + m) / 2 edits at most */
+    (0, 0, 0, 0)
+}
+
+// append the shortest edit script turning old into new, dividing it at its middle snake
+fn diff_into<'a>(old: &[&'a str], new: &[&'a str], edits: &mut Vec<Edit<'a>>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    edits.extend(old[..prefix].iter().map(|line| Edit::Keep(line)));
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (kept, old, new) = (
+        &old[old.len() - suffix..],
+        &old[..old.len() - suffix],
+        &new[..new.len() - suffix],
+    );
+
+    let (x, y, u, v) = if old.is_empty() || new.is_empty() {
+        (0, 0, 0, 0)
+    } else {
+        middle_snake(old, new)
+    };
+    if (u, v) == (0, 0) || (x, y) == (old.len(), new.len()) {
+        /* nothing in common (or nothing left on one side) */
+        edits.extend(old.iter().map(|line| Edit::Delete(line)));
+        edits.extend(new.iter().map(|line| Edit::Insert(line)));
+    } else {
+        diff_into(&old[..x], &new[..y], edits);
+        edits.extend(old[x..u].iter().map(|line| Edit::Keep(line)));
+        diff_into(&old[u..], &new[v..], edits);
+    }
+    edits.extend(kept.iter().map(|line| Edit::Keep(line)));
+}
+
+// the shortest edit script turning old into new (Myers' algorithm, in linear space). Lines found
+// on one side only can not be kept: they are left out of the search, so that diffing unrelated
+// configs takes linear time. Within every run of changes, the deletions come first.
+pub(crate) fn diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+    let in_old: HashSet<&str> = old.iter().copied().collect();
+    let in_new: HashSet<&str> = new.iter().copied().collect();
+    let common = |lines: &[&'a str], other: &HashSet<&str>| -> Vec<&'a str> {
+        lines
+            .iter()
+            .copied()
+            .filter(|line| other.contains(line))
+            .collect()
+    };
+    let mut script = vec![];
+    diff_into(&common(old, &in_new), &common(new, &in_old), &mut script);
+
+    /* weave the lines left out back in, as deleted or inserted */
+    let mut edits = Vec::with_capacity(old.len().max(new.len()));
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    let mut changes: Vec<Edit<'a>> = vec![];
+    for edit in script {
+        if matches!(edit, Edit::Keep(_) | Edit::Delete(_)) {
+            while let Some(line) = old.next_if(|line| !in_new.contains(*line)) {
+                changes.push(Edit::Delete(line));
+            }
+            old.next();
+        }
+        if matches!(edit, Edit::Keep(_) | Edit::Insert(_)) {
+            while let Some(line) = new.next_if(|line| !in_old.contains(*line)) {
+                changes.push(Edit::Insert(line));
+            }
+            new.next();
+        }
+        if matches!(edit, Edit::Keep(_)) {
+            flush(&mut changes, &mut edits);
+            edits.push(edit);
+        } else {
+            changes.push(edit);
+        }
+    }
+    changes.extend(old.map(|line| Edit::Delete(line)));
+    changes.extend(new.map(|line| Edit::Insert(line)));
+    flush(&mut changes, &mut edits);
+    edits
+}
+
+// append a run of changes, deletions first
+fn flush<'a>(changes: &mut Vec<Edit<'a>>, edits: &mut Vec<Edit<'a>>) {
+    let (deleted, inserted): (Vec<_>, Vec<_>) = changes
+        .drain(..)
+        .partition(|edit| matches!(edit, Edit::Delete(_)));
+    edits.extend(deleted);
+    edits.extend(inserted);
+}
+
+// the lines removed (-) and added (+) from a config to another
+pub(crate) fn diff_configs(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let mut out = String::new();
+    for edit in diff(&old, &new) {
+        let (sign, line) = match edit {
+            Edit::Keep(_) => continue,
+            Edit::Insert(line) => ('+', line),
+            Edit::Delete(line) => ('-', line),
+        };
+        out.push(sign);
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the length of the longest common subsequence of two sequences of lines
+    fn lcs(old: &[&str], new: &[&str]) -> usize {
+        let mut row = vec![0usize; new.len() + 1];
+        for a in old {
+            let mut diagonal = 0;
+            for (j, b) in new.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if a == b {
+                    diagonal + 1
+                } else {
+                    above.max(row[j])
+                };
+                diagonal = above;
+            }
+        }
+        row[new.len()]
+    }
+
+    // check that the script turns old into new, with as few edits as possible
+    fn check<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Edit<'a>> {
+        let edits = diff(old, new);
+        let from: Vec<&str> = edits
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Keep(line) | Edit::Delete(line) => Some(*line),
+                Edit::Insert(_) => None,
+            })
+            .collect();
+        let to: Vec<&str> = edits
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Keep(line) | Edit::Insert(line) => Some(*line),
+                Edit::Delete(_) => None,
+            })
+            .collect();
+        assert_eq!(from, old);
+        assert_eq!(to, new);
+        let kept = edits
+            .iter()
+            .filter(|edit| matches!(edit, Edit::Keep(_)))
+            .count();
+        assert_eq!(kept, lcs(old, new), "{old:?} -> {new:?}");
+        edits
+    }
+
+    #[test]
+    fn empty() {
+        assert!(check(&[], &[]).is_empty());
+        assert_eq!(check(&[], &["a"]), vec![Edit::Insert("a")]);
+        assert_eq!(check(&["a"], &[]), vec![Edit::Delete("a")]);
+        assert_eq!(diff_configs("", ""), "");
+    }
+
+    #[test]
+    fn identical() {
+        let lines = [
+            "router bgp 65001",
+            " neighbor 10.0.0.1 remote-as 65002",
+            "!",
+        ];
+        let edits = check(&lines, &lines);
+        assert!(edits.iter().all(|edit| matches!(edit, Edit::Keep(_))));
+        assert_eq!(diff_configs("a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn disjoint() {
+        let edits = check(&["a", "b", "c"], &["x", "y"]);
+        assert_eq!(edits.len(), 5);
+        assert!(!edits.iter().any(|edit| matches!(edit, Edit::Keep(_))));
+        assert_eq!(diff_configs("a\nb\n", "x\n"), "-a\n-b\n+x\n");
+    }
+
+    #[test]
+    fn interleaved() {
+        check(
+            &["a", "b", "c", "a", "b", "b", "a"],
+            &["c", "b", "a", "b", "a", "c"],
+        );
+        check(&["a", "x", "b", "x", "c"], &["x", "a", "b", "c", "x"]);
+        check(&["a", "b"], &["b", "a"]);
+        assert_eq!(
+            diff_configs(
+                "hostname r1\nline 1\nline 2\n",
+                "hostname r2\nline 1\nline 3\n"
+            ),
+            "-hostname r1\n+hostname r2\n-line 2\n+line 3\n"
+        );
+    }
+
+    #[test]
+    fn all_small_inputs() {
+        /* every pair of sequences of up to 6 lines out of 2 distinct ones */
+        let sequences: Vec<Vec<&str>> = (0..=6u32)
+            .flat_map(|len| {
+                (0..1u32 << len).map(move |bits| {
+                    (0..len)
+                        .map(|i| if bits >> i & 1 == 1 { "a" } else { "b" })
+                        .collect()
+                })
+            })
+            .collect();
+        for old in &sequences {
+            for new in &sequences {
+                check(old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn large_unrelated_configs() {
+        let old: Vec<String> = (0..20_000).map(|i| format!("old {i}")).collect();
+        let new: Vec<String> = (0..20_000).map(|i| format!("new {i}")).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+        assert_eq!(diff(&old, &new).len(), 40_000);
+    }
+}
@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Dual-write: every generation applied is forwarded to secondary destinations, so that a live
+// standby router (another agent, at its socket) or an offline validation farm (a directory) stays
+// in sync automatically. Generations are forwarded in order by a background worker, so that
+// secondaries never delay the responses; failures are logged.
+
+use std::fmt::Display;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::framing::Framing;
+use crate::rawtext;
+use crate::reload::write_config_file;
+use crate::{GenId, send_response};
+
+enum Secondary {
+    Agent {
+        sock_path: PathBuf,
+        stream: Option<UnixStream>,
+    },
+    Dir(String),
+}
+
+impl Display for Secondary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Secondary::Agent { sock_path, .. } => write!(f, "agent at {}", sock_path.display()),
+            Secondary::Dir(dir) => write!(f, "directory {dir}"),
+        }
+    }
+}
+
+impl Secondary {
+    // push a config to an agent, reconnecting if needed
+    fn push(
+        sock_path: &PathBuf,
+        stream: &mut Option<UnixStream>,
+        genid: GenId,
+        config: &str,
+    ) -> Result<(), String> {
+        if stream.is_none() {
+            let connected = UnixStream::connect(sock_path)
+                .map_err(|e| format!("Could not connect to {}: {e}", sock_path.display()))?;
+            *stream = Some(connected);
+        }
+        let Some(sock) = stream.as_mut() else {
+            return Err("Not connected".to_string());
+        };
+        let response = send_response(sock, genid, &rawtext::encode(config))
+            .and_then(|()| Framing::V1.receive(sock));
+        match response {
+            Ok((_, response)) if response.starts_with("Ok") => Ok(()),
+            Ok((_, response)) => Err(response),
+            Err(e) => {
+                *stream = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn forward(&mut self, genid: GenId, config: &str) -> Result<(), String> {
+        match self {
+            Secondary::Agent { sock_path, stream } => Self::push(sock_path, stream, genid, config),
+            Secondary::Dir(dir) => write_config_file(genid, config, dir)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+// forwards the generations applied to the secondaries configured
+#[derive(Default)]
+pub(crate) struct DualWriter {
+    queue: Option<Sender<(GenId, String)>>,
+}
+
+impl DualWriter {
+    pub(crate) fn new(sock_path: Option<&str>, dir: Option<&str>) -> Self {
+        let mut secondaries = vec![];
+        if let Some(sock_path) = sock_path {
+            secondaries.push(Secondary::Agent {
+                sock_path: PathBuf::from(sock_path),
+                stream: None,
+            });
+        }
+        if let Some(dir) = dir {
+            secondaries.push(Secondary::Dir(dir.to_string()));
+        }
+        if secondaries.is_empty() {
+            return Self::default();
+        }
+        let (queue, generations) = channel::<(GenId, String)>();
+        thread::spawn(move || {
+            for (genid, config) in generations {
+                for secondary in &mut secondaries {
+                    match secondary.forward(genid, &config) {
+                        Ok(()) => debug!("Forwarded generation {genid} to {secondary}"),
+                        Err(e) => warn!("Failed to forward generation {genid} to {secondary}: {e}"),
+                    }
+                }
+            }
+        });
+        Self { queue: Some(queue) }
+    }
+
+    // forward a generation applied to the secondaries, in the background
+    pub(crate) fn forward(&self, genid: GenId, config: String) {
+        if let Some(queue) = &self.queue
+            && queue.send((genid, config)).is_err()
+        {
+            warn!("Failed to forward generation {genid}: dual-write worker is gone");
+        }
+    }
+}
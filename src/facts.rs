@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Node facts for config templating, so that a controller can send one generic template to every
+// node. With templating enabled, the placeholders like {{ name }} in configs are replaced with the
+// facts of the node before they are processed. The facts are gathered when a config is received:
+//   * hostname: the hostname of the node,
+//   * mac.<interface>: the MAC address of each interface,
+//   * ipv4.<interface> and ipv6.<interface>: the first address of each interface (link-local
+//     IPv6 addresses are skipped),
+//   * loopback.ipv4 and loopback.ipv6: the first address of lo that is not a loopback address,
+// along with the facts in the facts file (a flat JSON object like {"asn": 65001}), which take
+// precedence.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::json::Json;
+
+// the addresses of the interfaces of the node, in the order reported by getifaddrs
+#[allow(unsafe_code)]
+fn addresses() -> Vec<(String, IpAddr)> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: ifaddrs is valid for writes. The list is freed below.
+    if unsafe { libc::getifaddrs(&raw mut ifaddrs) } != 0 {
+        warn!(
+            "Could not get the interface addresses: {}",
+            std::io::Error::last_os_error()
+        );
+        return vec![];
+    }
+    let mut addresses = vec![];
+    let mut cursor = ifaddrs;
+    while !cursor.is_null() {
+        // SAFETY: cursor is a node of the list returned by getifaddrs, which is not freed yet
+        let ifa = unsafe { &*cursor };
+        cursor = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || ifa.ifa_name.is_null() {
+            continue;
+        }
+        // SAFETY: ifa_name is a NUL-terminated string and ifa_addr a socket address, of the
+        // size of the address family it holds
+        let (name, address) = unsafe {
+            let name = CStr::from_ptr(ifa.ifa_name).to_string_lossy().into_owned();
+            let address = match i32::from((*ifa.ifa_addr).sa_family) {
+                libc::AF_INET => {
+                    let sin = std::ptr::read_unaligned(ifa.ifa_addr.cast::<libc::sockaddr_in>());
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = std::ptr::read_unaligned(ifa.ifa_addr.cast::<libc::sockaddr_in6>());
+                    IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+                }
+                _ => continue,
+            };
+            (name, address)
+        };
+        addresses.push((name, address));
+    }
+    // SAFETY: ifaddrs was returned by getifaddrs and is not used afterwards
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    addresses
+}
+
+// the facts in a facts file
+fn file_facts(path: &str) -> Result<BTreeMap<String, String>, String> {
+    let facts = std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    let Ok(Json::Object(facts)) = Json::parse(&facts) else {
+        return Err(format!("{path} is not a JSON object"));
+    };
+    facts
+        .into_iter()
+        .map(|(name, value)| match value {
+            Json::String(value) => Ok((name, value)),
+            Json::Number(_) | Json::Bool(_) => Ok((name, value.to_string())),
+            _ => Err(format!("{path}: fact '{name}' is not a scalar")),
+        })
+        .collect()
+}
+
+// gather the facts of the node
+pub(crate) fn gather(facts_file: Option<&str>) -> Result<BTreeMap<String, String>, String> {
+    let mut facts = BTreeMap::new();
+    if let Ok(hostname) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        facts.insert("hostname".to_string(), hostname.trim().to_string());
+    }
+    if let Ok(interfaces) = std::fs::read_dir("/sys/class/net") {
+        for interface in interfaces.flatten() {
+            let name = interface.file_name().to_string_lossy().into_owned();
+            if let Ok(mac) = std::fs::read_to_string(interface.path().join("address")) {
+                facts.insert(format!("mac.{name}"), mac.trim().to_string());
+            }
+        }
+    }
+    for (name, address) in addresses() {
+        let (family, skipped) = match address {
+            IpAddr::V4(_) => ("ipv4", false),
+            IpAddr::V6(v6) => ("ipv6", v6.is_unicast_link_local()),
+        };
+        if skipped {
+            continue;
+        }
+        facts
+            .entry(format!("{family}.{name}"))
+            .or_insert_with(|| address.to_string());
+        if name == "lo" && !address.is_loopback() {
+            facts
+                .entry(format!("loopback.{family}"))
+                .or_insert_with(|| address.to_string());
+        }
+    }
+    if let Some(path) = facts_file {
+        facts.extend(file_facts(path)?);
+    }
+    Ok(facts)
+}
+
+// render a template, replacing the placeholders like {{ name }} with the facts
+pub(crate) fn render(template: &str, facts: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            return Err("Unterminated template placeholder".to_string());
+        };
+        let name = rest[start + 2..start + end].trim();
+        let fact = facts
+            .get(name)
+            .ok_or(format!("Unknown template variable '{name}'"))?;
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(fact);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
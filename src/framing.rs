@@ -0,0 +1,662 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Framing of the messages exchanged with clients. With the original framing (v1), a message is
+// |length|genid|data| (integers in native byte order), so that once a length turns out to be
+// garbage, the stream can not be recovered and the client must reconnect. With the v2 framing,
+// every message starts with a magic (0xf7 'F' 'R' '2') and integers are big-endian:
+// |magic|length|genid|data|. When a message does not start with the magic or claims an absurd
+// length, the agent scans the stream for the next magic and carries on from there. The v3 framing
+// adds a checksum of the data to v2 (with the magic 0xf7 'F' 'R' '3'): |magic|length|genid|sha256|data|,
+// so that corrupted messages are told apart rather than processed; the connection is closed on a
+// checksum mismatch. The v4 framing adds flags to v3 (with the magic 0xf7 'F' 'R' '4'):
+// |magic|flags|length|genid|sha256|data|, the flag 0x01 telling that the data is compressed with
+// zstd (the length and checksum being those of the data as sent), and a message with unknown flags
+// being garbage. Messages are decompressed as they are received, into at most the maximum message
+// size; those the agent sends are never compressed. Clients opt in to v2, v3 or v4 by sending such
+// messages: the framing of a connection is that of its first message, unless a framing is
+// negotiated with HELLO ("framing=4,3,2,1": the first of those offered that the agent supports is
+// used by both ends once the response to the HELLO is sent). Clients learn whether the agent
+// decompresses zstd (see the zstd feature) with HELLO too ("compression=zstd").
+
+use std::io::{Cursor, Read};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::json::Json;
+use crate::rawtext;
+use crate::sha256::sha256;
+use crate::{GenId, receive_request, send_response, write_wire};
+
+pub(crate) const FRAME_MAGIC: [u8; 4] = [0xf7, b'F', b'R', b'2'];
+pub(crate) const FRAME_MAGIC_V3: [u8; 4] = [0xf7, b'F', b'R', b'3'];
+pub(crate) const FRAME_MAGIC_V4: [u8; 4] = [0xf7, b'F', b'R', b'4'];
+
+// flags of v4 messages
+pub(crate) const FLAG_ZSTD: u8 = 0x01; /* the data is compressed with zstd */
+
+// messages claiming to be longer than this are garbage
+const MAX_FRAME_LEN: u64 = 1 << 30;
+
+// messages longer than this are refused before their data is allocated (see --max-message-size)
+static MAX_MESSAGE_SIZE: AtomicU64 = AtomicU64::new(64 << 20);
+
+// set the size messages may have, once at startup
+pub(crate) fn set_max_message_size(size: u64) {
+    MAX_MESSAGE_SIZE.store(size, Ordering::Relaxed);
+}
+
+// the size messages may have, in octets
+pub(crate) fn max_message_size() -> u64 {
+    MAX_MESSAGE_SIZE.load(Ordering::Relaxed)
+}
+
+// the length of a message, if it may be received
+pub(crate) fn message_size(length: u64, max: u64) -> Result<usize, String> {
+    usize::try_from(length)
+        .ok()
+        .filter(|_| length <= max)
+        .ok_or(format!(
+            "Message of {length} octets exceeds the maximum message size ({max} octets)"
+        ))
+}
+
+// resynchronizations of v2, v3 and v4 streams, the octets skipped doing so, and the v3 and v4
+// messages whose checksum did not match
+pub(crate) struct FramingStats {
+    pub(crate) resyncs: AtomicU64,
+    pub(crate) skipped: AtomicU64,
+    pub(crate) checksum_errors: AtomicU64,
+}
+pub(crate) static FRAMING_STATS: FramingStats = FramingStats {
+    resyncs: AtomicU64::new(0),
+    skipped: AtomicU64::new(0),
+    checksum_errors: AtomicU64::new(0),
+};
+impl FramingStats {
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            ("resyncs", self.resyncs.load(Ordering::Relaxed).into()),
+            ("skipped", self.skipped.load(Ordering::Relaxed).into()),
+            (
+                "checksum_errors",
+                self.checksum_errors.load(Ordering::Relaxed).into(),
+            ),
+        ])
+    }
+}
+
+// what a client learns when the agent does not decompress zstd
+#[cfg(not(feature = "zstd"))]
+const ZSTD_UNSUPPORTED: &str = "zstd compression is not supported by this agent";
+
+// the compression of the messages a client sends, negotiated with HELLO
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Zstd,
+}
+
+impl Compression {
+    // the compression of a name, if the agent supports it
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "none" => Some(Compression::None),
+            "zstd" if cfg!(feature = "zstd") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // the compression agreed for those offered by a client (e.g. "zstd,none"), in its order of
+    // preference
+    pub(crate) fn negotiate(offered: &str) -> Option<Self> {
+        offered.split(',').find_map(Compression::parse)
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    V1,
+    V2,
+    V3,
+    V4,
+}
+
+impl Framing {
+    // the framing of a connection, from the first octets received
+    #[must_use]
+    pub fn detect(first: &[u8]) -> Self {
+        if first.starts_with(&FRAME_MAGIC) {
+            Framing::V2
+        } else if first.starts_with(&FRAME_MAGIC_V3) {
+            Framing::V3
+        } else if first.starts_with(&FRAME_MAGIC_V4) {
+            Framing::V4
+        } else {
+            Framing::V1
+        }
+    }
+
+    // the framing of a version (1, 2, 3 or 4)
+    #[must_use]
+    pub fn parse(version: &str) -> Option<Self> {
+        match version.trim() {
+            "1" => Some(Framing::V1),
+            "2" => Some(Framing::V2),
+            "3" => Some(Framing::V3),
+            "4" => Some(Framing::V4),
+            _ => None,
+        }
+    }
+
+    // the framing agreed for those offered by a client (e.g. "4,3,2,1"), in its order of preference
+    pub(crate) fn negotiate(offered: &str) -> Option<Self> {
+        offered.split(',').find_map(Framing::parse)
+    }
+
+    #[must_use]
+    pub fn version(self) -> u8 {
+        match self {
+            Framing::V1 => 1,
+            Framing::V2 => 2,
+            Framing::V3 => 3,
+            Framing::V4 => 4,
+        }
+    }
+
+    // the magic v2, v3 and v4 messages start with
+    fn magic(self) -> [u8; 4] {
+        match self {
+            Framing::V1 | Framing::V2 => FRAME_MAGIC,
+            Framing::V3 => FRAME_MAGIC_V3,
+            Framing::V4 => FRAME_MAGIC_V4,
+        }
+    }
+
+    // whether messages have a checksum
+    fn checked(self) -> bool {
+        matches!(self, Framing::V3 | Framing::V4)
+    }
+
+    // whether messages have flags
+    fn flagged(self) -> bool {
+        self == Framing::V4
+    }
+
+    // receive a message
+    pub fn receive(self, sock: &mut impl Read) -> Result<(GenId, String), String> {
+        self.receive_within(sock, max_message_size())
+    }
+
+    // receive a message of at most max octets
+    pub(crate) fn receive_within(
+        self,
+        sock: &mut impl Read,
+        max: u64,
+    ) -> Result<(GenId, String), String> {
+        match self {
+            Framing::V1 => receive_request(sock, max),
+            framing => receive_framed(sock, framing, max),
+        }
+    }
+
+    // send a message
+    pub fn send(self, sock: &mut UnixStream, genid: GenId, msg: &[u8]) -> Result<(), String> {
+        match self {
+            Framing::V1 => send_response(sock, genid, msg),
+            framing => send_framed(sock, genid, msg, framing),
+        }
+    }
+}
+
+// skip octets until the last 4 read are the magic
+fn resync(sock: &mut impl Read, window: &mut [u8; 4], magic: [u8; 4]) -> Result<(), String> {
+    let mut skipped = 0u64;
+    while *window != magic {
+        let mut octet = [0u8; 1];
+        sock.read_exact(&mut octet).map_err(|e| {
+            format!("Could not resynchronize stream ({skipped} octets skipped): {e}")
+        })?;
+        window.rotate_left(1);
+        window[3] = octet[0];
+        skipped += 1;
+    }
+    warn!("Resynchronized stream after skipping {skipped} octets");
+    FRAMING_STATS.resyncs.fetch_add(1, Ordering::Relaxed);
+    FRAMING_STATS.skipped.fetch_add(skipped, Ordering::Relaxed);
+    Ok(())
+}
+
+// receive a v2, v3 or v4 message of at most max octets (once decompressed)
+fn receive_framed(
+    sock: &mut impl Read,
+    framing: Framing,
+    max: u64,
+) -> Result<(GenId, String), String> {
+    debug!("━━━━━━ Waiting for data ━━━━━━");
+
+    /* the header of a garbage message is scanned for the magic of the next one */
+    let mut stream = Cursor::new(vec![]).chain(sock);
+    let expected = framing.magic();
+    let mut magic = [0u8; 4];
+    stream
+        .read_exact(&mut magic)
+        .map_err(|e| format!("Could not receive magic: {e}"))?;
+    loop {
+        if magic != expected {
+            warn!("Message does not start with the magic. Resynchronizing...");
+            resync(&mut stream, &mut magic, expected)?;
+        }
+        /* |flags (v4)|length|genid| */
+        let mut header = [0u8; 17];
+        let header = &mut header[usize::from(!framing.flagged())..];
+        stream
+            .read_exact(header)
+            .map_err(|e| format!("Could not receive header: {e}"))?;
+        let (flags, fields) = match header.split_first() {
+            Some((flags, fields)) if framing.flagged() => (*flags, fields),
+            _ => (0, &header[..]),
+        };
+        let (len_buf, genid_buf) = fields.split_at(8);
+        let msg_size = u64::from_be_bytes(len_buf.try_into().unwrap_or_default());
+        let genid = i64::from_be_bytes(genid_buf.try_into().unwrap_or_default());
+        if msg_size > MAX_FRAME_LEN || flags & !FLAG_ZSTD != 0 {
+            warn!(
+                "Message claims a length of {msg_size} octets (flags {flags:#04x}). Resynchronizing..."
+            );
+            let (_, sock) = stream.into_inner();
+            stream = Cursor::new(header.to_vec()).chain(sock);
+            magic = [0u8; 4];
+            continue;
+        }
+        /* a plausible length that is too long is refused, before anything is allocated */
+        let msg_size = message_size(msg_size, max)?;
+        let mut checksum = [0u8; 32];
+        if framing.checked() {
+            stream
+                .read_exact(&mut checksum)
+                .map_err(|e| format!("Could not receive checksum: {e}"))?;
+        }
+        let mut rx_buff = vec![0u8; msg_size];
+        stream
+            .read_exact(&mut rx_buff)
+            .map_err(|e| format!("Could not receive request body: {e}"))?;
+        if framing.checked() && sha256(&rx_buff) != checksum {
+            FRAMING_STATS
+                .checksum_errors
+                .fetch_add(1, Ordering::Relaxed);
+            return Err(format!(
+                "Checksum mismatch on message of {msg_size} octets (genid {genid})"
+            ));
+        }
+        if flags & FLAG_ZSTD != 0 {
+            rx_buff = decompress(&rx_buff, max)
+                .map_err(|e| format!("Could not decompress message (genid {genid}): {e}"))?;
+            debug!("Decompressed {msg_size} octets into {}", rx_buff.len());
+        }
+        let request = rawtext::decode(rx_buff);
+        debug!("Successfully received request. data-len: {msg_size} octets genid:{genid}");
+        return Ok((genid, request));
+    }
+}
+
+// decompress the data of a message, into at most max octets
+#[cfg(feature = "zstd")]
+fn decompress(data: &[u8], max: u64) -> Result<Vec<u8>, String> {
+    let mut source = data;
+    let mut decompressed = vec![];
+    /* the data may hold several zstd frames */
+    while !source.is_empty() {
+        let decoder = ruzstd::decoding::StreamingDecoder::new(&mut source)
+            .map_err(|e| format!("Bad zstd frame: {e}"))?;
+        let left = (max + 1).saturating_sub(decompressed.len() as u64);
+        decoder
+            .take(left)
+            .read_to_end(&mut decompressed)
+            .map_err(|e| format!("Bad zstd data: {e}"))?;
+        if decompressed.len() as u64 > max {
+            return Err(format!(
+                "Message exceeds the maximum message size ({max} octets) once decompressed"
+            ));
+        }
+    }
+    Ok(decompressed)
+}
+
+// decompress the data of a message: not supported without the zstd feature
+#[cfg(not(feature = "zstd"))]
+fn decompress(_data: &[u8], _max: u64) -> Result<Vec<u8>, String> {
+    Err(ZSTD_UNSUPPORTED.to_string())
+}
+
+// send a v2, v3 or v4 message, never compressed
+fn send_framed(
+    sock: &mut UnixStream,
+    genid: GenId,
+    msg: &[u8],
+    framing: Framing,
+) -> Result<(), String> {
+    let length = msg.len() as u64;
+
+    /* assemble wire message: |magic|length|genid|data|, with the flags before the length (v4)
+     * and the checksum before the data (v3 and v4) */
+    let mut wire_msg = Vec::with_capacity(msg.len() + 53);
+    wire_msg.extend_from_slice(&framing.magic());
+    if framing.flagged() {
+        wire_msg.push(0);
+    }
+    wire_msg.extend_from_slice(&length.to_be_bytes());
+    wire_msg.extend_from_slice(&genid.to_be_bytes());
+    if framing.checked() {
+        wire_msg.extend_from_slice(&sha256(msg));
+    }
+    wire_msg.extend_from_slice(msg);
+
+    write_wire(sock, &wire_msg)?;
+    debug!("Successfully sent msg. data-len: {length} genid: {genid}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v1(genid: GenId, data: &[u8]) -> Vec<u8> {
+        let mut frame = (data.len() as u64).to_ne_bytes().to_vec();
+        frame.extend_from_slice(&genid.to_ne_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn framed(framing: Framing, flags: u8, length: u64, genid: GenId, data: &[u8]) -> Vec<u8> {
+        let mut frame = framing.magic().to_vec();
+        if framing.flagged() {
+            frame.push(flags);
+        }
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&genid.to_be_bytes());
+        if framing.checked() {
+            frame.extend_from_slice(&sha256(data));
+        }
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn v2(genid: GenId, data: &[u8]) -> Vec<u8> {
+        framed(Framing::V2, 0, data.len() as u64, genid, data)
+    }
+
+    fn v3(genid: GenId, data: &[u8]) -> Vec<u8> {
+        framed(Framing::V3, 0, data.len() as u64, genid, data)
+    }
+
+    fn v4(genid: GenId, data: &[u8]) -> Vec<u8> {
+        framed(Framing::V4, 0, data.len() as u64, genid, data)
+    }
+
+    #[cfg(feature = "zstd")]
+    fn v4_zstd(genid: GenId, data: &[u8]) -> Vec<u8> {
+        let compressed =
+            ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest);
+        framed(
+            Framing::V4,
+            FLAG_ZSTD,
+            compressed.len() as u64,
+            genid,
+            &compressed,
+        )
+    }
+
+    fn receive_all(
+        framing: Framing,
+        wire: Vec<u8>,
+        max: u64,
+    ) -> Vec<Result<(GenId, String), String>> {
+        let mut sock = Cursor::new(wire);
+        let mut received = vec![];
+        while sock.position() < sock.get_ref().len() as u64 {
+            let message = framing.receive_within(&mut sock, max);
+            let failed = message.is_err();
+            received.push(message);
+            if failed {
+                break;
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn detect_and_negotiate() {
+        assert_eq!(Framing::detect(&v1(1, b"x")), Framing::V1);
+        assert_eq!(Framing::detect(&v2(1, b"x")), Framing::V2);
+        assert_eq!(Framing::detect(&v3(1, b"x")), Framing::V3);
+        assert_eq!(Framing::detect(&v4(1, b"x")), Framing::V4);
+        assert_eq!(Framing::negotiate("5, 3,2"), Some(Framing::V3));
+        assert_eq!(Framing::negotiate("5,x"), None);
+    }
+
+    #[test]
+    fn messages_are_received() {
+        for (framing, frame) in [
+            (Framing::V1, v1 as fn(GenId, &[u8]) -> Vec<u8>),
+            (Framing::V2, v2),
+            (Framing::V3, v3),
+            (Framing::V4, v4),
+        ] {
+            let mut wire = frame(7, b"router bgp 65001\n");
+            wire.extend(frame(-1, b""));
+            wire.extend(frame(8, b"caf\xe9"));
+            let received = receive_all(framing, wire, 1024);
+            assert_eq!(received.len(), 3, "{framing:?}");
+            assert_eq!(received[0], Ok((7, "router bgp 65001\n".to_string())));
+            assert_eq!(received[1], Ok((-1, String::new())));
+            let (genid, data) = received[2].clone().unwrap_or_default();
+            assert_eq!(genid, 8);
+            assert_eq!(rawtext::encode(&data).as_ref(), b"caf\xe9");
+        }
+    }
+
+    #[test]
+    fn truncated_messages_fail() {
+        for wire in [
+            v1(1, b"config"),
+            v2(1, b"config"),
+            v3(1, b"config"),
+            v4(1, b"config"),
+        ] {
+            let framing = Framing::detect(&wire);
+            for len in 0..wire.len() {
+                let received = framing.receive_within(&mut Cursor::new(&wire[..len]), 1024);
+                assert!(received.is_err(), "{framing:?} truncated at {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn long_messages_are_refused() {
+        for (framing, wire) in [
+            (Framing::V1, v1(1, &[b'x'; 11])),
+            (Framing::V2, v2(1, &[b'x'; 11])),
+            (Framing::V3, v3(1, &[b'x'; 11])),
+            (Framing::V4, v4(1, &[b'x'; 11])),
+        ] {
+            let received = framing.receive_within(&mut Cursor::new(&wire), 10);
+            assert!(
+                received.is_err_and(|e| e.contains("exceeds the maximum message size")),
+                "{framing:?}"
+            );
+            assert!(framing.receive_within(&mut Cursor::new(&wire), 11).is_ok());
+        }
+        /* a v1 length that could never be allocated is refused rather than attempted */
+        let mut wire = u64::MAX.to_ne_bytes().to_vec();
+        wire.extend_from_slice(&1i64.to_ne_bytes());
+        assert!(
+            Framing::V1
+                .receive_within(&mut Cursor::new(wire), 1024)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn garbage_before_magic_is_skipped() {
+        for (framing, frame) in [
+            (Framing::V2, v2 as fn(GenId, &[u8]) -> Vec<u8>),
+            (Framing::V3, v3),
+            (Framing::V4, v4),
+        ] {
+            let mut wire = frame(1, b"first");
+            wire.extend_from_slice(b"\0\x01garbage\xf7F");
+            wire.extend(frame(2, b"second"));
+            let received = receive_all(framing, wire, 1024);
+            assert_eq!(
+                received,
+                [Ok((1, "first".to_string())), Ok((2, "second".to_string()))],
+                "{framing:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn absurd_lengths_resync() {
+        /* the header of a message claiming an absurd length is scanned for the next magic */
+        let mut wire = framed(Framing::V2, 0, MAX_FRAME_LEN + 1, 1, b"");
+        wire.extend(v2(2, b"next"));
+        assert_eq!(
+            receive_all(Framing::V2, wire, u64::MAX),
+            [Ok((2, "next".to_string()))]
+        );
+        /* even when the next magic is in that header */
+        let mut wire = FRAME_MAGIC.to_vec();
+        wire.extend_from_slice(&[0xff; 4]);
+        let next = v2(3, b"in header");
+        wire.extend_from_slice(&next);
+        assert_eq!(
+            receive_all(Framing::V2, wire, u64::MAX),
+            [Ok((3, "in header".to_string()))]
+        );
+    }
+
+    #[test]
+    fn resync_gives_up_at_end_of_stream() {
+        let wire = b"no magic in there".to_vec();
+        assert!(
+            Framing::V2
+                .receive_within(&mut Cursor::new(wire), 1024)
+                .is_err_and(|e| e.starts_with("Could not resynchronize"))
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch_fails() {
+        let mut wire = v3(1, b"config");
+        if let Some(last) = wire.last_mut() {
+            *last ^= 1;
+        }
+        assert!(
+            Framing::V3
+                .receive_within(&mut Cursor::new(wire), 1024)
+                .is_err_and(|e| e.starts_with("Checksum mismatch"))
+        );
+    }
+
+    #[test]
+    fn v2_and_v3_magics_are_not_mixed() {
+        /* a v3 message on a v2 stream is garbage, and the other way around */
+        let mut wire = v3(1, b"v3");
+        wire.extend(v2(2, b"v2"));
+        assert_eq!(
+            receive_all(Framing::V2, wire, 1024),
+            [Ok((2, "v2".to_string()))]
+        );
+    }
+
+    #[test]
+    fn unknown_flags_resync() {
+        let mut wire = framed(Framing::V4, 0x80, 5, 1, b"bogus");
+        wire.extend(v4(2, b"next"));
+        assert_eq!(
+            receive_all(Framing::V4, wire, 1024),
+            [Ok((2, "next".to_string()))]
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_messages_are_decompressed() {
+        let config = "router bgp 65001\n neighbor 10.0.0.1 remote-as 65002\n".repeat(100);
+        let mut wire = v4_zstd(1, config.as_bytes());
+        assert!(wire.len() < config.len());
+        wire.extend(v4(2, b"plain"));
+        wire.extend(v4_zstd(3, b""));
+        /* several zstd frames in a message */
+        let mut frames = ruzstd::encoding::compress_to_vec(
+            &b"a"[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        );
+        frames.extend(ruzstd::encoding::compress_to_vec(
+            &b"b"[..],
+            ruzstd::encoding::CompressionLevel::Uncompressed,
+        ));
+        wire.extend(framed(
+            Framing::V4,
+            FLAG_ZSTD,
+            frames.len() as u64,
+            4,
+            &frames,
+        ));
+        assert_eq!(
+            receive_all(Framing::V4, wire, 1 << 20),
+            [
+                Ok((1, config)),
+                Ok((2, "plain".to_string())),
+                Ok((3, String::new())),
+                Ok((4, "ab".to_string())),
+            ]
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_messages_are_bounded_once_decompressed() {
+        let wire = v4_zstd(1, &[b'x'; 4096]);
+        assert!(wire.len() < 1024);
+        assert!(
+            Framing::V4
+                .receive_within(&mut Cursor::new(&wire), 1024)
+                .is_err_and(|e| e.contains("once decompressed"))
+        );
+        assert!(
+            Framing::V4
+                .receive_within(&mut Cursor::new(&wire), 4096)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn bad_compressed_data_fails() {
+        let wire = framed(Framing::V4, FLAG_ZSTD, 6, 1, b"config");
+        assert!(
+            Framing::V4
+                .receive_within(&mut Cursor::new(wire), 1024)
+                .is_err_and(|e| e.starts_with("Could not decompress"))
+        );
+    }
+
+    #[test]
+    fn compression_negotiation() {
+        assert_eq!(Compression::negotiate("lz4,none"), Some(Compression::None));
+        assert_eq!(Compression::negotiate("lz4"), None);
+        let zstd = Compression::negotiate("zstd");
+        assert_eq!(zstd == Some(Compression::Zstd), cfg!(feature = "zstd"));
+    }
+}
@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Handling of the requests applying configs while FRR is down, as found by probing it before
+// applying. Depending on the policy, they are rejected or held, to be applied in order once FRR
+// is reachable again. While requests are held, any other request applying a config is held too,
+// so that generations are never applied out of order.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::peer::PeerCred;
+use crate::request::Request;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FrrDownPolicy {
+    Reject,
+    Hold,
+}
+impl FrrDownPolicy {
+    pub(crate) fn parse(policy: &str) -> Option<Self> {
+        match policy.trim() {
+            "reject" => Some(FrrDownPolicy::Reject),
+            "hold" => Some(FrrDownPolicy::Hold),
+            _ => None,
+        }
+    }
+}
+
+struct HeldRequest {
+    genid: GenId,
+    request: Request,
+    cred: Option<PeerCred>,
+    since: u64,
+}
+
+// the requests held until FRR is reachable, in the order received
+#[derive(Default)]
+pub(crate) struct Held {
+    queue: Mutex<VecDeque<HeldRequest>>,
+}
+
+impl Held {
+    // hold a request if others are held or if FRR is down (as told by up), returning the
+    // response to it if so
+    pub(crate) fn hold(
+        &self,
+        genid: GenId,
+        request: &Request,
+        cred: Option<PeerCred>,
+        up: impl FnOnce() -> Result<(), String>,
+    ) -> Option<String> {
+        let Ok(mut queue) = self.queue.lock() else {
+            return None;
+        };
+        let reason = match queue.front() {
+            Some(first) => format!("generation {} is held before it", first.genid),
+            None => up().err()?,
+        };
+        warn!(
+            "Holding {} until FRR is reachable: {reason}",
+            request.describe(genid)
+        );
+        queue.push_back(HeldRequest {
+            genid,
+            request: request.clone(),
+            cred,
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+        Some(format!(
+            "HELD\ngeneration {genid} will be applied once FRR is reachable"
+        ))
+    }
+
+    // whether no request is held
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.lock().map_or(true, |queue| queue.is_empty())
+    }
+
+    // process the requests held, in order, as long as FRR is up (as told by up). No request is
+    // held meanwhile.
+    pub(crate) fn release(
+        &self,
+        up: impl Fn() -> Result<(), String>,
+        mut process: impl FnMut(GenId, &Request, Option<PeerCred>),
+    ) {
+        let Ok(mut queue) = self.queue.lock() else {
+            return;
+        };
+        while !queue.is_empty() {
+            if let Err(e) = up() {
+                debug!("Not releasing {} held requests: {e}", queue.len());
+                return;
+            }
+            if let Some(held) = queue.pop_front() {
+                process(held.genid, &held.request, held.cred);
+            }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        let Ok(queue) = self.queue.lock() else {
+            return Json::Null;
+        };
+        let held = queue.iter().map(|held| {
+            Json::object([
+                ("genid", held.genid.into()),
+                ("request", held.request.describe(held.genid).into()),
+                (
+                    "peer",
+                    held.cred
+                        .map_or("unknown".to_string(), |cred| cred.to_string())
+                        .into(),
+                ),
+                ("since", held.since.into()),
+            ])
+        });
+        Json::Array(held.collect())
+    }
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Probing of FRR. FRR is considered up when the vty sockets of its daemons (at least zebra's)
+// accept connections and vtysh can run a command against them, so that the agent can wait for
+// FRR at startup rather than racing with it.
+
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::vtysh::vtysh;
+
+// time between probes while waiting for FRR
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// check that FRR is up, telling why not otherwise
+pub fn probe(args: &Args) -> Result<(), String> {
+    let vtydir = Path::new(args.vtysock.as_deref().unwrap_or(args.rundir()));
+    let zebra = vtydir.join("zebra.vty");
+    if !zebra.exists() {
+        return Err(format!("{} does not exist", zebra.display()));
+    }
+    let sockets = fs::read_dir(vtydir)
+        .map_err(|e| format!("Could not read {}: {e}", vtydir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "vty"));
+    for socket in sockets {
+        UnixStream::connect(&socket)
+            .map_err(|e| format!("Could not connect to {}: {e}", socket.display()))?;
+    }
+    vtysh(args, "show version").map(|_| ())
+}
+
+// wait until FRR is up, probing it periodically, for at most some time
+pub fn wait_for_frr(args: &Args, timeout: Duration) -> Result<(), String> {
+    let started = Instant::now();
+    info!(
+        "Waiting for FRR to be up (for at most {}s)...",
+        timeout.as_secs()
+    );
+    loop {
+        match probe(args) {
+            Ok(()) => {
+                info!("FRR is up after {:.1}s", started.elapsed().as_secs_f64());
+                return Ok(());
+            }
+            Err(e) if started.elapsed() >= timeout => {
+                return Err(format!("FRR not up after {}s: {e}", timeout.as_secs()));
+            }
+            Err(e) => debug!("FRR is not up yet: {e}"),
+        }
+        sleep(PROBE_INTERVAL);
+    }
+}
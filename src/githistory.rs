@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Git-backed config history. Every generation applied is committed to a local git repository
+// (frr.conf, along with the metadata of the generation in generation.json and in the commit
+// message), so that the usual git tooling (log, diff, blame) can be used on the history of a node.
+// The repository may optionally be pushed to a remote after every commit.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+
+#[derive(Clone, Debug)]
+pub(crate) struct GitHistory {
+    repo: PathBuf,
+    remote: Option<String>,
+}
+
+impl GitHistory {
+    pub(crate) fn new(repo: &str, remote: Option<&str>) -> Self {
+        Self {
+            repo: PathBuf::from(repo),
+            remote: remote.map(str::to_string),
+        }
+    }
+
+    // run git in the repository
+    pub(crate) fn git(&self, args: &[&str]) -> Result<String, String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repo)
+            .args([
+                "-c",
+                "user.name=frr-agent",
+                "-c",
+                "user.email=frr-agent@localhost",
+            ])
+            .args(args)
+            .output()
+            .map_err(|e| format!("Failed to run git: {e}"))?;
+        if !output.status.success() {
+            return Err(format!(
+                "git {} failed: {}",
+                args.first().unwrap_or(&""),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    // create the repository if it does not exist
+    pub(crate) fn init(&self) -> Result<(), String> {
+        fs::create_dir_all(&self.repo)
+            .map_err(|e| format!("Could not create {}: {e}", self.repo.display()))?;
+        if !self.repo.join(".git").exists() {
+            self.git(&["init", "--quiet"])?;
+            info!("Created git history at {}", self.repo.display());
+        }
+        Ok(())
+    }
+
+    // commit a generation applied. The commit is recorded even if the config did not change
+    // (e.g. on a rollback), so that the log tells when every generation was applied.
+    pub(crate) fn commit(
+        &self,
+        genid: GenId,
+        config_file: &Path,
+        metadata: &Json,
+    ) -> Result<(), String> {
+        fs::copy(config_file, self.repo.join("frr.conf"))
+            .map_err(|e| format!("Could not copy {}: {e}", config_file.display()))?;
+        fs::write(self.repo.join("generation.json"), metadata.to_string())
+            .map_err(|e| format!("Could not write metadata: {e}"))?;
+
+        let mut message = format!("Generation {genid}\n");
+        if let Json::Object(members) = metadata {
+            message.push('\n');
+            for (key, value) in members {
+                let value = value.as_str().map_or(value.to_string(), str::to_string);
+                let _ = writeln!(message, "{key}: {value}");
+            }
+        }
+        self.git(&["add", "frr.conf", "generation.json"])?;
+        self.git(&["commit", "--quiet", "--allow-empty", "-m", &message])?;
+        debug!("Committed generation {genid} to git history");
+
+        /* pushing may be slow: do it in the background */
+        if let Some(remote) = &self.remote {
+            let history = self.clone();
+            let remote = remote.clone();
+            thread::spawn(move || {
+                if let Err(e) = history.git(&["push", "--quiet", &remote, "HEAD"]) {
+                    warn!("Failed to push git history to {remote}: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
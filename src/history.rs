@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Forensics on the generation history stored in outdir: which generation introduced a line of a
+// config (blame) and what changed in a time window (change log). Since the metadata of a generation
+// only tells the last request that wrote it, every request writing a generation is also appended to
+// an index (history.jsonl in outdir) with its time, checksum and result, so that the controller can
+// reconcile its state with what was applied after a restart of either.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::diff::diff_configs;
+use crate::json::Json;
+use crate::rawtext;
+use crate::reload::{config_file_path, metadata_file_path, stored_generations};
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+fn read_config(genid: GenId, outdir: &str) -> Option<String> {
+    rawtext::read(config_file_path(genid, outdir)).ok()
+}
+
+// the metadata stored for a generation, if any
+pub(crate) fn read_metadata(genid: GenId, outdir: &str) -> Option<Json> {
+    let metadata = fs::read_to_string(metadata_file_path(genid, outdir)).ok()?;
+    Json::parse(&metadata).ok()
+}
+
+fn contains_line(config: &str, line: &str) -> bool {
+    config.lines().any(|l| l.trim() == line)
+}
+
+// the generation that introduced a line in the config of a generation, i.e. the oldest of the
+// generations preceding it that all contain the line
+pub(crate) fn blame(outdir: &str, genid: GenId, line: &str) -> Result<Json, String> {
+    let line = line.trim();
+    let config = read_config(genid, outdir).ok_or(format!("Generation {genid} is not stored"))?;
+    if !contains_line(&config, line) {
+        return Ok(Json::Null);
+    }
+    let mut introduced = genid;
+    for previous in stored_generations(outdir)
+        .into_iter()
+        .rev()
+        .filter(|previous| *previous < genid)
+    {
+        match read_config(previous, outdir) {
+            Some(config) if contains_line(&config, line) => introduced = previous,
+            _ => break,
+        }
+    }
+    let metadata = read_metadata(introduced, outdir);
+    let field = |name: &str| metadata.as_ref().and_then(|m| m.get(name)).cloned();
+    Ok(Json::object([
+        ("line", line.into()),
+        ("genid", introduced.into()),
+        ("time", field("time").into()),
+        ("request", field("request").into()),
+        ("peer", field("peer").into()),
+    ]))
+}
+
+// the generations processed between two times (in seconds since the epoch), with the changes
+// of each from the generation that preceded it
+pub(crate) fn changes(outdir: &str, from: u64, to: u64) -> Json {
+    let generations = stored_generations(outdir);
+    let mut log = vec![];
+    for (n, genid) in generations.iter().enumerate() {
+        let Some(metadata) = read_metadata(*genid, outdir) else {
+            continue;
+        };
+        let Some(time) = metadata
+            .get("time")
+            .and_then(Json::as_i64)
+            .and_then(|t| u64::try_from(t).ok())
+        else {
+            continue;
+        };
+        if time < from || time > to {
+            continue;
+        }
+        let previous = n.checked_sub(1).and_then(|n| generations.get(n)).copied();
+        let config = read_config(*genid, outdir).unwrap_or_default();
+        let old = previous
+            .and_then(|previous| read_config(previous, outdir))
+            .unwrap_or_default();
+        let mut entry = metadata;
+        if let Json::Object(members) = &mut entry {
+            members.insert("previous".to_string(), previous.into());
+            members.insert("diff".to_string(), diff_configs(&old, &config).into());
+        }
+        log.push((time, *genid, entry));
+    }
+    log.sort_by_key(|(time, genid, _)| (*time, *genid));
+    Json::Array(log.into_iter().map(|(_, _, entry)| entry).collect())
+}
+
+// append an entry to the history index
+pub(crate) fn record(outdir: &str, entry: &Json) -> Result<(), String> {
+    let path = Path::new(outdir).join(HISTORY_FILE);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{entry}"))
+        .map_err(|e| format!("Could not append to history index {}: {e}", path.display()))
+}
+
+// the last (count) entries of the history index, oldest first
+pub(crate) fn history(outdir: &str, count: Option<usize>) -> Json {
+    let index = fs::read_to_string(Path::new(outdir).join(HISTORY_FILE)).unwrap_or_default();
+    let entries: Vec<Json> = index
+        .lines()
+        .filter_map(|line| Json::parse(line).ok())
+        .collect();
+    let skip = count.map_or(0, |count| entries.len().saturating_sub(count));
+    Json::Array(entries.into_iter().skip(skip).collect())
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Hooks run around the reloads of generations (--pre-hook, --post-hook), e.g. to bounce BFD
+// sessions or update an external inventory whenever a new generation lands. The pre-hook is run
+// before the --test stage of a generation, once its config is written, and failing it fails the
+// request. The post-hook is run after a generation is applied (or failed to), with the result;
+// failing it is only logged. Hooks get the generation, the path of its config and the result in
+// FRR_AGENT_GENID, FRR_AGENT_CONFIG and FRR_AGENT_RESULT, and are run to completion.
+
+use std::fmt::Display;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum HookStage {
+    Pre,
+    Post,
+}
+impl Display for HookStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookStage::Pre => write!(f, "pre"),
+            HookStage::Post => write!(f, "post"),
+        }
+    }
+}
+
+// run a hook for a generation, with the result of its apply (post-hook only)
+pub(crate) fn run_hook(
+    hook: &str,
+    stage: HookStage,
+    genid: GenId,
+    config: &Path,
+    result: Option<&str>,
+) -> Result<(), String> {
+    debug!("Running {stage}-hook {hook} for generation {genid}...");
+    let mut cmd = Command::new(hook);
+    cmd.env("FRR_AGENT_HOOK", stage.to_string())
+        .env("FRR_AGENT_GENID", genid.to_string())
+        .env("FRR_AGENT_CONFIG", config)
+        .stdin(Stdio::null());
+    if let Some(result) = result {
+        cmd.env("FRR_AGENT_RESULT", result);
+    }
+    let started = Instant::now();
+    let output = cmd
+        .output()
+        .map_err(|e| format!("could not run {hook}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{hook} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    info!(
+        "Ran {stage}-hook {hook} for generation {genid} in {:?}",
+        started.elapsed()
+    );
+    Ok(())
+}
@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Impact analysis of config changes: prediction of the operations that applying a config would
+// make that disrupt the control plane, from the changes to the config applied:
+//   * BGP neighbors removed, or whose session is reset by changes to their session parameters
+//     (remote-as, peer-group, update-source, password...),
+//   * BGP instances removed or whose AS changes (resetting all their sessions),
+//   * router-id changes (global or of BGP instances),
+//   * VRFs deleted.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::json::Json;
+
+// the neighbor parameters whose change resets the session
+const SESSION_PARAMS: [&str; 7] = [
+    "remote-as",
+    "peer-group",
+    "update-source",
+    "password",
+    "ebgp-multihop",
+    "local-as",
+    "ttl-security",
+];
+
+pub(crate) struct Disruption {
+    kind: &'static str,
+    target: String,
+    detail: String,
+}
+
+impl Disruption {
+    fn new(kind: &'static str, target: String, detail: String) -> Self {
+        Self {
+            kind,
+            target,
+            detail,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            ("kind", self.kind.into()),
+            ("target", self.target.as_str().into()),
+            ("detail", self.detail.as_str().into()),
+        ])
+    }
+}
+
+impl Display for Disruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.kind, self.target, self.detail)
+    }
+}
+
+// a BGP instance, as configured
+#[derive(Default)]
+struct Bgp {
+    asn: String,
+    router_id: Option<String>,
+    neighbors: BTreeMap<String, BTreeMap<String, String>>, /* session params by neighbor */
+}
+
+// the parts of a config that disruptions are predicted from
+#[derive(Default)]
+struct Model {
+    router_id: Option<String>,
+    vrfs: Vec<String>,
+    bgp: BTreeMap<String, Bgp>, /* by vrf */
+}
+
+impl Model {
+    fn of(config: &str) -> Self {
+        let mut model = Self::default();
+        let mut vrf = None; /* the vrf of the BGP instance the current line is nested under */
+        for line in config.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words
+                .first()
+                .is_none_or(|word| word.starts_with('!') || word.starts_with('#'))
+            {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                vrf = None;
+                match words.as_slice() {
+                    ["router", "bgp", asn, rest @ ..] => {
+                        let name = match rest {
+                            ["vrf", name, ..] => (*name).to_string(),
+                            _ => "default".to_string(),
+                        };
+                        model.bgp.entry(name.clone()).or_default().asn = (*asn).to_string();
+                        vrf = Some(name);
+                    }
+                    ["vrf", name, ..] => model.vrfs.push((*name).to_string()),
+                    ["router-id", id, ..] | ["ip" | "ipv6", "router-id", id, ..] => {
+                        model.router_id = Some((*id).to_string());
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            let Some(bgp) = vrf.as_ref().and_then(|vrf| model.bgp.get_mut(vrf)) else {
+                continue;
+            };
+            match words.as_slice() {
+                ["bgp", "router-id", id, ..] => bgp.router_id = Some((*id).to_string()),
+                ["neighbor", name, param, rest @ ..] => {
+                    let params = bgp.neighbors.entry((*name).to_string()).or_default();
+                    if SESSION_PARAMS.contains(param) {
+                        params.insert((*param).to_string(), rest.join(" "));
+                    }
+                }
+                _ => {}
+            }
+        }
+        model
+    }
+}
+
+// a router-id, if set
+fn id(router_id: Option<&String>) -> &str {
+    router_id.map_or("none", String::as_str)
+}
+
+// the disruptions predicted from applying a config, given the config applied
+pub(crate) fn predict(applied: &str, config: &str) -> Vec<Disruption> {
+    let (old, new) = (Model::of(applied), Model::of(config));
+    let mut disruptions = vec![];
+    if old.router_id.is_some() && old.router_id != new.router_id {
+        disruptions.push(Disruption::new(
+            "router_id_changed",
+            "global".to_string(),
+            format!(
+                "{} -> {}",
+                id(old.router_id.as_ref()),
+                id(new.router_id.as_ref())
+            ),
+        ));
+    }
+    for vrf in old.vrfs.iter().filter(|vrf| !new.vrfs.contains(vrf)) {
+        disruptions.push(Disruption::new(
+            "vrf_deleted",
+            format!("vrf {vrf}"),
+            "VRF removed".to_string(),
+        ));
+    }
+    for (vrf, old) in &old.bgp {
+        let Some(new) = new.bgp.get(vrf) else {
+            disruptions.push(Disruption::new(
+                "bgp_instance_removed",
+                format!("router bgp {} (vrf {vrf})", old.asn),
+                format!("{} neighbors removed", old.neighbors.len()),
+            ));
+            continue;
+        };
+        if old.asn != new.asn {
+            disruptions.push(Disruption::new(
+                "bgp_asn_changed",
+                format!("router bgp (vrf {vrf})"),
+                format!("{} -> {}: all sessions reset", old.asn, new.asn),
+            ));
+            continue;
+        }
+        if old.router_id.is_some() && old.router_id != new.router_id {
+            disruptions.push(Disruption::new(
+                "router_id_changed",
+                format!("router bgp {} (vrf {vrf})", old.asn),
+                format!(
+                    "{} -> {}: all sessions reset",
+                    id(old.router_id.as_ref()),
+                    id(new.router_id.as_ref())
+                ),
+            ));
+        }
+        for (neighbor, params) in &old.neighbors {
+            let target = format!("neighbor {neighbor} (vrf {vrf})");
+            match new.neighbors.get(neighbor) {
+                None => disruptions.push(Disruption::new(
+                    "neighbor_removed",
+                    target,
+                    "session torn down".to_string(),
+                )),
+                Some(new) if new != params => {
+                    let changed: Vec<&str> = SESSION_PARAMS
+                        .into_iter()
+                        .filter(|param| params.get(*param) != new.get(*param))
+                        .collect();
+                    disruptions.push(Disruption::new(
+                        "neighbor_reset",
+                        target,
+                        format!("{} changed: session reset", changed.join(", ")),
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    disruptions
+}
+
+// the disruptions as a JSON array
+pub(crate) fn to_json(disruptions: &[Disruption]) -> Json {
+    Json::Array(disruptions.iter().map(Disruption::to_json).collect())
+}
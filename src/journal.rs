@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Crash-safe journal of the reloads in progress. A record (generation, stage and start time) is
+// written to outdir before each stage of a reload and cleared once the stage completes, so that an
+// agent starting with records left knows that reloads were interrupted (e.g. by a crash or a kill)
+// and can report them, rather than silently pretending nothing happened. Records left are kept
+// in the journal until the agent resolves them.
+
+use std::fmt::Display;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+
+const JOURNAL_FILE: &str = "reload-journal.json";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Stage {
+    Validate,
+    Apply,
+}
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Validate => write!(f, "validate"),
+            Stage::Apply => write!(f, "apply"),
+        }
+    }
+}
+impl Stage {
+    fn parse(stage: &str) -> Option<Self> {
+        match stage {
+            "validate" => Some(Stage::Validate),
+            "apply" => Some(Stage::Apply),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct JournalRecord {
+    pub(crate) genid: GenId,
+    pub(crate) stage: Stage,
+    pub(crate) started: u64, /* seconds since the epoch */
+}
+
+impl JournalRecord {
+    pub(crate) fn to_json(self) -> Json {
+        Json::object([
+            ("genid", self.genid.into()),
+            ("stage", self.stage.to_string().into()),
+            ("started", self.started.into()),
+        ])
+    }
+    fn from_json(record: &Json) -> Option<Self> {
+        Some(Self {
+            genid: record.get("genid")?.as_i64()?,
+            stage: Stage::parse(record.get("stage")?.as_str()?)?,
+            started: u64::try_from(record.get("started")?.as_i64()?).ok()?,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Records {
+    next: u64,
+    in_progress: Vec<(u64, JournalRecord)>,
+    interrupted: Vec<JournalRecord>, /* left by a previous run, not resolved yet */
+}
+
+pub(crate) struct Journal {
+    path: PathBuf,
+    records: Mutex<Records>,
+}
+
+// a stage in progress. Its record is cleared when dropped.
+pub(crate) struct InProgress<'a> {
+    journal: &'a Journal,
+    id: u64,
+}
+impl Drop for InProgress<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut records) = self.journal.records.lock() {
+            records.in_progress.retain(|(id, _)| *id != self.id);
+            self.journal.persist(&records);
+        }
+    }
+}
+
+impl Journal {
+    // open the journal in outdir, learning the records left by a previous run
+    pub(crate) fn open(outdir: &str) -> Self {
+        let path = Path::new(outdir).join(JOURNAL_FILE);
+        let interrupted = fs::read_to_string(&path).map_or(vec![], |contents| {
+            if let Ok(Json::Array(records)) = Json::parse(&contents) {
+                records
+                    .iter()
+                    .filter_map(JournalRecord::from_json)
+                    .collect()
+            } else {
+                warn!("Ignoring bad reload journal at {}", path.display());
+                vec![]
+            }
+        });
+        Self {
+            path,
+            records: Mutex::new(Records {
+                interrupted,
+                ..Records::default()
+            }),
+        }
+    }
+
+    // write the records to disk, replacing the journal atomically
+    fn persist(&self, records: &Records) {
+        let json = Json::Array(
+            records
+                .interrupted
+                .iter()
+                .chain(records.in_progress.iter().map(|(_, record)| record))
+                .map(|record| record.to_json())
+                .collect(),
+        );
+        let tmp = self.path.with_extension("tmp");
+        let written = fs::create_dir_all(self.path.parent().unwrap_or(Path::new(".")))
+            .and_then(|()| fs::File::create(&tmp))
+            .and_then(|mut file| {
+                file.write_all(json.to_string().as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if let Err(e) = written {
+            error!(
+                "Failed to write reload journal {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+
+    // record that a stage of the reload of a generation starts
+    pub(crate) fn begin(&self, genid: GenId, stage: Stage) -> InProgress<'_> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut id = 0;
+        if let Ok(mut records) = self.records.lock() {
+            id = records.next;
+            records.next += 1;
+            records.in_progress.push((
+                id,
+                JournalRecord {
+                    genid,
+                    stage,
+                    started,
+                },
+            ));
+            self.persist(&records);
+        }
+        InProgress { journal: self, id }
+    }
+
+    // the records left by a previous run, not resolved yet
+    pub(crate) fn interrupted(&self) -> Vec<JournalRecord> {
+        self.records
+            .lock()
+            .map(|records| records.interrupted.clone())
+            .unwrap_or_default()
+    }
+
+    // forget the records left by a previous run, once dealt with
+    pub(crate) fn resolve(&self) {
+        if let Ok(mut records) = self.records.lock() {
+            records.interrupted.clear();
+            self.persist(&records);
+        }
+    }
+}
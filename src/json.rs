@@ -0,0 +1,377 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// A minimal JSON value type with a parser and a serializer
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    // build an object from a list of (key, value) pairs
+    pub fn object<I: IntoIterator<Item = (&'static str, Json)>>(members: I) -> Json {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.get(key),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+    pub fn parse(input: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos != parser.input.len() {
+            return Err(format!("Trailing characters at offset {}", parser.pos));
+        }
+        Ok(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_string())
+    }
+}
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+impl From<i64> for Json {
+    #[allow(clippy::cast_precision_loss)]
+    fn from(n: i64) -> Self {
+        Json::Number(n as f64)
+    }
+}
+impl From<u64> for Json {
+    #[allow(clippy::cast_precision_loss)]
+    fn from(n: u64) -> Self {
+        Json::Number(n as f64)
+    }
+}
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if u32::from(c) < 0x20 => write!(f, "\\u{:04x}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Number(n) if n.is_finite() => write!(f, "{n}"),
+            Json::Null | Json::Number(_) => f.write_str("null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (n, item) in items.iter().enumerate() {
+                    if n > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(members) => {
+                f.write_char('{')?;
+                for (n, (key, value)) in members.iter().enumerate() {
+                    if n > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.input[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(format!("Expected '{token}' at offset {}", self.pos))
+        }
+    }
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|()| Json::Null),
+            Some(b't') => self.expect("true").map(|()| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|()| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(c) => Err(format!(
+                "Unexpected character '{}' at offset {}",
+                c as char, self.pos
+            )),
+            None => Err("Unexpected end of input".to_string()),
+        }
+    }
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| matches!(c, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        let number =
+            std::str::from_utf8(&self.input[start..self.pos]).map_err(|e| e.to_string())?;
+        number
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| format!("Bad number '{number}' at offset {start}"))
+    }
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or(format!("Bad unicode escape at offset {}", self.pos))?;
+        self.pos += 4;
+        Ok(hex)
+    }
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(c) = self.peek() else {
+                return Err("Unterminated string".to_string());
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = self.peek().ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&code) {
+                                /* surrogate pair */
+                                self.expect("\\u")?;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err("Bad surrogate pair".to_string());
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or("Bad unicode escape")?
+                        }
+                        _ => return Err(format!("Bad escape at offset {}", self.pos)),
+                    };
+                    let mut buf = [0u8; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect("[")?;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(format!("Expected ',' or ']' at offset {}", self.pos)),
+            }
+        }
+    }
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect("{")?;
+        let mut members = BTreeMap::new();
+        self.whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(":")?;
+            let value = self.value()?;
+            members.insert(key, value);
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(format!("Expected ',' or '}}' at offset {}", self.pos)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        assert_eq!(Json::parse(" null "), Ok(Json::Null));
+        assert_eq!(Json::parse("true"), Ok(Json::Bool(true)));
+        assert_eq!(Json::parse("-1.5e2"), Ok(Json::Number(-150.0)));
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(vec![])));
+        assert_eq!(Json::parse("{ }"), Ok(Json::Object(BTreeMap::new())));
+        let request = Json::parse(r#"{"method": "reload", "params": {"genid": 3, "x": [1, "a"]}}"#);
+        let Ok(request) = request else {
+            unreachable!("{request:?}");
+        };
+        assert_eq!(request.get("method").and_then(Json::as_str), Some("reload"));
+        let params = request.get("params");
+        assert_eq!(
+            params.and_then(|p| p.get("genid")).and_then(Json::as_i64),
+            Some(3)
+        );
+        assert_eq!(
+            params.and_then(|p| p.get("x")),
+            Some(&Json::Array(vec![Json::Number(1.0), "a".into()]))
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            Json::parse(r#""a\"b\\c\/\n\t\u00e9""#),
+            Ok("a\"b\\c/\n\té".into())
+        );
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#), Ok("\u{1F600}".into()));
+        assert_eq!(Json::parse("\"café\""), Ok("café".into()));
+    }
+
+    #[test]
+    fn bad_input() {
+        for input in [
+            "",
+            "nul",
+            "[1,",
+            "[1 2]",
+            r#"{"a" 1}"#,
+            r#"{"a": 1,}"#,
+            "{1: 2}",
+            r#""unterminated"#,
+            r#""\x""#,
+            r#""\u12""#,
+            r#""\ud83d""#,
+            r#""\ud83d\u0041""#,
+            r#""\udc00""#,
+            "1 2",
+            "--1",
+            "@",
+        ] {
+            assert!(Json::parse(input).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = Json::object([
+            ("s", "line\n\"quoted\"\u{1}".into()),
+            ("n", Json::Number(-2.5)),
+            ("a", Json::Array(vec![Json::Null, true.into()])),
+            ("o", Json::object([("k", 1i64.into())])),
+        ]);
+        assert_eq!(Json::parse(&value.to_string()), Ok(value));
+        assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+    }
+}
@@ -0,0 +1,443 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// JSON-RPC 2.0 mode. Instead of the binary framing, a client may talk line-delimited JSON-RPC
+// by sending "JSON-RPC\n" as soon as it connects. Each request and response is then a JSON
+// object in a single line. The methods supported are:
+//
+//      keepalive
+//      reload      {"genid": <genid>, "config": <config>}
+//      upload      {"genid": <genid>, "config": <config>}
+//      preview     {"genid": <genid>, "config": <config>}
+//      promote     {"genid": <genid>}
+//      prepare     {"genid": <genid>, "config": <config>}
+//      commit      {"genid": <genid>, "token": <token>}
+//      abort       {"genid": <genid>, "token": <token>}
+//      rollback    {"genid": <genid>}
+//      cancel      {"genid": <genid>}
+//      apply       {"genid": <genid>, "confirm": <token>} (without confirm, previews the changes)
+//      daemons     {"daemons": [<daemon>, ..]}
+//      service     {"action": "start" | "stop" | "restart"}
+//      baseline
+//      status
+//      version
+//      hello       {"keepalive": <seconds>, "misses": <count>}
+//      interfaces  {"names": [<interface>, ..]} (optional)
+//      show        {"command": <show command>}
+//      subscribe   {"topic": "logs"}
+//      export      {"path": <archive>}
+//      import      {"path": <archive>}
+//      blame       {"line": <line>, "genid": <genid>} (genid optional)
+//      changes     {"from": <time>, "to": <time>} (to optional)
+//      logs        {"count": <count>} (optional)
+//      history     {"count": <count>} (optional)
+//      reset
+//
+// Any request may target an FRR instance (see --instance) with an "instance" param. Requests may
+// not be longer than messages (see --max-message-size): the connection is closed otherwise.
+// Once subscribed to logs, every FRR log line is sent as a "log" notification with a "line" param.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::agent::{Agent, SHADOWED};
+use crate::connection::Client;
+use crate::framing::max_message_size;
+use crate::json::Json;
+use crate::logstream::{LogSource, stream_logs};
+use crate::metrics::METRICS;
+use crate::request::Request;
+use crate::response::written;
+use crate::service::FrrAction;
+use crate::session::Keepalive;
+
+// what a client sends first to switch the connection to JSON-RPC.
+pub(crate) const JSONRPC_MAGIC: &[u8; 8] = b"JSON-RPC";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32000;
+
+struct RpcError(i64, String);
+
+fn param<'a>(params: Option<&'a Json>, name: &str) -> Result<&'a Json, RpcError> {
+    params
+        .and_then(|params| params.get(name))
+        .ok_or(RpcError(INVALID_PARAMS, format!("Missing param '{name}'")))
+}
+fn genid_param(params: Option<&Json>) -> Result<GenId, RpcError> {
+    param(params, "genid")?.as_i64().ok_or(RpcError(
+        INVALID_PARAMS,
+        "genid must be an integer".to_string(),
+    ))
+}
+fn string_param(params: Option<&Json>, name: &str) -> Result<String, RpcError> {
+    param(params, name)?
+        .as_str()
+        .map(str::to_string)
+        .ok_or(RpcError(INVALID_PARAMS, format!("{name} must be a string")))
+}
+
+fn strings_param(params: Option<&Json>, name: &str) -> Result<Vec<String>, RpcError> {
+    let Json::Array(items) = param(params, name)? else {
+        return Err(RpcError(INVALID_PARAMS, format!("{name} must be an array")));
+    };
+    items
+        .iter()
+        .map(|item| item.as_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(RpcError(INVALID_PARAMS, format!("{name} must be strings")))
+}
+
+fn changes_request(params: Option<&Json>) -> Result<Request, RpcError> {
+    let time = |name| {
+        param(params, name)?
+            .as_i64()
+            .and_then(|t| u64::try_from(t).ok())
+            .ok_or(RpcError(INVALID_PARAMS, format!("{name} must be a time")))
+    };
+    let to = match params.and_then(|params| params.get("to")) {
+        Some(_) => Some(time("to")?),
+        None => None,
+    };
+    Ok(Request::Changes(time("from")?, to))
+}
+
+// the optional "count" param
+fn count_param(params: Option<&Json>) -> Result<Option<usize>, RpcError> {
+    match params.and_then(|params| params.get("count")) {
+        Some(count) => count
+            .as_i64()
+            .and_then(|count| usize::try_from(count).ok())
+            .map(Some)
+            .ok_or(RpcError(INVALID_PARAMS, "count must be a count".into())),
+        None => Ok(None),
+    }
+}
+
+fn hello_request(params: Option<&Json>) -> Result<Request, RpcError> {
+    let interval = param(params, "keepalive")?
+        .as_i64()
+        .and_then(|i| u64::try_from(i).ok());
+    let misses = match params.and_then(|params| params.get("misses")) {
+        Some(misses) => misses.as_i64().and_then(|m| u32::try_from(m).ok()),
+        None => Some(3),
+    };
+    let (Some(interval), Some(misses)) = (interval, misses) else {
+        return Err(RpcError(INVALID_PARAMS, "Bad keepalive params".into()));
+    };
+    let keepalive = Keepalive {
+        interval: Duration::from_secs(interval),
+        misses,
+    };
+    Ok(Request::Hello(keepalive, None, None))
+}
+
+// map a JSON-RPC method call to a request
+fn to_request(
+    method: &str,
+    params: Option<&Json>,
+    agent: &Agent,
+) -> Result<(GenId, Request), RpcError> {
+    let request = match method {
+        "keepalive" => (0, Request::Keepalive),
+        "status" => (0, Request::Status),
+        "baseline" => (0, Request::Baseline),
+        "version" => (0, Request::Version),
+        "reset" => (0, Request::Reset),
+        "reload" => {
+            let force = params.and_then(|params| params.get("force"));
+            (
+                genid_param(params)?,
+                Request::Config(
+                    string_param(params, "config")?,
+                    matches!(force, Some(Json::Bool(true))),
+                ),
+            )
+        }
+        "upload" => (
+            genid_param(params)?,
+            Request::Upload(string_param(params, "config")?),
+        ),
+        "preview" => (
+            genid_param(params)?,
+            Request::Preview(string_param(params, "config")?),
+        ),
+        "promote" => (genid_param(params)?, Request::Promote),
+        "cancel" => (genid_param(params)?, Request::Cancel),
+        "prepare" => (
+            genid_param(params)?,
+            Request::Prepare(string_param(params, "config")?),
+        ),
+        "commit" => (
+            genid_param(params)?,
+            Request::Commit(string_param(params, "token")?),
+        ),
+        "abort" => (
+            genid_param(params)?,
+            Request::Abort(string_param(params, "token")?),
+        ),
+        "rollback" => {
+            let genid = match params.and_then(|params| params.get("genid")) {
+                Some(_) => genid_param(params)?,
+                None => 0,
+            };
+            (genid, Request::Rollback)
+        }
+        "apply" => {
+            let confirm = match params.and_then(|params| params.get("confirm")) {
+                Some(_) => Some(string_param(params, "confirm")?),
+                None => None,
+            };
+            (genid_param(params)?, Request::Apply(confirm))
+        }
+        "daemons" => (0, Request::Daemons(strings_param(params, "daemons")?)),
+        "export" => (0, Request::Export(string_param(params, "path")?)),
+        "import" => (0, Request::Import(string_param(params, "path")?)),
+        "blame" => {
+            let genid = match params.and_then(|params| params.get("genid")) {
+                Some(_) => genid_param(params)?,
+                None => 0,
+            };
+            (genid, Request::Blame(string_param(params, "line")?))
+        }
+        "changes" => (0, changes_request(params)?),
+        "logs" => (0, Request::Logs(count_param(params)?)),
+        "history" => (0, Request::History(count_param(params)?)),
+        "subscribe" => match string_param(params, "topic")?.as_str() {
+            "logs" => (0, Request::Subscribe(agent.args.log_source())),
+            topic => {
+                return Err(RpcError(INVALID_PARAMS, format!("Unknown topic '{topic}'")));
+            }
+        },
+        "interfaces" => {
+            let names = if params.and_then(|params| params.get("names")).is_some() {
+                strings_param(params, "names")?
+            } else {
+                vec![]
+            };
+            (0, Request::Interfaces(names))
+        }
+        "show" => (0, Request::Show(string_param(params, "command")?)),
+        "service" => {
+            let action = string_param(params, "action")?;
+            let action = FrrAction::parse(&action).ok_or(RpcError(
+                INVALID_PARAMS,
+                format!("Unknown action '{action}'"),
+            ))?;
+            (0, Request::Service(action))
+        }
+        "hello" => (0, hello_request(params)?),
+        _ => {
+            return Err(RpcError(
+                METHOD_NOT_FOUND,
+                format!("Unknown method '{method}'"),
+            ));
+        }
+    };
+    Ok(request)
+}
+
+// process a JSON-RPC message. Returns the response, if any (notifications get none).
+// If the client subscribed to logs, the source of the logs is returned in subscription.
+fn process(
+    line: &str,
+    agent: &Agent,
+    client: &Client,
+    subscription: &mut Option<LogSource>,
+) -> Option<Json> {
+    let message = match Json::parse(line) {
+        Ok(message) => message,
+        Err(e) => {
+            METRICS.decode_error();
+            return Some(error_response(Json::Null, &RpcError(PARSE_ERROR, e)));
+        }
+    };
+    let id = message.get("id").cloned();
+    let method = message.get("method").and_then(Json::as_str);
+    let result = match method {
+        Some(method) if message.get("jsonrpc").and_then(Json::as_str) == Some("2.0") => {
+            let params = message.get("params");
+            /* requests may target an FRR instance */
+            let agent = match params.and_then(|params| params.get("instance")) {
+                None => Ok(agent),
+                Some(instance) => instance
+                    .as_str()
+                    .and_then(|name| agent.instance(name))
+                    .ok_or(RpcError(
+                        INVALID_PARAMS,
+                        format!("Unknown FRR instance {instance}"),
+                    )),
+            };
+            agent
+                .and_then(|agent| to_request(method, params, agent).map(|r| (agent, r)))
+                .and_then(|(agent, (genid, request))| {
+                    let request = agent.render(genid, request);
+                    debug!("Got JSON-RPC {}", request.describe(genid));
+                    let in_flight = agent.received(genid, &request);
+                    in_flight.start();
+                    let response = agent.handle(genid, &request, client, None);
+                    drop(in_flight);
+                    match request {
+                        Request::Subscribe(source) if response == "Ok" => {
+                            *subscription = Some(source);
+                            Ok(Json::from("Ok"))
+                        }
+                        _ if request.is_json_query() => {
+                            Json::parse(&response).map_err(|_| RpcError(REQUEST_FAILED, response))
+                        }
+                        Request::Hello(requested, ..) => {
+                            let agreed = agent.negotiate_keepalive(&requested);
+                            Ok(Json::object([
+                                ("keepalive", agreed.interval.as_secs().into()),
+                                ("misses", u64::from(agreed.misses).into()),
+                            ]))
+                        }
+                        _ if response == "Ok" || response == SHADOWED => Ok(Json::from(response)),
+                        _ => written(&response).ok_or(RpcError(REQUEST_FAILED, response)),
+                    }
+                })
+        }
+        _ => Err(RpcError(
+            INVALID_REQUEST,
+            "Not a JSON-RPC 2.0 request".into(),
+        )),
+    };
+    let id = id?;
+    Some(match result {
+        Ok(result) => Json::object([("jsonrpc", "2.0".into()), ("result", result), ("id", id)]),
+        Err(e) => error_response(id, &e),
+    })
+}
+
+fn error_response(id: Json, error: &RpcError) -> Json {
+    Json::object([
+        ("jsonrpc", "2.0".into()),
+        (
+            "error",
+            Json::object([
+                ("code", error.0.into()),
+                ("message", error.1.as_str().into()),
+            ]),
+        ),
+        ("id", id),
+    ])
+}
+
+// read a line of at most max octets (its newline excluded), so that a client can not make the
+// agent allocate more by never ending it
+fn read_line_within(reader: &mut impl BufRead, line: &mut String, max: u64) -> io::Result<usize> {
+    let read = reader.take(max.saturating_add(1)).read_line(line)?;
+    if read as u64 > max && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("request exceeds the maximum message size ({max} octets)"),
+        ));
+    }
+    Ok(read)
+}
+
+// serve a connection in JSON-RPC mode, once the client sent the magic
+pub(crate) fn serve_jsonrpc(stream: UnixStream, agent: &Agent, client: &Client) {
+    let Ok(rx_stream) = stream.try_clone() else {
+        error!("Could not clone connection stream. Shutting down connection...");
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    };
+    let mut stream = stream;
+    let mut reader = BufReader::new(rx_stream);
+    let mut line = String::new();
+
+    let max = max_message_size();
+
+    /* the rest of the line with the magic is ignored */
+    if read_line_within(&mut reader, &mut line, max).is_err() {
+        return;
+    }
+    info!("Connection switched to JSON-RPC mode");
+    loop {
+        line.clear();
+        match read_line_within(&mut reader, &mut line, max) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                error!("Could not read JSON-RPC request: {e}. Shutting down connection...");
+                break;
+            }
+        }
+        agent.sessions.seen(client.session);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut subscription = None;
+        let response = process(line.trim_end(), agent, client, &mut subscription);
+        if let Some(source) = subscription {
+            if let Some(response) = response
+                && let Err(e) = writeln!(stream, "{response}")
+            {
+                error!("Error sending JSON-RPC response: {e}. Shutting down connection...");
+                break;
+            }
+            stream_logs(source, stream, |stream, line| {
+                let notification = Json::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("method", "log".into()),
+                    ("params", Json::object([("line", line.into())])),
+                ]);
+                writeln!(stream, "{notification}").map_err(|e| e.to_string())
+            });
+            return;
+        }
+        let Some(response) = response else {
+            continue;
+        };
+
+        /* enforce the keepalive interval the client negotiated */
+        if response
+            .get("result")
+            .and_then(|r| r.get("keepalive"))
+            .is_some()
+            && let Some(keepalive) = agent.sessions.keepalive(client.session)
+            && let Err(e) = reader
+                .get_ref()
+                .set_read_timeout(Some(keepalive.deadline()))
+        {
+            error!("Failed to set keepalive timeout: {e}");
+        }
+
+        if let Err(e) = writeln!(stream, "{response}") {
+            error!("Error sending JSON-RPC response: {e}. Shutting down connection...");
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn lines_are_bounded() {
+        let mut reader = Cursor::new(b"12345\n123456\n1234567".to_vec());
+        let mut line = String::new();
+        assert_eq!(read_line_within(&mut reader, &mut line, 6).ok(), Some(6));
+        assert_eq!(line, "12345\n");
+        line.clear();
+        assert_eq!(read_line_within(&mut reader, &mut line, 6).ok(), Some(7));
+        assert_eq!(line, "123456\n");
+        line.clear();
+        assert!(read_line_within(&mut reader, &mut line, 6).is_err());
+        assert!(line.len() <= 7);
+    }
+}
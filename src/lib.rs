@@ -0,0 +1,1763 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// An FRR config reloader daemon. The daemon is the Server; a Client of its protocol and the
+// Framing of its messages are exposed too, so that other programs (e.g. controllers) can embed
+// them instead of re-implementing the protocol.
+
+#![deny(
+    unsafe_code,
+    clippy::all,
+    clippy::pedantic,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic
+)]
+/* the API is documented with plain comments: errors are strings telling what failed */
+#![allow(clippy::missing_errors_doc)]
+
+use bytes::BytesMut;
+use clap::{Parser, Subcommand};
+
+use signal_hook::consts::{SIGINT, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::{PermissionsExt, chown};
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use std::path::Path;
+use std::process::exit;
+use std::str;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{OnceLock, RwLock};
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+#[allow(unused)]
+use tracing::{Level, debug, error, info, warn};
+use tracing_subscriber::Registry;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::agent::Agent;
+use crate::alerting::Alerter;
+use crate::backup::ObjectStore;
+use crate::bench::{BenchArgs, bench};
+use crate::bgpgr::GrMode;
+use crate::client::{SendArgs, send};
+use crate::connection::{Peer, handle_connection};
+use crate::convergence::Convergence;
+use crate::daemons::set_frr_daemons;
+use crate::framing::{message_size, set_max_message_size};
+use crate::frrdown::FrrDownPolicy;
+use crate::frrprobe::wait_for_frr;
+use crate::githistory::GitHistory;
+use crate::json::Json;
+use crate::logging::{JsonFields, JsonFormat, LogFile, LogFormat};
+use crate::logstream::LogSource;
+use crate::metrics::METRICS;
+use crate::node::NodeFormat;
+use crate::passthrough::DEFAULT_SHOW_ALLOW;
+use crate::persist::PersistMode;
+use crate::policy::Policy;
+use crate::reload::{Reloader, ReloaderBinary, config_dir, keep_configs_in};
+use crate::request::parse_list;
+use crate::retention::Retention;
+use crate::retry::{RetryClass, RetryPolicy};
+use crate::runas::RunAs;
+use crate::service::FrrService;
+use crate::shipping::TranscriptShipper;
+use crate::tls::TlsListener;
+use crate::verify::Verifier;
+use crate::vsock::VsockListener;
+use crate::vtysh::Vtysh;
+use crate::vtyshlock::LockPolicy;
+
+pub use crate::client::Client;
+pub use crate::framing::Framing;
+
+mod agent;
+mod alerting;
+mod applyqueue;
+mod archive;
+mod audit;
+mod backup;
+mod bench;
+mod bgpgr;
+mod breaker;
+mod buildinfo;
+mod cancel;
+mod canonical;
+mod chunked;
+mod client;
+mod complexity;
+mod configfile;
+mod connection;
+mod convergence;
+mod daemons;
+mod diff;
+mod dualwrite;
+mod facts;
+mod framing;
+mod frrdown;
+mod frrprobe;
+mod githistory;
+mod history;
+mod hooks;
+mod impact;
+mod journal;
+mod json;
+mod jsonrpc;
+mod logging;
+mod logstream;
+mod metrics;
+mod monitor;
+mod native;
+mod netns;
+mod node;
+mod passthrough;
+mod peer;
+mod pending;
+mod persist;
+mod policy;
+mod privileged;
+mod probes;
+mod quota;
+mod rawtext;
+mod recent;
+mod reload;
+mod request;
+mod response;
+mod retention;
+mod retry;
+mod runas;
+mod service;
+mod session;
+mod sha256;
+mod shipping;
+mod slo;
+mod statefile;
+mod staticroutes;
+mod systemd;
+mod tempfiles;
+mod tls;
+mod verify;
+mod vsock;
+mod vtysh;
+mod vtysh_conf;
+mod vtyshlock;
+pub type GenId = i64;
+
+// initialize logging
+// log to stdout (or the log file given), or to stderr when stdout is for the output of a command
+// (e.g. send), in the format given
+fn init_logging(loglevel: Level, format: LogFormat, file: Option<LogFile>, to_stderr: bool) {
+    let ansi = file.is_none();
+    let writer = match file {
+        _ if to_stderr => BoxMakeWriter::new(std::io::stderr),
+        Some(file) => BoxMakeWriter::new(file),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    let (filter, handle) =
+        tracing_subscriber::reload::Layer::new(LevelFilter::from_level(loglevel));
+    let _ = LOG_FILTER.set(handle);
+    match format {
+        LogFormat::Text => tracing_subscriber::registry()
+            .with(filter)
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(ansi)
+                    .with_writer(writer)
+                    .event_format(NodeFormat(
+                        tracing_subscriber::fmt::format().with_level(true).compact(),
+                    )),
+            )
+            .init(),
+        LogFormat::Json => tracing_subscriber::registry()
+            .with(filter)
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(writer)
+                    .fmt_fields(JsonFields)
+                    .event_format(JsonFormat),
+            )
+            .init(),
+    }
+}
+
+// the handle to change the loglevel while running
+static LOG_FILTER: OnceLock<tracing_subscriber::reload::Handle<LevelFilter, Registry>> =
+    OnceLock::new();
+
+// change the loglevel
+pub(crate) fn set_loglevel(loglevel: Level) -> Result<(), String> {
+    let handle = LOG_FILTER.get().ok_or("Logging is not initialized")?;
+    handle
+        .reload(LevelFilter::from_level(loglevel))
+        .map_err(|e| format!("Could not change loglevel: {e}"))
+}
+
+pub(crate) fn create_unix_listener(
+    bind_addr: &str,
+    perms: &SockPerms,
+) -> Result<UnixListener, String> {
+    // clean up entry in file system
+    debug!("Removing {bind_addr}...");
+    match std::fs::remove_file(bind_addr) {
+        Ok(()) => debug!("Successfully deleted {bind_addr}"),
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound) => debug!("Did not find {bind_addr}"),
+        Err(e) => warn!("Could not remove {bind_addr}: {e}"),
+    }
+    let bind_path = Path::new(bind_addr);
+
+    // create intermediate directories if needed
+    if let Some(parent_dir) = bind_path.parent() {
+        fs::create_dir_all(parent_dir).map_err(|e| format!("Could not create sock paths: {e}"))?;
+    }
+
+    // build listener and bind it
+    let listener = UnixListener::bind(bind_addr).map_err(|e| format!("failed to bind: {e}"))?;
+    listener
+        .set_nonblocking(false)
+        .map_err(|e| format!("Failed to set blocking: {e}"))?;
+
+    // grant permissions
+    if perms.owner.is_some() || perms.group.is_some() {
+        chown(bind_addr, perms.owner, perms.group)
+            .map_err(|e| format!("Failure setting ownership: {e}"))?;
+    }
+    fs::set_permissions(bind_addr, fs::Permissions::from_mode(perms.mode))
+        .map_err(|e| format!("Failure setting permissions: {e}"))?;
+
+    Ok(listener)
+}
+
+// receive a v1 message of at most max octets
+pub(crate) fn receive_request(sock: &mut impl Read, max: u64) -> Result<(GenId, String), String> {
+    debug!("━━━━━━ Waiting for data ━━━━━━");
+
+    let mut len_buf = [0u8; 8];
+    let mut genid_buf = [0u8; 8];
+
+    sock.read_exact(&mut len_buf)
+        .map_err(|e| format!("Could not receive msg-len: {e}"))?;
+    sock.read_exact(&mut genid_buf)
+        .map_err(|e| format!("Could not receive genid: {e}"))?;
+
+    /* the length is checked before anything is allocated for the message */
+    let msg_size = message_size(u64::from_ne_bytes(len_buf), max)?;
+    let genid = i64::from_ne_bytes(genid_buf);
+
+    let mut rx_buff = vec![0u8; msg_size];
+    sock.read_exact(&mut rx_buff)
+        .map_err(|e| format!("Could not receive request body: {e}"))?;
+    /* octets that are not valid UTF-8 are kept, escaped */
+    let request = rawtext::decode(rx_buff);
+
+    debug!("Successfully received request. data-len: {msg_size} octets genid:{genid}");
+    Ok((genid, request))
+}
+
+// times a message is retried to be sent after a transient failure (e.g. a send timeout)
+const SEND_RETRIES: u32 = 3;
+
+// outcome counters of the messages sent, telling clients gone from transient failures
+pub(crate) struct SendStats {
+    retries: AtomicU64,
+    client_gone: AtomicU64,
+    failures: AtomicU64,
+}
+pub(crate) static SEND_STATS: SendStats = SendStats {
+    retries: AtomicU64::new(0),
+    client_gone: AtomicU64::new(0),
+    failures: AtomicU64::new(0),
+};
+impl SendStats {
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            ("retries", self.retries.load(Ordering::Relaxed).into()),
+            (
+                "client_gone",
+                self.client_gone.load(Ordering::Relaxed).into(),
+            ),
+            ("failures", self.failures.load(Ordering::Relaxed).into()),
+        ])
+    }
+}
+
+// write a wire message, resuming partial writes and retrying transient failures a bounded
+// number of times
+pub(crate) fn write_wire(sock: &mut UnixStream, wire_msg: &[u8]) -> Result<(), String> {
+    let mut sent = 0;
+    let mut retries = 0;
+    while sent < wire_msg.len() {
+        let e = match sock.write(&wire_msg[sent..]) {
+            Ok(0) => std::io::Error::from(ErrorKind::WriteZero),
+            Ok(written) => {
+                sent += written;
+                continue;
+            }
+            Err(e) => e,
+        };
+        match e.kind() {
+            ErrorKind::Interrupted => {}
+            ErrorKind::WouldBlock | ErrorKind::TimedOut if retries < SEND_RETRIES => {
+                retries += 1;
+                SEND_STATS.retries.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "Transient failure sending message ({sent}/{} octets sent): {e}. Retrying ({retries}/{SEND_RETRIES})...",
+                    wire_msg.len()
+                );
+                sleep(Duration::from_millis(10 << retries));
+            }
+            ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::WriteZero => {
+                SEND_STATS.client_gone.fetch_add(1, Ordering::Relaxed);
+                return Err(format!("Client is gone: {e}"));
+            }
+            _ => {
+                SEND_STATS.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(format!(
+                    "Failed to send message ({sent}/{} octets sent): {e}",
+                    wire_msg.len()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn send_response(sock: &mut UnixStream, genid: GenId, msg: &[u8]) -> Result<(), String> {
+    /* length of data */
+    let length = msg.len() as u64;
+
+    /* assemble wire message: |length|genid|data| */
+    let mut wire_msg = BytesMut::with_capacity(msg.len() + 16);
+    wire_msg.extend_from_slice(&length.to_ne_bytes());
+    wire_msg.extend_from_slice(&genid.to_ne_bytes());
+    wire_msg.extend_from_slice(msg);
+
+    /* send wire message */
+    write_wire(sock, &wire_msg)?;
+    debug!("Successfully sent msg. data-len: {length} genid: {genid}");
+    Ok(())
+}
+
+// build frr-reload args from cmd line. If some params are not specified, we provide our own defaults here
+// so that we can exactly log what parameters were passed (even if frr-reload has its own defaults)
+fn build_reload_args(args: &Args) -> Vec<&str> {
+    let mut reload_args = vec![
+        "--stdout",
+        "--debug",
+        "--bindir",
+        args.binddir(),
+        "--rundir",
+        args.rundir(),
+        "--confdir",
+        args.confdir(),
+    ];
+    if let Some(pathspace) = &args.pathspace {
+        reload_args.extend(["--pathspace", pathspace]);
+    }
+    reload_args
+}
+
+// the mode and ownership of the unix socket
+pub(crate) struct SockPerms {
+    mode: u32,
+    owner: Option<u32>, /* None to keep that of the agent */
+    group: Option<u32>,
+}
+
+pub(crate) fn sock_perms(args: &Args) -> Result<SockPerms, String> {
+    let mode = match &args.sock_mode {
+        Some(mode) => u32::from_str_radix(mode.trim().trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or(format!("Bad socket mode '{mode}'"))?,
+        None => 0o777,
+    };
+    Ok(SockPerms {
+        mode,
+        owner: args.sock_owner.as_deref().map(runas::user_id).transpose()?,
+        group: args.sock_group.as_deref().map(runas::group).transpose()?,
+    })
+}
+
+// the reloader executable, and the checksum it is pinned to
+pub(crate) fn reloader_binary(args: &Args) -> Result<ReloaderBinary, String> {
+    let sha256 = args
+        .reloader_sha256
+        .as_ref()
+        .map(|sha256| sha256.trim().to_lowercase());
+    if let Some(sha256) = &sha256
+        && (sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Err(format!("Bad reloader checksum '{sha256}'"));
+    }
+    Ok(ReloaderBinary {
+        path: args.reloader().to_string(),
+        sha256,
+    })
+}
+
+// the reloader, with its args, environment and the credentials to run it with
+pub(crate) fn build_reloader(args: &Args) -> Result<Reloader<'_>, String> {
+    let run_as = RunAs::resolve(
+        args.reload_as_user.as_deref(),
+        args.reload_as_group.as_deref(),
+    )?;
+    let env = args
+        .reload_env
+        .iter()
+        .map(|var| {
+            var.split_once('=')
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or(format!("Bad environment variable '{var}'"))
+        })
+        .collect::<Result<_, _>>()?;
+    let retry = RetryPolicy {
+        attempts: args.reload_attempts.unwrap_or(1).max(1),
+        backoff: Duration::from_millis(args.reload_retry_backoff.unwrap_or(500)),
+        classes: if args.reload_retry_on.is_empty() {
+            vec![RetryClass::Socket]
+        } else {
+            args.reload_retry_on
+                .iter()
+                .map(|class| RetryClass::parse(class))
+                .collect::<Result<_, _>>()?
+        },
+    };
+    Ok(Reloader {
+        binary: RwLock::new(reloader_binary(args)?),
+        rundir: args.rundir(),
+        args: build_reload_args(args),
+        env,
+        run_as,
+        lock: LockPolicy {
+            retries: args.lock_retries.unwrap_or(3),
+            interval: Duration::from_millis(args.lock_retry_interval.unwrap_or(1000)),
+            steal: args.steal_vtysh_lock,
+        },
+        retry,
+        timeout: args.reload_timeout.map(Duration::from_secs),
+        native: args.native_reloader.then(|| Vtysh::of(args)),
+        pre_hook: args.pre_hook.as_deref(),
+        netns: args.netns.as_deref(),
+    })
+}
+
+// cmd line args the reloader accepts. Fixme: use PathBuf instead of String?
+#[derive(Clone, Debug, PartialEq, Parser)]
+#[allow(clippy::struct_excessive_bools)]
+#[command(name = "FRR reload agent")]
+#[command(args_override_self = true)]
+#[command(version = "1.0")]
+#[command(about = "Daemon to reload FRR configs", long_about = None)]
+pub(crate) struct Args {
+    // mandatory
+    #[arg(long, value_name = "Unix socket bind path")]
+    sock_path: String,
+
+    // optional
+    #[arg(
+        long,
+        value_name = "TOML file of options (e.g. reload-timeout = 60), re-read on SIGHUP. Options on the command line take precedence"
+    )]
+    config: Option<String>,
+    #[arg(
+        long,
+        value_name = "Mode of the unix socket, in octal (e.g. 0660). Defaults to 0777"
+    )]
+    sock_mode: Option<String>,
+    #[arg(
+        long,
+        value_name = "Owner of the unix socket (user name or uid). Defaults to the user of the agent"
+    )]
+    sock_owner: Option<String>,
+    #[arg(
+        long,
+        value_name = "Group of the unix socket (group name or gid). Defaults to the group of the agent"
+    )]
+    sock_group: Option<String>,
+    #[arg(
+        long,
+        value_name = "User (name or uid) to drop privileges to once the listeners are bound. Conflicts with --netns. Disabled by default"
+    )]
+    run_as_user: Option<String>,
+    #[arg(
+        long,
+        value_name = "Group (name or gid) to drop privileges to. Defaults to the primary group of --run-as-user"
+    )]
+    run_as_group: Option<String>,
+    #[arg(
+        long,
+        value_name = "vsock port to listen on too, for agents run in a VM to be reached from the host"
+    )]
+    vsock_port: Option<u32>,
+    #[arg(long)]
+    vsock_only: bool,
+    #[arg(
+        long,
+        value_name = "vsock CID allowed to connect (can be repeated). Any CID may connect by default"
+    )]
+    vsock_allowed_cid: Vec<u32>,
+    #[arg(
+        long,
+        value_name = "TCP address (e.g. 0.0.0.0:4567) to listen on too, with mutual TLS. Requires --tls-cert, --tls-key and --tls-ca"
+    )]
+    tcp_addr: Option<String>,
+    #[arg(
+        long,
+        value_name = "PEM certificate (chain) of the agent, for --tcp-addr"
+    )]
+    tls_cert: Option<String>,
+    #[arg(long, value_name = "PEM private key of the agent, for --tcp-addr")]
+    tls_key: Option<String>,
+    #[arg(
+        long,
+        value_name = "PEM certificate(s) of the CA client certificates must be issued by, for --tcp-addr"
+    )]
+    tls_ca: Option<String>,
+    #[arg(
+        long,
+        value_name = "Loglevel (error, warn, info, debug, trace). Defaults to debug"
+    )]
+    loglevel: Option<String>,
+    #[arg(
+        long,
+        value_name = "Format of log lines (text, json). Defaults to text"
+    )]
+    log_format: Option<String>,
+    #[arg(long, value_name = "File to log to instead of stdout")]
+    log_file: Option<String>,
+    #[arg(
+        long,
+        value_name = "Megabytes the log file may grow to before it is rotated. Unbounded by default"
+    )]
+    log_file_max_size: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Hours the log file is written to before it is rotated. Unbounded by default"
+    )]
+    log_file_max_age: Option<u64>,
+    #[arg(long, value_name = "Rotated log files kept. Defaults to 5")]
+    log_file_keep: Option<u32>,
+    #[arg(
+        long,
+        value_name = "Identity of the node, included in responses, alerts, transcripts and log lines"
+    )]
+    node_id: Option<String>,
+    #[arg(
+        long,
+        value_name = "Fabric the node belongs to, included in responses, alerts, transcripts and log lines"
+    )]
+    fabric: Option<String>,
+    #[arg(long, value_name = "Directory where received configs are stored")]
+    outdir: Option<String>,
+    #[arg(
+        long,
+        value_name = "Directory (e.g. on tmpfs) where received configs are kept instead of outdir, which then only holds their metadata"
+    )]
+    volatile_dir: Option<String>,
+    #[arg(
+        long,
+        value_name = "Megabytes a message (or a JSON-RPC request) may have. Larger ones are refused and their connection closed. Defaults to 64"
+    )]
+    max_message_size: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Megabytes configs sent in chunks (see CHUNKED) may have once decompressed. Defaults to 256"
+    )]
+    max_transfer_size: Option<u64>,
+    #[arg(long, value_name = "Full path to reloader (frr-reload.bin|py)")]
+    reloader: Option<String>,
+    #[arg(
+        long,
+        value_name = "SHA-256 (hex) the reloader must match before every run. Not verified by default"
+    )]
+    reloader_sha256: Option<String>,
+    #[arg(
+        long,
+        value_name = "User (name or uid) to run the reloader as. Defaults to the user of the agent"
+    )]
+    reload_as_user: Option<String>,
+    #[arg(
+        long,
+        value_name = "Group (name or gid) to run the reloader as. Defaults to the primary group of --reload-as-user"
+    )]
+    reload_as_group: Option<String>,
+    #[arg(
+        long,
+        value_name = "Environment variable (NAME=value) of the reloader (can be repeated)"
+    )]
+    reload_env: Vec<String>,
+    #[arg(
+        long,
+        value_name = "Times an apply is retried while the vtysh configuration is locked by another session. Defaults to 3"
+    )]
+    lock_retries: Option<u32>,
+    #[arg(
+        long,
+        value_name = "Milliseconds between retries of an apply while the vtysh configuration is locked. Defaults to 1000"
+    )]
+    lock_retry_interval: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Times each stage of the reloader is attempted while it fails for transient reasons. Defaults to 1 (no retries)"
+    )]
+    reload_attempts: Option<u32>,
+    #[arg(
+        long,
+        value_name = "Milliseconds before the first retry of the reloader, doubling for each retry. Defaults to 500"
+    )]
+    reload_retry_backoff: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Class of reloader failures retried: socket, spawn, timeout or any (can be repeated). Defaults to socket"
+    )]
+    reload_retry_on: Vec<String>,
+    #[arg(
+        long,
+        value_name = "Seconds a run of the reloader may take before it is killed. Unbounded by default"
+    )]
+    reload_timeout: Option<u64>,
+    #[arg(long)]
+    steal_vtysh_lock: bool,
+    #[arg(long)]
+    native_reloader: bool,
+    #[arg(
+        long,
+        value_name = "Executable run before the --test stage of every generation. Failing it fails the request"
+    )]
+    pre_hook: Option<String>,
+    #[arg(
+        long,
+        value_name = "Executable run after every generation is applied (or failed to), with the result"
+    )]
+    post_hook: Option<String>,
+    #[arg(long, value_name = "Directory of vtysh")]
+    bindir: Option<String>,
+    #[arg(long, value_name = "Directory of where frr-reload writes temp files")]
+    rundir: Option<String>,
+    #[arg(long, value_name = "Directory of frr config files")]
+    confdir: Option<String>,
+    #[arg(long, value_name = "Directory of vtysh sockets")]
+    vtysock: Option<String>,
+    #[arg(
+        long,
+        value_name = "FRR instance (pathspace, as run with -N) requests may target, with its own confdir, rundir and outdir (can be repeated)"
+    )]
+    instance: Vec<String>,
+    #[arg(
+        long,
+        value_name = "Network namespace (name or path) the reloader and vtysh are run in"
+    )]
+    netns: Option<String>,
+    #[arg(skip)]
+    pathspace: Option<String>, /* of the FRR instance the args are those of */
+    #[arg(
+        long,
+        value_name = "Seconds to wait at startup for FRR to be up before serving requests"
+    )]
+    wait_for_frr: Option<u64>,
+    #[arg(
+        long,
+        value_name = "What to do with requests applying configs while FRR is down (reject, hold). FRR is not checked by default"
+    )]
+    frr_down: Option<String>,
+    #[arg(
+        long,
+        value_name = "How to persist the configs applied (copy to frr.conf, write-memory). Not persisted by default"
+    )]
+    persist: Option<String>,
+    #[arg(
+        long,
+        value_name = "Number of requests applying configs queued while another is processed, the others being refused as BUSY. Requests wait for their turn by default"
+    )]
+    apply_queue: Option<usize>,
+    #[arg(
+        long,
+        value_name = "Number of most recent generations kept in outdir. All are kept by default"
+    )]
+    keep_generations: Option<usize>,
+    #[arg(
+        long,
+        value_name = "Days generations are kept in outdir. Kept forever by default"
+    )]
+    keep_days: Option<u64>,
+    #[arg(
+        long,
+        value_name = "BGP graceful restart of configs changing BGP (enable, verify). Disabled by default"
+    )]
+    bgp_gr: Option<String>,
+    #[arg(
+        long,
+        value_name = "Long-lived graceful restart stale time (seconds) enabled along with --bgp-gr enable"
+    )]
+    bgp_llgr_stale_time: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Seconds to drain BGP peers (graceful shutdown) before applying configs changing BGP"
+    )]
+    bgp_graceful_shutdown: Option<u64>,
+    #[arg(long, value_name = "FRR init script (start|stop|restart)")]
+    frrinit: Option<String>,
+    #[arg(
+        long,
+        value_name = "systemd unit of FRR. If set, FRR is controlled via systemd instead of frrinit"
+    )]
+    frr_unit: Option<String>,
+    #[arg(long, value_name = "FRR daemons to enable on start (e.g. bgpd,bfdd)")]
+    daemons: Option<String>,
+    #[arg(
+        long,
+        value_name = "Uid allowed to start/stop/restart FRR (can be repeated). Defaults to 0"
+    )]
+    service_uid: Vec<u32>,
+    #[arg(
+        long,
+        value_name = "Uid allowed to connect (can be repeated). Any peer may connect by default"
+    )]
+    allowed_uid: Vec<u32>,
+    #[arg(
+        long,
+        value_name = "Gid allowed to connect (can be repeated). Any peer may connect by default"
+    )]
+    allowed_gid: Vec<u32>,
+    #[arg(
+        long,
+        value_name = "vtysh command allowed in passthrough sessions, by its leading words (e.g. 'show'; can be repeated). Passthrough is disabled by default"
+    )]
+    vtysh_allow: Vec<String>,
+    #[arg(
+        long,
+        value_name = "vtysh show command allowed in SHOW requests, by its leading words (can be repeated). Defaults to BGP, route, BFD, EVPN and interface state in JSON"
+    )]
+    show_allow: Vec<String>,
+    #[arg(long, value_name = "FRR log file. Defaults to /var/log/frr/frr.log")]
+    frr_log: Option<String>,
+    #[arg(long)]
+    frr_log_journal: bool,
+    #[arg(
+        long,
+        value_name = "Max keepalive interval (seconds) clients may negotiate. Defaults to 60"
+    )]
+    keepalive_max: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Seconds sending a response may block before it is retried. Defaults to 10"
+    )]
+    send_timeout: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Check the FRR daemons every this number of seconds. Disabled by default"
+    )]
+    monitor_interval: Option<u64>,
+    #[arg(
+        long,
+        value_name = "File the state of the agent is periodically written to (JSON). Not written by default"
+    )]
+    state_file: Option<String>,
+    #[arg(
+        long,
+        value_name = "Write the state file every this number of seconds. Defaults to 10"
+    )]
+    state_interval: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Address to serve Prometheus metrics at over HTTP (e.g. 127.0.0.1:9101). Disabled by default"
+    )]
+    metrics_addr: Option<String>,
+    #[arg(
+        long,
+        value_name = "Address to serve readiness and liveness probes at over HTTP (e.g. 0.0.0.0:9102). Disabled by default"
+    )]
+    probe_addr: Option<String>,
+    #[arg(
+        long,
+        value_name = "Seconds an apply may run before the liveness probe fails. Defaults to 600"
+    )]
+    liveness_max_apply: Option<u64>,
+    #[arg(long)]
+    systemd: bool,
+    #[arg(
+        long,
+        value_name = "Seconds routing must have been quiet for before applying. Disabled by default"
+    )]
+    quiet_window: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Max seconds an apply is deferred for routing to be quiet. Defaults to 300"
+    )]
+    quiet_max_wait: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Routes the RIB may change by per second while quiet. Defaults to 100"
+    )]
+    quiet_rib_churn: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Check run after every apply, like '<show command> => <condition>' (can be repeated). No verification by default"
+    )]
+    verify: Vec<String>,
+    #[arg(
+        long,
+        value_name = "Seconds the checks of --verify may take to hold after an apply. Defaults to 30"
+    )]
+    verify_timeout: Option<u64>,
+    #[arg(long)]
+    verify_rollback: bool,
+    #[arg(
+        long,
+        value_name = "Loki push url to ship reload transcripts to (e.g. http://loki:3100/loki/api/v1/push)"
+    )]
+    loki_url: Option<String>,
+    #[arg(
+        long,
+        value_name = "OTLP/HTTP logs url to ship reload transcripts to (e.g. http://otel:4318/v1/logs)"
+    )]
+    otlp_url: Option<String>,
+    #[arg(
+        long,
+        value_name = "Archive to restore the generation history from on first boot (see EXPORT)"
+    )]
+    restore_from: Option<String>,
+    #[arg(
+        long,
+        value_name = "Config to apply as generation 0 on first boot, before serving requests (- for stdin)"
+    )]
+    initial_config: Option<String>,
+    #[arg(
+        long,
+        value_name = "S3 bucket to back up the applied generations to. Disabled by default"
+    )]
+    s3_bucket: Option<String>,
+    #[arg(
+        long,
+        value_name = "S3 endpoint url. Defaults to https://s3.amazonaws.com"
+    )]
+    s3_endpoint: Option<String>,
+    #[arg(long, value_name = "S3 region. Defaults to us-east-1")]
+    s3_region: Option<String>,
+    #[arg(long, value_name = "Prefix of the S3 objects (e.g. node-1/)")]
+    s3_prefix: Option<String>,
+    #[arg(
+        long,
+        value_name = "Git repository to commit the applied generations to. Disabled by default"
+    )]
+    git_history: Option<String>,
+    #[arg(
+        long,
+        value_name = "Git remote to push the git history to after every commit"
+    )]
+    git_remote: Option<String>,
+    #[arg(long)]
+    audit_log: bool,
+    #[arg(long)]
+    canonicalize: bool,
+    #[arg(long)]
+    shadow: bool,
+    #[arg(long)]
+    baseline: bool,
+    #[arg(long)]
+    reapply_interrupted: bool,
+    #[arg(long)]
+    reapply_on_restart: bool,
+    #[arg(long)]
+    strict_utf8: bool,
+    #[arg(long)]
+    skip_unchanged: bool,
+    #[arg(long)]
+    monotonic_genid: bool,
+    #[arg(long)]
+    require_force: bool,
+    #[arg(long)]
+    static_fast_path: bool,
+    #[arg(long)]
+    templating: bool,
+    #[arg(
+        long,
+        value_name = "JSON file of node facts for templating (e.g. {\"asn\": 65001})"
+    )]
+    facts_file: Option<String>,
+    #[arg(
+        long,
+        value_name = "Socket of a secondary agent to forward the generations applied to"
+    )]
+    secondary_sock: Option<String>,
+    #[arg(long, value_name = "Directory to forward the generations applied to")]
+    secondary_dir: Option<String>,
+    #[arg(
+        long,
+        value_name = "Policy file restricting the content of the configs applied (see README)"
+    )]
+    policy: Option<String>,
+    #[arg(
+        long,
+        value_name = "Requests per second allowed to each peer (uid). Unlimited by default"
+    )]
+    peer_rate: Option<f64>,
+    #[arg(
+        long,
+        value_name = "Applies allowed to each peer (uid) per day. Unlimited by default"
+    )]
+    peer_daily_applies: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Applies allowed per minute, whatever the peer. Unlimited by default"
+    )]
+    max_applies_per_minute: Option<usize>,
+    #[arg(
+        long,
+        value_name = "Concurrent connections allowed to each peer (uid). Unlimited by default"
+    )]
+    max_peer_connections: Option<usize>,
+    #[arg(
+        long,
+        value_name = "Seconds a prepared generation stays locked in without commit. Defaults to 300"
+    )]
+    prepare_timeout: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Consecutive failed applies that trip the circuit breaker. Disabled by default"
+    )]
+    breaker_threshold: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Seconds the circuit breaker stays open unless reset. Defaults to 300"
+    )]
+    breaker_cooldown: Option<u64>,
+    #[arg(
+        long,
+        value_name = "Webhook url to POST alerts to (e.g. http://alertmanager:9095/frr-agent)"
+    )]
+    alert_webhook: Option<String>,
+    #[arg(
+        long,
+        value_name = "SNMP manager to send alerts to as traps (host[:port])"
+    )]
+    alert_snmp: Option<String>,
+    #[arg(long, value_name = "SNMP community of the traps. Defaults to public")]
+    alert_snmp_community: Option<String>,
+    #[arg(
+        long,
+        value_name = "Script to run on alerts, with the payload on stdin"
+    )]
+    alert_exec: Option<String>,
+    #[arg(
+        long,
+        value_name = "Template of the alert payload, with {event}, {genid}, {host}, {node_id}, {fabric}, {time} and {message}"
+    )]
+    alert_template: Option<String>,
+    #[arg(
+        long,
+        value_name = "Number of reload transcripts and events kept for LOGS. Defaults to 50"
+    )]
+    logs_size: Option<usize>,
+    #[arg(
+        long,
+        value_name = "Seconds the reloader runs of a request may take before an alert is fired. No SLO by default"
+    )]
+    reload_slo: Option<u64>,
+
+    // testing-only
+    #[arg(long)]
+    always_ok: bool,
+    #[arg(
+        long,
+        value_name = "Artificially increase processing time by this number of seconds"
+    )]
+    proc_time: Option<u64>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+// alternative modes of the binary
+#[derive(Clone, Debug, PartialEq, Subcommand)]
+enum Commands {
+    #[command(about = "Drive the agent at sock-path with synthetic configs and report latencies")]
+    Bench(BenchArgs),
+    #[command(
+        about = "Send a config or a request to the agent at sock-path and print the response"
+    )]
+    Send(SendArgs),
+    #[command(about = "Verify the chain of the audit log in outdir")]
+    VerifyAudit,
+    /* spawned by the agent before dropping its privileges (see privileged) */
+    #[command(name = privileged::HELPER_COMMAND, hide = true)]
+    PrivilegedHelper,
+}
+impl Args {
+    // whether logs go to stderr, stdout being the output of the command run (the privileged
+    // helper talks to the agent over stdout)
+    fn logs_to_stderr(&self) -> bool {
+        matches!(
+            self.command,
+            Some(Commands::Send(_) | Commands::PrivilegedHelper)
+        )
+    }
+    pub(crate) fn liveness_max_apply(&self) -> Duration {
+        Duration::from_secs(self.liveness_max_apply.unwrap_or(600))
+    }
+    pub fn binddir(&self) -> &str {
+        self.bindir.as_ref().map_or("/usr/local/bin", |v| v)
+    }
+    pub fn rundir(&self) -> &str {
+        self.rundir.as_ref().map_or("/var/run/frr", |v| v)
+    }
+    pub fn confdir(&self) -> &str {
+        self.confdir.as_ref().map_or("/etc/frr", |v| v)
+    }
+    pub fn reloader(&self) -> &str {
+        self.reloader
+            .as_ref()
+            .map_or("/hedgehog/frr-reload.py", |v| v)
+    }
+    pub fn frrinit(&self) -> &str {
+        self.frrinit
+            .as_ref()
+            .map_or("/usr/lib/frr/frrinit.sh", |v| v)
+    }
+    pub fn frr_service(&self) -> FrrService<'_> {
+        match &self.frr_unit {
+            Some(unit) => FrrService::Systemd(unit),
+            None => FrrService::Init(self.frrinit()),
+        }
+    }
+    pub fn service_uids(&self) -> &[u32] {
+        if self.service_uid.is_empty() {
+            &[0]
+        } else {
+            &self.service_uid
+        }
+    }
+    pub fn show_allow(&self) -> Vec<String> {
+        if self.show_allow.is_empty() {
+            DEFAULT_SHOW_ALLOW.iter().map(ToString::to_string).collect()
+        } else {
+            self.show_allow.clone()
+        }
+    }
+    pub fn log_source(&self) -> LogSource {
+        if self.frr_log_journal {
+            LogSource::Journal(self.frr_unit.clone().unwrap_or("frr".to_string()))
+        } else {
+            LogSource::File(
+                self.frr_log
+                    .clone()
+                    .unwrap_or("/var/log/frr/frr.log".to_string()),
+            )
+        }
+    }
+    pub fn keepalive_max(&self) -> Duration {
+        Duration::from_secs(self.keepalive_max.unwrap_or(60).max(1))
+    }
+    pub fn send_timeout(&self) -> Duration {
+        Duration::from_secs(self.send_timeout.unwrap_or(10).max(1))
+    }
+    pub fn prepare_timeout(&self) -> Duration {
+        Duration::from_secs(self.prepare_timeout.unwrap_or(300))
+    }
+    pub fn reload_slo(&self) -> Option<Duration> {
+        self.reload_slo.map(Duration::from_secs)
+    }
+    pub fn logs_size(&self) -> usize {
+        self.logs_size.unwrap_or(50)
+    }
+    pub fn breaker_cooldown(&self) -> Duration {
+        Duration::from_secs(self.breaker_cooldown.unwrap_or(300))
+    }
+    pub fn object_store(&self) -> Option<ObjectStore> {
+        let bucket = self.s3_bucket.as_ref()?;
+        Some(ObjectStore::new(
+            self.s3_endpoint
+                .as_ref()
+                .map_or("https://s3.amazonaws.com", |v| v),
+            bucket,
+            self.s3_region.as_ref().map_or("us-east-1", |v| v),
+            self.s3_prefix.as_ref().map_or("", |v| v),
+        ))
+    }
+    // the args of an FRR instance: its confdir, rundir (and vtysh sockets) are the subdirectories
+    // named after it, as with FRR run with -N, and its generations are stored apart
+    pub(crate) fn for_instance(&self, name: &str) -> Result<Args, String> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("Bad FRR instance name '{name}'"));
+        }
+        if self.volatile_dir.is_some() {
+            return Err("--volatile-dir is not supported with FRR instances".to_string());
+        }
+        let sub = |dir: &str| format!("{}/{name}", dir.trim_end_matches('/'));
+        let mut args = self.clone();
+        args.instance.clear();
+        args.pathspace = Some(name.to_string());
+        args.outdir = Some(sub(self.outdir()));
+        args.rundir = Some(sub(self.rundir()));
+        args.confdir = Some(sub(self.confdir()));
+        args.vtysock = self.vtysock.as_deref().map(sub);
+        args.git_history = self.git_history.as_deref().map(sub);
+        args.secondary_sock = None;
+        args.secondary_dir = self.secondary_dir.as_deref().map(sub);
+        args.s3_prefix = Some(format!(
+            "{}{name}/",
+            self.s3_prefix.as_deref().unwrap_or_default()
+        ));
+        /* the day-0 config and the archive restored are those of the default instance */
+        args.initial_config = None;
+        args.restore_from = None;
+        Ok(args)
+    }
+    pub fn git_history(&self) -> Option<GitHistory> {
+        let repo = self.git_history.as_ref()?;
+        Some(GitHistory::new(repo, self.git_remote.as_deref()))
+    }
+    pub fn outdir(&self) -> &str {
+        self.outdir.as_ref().map_or("/tmp/configs/hedgehog", |v| v)
+    }
+    pub(crate) fn max_message_size(&self) -> u64 {
+        self.max_message_size
+            .unwrap_or(64)
+            .saturating_mul(1024 * 1024)
+    }
+    pub(crate) fn max_transfer_size(&self) -> u64 {
+        self.max_transfer_size
+            .unwrap_or(256)
+            .saturating_mul(1024 * 1024)
+    }
+    pub fn loglevel(&self) -> Result<Level, ()> {
+        if let Some(loglevel) = &self.loglevel {
+            Level::from_str(loglevel.as_ref()).map_err(|_| ())
+        } else {
+            Ok(Level::DEBUG)
+        }
+    }
+    pub(crate) fn log_format(&self) -> Result<LogFormat, ()> {
+        self.log_format
+            .as_deref()
+            .map_or(Some(LogFormat::Text), LogFormat::parse)
+            .ok_or(())
+    }
+    pub(crate) fn log_file(&self) -> Option<Result<LogFile, String>> {
+        let path = self.log_file.as_deref()?;
+        Some(LogFile::open(
+            path,
+            self.log_file_max_size
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+            self.log_file_max_age
+                .map(|hours| Duration::from_secs(hours.saturating_mul(3600))),
+            self.log_file_keep.unwrap_or(5),
+        ))
+    }
+    pub(crate) fn frr_down_policy(&self) -> Option<FrrDownPolicy> {
+        self.frr_down.as_deref().and_then(FrrDownPolicy::parse)
+    }
+    pub(crate) fn persist_mode(&self) -> Option<PersistMode> {
+        self.persist.as_deref().and_then(PersistMode::parse)
+    }
+    pub(crate) fn retention(&self) -> Option<Retention> {
+        Retention::new(self.keep_generations, self.keep_days)
+    }
+    pub(crate) fn verifier(&self) -> Result<Option<Verifier>, String> {
+        Verifier::new(
+            &self.verify,
+            Duration::from_secs(self.verify_timeout.unwrap_or(30)),
+        )
+    }
+    pub(crate) fn convergence(&self) -> Option<Convergence> {
+        let window = self.quiet_window?;
+        Some(Convergence::new(
+            Duration::from_secs(window),
+            Duration::from_secs(self.quiet_max_wait.unwrap_or(300)),
+            self.quiet_rib_churn.unwrap_or(100),
+        ))
+    }
+    pub(crate) fn bgp_gr_mode(&self) -> Option<GrMode> {
+        self.bgp_gr.as_deref().and_then(GrMode::parse)
+    }
+    pub fn proc_time(&self) {
+        if let Some(time) = self.proc_time {
+            debug!("Sleeping for {time} seconds...");
+            sleep(Duration::from_secs(time));
+        }
+    }
+}
+
+// remove the socket and exit on termination signals
+fn handle_signals(bind_addr: String) {
+    if let Ok(mut signals) = Signals::new([SIGINT, SIGQUIT, SIGTERM]) {
+        thread::spawn(move || {
+            if let Some(sig) = signals.forever().next() {
+                match sig {
+                    SIGINT | SIGTERM | SIGQUIT => {
+                        warn!("Terminated (pid {}) on signal {sig}", std::process::id());
+                        systemd::notify("STOPPING=1");
+                        match privileged::remove_socket(&bind_addr) {
+                            Ok(()) => info!("Removed sock at {bind_addr}"),
+                            Err(e) if matches!(e.kind(), ErrorKind::NotFound) => {
+                                debug!("Did not find {bind_addr}");
+                            }
+                            Err(e) => warn!("Could not remove {bind_addr}: {e}"),
+                        }
+                        std::process::exit(0);
+                    }
+                    _ => {
+                        warn!("Ignoring signal {sig}");
+                    }
+                }
+            }
+        });
+    }
+}
+
+// get FRR ready for the agent: enable the requested daemons and wait for FRR to be up, if told so
+fn prepare_frr(args: &Args) {
+    // the policy for requests applying configs while FRR is down
+    if let Some(policy) = &args.frr_down
+        && args.frr_down_policy().is_none()
+    {
+        error!("FATAL: Bad FRR down policy '{policy}'. Exiting....");
+        exit(1);
+    }
+
+    // the persistence of the configs applied
+    if let Some(mode) = &args.persist
+        && args.persist_mode().is_none()
+    {
+        error!("FATAL: Bad persistence mode '{mode}'. Exiting....");
+        exit(1);
+    }
+
+    // the checks verifying the configs applied
+    if let Err(e) = args.verifier() {
+        error!("FATAL: {e}. Exiting....");
+        exit(1);
+    }
+
+    // the graceful restart of configs changing BGP
+    if let Some(mode) = &args.bgp_gr
+        && args.bgp_gr_mode().is_none()
+    {
+        error!("FATAL: Bad BGP graceful restart mode '{mode}'. Exiting....");
+        exit(1);
+    }
+
+    // make sure the requested FRR daemons are enabled
+    if let Some(daemons) = &args.daemons {
+        let daemons = parse_list(daemons);
+        if let Err(e) = set_frr_daemons(args.confdir(), &args.frr_service(), &daemons) {
+            error!("Failed to enable FRR daemons {daemons:?}: {e}");
+        }
+    }
+
+    // wait for FRR to be up before serving requests
+    if let Some(timeout) = args.wait_for_frr
+        && let Err(e) = wait_for_frr(args, Duration::from_secs(timeout))
+    {
+        error!("FATAL: {e}. Exiting....");
+        exit(1);
+    }
+}
+
+// get the generation history of the node ready before serving requests: recover from the
+// reloads interrupted and, on first boot, restore it, apply the day-0 config and take a baseline
+fn prepare_history(args: &Args, agent: &Agent) {
+    // create the git history, if enabled
+    if let Some(history) = &agent.history
+        && let Err(e) = history.init()
+    {
+        error!("FATAL: Failed to initialize git history: {e}. Exiting....");
+        exit(1);
+    }
+
+    // report (and clean up after) the reloads interrupted by the agent stopping, if any
+    agent.recover();
+
+    // restore the history of the node before serving requests
+    if let Some(archive) = &args.restore_from
+        && let Err(e) = agent.restore(archive)
+    {
+        error!("FATAL: Failed to restore from {archive}: {e}. Exiting....");
+        exit(1);
+    }
+
+    // apply the day-0 config on first boot
+    if let Some(path) = &args.initial_config
+        && let Err(e) = agent.apply_initial(path)
+    {
+        error!("FATAL: {e}. Exiting....");
+        exit(1);
+    }
+
+    // store the running config as generation 0 on first boot
+    if args.baseline {
+        agent.baseline();
+    }
+}
+
+// run an alternative mode of the binary, then exit
+fn run_command(args: &Args, command: &Commands) -> ! {
+    match command {
+        Commands::Bench(bench_args) => match bench(args, bench_args) {
+            Ok(report) => {
+                println!("{report}");
+                exit(0);
+            }
+            Err(e) => {
+                error!("Benchmark failed: {e}");
+                exit(1);
+            }
+        },
+        Commands::Send(send_args) => match send(args, send_args) {
+            Ok(success) => exit(i32::from(!success)),
+            Err(e) => {
+                error!("Failed to send request: {e}");
+                exit(2);
+            }
+        },
+        Commands::VerifyAudit => match audit::verify(args.outdir()) {
+            Ok(records) => {
+                println!("{records} records, chain intact");
+                exit(0);
+            }
+            Err(e) => {
+                println!("Audit log chain broken: {e}");
+                exit(1);
+            }
+        },
+        Commands::PrivilegedHelper => privileged::run(args),
+    }
+}
+
+// the agent, serving the requests of clients on its socket(s), as run by the frr-agent binary
+pub struct Server {
+    args: Args,
+}
+
+impl Server {
+    // a server configured by a command line (program name first), merged with the config file
+    // it names (--config), if any
+    pub fn from_cmdline(cmdline: &[String]) -> Result<Self, String> {
+        let args = Args::try_parse_from(configfile::merged(cmdline)?).map_err(|e| e.to_string())?;
+        Ok(Self { args })
+    }
+
+    // a server configured by the command line of the process, exiting if it is bad
+    #[must_use]
+    pub fn from_env() -> Self {
+        let cmdline: Vec<String> = std::env::args().collect();
+        match configfile::merged(&cmdline) {
+            Ok(args) => Self {
+                args: Args::parse_from(args),
+            },
+            Err(e) => {
+                println!("{e}");
+                exit(1);
+            }
+        }
+    }
+
+    // run the agent until terminated, or the command given (e.g. send) until done
+    pub fn run(&self) -> ! {
+        let args = &self.args;
+        let Ok(loglevel) = args.loglevel() else {
+            println!("Bad loglevel");
+            exit(1);
+        };
+        let Ok(log_format) = args.log_format() else {
+            println!("Bad log format");
+            exit(1);
+        };
+        let log_file = match args.log_file().transpose() {
+            Ok(log_file) => log_file,
+            Err(e) => {
+                println!("{e}");
+                exit(1);
+            }
+        };
+        node::set_identity(args.node_id.as_deref(), args.fabric.as_deref());
+        init_logging(loglevel, log_format, log_file, args.logs_to_stderr());
+
+        if let Some(command) = &args.command {
+            run_command(args, command);
+        }
+
+        handle_signals(args.sock_path.clone());
+
+        debug!("Starting FRR-agent...");
+
+        let listeners = listen(args);
+        if args.systemd {
+            systemd::enable();
+        }
+
+        prepare_frr(args);
+
+        // the node facts for templating
+        if args.templating
+            && let Err(e) = facts::gather(args.facts_file.as_deref())
+        {
+            error!("FATAL: Bad node facts: {e}. Exiting....");
+            exit(1);
+        }
+        // configs may be kept apart from their metadata
+        if let Some(dir) = &args.volatile_dir {
+            keep_configs_in(dir);
+        }
+
+        // the args of the FRR instances requests may target
+        let instances: Vec<(&String, Args)> = match args
+            .instance
+            .iter()
+            .map(|name| args.for_instance(name).map(|instance| (name, instance)))
+            .collect()
+        {
+            Ok(instances) => instances,
+            Err(e) => {
+                error!("FATAL: {e}. Exiting....");
+                exit(1);
+            }
+        };
+
+        let mut agent = match build_agent(args) {
+            Ok(agent) => agent,
+            Err(e) => {
+                error!("FATAL: {e}. Exiting....");
+                exit(1);
+            }
+        };
+        prepare_history(args, &agent);
+
+        // the FRR instances are served by agents of their own
+        for (name, instance_args) in &instances {
+            let instance = match build_agent(instance_args) {
+                Ok(instance) => instance,
+                Err(e) => {
+                    error!("FATAL: FRR instance {name}: {e}. Exiting....");
+                    exit(1);
+                }
+            };
+            prepare_history(instance_args, &instance);
+            info!(
+                "frr-agent serves FRR instance {name} (configs at '{}')",
+                instance_args.outdir()
+            );
+            agent.add_instance(name, instance);
+        }
+
+        info!(
+            "frr-agent {} (git {}, built with {}) listening at {} started",
+            buildinfo::VERSION,
+            buildinfo::GIT_SHA,
+            buildinfo::RUSTC_VERSION,
+            listeners.describe()
+        );
+        debug!(
+            "frr-agent writes configs at '{}'",
+            config_dir(args.outdir())
+        );
+        debug!("frr-agent reloader is '{}'", args.reloader());
+        if let Some(run_as) = agent.reloader.run_as {
+            debug!("frr-agent runs the reloader as {run_as}");
+        }
+        debug!("frr-agent loglevel is '{}'", loglevel);
+
+        if let Err(e) = drop_privileges(args, &agent) {
+            error!("FATAL: Could not drop privileges: {e}. Exiting....");
+            exit(1);
+        }
+        systemd::notify("READY=1");
+        serve(args, &listeners, &agent);
+        exit(0);
+    }
+}
+
+// the agent serving the requests for the FRR instance the args are those of, with the transcript
+// collectors, alert sinks, reloader and policy set in the args
+fn build_agent(args: &Args) -> Result<Agent<'_>, String> {
+    let shipper = TranscriptShipper::new(args.loki_url.as_deref(), args.otlp_url.as_deref())
+        .map_err(|e| format!("Bad transcript collector: {e}"))?;
+    let alerter = Alerter::new(
+        args.alert_webhook.as_deref(),
+        args.alert_snmp.as_deref(),
+        args.alert_snmp_community.as_deref().unwrap_or("public"),
+        args.alert_exec.as_deref(),
+        args.alert_template.as_deref(),
+    )
+    .map_err(|e| format!("Bad alert sink: {e}"))?;
+    let reloader = build_reloader(args).map_err(|e| format!("Bad reloader settings: {e}"))?;
+    let policy = Policy::load(args.policy.as_deref()).map_err(|e| format!("Bad policy: {e}"))?;
+    Ok(Agent::new(args, reloader, shipper, alerter, policy))
+}
+
+// the listeners connections to the agent are accepted on, and those of its HTTP endpoints
+pub(crate) struct Listeners {
+    unix: Option<UnixListener>,
+    vsock: Option<VsockListener>,
+    tcp: Option<TlsListener>,
+    metrics: Option<TcpListener>,
+    probes: Option<TcpListener>,
+}
+impl Listeners {
+    pub(crate) fn unix(listener: UnixListener) -> Self {
+        Self {
+            unix: Some(listener),
+            vsock: None,
+            tcp: None,
+            metrics: None,
+            probes: None,
+        }
+    }
+    fn describe(&self) -> String {
+        let mut addrs = vec![];
+        if let Some(addr) = self.unix.as_ref().and_then(|unix| unix.local_addr().ok()) {
+            addrs.push(format!(
+                "'{}'",
+                addr.as_pathname().unwrap_or(Path::new("?")).display()
+            ));
+        }
+        if let Some(vsock) = &self.vsock {
+            addrs.push(format!("vsock port {}", vsock.port()));
+        }
+        if let Some(addr) = self.tcp.as_ref().and_then(TlsListener::local_addr) {
+            addrs.push(format!("tcp {addr} (mutual TLS)"));
+        }
+        addrs.join(" and ")
+    }
+}
+
+// bind the listener of an HTTP endpoint, which is not served if it can not be bound
+fn listen_http(addr: Option<&String>, endpoint: &str) -> Option<TcpListener> {
+    let addr = addr?;
+    TcpListener::bind(addr)
+        .inspect_err(|e| error!("Could not serve {endpoint} at {addr}: {e}"))
+        .ok()
+}
+
+// bind the TCP listener, which requires the certificate and key of the agent and the CA of clients
+fn listen_tls(args: &Args) -> Option<TlsListener> {
+    let addr = args.tcp_addr.as_ref()?;
+    let (Some(cert), Some(key), Some(ca)) = (&args.tls_cert, &args.tls_key, &args.tls_ca) else {
+        error!("FATAL: --tcp-addr requires --tls-cert, --tls-key and --tls-ca. Exiting....");
+        exit(1);
+    };
+    match TlsListener::bind(addr, cert, key, ca) {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            error!("FATAL: {e}. Exiting....");
+            exit(1);
+        }
+    }
+}
+
+// create the unix socket (unless --vsock-only), vsock, TCP and HTTP listeners
+fn listen(args: &Args) -> Listeners {
+    set_max_message_size(args.max_message_size());
+    if args.vsock_only && args.vsock_port.is_none() {
+        error!("FATAL: --vsock-only requires --vsock-port. Exiting....");
+        exit(1);
+    }
+    if !args.vsock_allowed_cid.is_empty() && args.vsock_port.is_none() {
+        error!("FATAL: --vsock-allowed-cid requires --vsock-port. Exiting....");
+        exit(1);
+    }
+    /* uids and gids can not restrict vsock peers: restricting only the unix socket would be
+     * deceptive, so vsock peers must be restricted too */
+    if args.vsock_port.is_some()
+        && args.vsock_allowed_cid.is_empty()
+        && !(args.allowed_uid.is_empty() && args.allowed_gid.is_empty())
+    {
+        error!(
+            "FATAL: --allowed-uid/--allowed-gid do not apply to vsock peers: restrict them with --vsock-allowed-cid. Exiting...."
+        );
+        exit(1);
+    }
+    let unix = if args.vsock_only {
+        None
+    } else {
+        match sock_perms(args).and_then(|perms| create_unix_listener(&args.sock_path, &perms)) {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                error!("FATAL: Failed to open unix socket: {e:?}. Exiting....");
+                exit(1);
+            }
+        }
+    };
+    let vsock = args.vsock_port.map(|port| match VsockListener::bind(port) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("FATAL: {e}. Exiting....");
+            exit(1);
+        }
+    });
+    Listeners {
+        unix,
+        vsock,
+        tcp: listen_tls(args),
+        metrics: listen_http(args.metrics_addr.as_ref(), "metrics"),
+        probes: listen_http(args.probe_addr.as_ref(), "probes"),
+    }
+}
+
+// drop the privileges of the agent to --run-as-user, once its listeners are bound and before
+// any client is served. The agent keeps the supplementary groups of the user (e.g. frrvty), so
+// that it can still run vtysh and the reloader: nothing requiring root may be asked of them.
+fn drop_privileges(args: &Args, agent: &Agent) -> Result<(), String> {
+    let Some(user) = &args.run_as_user else {
+        if args.run_as_group.is_some() {
+            return Err("--run-as-group requires --run-as-user".to_string());
+        }
+        return Ok(());
+    };
+    let Some(run_as) = RunAs::resolve(Some(user), args.run_as_group.as_deref())? else {
+        return Err(format!("No credentials for user '{user}'"));
+    };
+    for agent in agent.all() {
+        let reloader = &agent.reloader;
+        if reloader.netns.is_some() {
+            return Err("Entering a network namespace requires root: drop --netns".to_string());
+        }
+        if let Some(reload_as) = reloader.run_as
+            && (reload_as.uid.is_some_and(|uid| Some(uid) != run_as.uid)
+                || reload_as.gid != run_as.gid)
+        {
+            return Err(format!(
+                "The reloader can not be run as {reload_as} by {run_as}: drop --reload-as-user"
+            ));
+        }
+    }
+    let groups = runas::supplementary_groups(user)?;
+    /* what requires root once privileges are dropped is done by a helper keeping them */
+    privileged::spawn()?;
+    run_as.drop_privileges(&groups)?;
+    info!("frr-agent dropped privileges to {run_as} (supplementary groups {groups:?})");
+    /* what the agent keeps must still be writable */
+    for agent in agent.all() {
+        let outdir = agent.args.outdir();
+        for dir in [outdir, config_dir(outdir)] {
+            let probe = Path::new(dir).join(".frr-agent-writable");
+            let writable = fs::create_dir_all(dir)
+                .and_then(|()| fs::write(&probe, b""))
+                .and_then(|()| fs::remove_file(&probe));
+            if let Err(e) = writable {
+                return Err(format!("{dir} is not writable by {run_as}: {e}"));
+            }
+        }
+    }
+    Ok(())
+}
+
+// serve the connections to the agent forever
+pub(crate) fn serve(args: &Args, listeners: &Listeners, agent: &Agent) {
+    thread::scope(|scope| {
+        for agent in agent.all() {
+            // monitor the FRR daemons
+            if let Some(interval) = args.monitor_interval {
+                scope.spawn(move || agent.monitor.run(agent, Duration::from_secs(interval)));
+            }
+
+            // watch the convergence of routing, if applies are deferred until it is quiet
+            if let Some(convergence) = &agent.convergence {
+                scope.spawn(move || convergence.watch(agent.args));
+            }
+
+            // apply the requests held while FRR is down, once it is reachable
+            scope.spawn(move || agent.release_held());
+
+            // process the requests queued while others were processed
+            scope.spawn(move || agent.run_apply_queue());
+        }
+
+        // write the state of the agent for node-local consumers
+        if let Some(path) = &args.state_file {
+            let interval = Duration::from_secs(args.state_interval.unwrap_or(10).max(1));
+            scope.spawn(move || statefile::run(agent, path, interval));
+        }
+
+        // serve metrics to Prometheus
+        if let Some(listener) = &listeners.metrics {
+            scope.spawn(move || metrics::run(agent, listener));
+        }
+
+        // serve readiness and liveness probes
+        if let Some(listener) = &listeners.probes {
+            scope.spawn(move || probes::run(agent, listener));
+        }
+
+        // tell systemd the agent is not wedged
+        if let Some(period) = systemd::watchdog() {
+            scope.spawn(move || systemd::run_watchdog(agent, period));
+        }
+
+        // re-read the config file on SIGHUP
+        scope.spawn(move || configfile::watch(agent));
+
+        // serve the connections over vsock
+        if let Some(listener) = &listeners.vsock {
+            scope.spawn(move || {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, peer)) => {
+                            debug!("Got connection from {peer:?}");
+                            let peer = Peer::Vsock(peer);
+                            scope.spawn(move || handle_connection(stream, &peer, agent));
+                        }
+                        Err(e) => warn!("{e}"),
+                    }
+                }
+            });
+        }
+
+        // serve the connections over TCP, once their TLS handshake completes
+        if let Some(listener) = &listeners.tcp {
+            scope.spawn(move || {
+                loop {
+                    match listener.accept() {
+                        Ok((tcp, addr)) => {
+                            /* a slow handshake must not hold the others */
+                            scope.spawn(move || match listener.handshake(tcp, addr) {
+                                Ok((stream, peer)) => {
+                                    debug!("Got connection from {peer:?}");
+                                    let peer = Peer::Tls(peer);
+                                    handle_connection(stream, &peer, agent);
+                                }
+                                Err(e) => {
+                                    warn!("Rejecting connection: {e}");
+                                    METRICS.rejected_connection();
+                                }
+                            });
+                        }
+                        Err(e) => warn!("{e}"),
+                    }
+                }
+            });
+        }
+
+        if let Some(listener) = &listeners.unix {
+            loop {
+                debug!("┣━━━━ Waiting for connection ━━━━━┫");
+                if let Ok((stream, peer)) = listener.accept() {
+                    debug!("Got connection from {peer:?}");
+                    /* clients are served concurrently, each by a thread of its own */
+                    let peer = Peer::Unix(peer);
+                    scope.spawn(move || handle_connection(stream, &peer, agent));
+                }
+            }
+        }
+    });
+}
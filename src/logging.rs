@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Output of the logs: the format of log lines (--log-format) and the file they are written to
+// (--log-file), if not stdout. In the JSON format, every event is a line like
+//
+//   {"timestamp":"...","level":"INFO","target":"frr_agent::connection","message":"...",
+//    "spans":["peer"],"uid":0,"pid":1234,"comm":"controller","genid":7,...}
+//
+// with the fields of the event and those of the spans it is in (e.g. the credentials of the peer),
+// and messages spanning several lines (e.g. configs) escaped, so that log pipelines need not parse
+// the human format. Log files are rotated when they grow beyond --log-file-max-size or get older
+// than --log-file-max-age: the file is renamed with a suffix (.1, the previous .1 becoming .2 and so
+// on) and --log-file-keep rotated files are kept.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::field::{Field, Visit};
+use tracing::span::Record;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::json::Json;
+use crate::node::identity;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+impl LogFormat {
+    pub(crate) fn parse(format: &str) -> Option<Self> {
+        match format {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// collects the fields of events and spans as JSON values
+#[derive(Default)]
+struct JsonVisitor(BTreeMap<String, Json>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Json::Number(value));
+    }
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+// formats the fields of spans as JSON objects, so that they can be merged into log lines
+pub(crate) struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = JsonVisitor::default();
+        fields.record(&mut visitor);
+        write!(writer, "{}", Json::Object(visitor.0))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &Record<'_>,
+    ) -> fmt::Result {
+        let members = match Json::parse(&current.fields) {
+            Ok(Json::Object(members)) => members,
+            _ => BTreeMap::new(),
+        };
+        let mut visitor = JsonVisitor(members);
+        fields.record(&mut visitor);
+        current.fields = Json::Object(visitor.0).to_string();
+        Ok(())
+    }
+}
+
+// log line format with every event as a JSON object
+pub(crate) struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let metadata = event.metadata();
+        let mut members = BTreeMap::new();
+
+        /* the fields of the spans, outermost first, so that inner ones prevail */
+        let mut spans = vec![];
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                spans.push(Json::from(span.name()));
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<JsonFields>>()
+                    && let Ok(Json::Object(fields)) = Json::parse(&fields.fields)
+                {
+                    members.extend(fields);
+                }
+            }
+        }
+        let mut visitor = JsonVisitor(members);
+        event.record(&mut visitor);
+        let mut members = visitor.0;
+        members.insert("timestamp".to_string(), timestamp.into());
+        members.insert("level".to_string(), metadata.level().as_str().into());
+        members.insert("target".to_string(), metadata.target().into());
+        if !spans.is_empty() {
+            members.insert("spans".to_string(), Json::Array(spans));
+        }
+        let identity = identity();
+        if identity.is_set() {
+            members.insert("node".to_string(), identity.to_json());
+        }
+        writeln!(writer, "{}", Json::Object(members))
+    }
+}
+
+// a log file, rotated when too large or too old
+pub(crate) struct LogFile {
+    path: PathBuf,
+    max_size: Option<u64>,     /* bytes */
+    max_age: Option<Duration>, /* since the file was opened */
+    keep: u32,                 /* rotated files kept */
+    state: Mutex<LogFileState>,
+}
+
+struct LogFileState {
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+fn open(path: &PathBuf) -> io::Result<LogFileState> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(LogFileState {
+        file,
+        size,
+        opened: Instant::now(),
+    })
+}
+
+impl LogFile {
+    pub(crate) fn open(
+        path: &str,
+        max_size: Option<u64>,
+        max_age: Option<Duration>,
+        keep: u32,
+    ) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        let state =
+            open(&path).map_err(|e| format!("Could not open log file {}: {e}", path.display()))?;
+        Ok(Self {
+            path,
+            max_size,
+            max_age,
+            keep,
+            state: Mutex::new(state),
+        })
+    }
+
+    // the path of a rotated file
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    }
+
+    fn due(&self, state: &LogFileState) -> bool {
+        state.size > 0
+            && (self.max_size.is_some_and(|max| state.size >= max)
+                || self
+                    .max_age
+                    .is_some_and(|max| state.opened.elapsed() >= max))
+    }
+
+    // rename the file (and those rotated before), then open a new one
+    fn rotate(&self, state: &mut LogFileState) -> io::Result<()> {
+        let _ = fs::remove_file(self.rotated(self.keep.max(1)));
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        *state = open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Write for &LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::other("Log file lock poisoned"))?;
+        if self.due(&state) {
+            self.rotate(&mut state)?;
+        }
+        state.file.write_all(buf)?;
+        state.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::other("Log file lock poisoned"))?;
+        state.file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = &'a LogFile;
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
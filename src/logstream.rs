@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Streaming of FRR log lines to subscribed clients. Once a client subscribes, its connection becomes
+// an event channel on which every new FRR log line is sent, until the client disconnects. The log
+// lines are read from the FRR log file (with tail -F) or from the journal (with journalctl -f).
+
+use std::io::{BufRead, BufReader};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Stdio};
+use std::thread;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Debug)]
+pub(crate) enum LogSource {
+    File(String),    /* path of the FRR log file */
+    Journal(String), /* systemd unit of FRR */
+}
+
+impl LogSource {
+    fn command(&self) -> Command {
+        match self {
+            LogSource::File(path) => {
+                let mut cmd = Command::new("tail");
+                cmd.args(["-F", "-n", "0", path]);
+                cmd
+            }
+            LogSource::Journal(unit) => {
+                let mut cmd = Command::new("journalctl");
+                cmd.args(["-f", "-n", "0", "-o", "cat", "-u", unit]);
+                cmd
+            }
+        }
+    }
+}
+
+// stream the FRR log lines to a client in a thread of its own. Each line is sent with send_line
+// until it fails (e.g. because the client disconnected).
+pub(crate) fn stream_logs<F>(source: LogSource, stream: UnixStream, send_line: F)
+where
+    F: Fn(&mut UnixStream, &str) -> Result<(), String> + Send + 'static,
+{
+    let mut stream = stream;
+    thread::spawn(move || {
+        let mut cmd = source.command();
+        let mut child = match cmd.stdout(Stdio::piped()).stderr(Stdio::null()).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to spawn {cmd:?} to stream FRR logs: {e}");
+                let _ = stream.shutdown(Shutdown::Both);
+                return;
+            }
+        };
+        info!("Streaming FRR logs from {source:?}");
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if let Err(e) = send_line(&mut stream, &line) {
+                    debug!("Stopped streaming FRR logs: {e}");
+                    break;
+                }
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        let _ = stream.shutdown(Shutdown::Both);
+        info!("FRR log subscription ended");
+    });
+}
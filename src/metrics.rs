@@ -0,0 +1,338 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Prometheus metrics. With --metrics-addr, the agent serves its metrics over HTTP (GET /metrics)
+// in the Prometheus text format, so that a fleet of agents can be monitored without scraping logs:
+// the runs of the reloader (count by stage and result, and duration histograms), the generations
+// applied, the connections and protocol decode errors, along with the counters otherwise reported
+// by STATUS. Every series is labelled with the identity of the node, if set.
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::SEND_STATS;
+use crate::agent::Agent;
+use crate::buildinfo::{GIT_SHA, VERSION};
+use crate::framing::FRAMING_STATS;
+use crate::node;
+use crate::reload::ReloaderRun;
+
+// upper bounds of the buckets of the duration histograms, in seconds
+const BUCKETS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+// scrapes not completed within this time are dropped
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()], /* observations per bucket, not cumulative */
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        let us = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+}
+
+// the runs of the reloader in a stage (--test or --reload)
+pub(crate) struct StageMetrics {
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    duration: Histogram,
+}
+impl StageMetrics {
+    const fn new() -> Self {
+        Self {
+            succeeded: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            duration: Histogram::new(),
+        }
+    }
+}
+
+pub(crate) struct Metrics {
+    tests: StageMetrics,
+    reloads: StageMetrics,
+    connections: AtomicU64,
+    active_connections: AtomicU64,
+    rejected_connections: AtomicU64,
+    decode_errors: AtomicU64,
+}
+pub(crate) static METRICS: Metrics = Metrics {
+    tests: StageMetrics::new(),
+    reloads: StageMetrics::new(),
+    connections: AtomicU64::new(0),
+    active_connections: AtomicU64::new(0),
+    rejected_connections: AtomicU64::new(0),
+    decode_errors: AtomicU64::new(0),
+};
+
+// a connection accounted as active until dropped
+pub(crate) struct ConnectionGuard;
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        METRICS.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub(crate) fn reloader_run(&self, run: &ReloaderRun) {
+        let stage = if run.test { &self.tests } else { &self.reloads };
+        let result = if run.success {
+            &stage.succeeded
+        } else {
+            &stage.failed
+        };
+        result.fetch_add(1, Ordering::Relaxed);
+        stage.duration.observe(run.duration);
+    }
+    pub(crate) fn connection(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard
+    }
+    pub(crate) fn rejected_connection(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+    pub(crate) fn decode_error(&self) {
+        self.decode_errors.fetch_add(1, Ordering::Relaxed);
+    }
+    // failing to receive the start of a message is the client going away, anything else is an
+    // error decoding the message
+    pub(crate) fn receive_error(&self, e: &str) {
+        if !e.starts_with("Could not receive msg-len") && !e.starts_with("Could not receive magic")
+        {
+            self.decode_error();
+        }
+    }
+}
+
+// metrics in the Prometheus text format
+struct Exposition {
+    text: String,
+    labels: Vec<(&'static str, String)>, /* of every series */
+}
+impl Exposition {
+    fn new() -> Self {
+        let labels = node::identity()
+            .labels()
+            .into_iter()
+            .map(|(name, value)| (name, value.to_string()))
+            .collect();
+        Self {
+            text: String::new(),
+            labels,
+        }
+    }
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+    }
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        let escape = |value: &str| {
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        };
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .chain(labels.iter().copied())
+            .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+            .collect();
+        if labels.is_empty() {
+            let _ = writeln!(self.text, "{name} {value}");
+        } else {
+            let _ = writeln!(self.text, "{name}{{{}}} {value}", labels.join(","));
+        }
+    }
+    fn counter(&mut self, name: &str, help: &str, value: &AtomicU64) {
+        self.header(name, "counter", help);
+        self.sample(name, &[], value.load(Ordering::Relaxed));
+    }
+    fn gauge(&mut self, name: &str, help: &str, value: Option<impl std::fmt::Display>) {
+        if let Some(value) = value {
+            self.header(name, "gauge", help);
+            self.sample(name, &[], value);
+        }
+    }
+    fn stage(&mut self, stage: &str, metrics: &StageMetrics) {
+        let name = format!("frr_agent_{stage}s_total");
+        self.header(
+            &name,
+            "counter",
+            &format!("Runs of the reloader in the {stage} stage"),
+        );
+        self.sample(
+            &name,
+            &[("result", "success")],
+            metrics.succeeded.load(Ordering::Relaxed),
+        );
+        self.sample(
+            &name,
+            &[("result", "failure")],
+            metrics.failed.load(Ordering::Relaxed),
+        );
+
+        let name = format!("frr_agent_{stage}_duration_seconds");
+        let histogram = &metrics.duration;
+        self.header(
+            &name,
+            "histogram",
+            &format!("Duration of the {stage} stage"),
+        );
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            self.sample(
+                &format!("{name}_bucket"),
+                &[("le", &bound.to_string())],
+                cumulative,
+            );
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        #[allow(clippy::cast_precision_loss)]
+        let sum = histogram.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        self.sample(&format!("{name}_bucket"), &[("le", "+Inf")], count);
+        self.sample(&format!("{name}_sum"), &[], sum);
+        self.sample(&format!("{name}_count"), &[], count);
+    }
+}
+
+// the metrics of an agent
+pub(crate) fn render(agent: &Agent) -> String {
+    let mut metrics = Exposition::new();
+    metrics.header("frr_agent_build_info", "gauge", "Build of the agent");
+    metrics.sample(
+        "frr_agent_build_info",
+        &[("version", VERSION), ("git_sha", GIT_SHA)],
+        1,
+    );
+    metrics.stage("test", &METRICS.tests);
+    metrics.stage("reload", &METRICS.reloads);
+    metrics.gauge(
+        "frr_agent_last_applied_generation",
+        "Last generation applied",
+        agent.last_applied(),
+    );
+    metrics.gauge(
+        "frr_agent_last_known_good_generation",
+        "Last generation applied that passed the health check",
+        agent.last_known_good(),
+    );
+    metrics.gauge(
+        "frr_agent_breaker_open",
+        "Whether the circuit breaker is open",
+        Some(u8::from(agent.breaker_open())),
+    );
+    metrics.counter(
+        "frr_agent_connections_total",
+        "Connections accepted",
+        &METRICS.connections,
+    );
+    metrics.gauge(
+        "frr_agent_connections",
+        "Connections open",
+        Some(METRICS.active_connections.load(Ordering::Relaxed)),
+    );
+    metrics.counter(
+        "frr_agent_rejected_connections_total",
+        "Connections rejected as the peer is not allowed or has too many connections",
+        &METRICS.rejected_connections,
+    );
+    metrics.counter(
+        "frr_agent_decode_errors_total",
+        "Messages from clients that could not be decoded",
+        &METRICS.decode_errors,
+    );
+    metrics.counter(
+        "frr_agent_framing_resyncs_total",
+        "Resynchronizations of v2 and v3 streams",
+        &FRAMING_STATS.resyncs,
+    );
+    metrics.counter(
+        "frr_agent_framing_checksum_errors_total",
+        "Messages whose checksum did not match",
+        &FRAMING_STATS.checksum_errors,
+    );
+    metrics.counter(
+        "frr_agent_send_retries_total",
+        "Responses retried after a transient failure",
+        &SEND_STATS.retries,
+    );
+    metrics.counter(
+        "frr_agent_send_failures_total",
+        "Responses that could not be sent",
+        &SEND_STATS.failures,
+    );
+    metrics.text
+}
+
+fn scrape(stream: &TcpStream, agent: &Agent) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(SCRAPE_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(SCRAPE_TIMEOUT)))
+        .map_err(|e| format!("Could not set scrape timeouts: {e}"))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(|e| format!("Could not read scrape request: {e}"))?;
+    /* the headers are of no interest */
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 0) && header.trim() != "" {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render(agent)),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let mut stream = stream;
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| format!("Could not send metrics: {e}"))
+}
+
+// serve the metrics on the listener bound forever
+pub(crate) fn run(agent: &Agent, listener: &TcpListener) {
+    let addr = listener
+        .local_addr()
+        .map_or("?".to_string(), |addr| addr.to_string());
+    info!("Serving metrics at http://{addr}/metrics");
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = scrape(&stream, agent) {
+                    warn!("{e}");
+                }
+            }
+            Err(e) => warn!("Failed to accept metrics connection: {e}"),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Monitoring of the FRR daemons. The pids of the daemons are learnt from the pid files in rundir
+// and periodically checked, so that daemons dying or being restarted (e.g. by watchfrr) are detected.
+// The CPU and memory usage of the daemons is sampled from /proc at the same time. With
+// --reapply-on-restart, daemons being restarted get the running config of FRR checked for the
+// config last applied, which is re-applied if FRR lost (part of) it, e.g. when zebra crashed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::agent::Agent;
+use crate::alerting::AlertEvent;
+use crate::json::Json;
+
+// USER_HZ, the unit of the cpu times in /proc/<pid>/stat. It is 100 on all Linux platforms we run on.
+const CLOCK_TICKS_PER_SEC: f64 = 100.0;
+
+// resource usage of a daemon
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    cpu_ticks: u64,
+    cpu_percent: f64,
+    rss_kb: u64,
+    peak_rss_kb: u64,
+    sampled: Option<Instant>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct DaemonState {
+    pid: Option<i32>,
+    alive: bool,
+    deaths: u64,
+    restarts: u64,
+    usage: Usage,
+}
+
+// what a check of the FRR daemons found
+#[derive(Default)]
+pub(crate) struct Scan {
+    pub(crate) dead: Vec<(String, i32)>, /* daemons found dead, with their pid */
+    pub(crate) restarted: Vec<String>,   /* daemons found restarted */
+}
+
+#[derive(Default)]
+pub(crate) struct FrrMonitor {
+    daemons: Mutex<BTreeMap<String, DaemonState>>,
+}
+
+fn process_alive(pid: i32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// cpu time (user + system) consumed by a process, in clock ticks
+fn cpu_ticks(pid: i32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    /* the process name may contain spaces: fields are counted after its closing parenthesis */
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+// resident memory of a process, in kB
+fn rss_kb(pid: i32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+impl Usage {
+    // sample the resource usage of a process
+    fn sample(&mut self, pid: i32, restarted: bool) {
+        let now = Instant::now();
+        if let Some(ticks) = cpu_ticks(pid) {
+            if let Some(sampled) = self.sampled.filter(|_| !restarted) {
+                #[allow(clippy::cast_precision_loss)]
+                let used = ticks.saturating_sub(self.cpu_ticks) as f64 / CLOCK_TICKS_PER_SEC;
+                let elapsed = now.duration_since(sampled).as_secs_f64();
+                if elapsed > 0.0 {
+                    self.cpu_percent = (1000.0 * used / elapsed).round() / 10.0;
+                }
+            }
+            self.cpu_ticks = ticks;
+            self.sampled = Some(now);
+        }
+        if let Some(rss_kb) = rss_kb(pid) {
+            self.rss_kb = rss_kb;
+            self.peak_rss_kb = self.peak_rss_kb.max(rss_kb);
+        }
+    }
+}
+
+// read the pids of the FRR daemons from the pid files in rundir
+fn read_pids(rundir: &str) -> BTreeMap<String, i32> {
+    let mut pids = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(rundir) else {
+        return pids;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().is_none_or(|ext| ext != "pid") {
+            continue;
+        }
+        let Some(daemon) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if let Some(pid) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|pid| pid.trim().parse().ok())
+        {
+            pids.insert(daemon.to_string(), pid);
+        }
+    }
+    pids
+}
+
+impl FrrMonitor {
+    // check the FRR daemons, reporting the ones that died or were restarted
+    pub(crate) fn scan(&self, rundir: &str, last_applied: Option<GenId>) -> Scan {
+        let pids = read_pids(rundir);
+        let Ok(mut daemons) = self.daemons.lock() else {
+            return Scan::default();
+        };
+        let mut scan = Scan::default();
+        let genid = last_applied.map_or("none".to_string(), |genid| genid.to_string());
+
+        for (daemon, pid) in &pids {
+            let state = daemons.entry(daemon.clone()).or_default();
+            let alive = process_alive(*pid);
+            let restarted = state.pid.is_some_and(|old| old != *pid);
+            match state.pid {
+                None => debug!("Monitoring FRR daemon {daemon} (pid {pid})"),
+                Some(old) if restarted && alive => {
+                    state.restarts += 1;
+                    warn!(
+                        "EVENT: FRR daemon {daemon} was restarted (pid {old} -> {pid}, restarts: {}). Last applied generation: {genid}",
+                        state.restarts
+                    );
+                    scan.restarted.push(daemon.clone());
+                }
+                _ => {}
+            }
+            if state.alive && !alive {
+                state.deaths += 1;
+                error!(
+                    "EVENT: FRR daemon {daemon} (pid {pid}) died (deaths: {}). Last applied generation: {genid}",
+                    state.deaths
+                );
+                scan.dead.push((daemon.clone(), *pid));
+            }
+            state.pid = Some(*pid);
+            state.alive = alive;
+            if alive {
+                state.usage.sample(*pid, restarted);
+            }
+        }
+
+        /* daemons whose pid file vanished */
+        for (daemon, state) in daemons.iter_mut() {
+            if state.alive
+                && !pids.contains_key(daemon)
+                && let Some(pid) = state.pid.filter(|pid| !process_alive(*pid))
+            {
+                state.deaths += 1;
+                state.alive = false;
+                error!(
+                    "EVENT: FRR daemon {daemon} (pid {pid}) died (deaths: {}). Last applied generation: {genid}",
+                    state.deaths
+                );
+                scan.dead.push((daemon.clone(), pid));
+            }
+        }
+        scan
+    }
+
+    // whether all the FRR daemons are alive now, as told by their pid files in rundir, if any
+    // is found. The state of the monitor is not changed.
+    pub(crate) fn alive_now(rundir: &str) -> Option<bool> {
+        let pids = read_pids(rundir);
+        if pids.is_empty() {
+            return None;
+        }
+        Some(pids.values().all(|pid| process_alive(*pid)))
+    }
+
+    // whether all the FRR daemons monitored are alive, if any is
+    pub(crate) fn healthy(&self) -> Option<bool> {
+        let daemons = self.daemons.lock().ok()?;
+        if daemons.is_empty() {
+            return None;
+        }
+        Some(daemons.values().all(|state| state.alive))
+    }
+
+    // state and resource usage of the FRR daemons
+    pub(crate) fn to_json(&self) -> Json {
+        let Ok(daemons) = self.daemons.lock() else {
+            return Json::Null;
+        };
+        let daemons = daemons.iter().map(|(daemon, state)| {
+            let value = Json::object([
+                ("pid", state.pid.map(i64::from).into()),
+                ("alive", state.alive.into()),
+                ("deaths", state.deaths.into()),
+                ("restarts", state.restarts.into()),
+                ("cpu_percent", Json::Number(state.usage.cpu_percent)),
+                ("rss_kb", state.usage.rss_kb.into()),
+                ("peak_rss_kb", state.usage.peak_rss_kb.into()),
+            ]);
+            (daemon.clone(), value)
+        });
+        Json::Object(daemons.collect())
+    }
+
+    // monitor the FRR daemons forever
+    pub(crate) fn run(&self, agent: &Agent, interval: Duration) {
+        info!("Monitoring FRR daemons every {interval:?}");
+        loop {
+            let last_applied = agent.last_applied();
+            let scan = self.scan(agent.args.rundir(), last_applied);
+            for (daemon, pid) in scan.dead {
+                let message = format!("FRR daemon {daemon} (pid {pid}) died");
+                agent.notify(AlertEvent::DaemonDied, last_applied, &message);
+            }
+            if agent.args.reapply_on_restart && !scan.restarted.is_empty() {
+                agent.reapply_lost(&scan.restarted);
+            }
+            sleep(interval);
+        }
+    }
+}
+
+// whether a line of a config is a top-level statement telling a part of the config that FRR may
+// lose, e.g. "interface eth0", "router bgp 65001" or "ip route 0.0.0.0/0 10.0.0.1"
+fn significant(line: &str) -> bool {
+    !line.is_empty()
+        && !line.starts_with(char::is_whitespace)
+        && !line.starts_with(['!', '#'])
+        && !["end", "exit", "frr version", "frr defaults", "line vty"]
+            .iter()
+            .any(|statement| line.starts_with(statement))
+}
+
+// the top-level statements of a config applied that are missing in the running config of FRR
+pub(crate) fn missing_statements<'a>(applied: &'a str, running: &str) -> Vec<&'a str> {
+    let running: BTreeSet<&str> = running.lines().map(str::trim_end).collect();
+    applied
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| significant(line) && !running.contains(line))
+        .collect()
+}
@@ -0,0 +1,391 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Native reload path (--native-reloader), which applies configs without frr-reload.py (and thus
+// without python on the node). The running config, read with vtysh, and the config to apply are
+// split into contexts (e.g. "router bgp 65000" > "address-family ipv4 unicast") and compared
+// line by line within each; the lines only in the running config are negated ("no ...") and those
+// only in the config to apply are added, context by context, with one vtysh call per context.
+// Contexts only in the running config are removed as a whole if top-level ("no router bgp 65000").
+// The --test stage checks the config with vtysh --dryrun and outputs the commands that would be
+// run, in the fashion of frr-reload. The external reloader remains the default.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::time::Instant;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::rawtext;
+use crate::vtysh::Vtysh;
+
+// commands entering a top-level context
+const CONTEXTS: [&str; 18] = [
+    "interface ",
+    "router ",
+    "vrf ",
+    "route-map ",
+    "line vty",
+    "key chain ",
+    "segment-routing",
+    "mpls ldp",
+    "bfd",
+    "rpki",
+    "pseudowire ",
+    "nexthop-group ",
+    "pbr-map ",
+    "evpn mh",
+    "affinity-map",
+    "srv6",
+    "ip multicast",
+    "control-plane",
+];
+
+// commands entering a context nested in another (e.g. address-family in router bgp)
+const SUBCONTEXTS: [&str; 7] = [
+    "address-family ",
+    "vni ",
+    "peer ",
+    "profile ",
+    "segment-routing",
+    "locators",
+    "locator ",
+];
+
+// commands leaving a context
+const EXITS: [&str; 6] = [
+    "exit",
+    "exit-address-family",
+    "exit-vrf",
+    "exit-vni",
+    "quit",
+    "end",
+];
+
+// passes of commands applying a config, at most
+const PASSES: usize = 3;
+
+// the path of a context: the commands entering it, outermost first (empty at top level)
+type Path = Vec<String>;
+
+// a config split into contexts, with the lines of each, in the order they appear
+#[derive(Debug, Default)]
+struct Contexts {
+    order: Vec<Path>,
+    lines: BTreeMap<Path, Vec<String>>,
+}
+
+impl Contexts {
+    fn enter(&mut self, path: &Path) {
+        if !self.lines.contains_key(path) {
+            self.order.push(path.clone());
+            self.lines.insert(path.clone(), vec![]);
+        }
+    }
+    fn add(&mut self, path: &Path, line: &str) {
+        self.enter(path);
+        if let Some(lines) = self.lines.get_mut(path)
+            && !lines.iter().any(|l| l == line)
+        {
+            lines.push(line.to_string());
+        }
+    }
+    fn get(&self, path: &Path) -> Option<&Vec<String>> {
+        self.lines.get(path)
+    }
+}
+
+fn is_context(line: &str) -> bool {
+    CONTEXTS.iter().any(|c| line.starts_with(c))
+}
+fn is_subcontext(line: &str) -> bool {
+    SUBCONTEXTS.iter().any(|c| line.starts_with(c))
+}
+
+// split a config into contexts. If the config is indented (as the running config is), the
+// nesting of lines is told by their indentation (a context holding the lines indented more than
+// the command entering it, whatever the width of indents); else by the commands entering and
+// leaving contexts, so that the lines following a context without "exit" are taken to be in it.
+fn parse(config: &str) -> Contexts {
+    let indented = config
+        .lines()
+        .any(|line| line.starts_with(' ') && !line.trim().is_empty());
+    let mut contexts = Contexts::default();
+    let mut stack: Vec<(usize, String)> = vec![]; /* the contexts entered, with their indent */
+    let path = |stack: &[(usize, String)]| -> Path {
+        stack.iter().map(|(_, line)| line.clone()).collect()
+    };
+    for raw in config.lines() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('!') || line.starts_with('#') {
+            continue;
+        }
+        let indent = raw.len() - raw.trim_start().len();
+        if indented {
+            while stack.last().is_some_and(|(entered, _)| *entered >= indent) {
+                stack.pop();
+            }
+        }
+        if EXITS.contains(&line) {
+            if line == "end" {
+                stack.clear();
+            } else if !indented {
+                stack.pop();
+            }
+        } else if !stack.is_empty() && is_subcontext(line) {
+            stack.push((indent, line.to_string()));
+            contexts.enter(&path(&stack));
+        } else if is_context(line) {
+            stack = vec![(indent, line.to_string())];
+            contexts.enter(&path(&stack));
+        } else {
+            contexts.add(&path(&stack), line);
+        }
+    }
+    contexts
+}
+
+// the command negating a line
+fn negate(line: &str) -> String {
+    line.strip_prefix("no ")
+        .map_or_else(|| format!("no {line}"), str::to_string)
+}
+
+// the commands to run in a context (entered with the commands of its path)
+#[derive(Debug)]
+struct Batch {
+    path: Path,
+    commands: Vec<String>,
+}
+
+// the batches of commands turning the running config into the config to apply: removals first
+// (unless only additions are wanted), then additions
+fn delta(running: &Contexts, candidate: &Contexts, removing: bool) -> Vec<Batch> {
+    let mut removals = vec![];
+    let mut removed_contexts = vec![];
+    let mut gone: BTreeSet<&Path> = BTreeSet::new();
+    for path in running.order.iter().filter(|_| removing) {
+        let lines = running.get(path).map_or(&[][..], Vec::as_slice);
+        match candidate.get(path) {
+            /* top-level contexts are removed as a whole, with what is nested in them */
+            None if path.len() == 1 => {
+                gone.insert(path);
+                removed_contexts.push(negate(&path[0]));
+            }
+            None if gone.iter().any(|gone| path.starts_with(gone)) => {}
+            None => {
+                /* nested contexts are emptied, since not all can be removed */
+                let commands: Vec<String> = lines.iter().map(|line| negate(line)).collect();
+                if !commands.is_empty() {
+                    removals.push(Batch {
+                        path: path.clone(),
+                        commands,
+                    });
+                }
+            }
+            Some(wanted) => {
+                let commands: Vec<String> = lines
+                    .iter()
+                    .filter(|line| !wanted.contains(line))
+                    .map(|line| negate(line))
+                    .collect();
+                if !commands.is_empty() {
+                    removals.push(Batch {
+                        path: path.clone(),
+                        commands,
+                    });
+                }
+            }
+        }
+    }
+    let mut batches = removals;
+    if !removed_contexts.is_empty() {
+        batches.push(Batch {
+            path: vec![],
+            commands: removed_contexts,
+        });
+    }
+    for path in &candidate.order {
+        let lines = candidate.get(path).map_or(&[][..], Vec::as_slice);
+        let present = running
+            .get(path)
+            .filter(|_| !gone.iter().any(|gone| path.starts_with(gone)));
+        let commands: Vec<String> = lines
+            .iter()
+            .filter(|line| present.is_none_or(|present| !present.contains(line)))
+            .cloned()
+            .collect();
+        /* new contexts are created even if empty */
+        if !commands.is_empty() || present.is_none() {
+            batches.push(Batch {
+                path: path.clone(),
+                commands,
+            });
+        }
+    }
+    batches
+}
+
+// the batches, as the commands that would be run, in the fashion of frr-reload --test
+fn render(batches: &[Batch]) -> String {
+    let mut out = String::new();
+    if batches.is_empty() {
+        out.push_str("No changes\n");
+    }
+    for batch in batches {
+        if batch.commands.is_empty() {
+            let _ = writeln!(out, "{}", batch.path.join(" > "));
+        }
+        for command in &batch.commands {
+            let mut line = batch.path.clone();
+            line.push(command.clone());
+            let _ = writeln!(out, "{}", line.join(" > "));
+        }
+    }
+    out
+}
+
+// run batches of commands, in the config mode of vtysh
+fn apply(vtysh: &Vtysh, batches: &[Batch]) -> Result<(), String> {
+    for batch in batches {
+        let mut commands = vec!["configure terminal"];
+        commands.extend(batch.path.iter().map(String::as_str));
+        commands.extend(batch.commands.iter().map(String::as_str));
+        vtysh.run(&commands)?;
+    }
+    Ok(())
+}
+
+// outcome of a native run: whether it succeeded, its stdout and stderr
+pub(crate) struct NativeRun {
+    pub(crate) success: bool,
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+}
+
+// test (check and preview) or apply a config file with vtysh. Since negating a line may remove
+// others (e.g. "no neighbor X remote-as" removes the other settings of neighbor X), the running
+// config is read again after applying, and the lines still missing are added, a few times at most.
+pub(crate) fn run(vtysh: &Vtysh, conf_file: &str, test: bool) -> NativeRun {
+    let started = Instant::now();
+    let mut outcome = NativeRun {
+        success: false,
+        stdout: String::new(),
+        stderr: String::new(),
+    };
+    let candidate = match rawtext::read(conf_file) {
+        Ok(candidate) => parse(&candidate),
+        Err(e) => {
+            outcome.stderr = format!("Could not read {conf_file}: {e}");
+            return outcome;
+        }
+    };
+    for pass in 0..PASSES {
+        let running = match vtysh.running_config() {
+            Ok(running) => parse(&running),
+            Err(e) => {
+                outcome.stderr = format!("Could not get the running config: {e}");
+                return outcome;
+            }
+        };
+        let batches = delta(&running, &candidate, pass == 0);
+        if pass > 0 && batches.is_empty() {
+            break;
+        }
+        outcome.stdout.push_str(&render(&batches));
+        if test {
+            match vtysh.check(conf_file) {
+                Ok(_) => outcome.success = true,
+                Err(e) => outcome.stderr = e,
+            }
+            return outcome;
+        }
+        if let Err(e) = apply(vtysh, &batches) {
+            error!("Native reload failed: {e}");
+            outcome.stderr = e;
+            return outcome;
+        }
+        if pass + 1 == PASSES {
+            warn!(
+                "Lines of {conf_file} were still missing at pass {PASSES}: the running config may not match it"
+            );
+        }
+    }
+    let _ = writeln!(outcome.stdout, "Applied in {:?}", started.elapsed());
+    outcome.success = true;
+    outcome
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(commands: &[&str]) -> Path {
+        commands.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn nesting_is_told_by_indentation() {
+        let running = "router bgp 65000\n neighbor 10.0.0.1 remote-as 65001\n !\n \
+                       address-family ipv4 unicast\n  network 10.1.0.0/16\n \
+                       exit-address-family\nexit\n!\ninterface eth0\n ip address 10.0.0.2/31\nexit\n";
+        let contexts = parse(running);
+        let bgp = path(&["router bgp 65000"]);
+        let af = path(&["router bgp 65000", "address-family ipv4 unicast"]);
+        assert_eq!(
+            contexts.order,
+            vec![bgp.clone(), af.clone(), path(&["interface eth0"])]
+        );
+        assert_eq!(
+            contexts.get(&bgp),
+            Some(&vec!["neighbor 10.0.0.1 remote-as 65001".to_string()])
+        );
+        assert_eq!(
+            contexts.get(&af),
+            Some(&vec!["network 10.1.0.0/16".to_string()])
+        );
+        assert_eq!(
+            contexts.get(&path(&["interface eth0"])),
+            Some(&vec!["ip address 10.0.0.2/31".to_string()])
+        );
+    }
+
+    #[test]
+    fn nesting_is_told_by_indentation_of_any_width() {
+        let config = "router bgp 65000\n  address-family ipv4 unicast\n    network 10.1.0.0/16\n  \
+                      exit-address-family\n  neighbor 10.0.0.1 remote-as 65001\n";
+        let contexts = parse(config);
+        let bgp = path(&["router bgp 65000"]);
+        let af = path(&["router bgp 65000", "address-family ipv4 unicast"]);
+        assert_eq!(
+            contexts.get(&af),
+            Some(&vec!["network 10.1.0.0/16".to_string()])
+        );
+        assert_eq!(
+            contexts.get(&bgp),
+            Some(&vec!["neighbor 10.0.0.1 remote-as 65001".to_string()])
+        );
+    }
+
+    #[test]
+    fn nesting_is_told_by_exits_without_indentation() {
+        let config = "router bgp 65000\naddress-family ipv4 unicast\nnetwork 10.1.0.0/16\n\
+                      exit-address-family\nneighbor 10.0.0.1 remote-as 65001\nexit\nip forwarding\n";
+        let contexts = parse(config);
+        let bgp = path(&["router bgp 65000"]);
+        let af = path(&["router bgp 65000", "address-family ipv4 unicast"]);
+        assert_eq!(
+            contexts.get(&af),
+            Some(&vec!["network 10.1.0.0/16".to_string()])
+        );
+        assert_eq!(
+            contexts.get(&bgp),
+            Some(&vec!["neighbor 10.0.0.1 remote-as 65001".to_string()])
+        );
+        assert_eq!(
+            contexts.get(&vec![]),
+            Some(&vec!["ip forwarding".to_string()])
+        );
+    }
+}
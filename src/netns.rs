@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Network namespace the reloader and vtysh are run in (--netns), for FRR running in a namespace
+// of its own, without wrapping the agent in scripts. Commands enter the namespace with setns(2)
+// before they are executed; the agent itself stays in its namespace, so that its socket is
+// reachable as before.
+
+use std::fs::File;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::runas::RunAs;
+
+// the path of a network namespace, given by name (as with ip netns) or path
+pub(crate) fn netns_path(netns: &str) -> PathBuf {
+    if netns.contains('/') {
+        PathBuf::from(netns)
+    } else {
+        PathBuf::from("/var/run/netns").join(netns)
+    }
+}
+
+// make a command run in a network namespace, with the credentials given if any. Since entering a
+// namespace takes privileges, the credentials are switched once in it, rather than by Command
+// (which switches them first).
+#[allow(unsafe_code)]
+pub(crate) fn enter(cmd: &mut Command, netns: &str, run_as: Option<RunAs>) -> Result<(), String> {
+    let path = netns_path(netns);
+    let ns: OwnedFd = File::open(&path)
+        .map_err(|e| format!("Could not open network namespace {}: {e}", path.display()))?
+        .into();
+    // SAFETY: the closure only makes system calls that are async-signal-safe (setns, setgroups,
+    // setgid, setuid), on an fd the closure owns and that stays open until the command is spawned
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let Some(run_as) = run_as else {
+                return Ok(());
+            };
+            if run_as.uid.is_some()
+                && libc::getuid() == 0
+                && libc::setgroups(0, std::ptr::null()) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            if libc::setgid(run_as.gid) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if let Some(uid) = run_as.uid
+                && libc::setuid(uid) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Identity of the node the agent runs on (--node-id and --fabric), so that the data aggregated
+// centrally from many agents is attributable without reverse-engineering socket paths or
+// hostnames. It is included in the structured responses (STATUS, VERSION and keepalives), in
+// alerts, in the transcripts shipped and in every log line.
+
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::json::Json;
+
+#[derive(Debug, Default)]
+pub(crate) struct NodeIdentity {
+    pub(crate) node_id: Option<String>,
+    pub(crate) fabric: Option<String>,
+}
+
+static IDENTITY: OnceLock<NodeIdentity> = OnceLock::new();
+
+// set the identity of the node, once at startup
+pub(crate) fn set_identity(node_id: Option<&str>, fabric: Option<&str>) {
+    let _ = IDENTITY.set(NodeIdentity {
+        node_id: node_id.map(str::to_string),
+        fabric: fabric.map(str::to_string),
+    });
+}
+
+// the identity of the node
+pub(crate) fn identity() -> &'static NodeIdentity {
+    static UNKNOWN: NodeIdentity = NodeIdentity {
+        node_id: None,
+        fabric: None,
+    };
+    IDENTITY.get().unwrap_or(&UNKNOWN)
+}
+
+impl NodeIdentity {
+    pub(crate) fn is_set(&self) -> bool {
+        self.node_id.is_some() || self.fabric.is_some()
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            ("node_id", self.node_id.as_deref().into()),
+            ("fabric", self.fabric.as_deref().into()),
+        ])
+    }
+
+    // the identity as labels (those set), e.g. for transcripts shipped
+    pub(crate) fn labels(&self) -> Vec<(&'static str, &str)> {
+        [("node_id", &self.node_id), ("fabric", &self.fabric)]
+            .into_iter()
+            .filter_map(|(name, value)| value.as_deref().map(|value| (name, value)))
+            .collect()
+    }
+
+    // add the identity to a structured response, if set
+    pub(crate) fn tag(&self, response: Json) -> Json {
+        match response {
+            Json::Object(mut members) if self.is_set() => {
+                members.insert("node".to_string(), self.to_json());
+                Json::Object(members)
+            }
+            response => response,
+        }
+    }
+}
+
+impl Display for NodeIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let labels = self.labels();
+        for (n, (name, value)) in labels.iter().enumerate() {
+            let separator = if n == 0 { "" } else { " " };
+            write!(f, "{separator}{name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+// log line format prefixing every line with the identity of the node, if set
+pub(crate) struct NodeFormat<F>(pub(crate) F);
+
+impl<S, N, F> FormatEvent<S, N> for NodeFormat<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let identity = identity();
+        if identity.is_set() {
+            write!(writer, "{identity} ")?;
+        }
+        self.0.format_event(ctx, writer, event)
+    }
+}
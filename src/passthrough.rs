@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Interactive vtysh passthrough, so that operators can troubleshoot a node via the controller
+// without separate SSH access to it. A client sending VTYSH turns its connection into a vtysh
+// session: every message it sends afterwards is a vtysh command, run in exec mode, and the
+// response is its output (with the genid of the message). The session ends with "exit" or "quit",
+// when the connection is closed. Only the commands allowed with --vtysh-allow are run (e.g.
+// "show" allows every show command), never configuration commands, and every command is logged
+// along with the identity of the peer.
+//
+// SHOW requests run a single show command, among those allowed with --show-allow (by default,
+// the commands reporting the state of BGP, routes, BFD, EVPN and interfaces in JSON), so that a
+// controller can check that a config applied converged without another channel into the node.
+
+use std::os::unix::net::UnixStream;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::framing::Framing;
+use crate::vtysh::vtysh;
+
+// commands that leave exec mode or change the config, refused whatever the allowlist
+const FORBIDDEN: [&str; 5] = ["configure", "conf", "write", "copy", "end"];
+
+// show commands allowed in SHOW requests by default
+pub(crate) const DEFAULT_SHOW_ALLOW: [&str; 8] = [
+    "show bgp summary json",
+    "show bgp vrf all summary json",
+    "show bgp neighbors json",
+    "show ip route json",
+    "show ipv6 route json",
+    "show bfd peers json",
+    "show evpn vni json",
+    "show interface json",
+];
+
+// whether a command is allowed: its leading words must be those of an allowed command
+// (abbreviations are not expanded)
+fn check(allowlist: &[String], command: &str) -> Result<(), String> {
+    if command.chars().any(char::is_control) {
+        return Err("Commands must be a single line".to_string());
+    }
+    let words: Vec<&str> = command.split_whitespace().collect();
+    let Some(first) = words.first() else {
+        return Err("Empty command".to_string());
+    };
+    if FORBIDDEN.contains(first) {
+        return Err(format!("'{first}' is not allowed in passthrough sessions"));
+    }
+    let allowed = allowlist.iter().any(|allowed| {
+        let allowed: Vec<&str> = allowed.split_whitespace().collect();
+        !allowed.is_empty() && words.starts_with(&allowed)
+    });
+    if !allowed {
+        return Err(format!("Command '{command}' is not allowed"));
+    }
+    Ok(())
+}
+
+// serve a vtysh session on a connection until the client ends it or disconnects
+pub(crate) fn serve_vtysh(args: &Args, stream: &mut UnixStream, framing: Framing) {
+    info!("vtysh passthrough session started");
+    loop {
+        let (genid, command) = match framing.receive(stream) {
+            Ok(received) => received,
+            Err(e) => {
+                info!("vtysh passthrough session ended: {e}");
+                return;
+            }
+        };
+        let command = command.trim();
+        if matches!(command, "exit" | "quit") {
+            info!("vtysh passthrough session ended by the client");
+            let _ = framing.send(stream, genid, b"Ok");
+            return;
+        }
+        let output = match check(&args.vtysh_allow, command) {
+            Ok(()) => match vtysh(args, command) {
+                Ok(output) => {
+                    info!(
+                        "vtysh passthrough: ran '{command}' ({} octets)",
+                        output.len()
+                    );
+                    output
+                }
+                Err(e) => {
+                    warn!("vtysh passthrough: '{command}' failed: {e}");
+                    e
+                }
+            },
+            Err(e) => {
+                warn!("vtysh passthrough: refused '{command}': {e}");
+                e
+            }
+        };
+        if let Err(e) = framing.send(stream, genid, output.as_bytes()) {
+            error!("Error sending vtysh output: {e}. Ending passthrough session...");
+            return;
+        }
+    }
+}
+
+// run the show command of a SHOW request, returning its output
+pub(crate) fn show(args: &Args, command: &str) -> String {
+    if !command.starts_with("show ") {
+        warn!("SHOW: refused '{command}': not a show command");
+        return format!("'{command}' is not a show command");
+    }
+    if let Err(e) = check(&args.show_allow(), command) {
+        warn!("SHOW: refused '{command}': {e}");
+        return e;
+    }
+    match vtysh(args, command) {
+        Ok(output) => {
+            debug!("SHOW: ran '{command}' ({} octets)", output.len());
+            output
+        }
+        Err(e) => {
+            warn!("SHOW: '{command}' failed: {e}");
+            e
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Credentials of the peers connecting to the agent
+
+use std::fmt::Display;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PeerCred {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+impl Display for PeerCred {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid:{} uid:{} gid:{}", self.pid, self.uid, self.gid)
+    }
+}
+
+// the name of a process (its comm), if it can be resolved
+pub fn process_name(pid: i32) -> Option<String> {
+    let comm = std::fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+// retrieve the credentials of the peer of a unix stream with SO_PEERCRED
+#[allow(unsafe_code)]
+pub fn peer_cred(stream: &UnixStream) -> Result<PeerCred, String> {
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = libc::socklen_t::try_from(size_of::<libc::ucred>())
+        .map_err(|e| format!("Bad ucred size: {e}"))?;
+
+    // SAFETY: ucred and len are valid for writes and len is the size of ucred
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut ucred).cast(),
+            &raw mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(format!(
+            "Could not get peer credentials: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(PeerCred {
+        pid: ucred.pid,
+        uid: ucred.uid,
+        gid: ucred.gid,
+    })
+}
+
+// whether a peer may connect, given the uids and gids allowed. Any peer may connect if none are
+// set; otherwise, peers whose credentials are unknown may not.
+pub fn allowed(cred: Option<&PeerCred>, uids: &[u32], gids: &[u32]) -> Result<(), String> {
+    if uids.is_empty() && gids.is_empty() {
+        return Ok(());
+    }
+    let Some(cred) = cred else {
+        return Err("peer credentials are unknown".to_string());
+    };
+    if uids.contains(&cred.uid) || gids.contains(&cred.gid) {
+        Ok(())
+    } else {
+        Err(format!("peer ({cred}) is not allowed"))
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// The requests received by the agent and not responded to yet, in the order received, so that
+// operators can see at a glance why a push has not been applied yet: what is being processed,
+// what is queued ahead of it and for how long. Configs with a newer config queued behind them are
+// reported as superseded (they are still applied, in order). Applies still waiting in the queue
+// of their connection can be cancelled (CANCEL): they are responded to without being processed.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::request::Request;
+
+struct Entry {
+    id: u64,
+    genid: GenId,
+    request: String,
+    applies: bool,
+    received: Instant,
+    started: Option<Instant>, /* when its processing started */
+    cancelled: bool,
+}
+
+#[derive(Default)]
+struct Entries {
+    next: u64,
+    entries: Vec<Entry>,
+}
+
+#[derive(Default)]
+pub(crate) struct Pending {
+    entries: Mutex<Entries>,
+}
+
+// a request received by the agent and not responded to yet
+pub(crate) struct InFlight<'a> {
+    pending: &'a Pending,
+    id: u64,
+}
+impl InFlight<'_> {
+    // tell that the processing of the request starts
+    pub(crate) fn start(&self) {
+        if let Ok(mut entries) = self.pending.entries.lock()
+            && let Some(entry) = entries.entries.iter_mut().find(|e| e.id == self.id)
+        {
+            entry.started = Some(Instant::now());
+        }
+    }
+
+    // whether the request was cancelled before its processing started
+    pub(crate) fn cancelled(&self) -> bool {
+        self.pending.entries.lock().is_ok_and(|entries| {
+            entries
+                .entries
+                .iter()
+                .any(|entry| entry.id == self.id && entry.cancelled)
+        })
+    }
+}
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut entries) = self.pending.entries.lock() {
+            entries.entries.retain(|entry| entry.id != self.id);
+        }
+    }
+}
+
+impl Pending {
+    // account for a request received until it is responded to (or dropped)
+    pub(crate) fn received(&self, genid: GenId, request: &Request) -> InFlight<'_> {
+        let mut id = 0;
+        if let Ok(mut entries) = self.entries.lock() {
+            id = entries.next;
+            entries.next += 1;
+            entries.entries.push(Entry {
+                id,
+                genid,
+                request: request.describe(genid),
+                applies: request.applies(),
+                received: Instant::now(),
+                started: None,
+                cancelled: false,
+            });
+        }
+        InFlight { pending: self, id }
+    }
+
+    // the number of requests not responded to yet
+    pub(crate) fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map(|entries| entries.entries.len())
+            .unwrap_or_default()
+    }
+
+    // cancel the applies of a generation whose processing has not started yet, returning whether
+    // there are any
+    pub(crate) fn cancel(&self, genid: GenId) -> bool {
+        let Ok(mut entries) = self.entries.lock() else {
+            return false;
+        };
+        let mut waiting = false;
+        for entry in entries.entries.iter_mut().filter(|entry| {
+            entry.genid == genid && entry.applies && entry.started.is_none() && !entry.cancelled
+        }) {
+            entry.cancelled = true;
+            waiting = true;
+        }
+        waiting
+    }
+
+    // the generation of the apply being processed, if any
+    pub(crate) fn applying(&self) -> Option<GenId> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .entries
+            .iter()
+            .find(|entry| entry.applies && entry.started.is_some())
+            .map(|entry| entry.genid)
+    }
+
+    // for how long the longest running of the applies being processed has been running
+    pub(crate) fn longest_apply(&self) -> Option<Duration> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .entries
+            .iter()
+            .filter(|entry| entry.applies)
+            .filter_map(|entry| entry.started.map(|started| started.elapsed()))
+            .max()
+    }
+
+    // the requests not responded to yet, with their age in milliseconds
+    pub(crate) fn to_json(&self) -> Json {
+        let Ok(entries) = self.entries.lock() else {
+            return Json::Null;
+        };
+        let entries = &entries.entries;
+        let ms =
+            |instant: Instant| u64::try_from(instant.elapsed().as_millis()).unwrap_or(u64::MAX);
+        let pending = entries.iter().enumerate().map(|(position, entry)| {
+            let superseded = entry.applies
+                && entries[position + 1..]
+                    .iter()
+                    .any(|later| later.applies && later.genid > entry.genid);
+            Json::object([
+                ("genid", entry.genid.into()),
+                ("request", entry.request.as_str().into()),
+                (
+                    "state",
+                    if entry.started.is_some() {
+                        "processing"
+                    } else if entry.cancelled {
+                        "cancelled"
+                    } else {
+                        "queued"
+                    }
+                    .into(),
+                ),
+                ("age_ms", ms(entry.received).into()),
+                ("processing_ms", entry.started.map(ms).into()),
+                ("superseded", superseded.into()),
+            ])
+        });
+        Json::Array(pending.collect())
+    }
+}
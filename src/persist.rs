@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Persistence of the configs applied (--persist), so that they survive a restart of FRR or of the
+// node: once a generation is applied, either its config is copied to frr.conf in the FRR confdir
+// ("copy"), atomically and keeping the ownership and mode of the file replaced, or FRR is told to
+// save its running config ("write-memory").
+
+use std::fs;
+use std::os::unix::fs::{MetadataExt, chown};
+use std::path::Path;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::privileged;
+use crate::reload::config_file_path;
+use crate::vtysh::vtysh;
+use crate::{Args, GenId};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum PersistMode {
+    Copy,
+    WriteMemory,
+}
+impl PersistMode {
+    pub(crate) fn parse(mode: &str) -> Option<Self> {
+        match mode.trim() {
+            "copy" => Some(PersistMode::Copy),
+            "write-memory" => Some(PersistMode::WriteMemory),
+            _ => None,
+        }
+    }
+}
+
+// replace frr.conf in a confdir with a config
+pub(crate) fn install(confdir: &str, config: &[u8]) -> Result<(), String> {
+    let path = Path::new(confdir).join("frr.conf");
+    let tmp = Path::new(confdir).join("frr.conf.tmp");
+    let replaced = fs::metadata(&path).ok();
+    let written = fs::write(&tmp, config)
+        .and_then(|()| match &replaced {
+            Some(replaced) => fs::set_permissions(&tmp, replaced.permissions())
+                .and_then(|()| chown(&tmp, Some(replaced.uid()), Some(replaced.gid()))),
+            None => Ok(()),
+        })
+        .and_then(|()| fs::rename(&tmp, &path));
+    written.map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        format!("Could not write {}: {e}", path.display())
+    })
+}
+
+// replace frr.conf with the config of a generation (as root, if privileges were dropped)
+fn copy(args: &Args, genid: GenId) -> Result<(), String> {
+    let config = fs::read(config_file_path(genid, args.outdir()))
+        .map_err(|e| format!("Could not read generation {genid}: {e}"))?;
+    privileged::install_config(args, &config)
+}
+
+// persist a generation applied
+pub(crate) fn persist(args: &Args, mode: PersistMode, genid: GenId) -> Result<(), String> {
+    match mode {
+        PersistMode::Copy => copy(args, genid)?,
+        PersistMode::WriteMemory => {
+            vtysh(args, "write memory")?;
+        }
+    }
+    debug!("Persisted generation {genid} ({mode:?})");
+    Ok(())
+}
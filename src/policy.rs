@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Policy restricting the content of the configs applied. A policy file holds one rule per line
+// (blank lines and lines starting with '#' are ignored):
+//   * "deny <pattern>": lines matching the pattern are forbidden,
+//   * "allow <pattern>": lines matching the pattern are allowed (as exceptions to later denies),
+//   * "protect <pattern>": the stanzas (top-level line along with the lines nested under it)
+//     whose top-level line matches the pattern may not be added, removed or changed with respect
+//     to the config applied.
+// Config lines are matched against the allow and deny rules in order, the first rule matching
+// deciding; lines matched by none are allowed. Patterns match whole lines (with whitespace
+// collapsed), '*' matching any run of characters and '?' any single character.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+enum Rule {
+    Allow(String),
+    Deny(String),
+    Protect(String),
+}
+
+#[derive(Default)]
+pub(crate) struct Policy {
+    rules: Vec<Rule>,
+}
+
+// whether text matches a glob pattern, '*' matching any run of characters and '?' any one
+fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None; /* position of the last '*' and of the text it was tried at */
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    backtrack = Some((star, tried + 1));
+                    p = star + 1;
+                    t = tried + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// a config line with its whitespace collapsed, if it has any content
+fn content(line: &str) -> Option<String> {
+    let content = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!content.is_empty() && !content.starts_with('!') && !content.starts_with('#'))
+        .then_some(content)
+}
+
+// the stanzas of a config whose top-level line matches a pattern, by top-level line
+fn stanzas(config: &str, pattern: &str) -> BTreeMap<String, Vec<String>> {
+    let mut stanzas: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut current = None;
+    for line in config.lines() {
+        let Some(content) = content(line) else {
+            continue;
+        };
+        if !line.starts_with(char::is_whitespace) {
+            current = matches(pattern, &content).then(|| content.clone());
+            if let Some(header) = &current {
+                stanzas.entry(header.clone()).or_default();
+            }
+            continue;
+        }
+        if let Some(header) = &current {
+            stanzas.entry(header.clone()).or_default().push(content);
+        }
+    }
+    stanzas
+}
+
+impl Policy {
+    pub(crate) fn parse(policy: &str) -> Result<Self, String> {
+        let mut rules = vec![];
+        for (n, line) in policy.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (verb, pattern) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let pattern = pattern.split_whitespace().collect::<Vec<_>>().join(" ");
+            if pattern.is_empty() {
+                return Err(format!("line {}: missing pattern", n + 1));
+            }
+            rules.push(match verb {
+                "allow" => Rule::Allow(pattern),
+                "deny" => Rule::Deny(pattern),
+                "protect" => Rule::Protect(pattern),
+                _ => return Err(format!("line {}: unknown rule '{verb}'", n + 1)),
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    // load the policy in a file, if any. No policy allows everything.
+    pub(crate) fn load(path: Option<&str>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let policy =
+            std::fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+        let policy = Self::parse(&policy).map_err(|e| format!("{path}: {e}"))?;
+        info!("Loaded policy from {path} ({} rules)", policy.rules.len());
+        Ok(policy)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // the rule deciding on a config line, if any
+    fn decide(&self, line: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| match rule {
+            Rule::Allow(pattern) | Rule::Deny(pattern) => matches(pattern, line),
+            Rule::Protect(_) => false,
+        })
+    }
+
+    // the violations of the policy by a candidate config, given the config applied (if any)
+    pub(crate) fn violations(&self, config: &str, applied: Option<&str>) -> Vec<String> {
+        let mut violations = vec![];
+        for line in config.lines().filter_map(content) {
+            if let Some(Rule::Deny(pattern)) = self.decide(&line) {
+                violations.push(format!("'{line}' is denied by rule 'deny {pattern}'"));
+            }
+        }
+        if let Some(applied) = applied {
+            for rule in &self.rules {
+                let Rule::Protect(pattern) = rule else {
+                    continue;
+                };
+                let (candidate, current) = (stanzas(config, pattern), stanzas(applied, pattern));
+                let headers: BTreeSet<&String> = candidate.keys().chain(current.keys()).collect();
+                for header in headers
+                    .into_iter()
+                    .filter(|header| candidate.get(*header) != current.get(*header))
+                {
+                    violations.push(format!(
+                        "stanza '{header}' is protected by rule 'protect {pattern}'"
+                    ));
+                }
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(rules: &str) -> Policy {
+        let policy = Policy::parse(rules);
+        let Ok(policy) = policy else {
+            unreachable!("{:?}", policy.err());
+        };
+        policy
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("ip route *", "ip route 0.0.0.0/0 10.0.0.1"));
+        assert!(matches("*", ""));
+        assert!(matches(
+            "neighbor * password *",
+            "neighbor 10.0.0.1 password s3cr3t"
+        ));
+        assert!(matches("vrf ?", "vrf a"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("vrf ?", "vrf ab"));
+        assert!(!matches("vrf ?", "vrf "));
+        assert!(!matches("ip route *", "ipv6 route ::/0 fe80::1"));
+        assert!(!matches("a*b", "aXbY"));
+    }
+
+    #[test]
+    fn first_rule_matching_decides() {
+        let policy = parsed(
+            "# management routes only\n\nallow ip route 10.*\ndeny ip route *\ndeny   no   *\n",
+        );
+        let violations = |config| policy.violations(config, None);
+        assert!(violations("ip route 10.0.0.0/8 192.168.0.1\n").is_empty());
+        assert_eq!(
+            violations("ip route 0.0.0.0/0 192.168.0.1\n!\nhostname leaf1\n"),
+            vec!["'ip route 0.0.0.0/0 192.168.0.1' is denied by rule 'deny ip route *'"]
+        );
+        /* whitespace is collapsed, in rules and lines alike */
+        assert_eq!(
+            violations(" no  ip  forwarding\n"),
+            vec!["'no ip forwarding' is denied by rule 'deny no *'"]
+        );
+        /* a deny before an allow wins */
+        let policy = parsed("deny ip route *\nallow ip route 10.*\n");
+        assert_eq!(
+            policy
+                .violations("ip route 10.0.0.0/8 1.1.1.1\n", None)
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn protected_stanzas() {
+        let policy = parsed("protect interface mgmt*\n");
+        let applied = "interface mgmt0\n ip address 10.0.0.1/24\n!\ninterface eth0\n mtu 9000\n";
+        let violation =
+            vec!["stanza 'interface mgmt0' is protected by rule 'protect interface mgmt*'"];
+        /* other stanzas may change at will */
+        assert!(
+            policy
+                .violations("interface mgmt0\n ip address 10.0.0.1/24\n", Some(applied))
+                .is_empty()
+        );
+        /* changed */
+        assert_eq!(
+            policy.violations("interface mgmt0\n ip address 10.0.0.2/24\n", Some(applied)),
+            violation
+        );
+        /* removed */
+        assert_eq!(
+            policy.violations("interface eth0\n mtu 9000\n", Some(applied)),
+            violation
+        );
+        /* added */
+        assert_eq!(
+            policy.violations("interface mgmt1\n!\n", Some("interface eth0\n mtu 9000\n")),
+            vec!["stanza 'interface mgmt1' is protected by rule 'protect interface mgmt*'"]
+        );
+        /* with no config applied, there is nothing to protect */
+        assert!(policy.violations("interface mgmt1\n", None).is_empty());
+    }
+
+    #[test]
+    fn bad_rules() {
+        assert_eq!(
+            Policy::parse("allow *\npermit *").err(),
+            Some("line 2: unknown rule 'permit'".to_string())
+        );
+        assert_eq!(
+            Policy::parse("deny  ").err(),
+            Some("line 1: missing pattern".to_string())
+        );
+    }
+}
@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Privileged helper of an agent dropping its privileges (--run-as-user). Right before the drop, the
+// agent spawns a copy of itself that keeps them and performs, on its behalf, the few operations
+// that require root and nothing else: controlling FRR (SERVICE, and DAEMONS restarting it),
+// writing the daemons file and frr.conf in confdir (DAEMONS and --persist copy), and removing the
+// socket when the agent is terminated. Requests are lines written to the stdin of the helper,
+// each responded to with a line on its stdout ("Ok", or why it failed). The helper ignores
+// termination signals, so that it is still there to remove the socket when the agent is stopped,
+// and exits once the agent exits (its stdin is closed). Without --run-as-user, these operations
+// are performed by the agent itself.
+
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio, exit};
+use std::sync::{Mutex, OnceLock};
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::daemons;
+use crate::persist;
+use crate::request::parse_list;
+use crate::service::FrrAction;
+
+// the subcommand running the helper
+pub(crate) const HELPER_COMMAND: &str = "privileged-helper";
+
+// the name of the default FRR instance in requests
+const DEFAULT_INSTANCE: &str = "-";
+
+struct Helper {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+impl Helper {
+    fn response(&mut self) -> Result<(), String> {
+        let mut line = String::new();
+        match self.stdout.read_line(&mut line) {
+            Ok(0) => Err("The privileged helper exited".to_string()),
+            Ok(_) if line.trim_end() == "Ok" => Ok(()),
+            Ok(_) => Err(line.trim_end().to_string()),
+            Err(e) => Err(format!("Could not read from the privileged helper: {e}")),
+        }
+    }
+
+    fn request(&mut self, request: &str, payload: &[u8]) -> Result<(), String> {
+        self.stdin
+            .write_all(format!("{request}\n").as_bytes())
+            .and_then(|()| self.stdin.write_all(payload))
+            .and_then(|()| self.stdin.flush())
+            .map_err(|e| format!("Could not write to the privileged helper: {e}"))?;
+        self.response()
+    }
+}
+
+static HELPER: OnceLock<Mutex<Helper>> = OnceLock::new();
+
+// spawn the helper, while the agent still has its privileges
+pub(crate) fn spawn() -> Result<(), String> {
+    let exe = std::env::current_exe()
+        .map_err(|e| format!("Could not find the executable of the agent: {e}"))?;
+    let mut child = Command::new(exe)
+        .args(std::env::args().skip(1))
+        .arg(HELPER_COMMAND)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not spawn the privileged helper: {e}"))?;
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err("Could not talk to the privileged helper".to_string());
+    };
+    let pid = child.id();
+    let mut helper = Helper {
+        _child: child,
+        stdin,
+        stdout: BufReader::new(stdout),
+    };
+    /* the helper tells once it is ready */
+    helper.response()?;
+    HELPER
+        .set(Mutex::new(helper))
+        .map_err(|_| "The privileged helper was already spawned".to_string())?;
+    info!("Spawned the privileged helper (pid {pid})");
+    Ok(())
+}
+
+// send a request to the helper, if it was spawned
+fn request(request: &str, payload: &[u8]) -> Option<Result<(), String>> {
+    let helper = HELPER.get()?;
+    Some(match helper.lock() {
+        Ok(mut helper) => helper.request(request, payload),
+        Err(_) => Err("The privileged helper lock is poisoned".to_string()),
+    })
+}
+
+fn instance(args: &Args) -> &str {
+    args.pathspace.as_deref().unwrap_or(DEFAULT_INSTANCE)
+}
+
+// start, stop or restart FRR
+pub(crate) fn control_frr(args: &Args, action: FrrAction) -> Result<(), String> {
+    request(&format!("service {} {action}", instance(args)), &[])
+        .unwrap_or_else(|| args.frr_service().control(action))
+}
+
+// set the FRR daemons to be enabled, restarting FRR if that changed them
+pub(crate) fn set_frr_daemons(args: &Args, enabled: &[String]) -> Result<(), String> {
+    let list = enabled.join(",");
+    request(&format!("daemons {} {list}", instance(args)), &[])
+        .unwrap_or_else(|| daemons::set_frr_daemons(args.confdir(), &args.frr_service(), enabled))
+}
+
+// replace frr.conf in confdir with a config
+pub(crate) fn install_config(args: &Args, config: &[u8]) -> Result<(), String> {
+    request(
+        &format!("install {} {}", instance(args), config.len()),
+        config,
+    )
+    .unwrap_or_else(|| persist::install(args.confdir(), config))
+}
+
+// remove the socket of the agent, when terminated
+pub(crate) fn remove_socket(path: &str) -> std::io::Result<()> {
+    match request("unlink", &[]) {
+        Some(result) => result.map_err(std::io::Error::other),
+        None => fs::remove_file(path),
+    }
+}
+
+// the args of the FRR instance a request targets
+fn instance_args(args: &Args, name: &str) -> Result<Args, String> {
+    if name == DEFAULT_INSTANCE {
+        return Ok(args.clone());
+    }
+    if !args.instance.iter().any(|instance| instance == name) {
+        return Err(format!("Unknown FRR instance '{name}'"));
+    }
+    args.for_instance(name)
+}
+
+// perform a request of the agent
+fn handle(args: &Args, request: &str, stdin: &mut impl Read) -> Result<(), String> {
+    let mut words = request.split(' ');
+    let operation = words.next().unwrap_or_default();
+    if operation == "unlink" {
+        return match fs::remove_file(&args.sock_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        };
+    }
+    let args = instance_args(args, words.next().unwrap_or_default())?;
+    let arg = words.next().unwrap_or_default();
+    match operation {
+        "service" => match FrrAction::parse(arg) {
+            Some(action) => args.frr_service().control(action),
+            None => Err(format!("Bad FRR action '{arg}'")),
+        },
+        "daemons" => {
+            daemons::set_frr_daemons(args.confdir(), &args.frr_service(), &parse_list(arg))
+        }
+        "install" => {
+            let length: u64 = arg.parse().map_err(|_| format!("Bad length '{arg}'"))?;
+            let mut config = Vec::new();
+            stdin
+                .take(length)
+                .read_to_end(&mut config)
+                .map_err(|e| format!("Could not read config: {e}"))?;
+            if config.len() as u64 != length {
+                return Err(format!("Config has {} octets, not {length}", config.len()));
+            }
+            persist::install(args.confdir(), &config)
+        }
+        operation => Err(format!("Unknown operation '{operation}'")),
+    }
+}
+
+// run the helper until the agent exits
+pub(crate) fn run(args: &Args) -> ! {
+    /* the signals stopping the agent are left to it: they are caught and never handled */
+    let _signals = Signals::new([SIGHUP, SIGINT, SIGQUIT, SIGTERM]);
+    let mut stdin = BufReader::new(std::io::stdin().lock());
+    let mut stdout = std::io::stdout().lock();
+    let mut response = "Ok".to_string();
+    loop {
+        if writeln!(stdout, "{}", response.replace('\n', " ")).is_err() || stdout.flush().is_err() {
+            exit(1);
+        }
+        let mut request = String::new();
+        match stdin.read_line(&mut request) {
+            Ok(0) | Err(_) => exit(0),
+            Ok(_) => {}
+        }
+        response = match handle(args, request.trim_end(), &mut stdin) {
+            Ok(()) => "Ok".to_string(),
+            Err(e) => e,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use clap::Parser;
+
+    use super::*;
+
+    // a directory of its own for a test, as the confdir and the socket of the agent
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "frr-agent-privileged-{}-{test}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            unreachable!("{e}");
+        }
+        dir
+    }
+
+    fn args(dir: &Path) -> Args {
+        let (sock, confdir) = (dir.join("sock"), dir.display().to_string());
+        let cmdline = ["frr-agent", "--sock-path", &sock.display().to_string()];
+        let options = ["--confdir", &confdir, "--instance", "red"];
+        Args::try_parse_from(cmdline.iter().chain(&options)).unwrap_or_else(|e| {
+            unreachable!("{e}");
+        })
+    }
+
+    fn handled(args: &Args, request: &str, payload: &[u8]) -> Result<(), String> {
+        handle(args, request, &mut &payload[..])
+    }
+
+    #[test]
+    fn bad_requests() {
+        let dir = dir("bad");
+        let args = args(&dir);
+        assert_eq!(
+            handled(&args, "service blue restart", b""),
+            Err("Unknown FRR instance 'blue'".to_string())
+        );
+        assert_eq!(
+            handled(&args, "install ../red 3", b"abc"),
+            Err("Unknown FRR instance '../red'".to_string())
+        );
+        assert_eq!(
+            handled(&args, "service - reboot", b""),
+            Err("Bad FRR action 'reboot'".to_string())
+        );
+        assert_eq!(
+            handled(&args, "service red", b""),
+            Err("Bad FRR action ''".to_string())
+        );
+        assert_eq!(
+            handled(&args, "install - -1", b""),
+            Err("Bad length '-1'".to_string())
+        );
+        assert_eq!(
+            handled(&args, "chmod - 777", b""),
+            Err("Unknown operation 'chmod'".to_string())
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn install() {
+        let dir = dir("install");
+        let args = args(&dir);
+        let installed = || fs::read(dir.join("frr.conf")).ok();
+        assert_eq!(handled(&args, "install - 4", b"abc\n"), Ok(()));
+        assert_eq!(installed(), Some(b"abc\n".to_vec()));
+        /* a short payload installs nothing */
+        assert_eq!(
+            handled(&args, "install - 10", b"abcd"),
+            Err("Config has 4 octets, not 10".to_string())
+        );
+        assert_eq!(installed(), Some(b"abc\n".to_vec()));
+        /* only the octets announced are read, the rest being the next request */
+        let mut stdin = &b"xyzunlink\n"[..];
+        assert_eq!(handle(&args, "install - 3", &mut stdin), Ok(()));
+        assert_eq!(installed(), Some(b"xyz".to_vec()));
+        assert_eq!(stdin, b"unlink\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unlink() {
+        let dir = dir("unlink");
+        let args = args(&dir);
+        if let Err(e) = fs::write(dir.join("sock"), b"") {
+            unreachable!("{e}");
+        }
+        assert_eq!(handled(&args, "unlink", b""), Ok(()));
+        assert!(!dir.join("sock").exists());
+        /* the socket being gone already is fine */
+        assert_eq!(handled(&args, "unlink", b""), Ok(()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Readiness and liveness probes. With --probe-addr, the agent serves them over HTTP, so that it
+// can be deployed (e.g. as a sidecar in Kubernetes) with httpGet probes rather than exec-based
+// socket checks:
+//   GET /readyz  the agent is listening and can exec the reloader (present, executable and
+//                matching its pinned checksum, if any), or vtysh with the native reloader
+//   GET /livez   the agent is listening and not wedged, i.e. no apply has been running for
+//                longer than --liveness-max-apply (e.g. a reloader hanging)
+// Probes are answered with 200 if they pass and 503 otherwise, with a JSON body telling the
+// outcome of every check, e.g. {"ok":false,"checks":{"socket":"ok","reloader":"..."}}.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::agent::Agent;
+use crate::json::Json;
+use crate::reload::verify;
+
+// probes not completed within this time are dropped
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// whether a file can be executed
+fn executable(path: &Path) -> Result<(), String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("{} is missing: {e}", path.display()))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(format!("{} is not executable", path.display()));
+    }
+    Ok(())
+}
+
+// whether the agent listens on its unix socket (unless it only listens over vsock)
+fn listening(agent: &Agent) -> Result<(), String> {
+    if agent.args.vsock_only || Path::new(&agent.args.sock_path).exists() {
+        return Ok(());
+    }
+    Err(format!("{} is missing", agent.args.sock_path))
+}
+
+// whether the reloader (or vtysh, with the native reloader) can be executed
+fn execable(agent: &Agent) -> Result<(), String> {
+    if agent.args.native_reloader {
+        return executable(&Path::new(agent.args.binddir()).join("vtysh"));
+    }
+    let binary = agent.reloader.binary();
+    executable(Path::new(&binary.path))?;
+    verify(&binary).map_err(|e| e.to_string())
+}
+
+// whether no apply has been running for too long
+fn not_wedged(agent: &Agent) -> Result<(), String> {
+    match agent.longest_apply() {
+        Some(running) if running > agent.args.liveness_max_apply() => Err(format!(
+            "an apply has been running for {}s",
+            running.as_secs()
+        )),
+        _ => Ok(()),
+    }
+}
+
+// the outcome of the checks of a probe, and whether they all passed
+fn probe(checks: &[(&str, Result<(), String>)]) -> (bool, Json) {
+    let ok = checks.iter().all(|(_, check)| check.is_ok());
+    let checks = checks.iter().map(|(name, check)| {
+        let outcome = match check {
+            Ok(()) => "ok".to_string(),
+            Err(e) => e.clone(),
+        };
+        (name.to_string(), Json::from(outcome))
+    });
+    let body = Json::object([
+        ("ok", ok.into()),
+        ("checks", Json::Object(checks.collect())),
+    ]);
+    (ok, body)
+}
+
+fn answer(stream: &TcpStream, agent: &Agent) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(PROBE_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(PROBE_TIMEOUT)))
+        .map_err(|e| format!("Could not set probe timeouts: {e}"))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(|e| format!("Could not read probe request: {e}"))?;
+    /* the headers are of no interest */
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 0) && header.trim() != "" {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let probed = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/readyz")) => Some(probe(&[
+            ("socket", listening(agent)),
+            ("reloader", execable(agent)),
+        ])),
+        (Some("GET"), Some("/livez")) => Some(probe(&[
+            ("socket", listening(agent)),
+            ("apply", not_wedged(agent)),
+        ])),
+        _ => None,
+    };
+    let (status, body) = match probed {
+        Some((true, body)) => ("200 OK", format!("{body}\n")),
+        Some((false, body)) => {
+            debug!("Probe {} failed: {body}", request_line.trim());
+            ("503 Service Unavailable", format!("{body}\n"))
+        }
+        None => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let mut stream = stream;
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| format!("Could not answer probe: {e}"))
+}
+
+// serve the probes on the listener bound forever
+pub(crate) fn run(agent: &Agent, listener: &TcpListener) {
+    let addr = listener
+        .local_addr()
+        .map_or("?".to_string(), |addr| addr.to_string());
+    info!("Serving probes at http://{addr}/readyz and http://{addr}/livez");
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = answer(&stream, agent) {
+                    warn!("{e}");
+                }
+            }
+            Err(e) => warn!("Failed to accept probe connection: {e}"),
+        }
+    }
+}
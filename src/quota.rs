@@ -0,0 +1,334 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Per-peer request rate limits and daily apply quotas, so that one misbehaving client can not
+// monopolize the reload pipeline shared with others. Peers are identified by the uid in their
+// credentials. Requests are rate-limited with a token bucket (refilled at the rate configured,
+// holding up to one second worth of requests); applies are counted per day (UTC). The agent also
+// defends FRR against clients looping on reloads: applies are capped per minute whatever the
+// peer (over a sliding window), and so are the concurrent connections of each peer.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::json::Json;
+use crate::peer::PeerCred;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+// the window applies are capped over
+const APPLY_WINDOW: Duration = Duration::from_mins(1);
+
+struct PeerUsage {
+    tokens: f64,
+    refilled: Instant,
+    day: u64,
+    applies: u64,
+    connections: usize,
+}
+
+// the day (since the epoch) it is now
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / SECS_PER_DAY
+}
+
+// a connection of a peer, accounted until dropped
+pub(crate) struct ConnectionSlot<'a> {
+    quotas: &'a Quotas,
+    uid: Option<u32>,
+}
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut usage) = self.quotas.usage.lock()
+            && let Some(usage) = usage.get_mut(&self.uid)
+        {
+            usage.connections = usage.connections.saturating_sub(1);
+        }
+    }
+}
+
+pub(crate) struct Quotas {
+    rate: Option<f64>, /* requests per second */
+    daily_applies: Option<u64>,
+    minute_applies: Option<usize>, /* whatever the peer */
+    connections: Option<usize>,    /* of each peer */
+    usage: Mutex<BTreeMap<Option<u32>, PeerUsage>>, /* by uid, None if unknown */
+    applied: Mutex<VecDeque<Instant>>, /* applies admitted within the last minute */
+}
+
+impl Quotas {
+    pub(crate) fn new(
+        rate: Option<f64>,
+        daily_applies: Option<u64>,
+        minute_applies: Option<usize>,
+        connections: Option<usize>,
+    ) -> Self {
+        Self {
+            rate: rate.filter(|rate| *rate > 0.0),
+            daily_applies,
+            minute_applies,
+            connections,
+            usage: Mutex::new(BTreeMap::new()),
+            applied: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn new_usage(&self) -> PeerUsage {
+        PeerUsage {
+            tokens: self.rate.unwrap_or_default().max(1.0),
+            refilled: Instant::now(),
+            day: today(),
+            applies: 0,
+            connections: 0,
+        }
+    }
+
+    // account for a connection of a peer, telling why it is refused if so
+    pub(crate) fn connect(&self, peer: Option<&PeerCred>) -> Result<ConnectionSlot<'_>, String> {
+        let uid = peer.map(|peer| peer.uid);
+        if let Ok(mut usage) = self.usage.lock() {
+            let usage = usage.entry(uid).or_insert_with(|| self.new_usage());
+            if let Some(max) = self.connections
+                && usage.connections >= max
+            {
+                return Err(format!("limit of {max} concurrent connections reached"));
+            }
+            usage.connections += 1;
+        }
+        Ok(ConnectionSlot { quotas: self, uid })
+    }
+
+    // account for an apply, whatever the peer, telling why it is rate-limited if so
+    pub(crate) fn admit_apply(&self) -> Result<(), String> {
+        self.account_apply(true)
+    }
+
+    // account for a request of a peer, telling why it is throttled if so
+    pub(crate) fn admit(&self, peer: Option<&PeerCred>, applies: bool) -> Result<(), String> {
+        self.account(peer, applies, true)
+    }
+
+    // tell why a request of a peer would be throttled or rate-limited if it were admitted now,
+    // without accounting for it
+    pub(crate) fn would_admit(&self, peer: Option<&PeerCred>, applies: bool) -> Result<(), String> {
+        self.account(peer, applies, false)?;
+        if applies {
+            self.account_apply(false)?;
+        }
+        Ok(())
+    }
+
+    // check an apply against the applies allowed per minute, accounting for it if told so
+    fn account_apply(&self, consume: bool) -> Result<(), String> {
+        let Some(max) = self.minute_applies else {
+            return Ok(());
+        };
+        let Ok(mut applied) = self.applied.lock() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        while applied
+            .front()
+            .is_some_and(|admitted| now.duration_since(*admitted) >= APPLY_WINDOW)
+        {
+            applied.pop_front();
+        }
+        if applied.len() >= max {
+            return Err(format!("limit of {max} applies per minute reached"));
+        }
+        if consume {
+            applied.push_back(now);
+        }
+        Ok(())
+    }
+
+    // check a request of a peer against its quotas, accounting for it if told so
+    fn account(&self, peer: Option<&PeerCred>, applies: bool, consume: bool) -> Result<(), String> {
+        if self.rate.is_none() && self.daily_applies.is_none() {
+            return Ok(());
+        }
+        let Ok(mut usage) = self.usage.lock() else {
+            return Ok(());
+        };
+        let burst = self.rate.unwrap_or_default().max(1.0);
+        let usage = usage
+            .entry(peer.map(|peer| peer.uid))
+            .or_insert_with(|| self.new_usage());
+
+        if let Some(rate) = self.rate {
+            let now = Instant::now();
+            let elapsed = now.duration_since(usage.refilled).as_secs_f64();
+            usage.tokens = (usage.tokens + elapsed * rate).min(burst);
+            usage.refilled = now;
+            if usage.tokens < 1.0 {
+                return Err(format!("rate limit of {rate} requests/s exceeded"));
+            }
+        }
+        if applies && let Some(quota) = self.daily_applies {
+            let today = today();
+            if usage.day != today {
+                usage.day = today;
+                usage.applies = 0;
+            }
+            if usage.applies >= quota {
+                return Err(format!("daily quota of {quota} applies exhausted"));
+            }
+            if consume {
+                usage.applies += 1;
+            }
+        }
+        if consume && self.rate.is_some() {
+            usage.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    // the applies of each peer today, and its connections
+    pub(crate) fn to_json(&self) -> Json {
+        let Ok(usage) = self.usage.lock() else {
+            return Json::Null;
+        };
+        let today = today();
+        let peers = usage.iter().map(|(uid, usage)| {
+            let uid = uid.map_or("unknown".to_string(), |uid| format!("uid:{uid}"));
+            let applies = if usage.day == today { usage.applies } else { 0 };
+            let members = [
+                ("applies_today", applies.into()),
+                ("connections", (usage.connections as u64).into()),
+            ];
+            (uid, Json::object(members))
+        });
+        Json::Object(peers.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: PeerCred = PeerCred {
+        pid: 1,
+        uid: 1000,
+        gid: 1000,
+    };
+    const OTHER: PeerCred = PeerCred {
+        pid: 2,
+        uid: 1001,
+        gid: 1001,
+    };
+
+    // move the last refill of the bucket of a peer back in time
+    fn age(quotas: &Quotas, peer: &PeerCred, by: Duration) {
+        if let Ok(mut usage) = quotas.usage.lock()
+            && let Some(usage) = usage.get_mut(&Some(peer.uid))
+            && let Some(refilled) = usage.refilled.checked_sub(by)
+        {
+            usage.refilled = refilled;
+        }
+    }
+
+    #[test]
+    fn token_bucket() {
+        let quotas = Quotas::new(Some(2.0), None, None, None);
+        /* bursts of up to one second worth of requests */
+        assert_eq!(quotas.admit(Some(&PEER), false), Ok(()));
+        assert_eq!(quotas.admit(Some(&PEER), true), Ok(()));
+        assert_eq!(
+            quotas.admit(Some(&PEER), false),
+            Err("rate limit of 2 requests/s exceeded".to_string())
+        );
+        /* peers have buckets of their own */
+        assert_eq!(quotas.admit(Some(&OTHER), false), Ok(()));
+        assert_eq!(quotas.admit(None, false), Ok(()));
+        /* refilled at the rate, up to the burst */
+        age(&quotas, &PEER, Duration::from_millis(600));
+        assert_eq!(quotas.would_admit(Some(&PEER), false), Ok(()));
+        assert_eq!(quotas.admit(Some(&PEER), false), Ok(()));
+        assert!(quotas.admit(Some(&PEER), false).is_err());
+        age(&quotas, &PEER, Duration::from_mins(1));
+        assert_eq!(quotas.admit(Some(&PEER), false), Ok(()));
+        assert_eq!(quotas.admit(Some(&PEER), false), Ok(()));
+        assert!(quotas.admit(Some(&PEER), false).is_err());
+    }
+
+    #[test]
+    fn daily_applies() {
+        let quotas = Quotas::new(None, Some(2), None, None);
+        for _ in 0..2 {
+            assert_eq!(quotas.would_admit(Some(&PEER), true), Ok(()));
+            assert_eq!(quotas.admit(Some(&PEER), true), Ok(()));
+        }
+        let exhausted = Err("daily quota of 2 applies exhausted".to_string());
+        assert_eq!(quotas.admit(Some(&PEER), true), exhausted);
+        /* requests that do not apply are not counted */
+        assert_eq!(quotas.admit(Some(&PEER), false), Ok(()));
+        assert_eq!(quotas.admit(Some(&OTHER), true), Ok(()));
+        /* the quota is renewed the next day */
+        if let Ok(mut usage) = quotas.usage.lock()
+            && let Some(usage) = usage.get_mut(&Some(PEER.uid))
+        {
+            usage.day -= 1;
+        }
+        assert_eq!(quotas.admit(Some(&PEER), true), Ok(()));
+        assert_eq!(quotas.admit(Some(&PEER), true), Ok(()));
+        assert_eq!(quotas.admit(Some(&PEER), true), exhausted);
+    }
+
+    #[test]
+    fn applies_per_minute() {
+        let quotas = Quotas::new(None, None, Some(2), None);
+        assert_eq!(quotas.admit_apply(), Ok(()));
+        assert_eq!(quotas.would_admit(Some(&PEER), true), Ok(()));
+        assert_eq!(quotas.admit_apply(), Ok(()));
+        let reached = Err("limit of 2 applies per minute reached".to_string());
+        assert_eq!(quotas.would_admit(Some(&OTHER), true), reached);
+        assert_eq!(quotas.would_admit(Some(&OTHER), false), Ok(()));
+        assert_eq!(quotas.admit_apply(), reached);
+        /* the window slides: only the applies of the last minute count */
+        if let Ok(mut applied) = quotas.applied.lock()
+            && let Some(admitted) = applied.front_mut()
+            && let Some(earlier) = admitted.checked_sub(APPLY_WINDOW)
+        {
+            *admitted = earlier;
+        }
+        assert_eq!(quotas.admit_apply(), Ok(()));
+        assert_eq!(quotas.admit_apply(), reached);
+    }
+
+    #[test]
+    fn connections() {
+        let quotas = Quotas::new(None, None, None, Some(2));
+        let first = quotas.connect(Some(&PEER));
+        let second = quotas.connect(Some(&PEER));
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(
+            quotas.connect(Some(&PEER)).err(),
+            Some("limit of 2 concurrent connections reached".to_string())
+        );
+        assert!(quotas.connect(Some(&OTHER)).is_ok());
+        /* a connection is accounted until dropped */
+        drop(first);
+        let third = quotas.connect(Some(&PEER));
+        assert!(third.is_ok());
+        assert!(quotas.connect(Some(&PEER)).is_err());
+        let connections = |uid: &str| {
+            quotas
+                .to_json()
+                .get(uid)
+                .and_then(|usage| usage.get("connections"))
+                .and_then(Json::as_i64)
+        };
+        assert_eq!(connections("uid:1000"), Some(2));
+        assert_eq!(connections("uid:1001"), Some(0));
+        drop((second, third));
+        assert_eq!(connections("uid:1000"), Some(0));
+    }
+}
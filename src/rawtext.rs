@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Lossless handling of configs that are not valid UTF-8 (e.g. with latin-1 descriptions written
+// by legacy tooling). Configs are handled as strings in which every octet that is not part of a
+// valid UTF-8 sequence is escaped as a character of a private use area (U+10FF00 + the octet), and
+// are written back verbatim. So that the escape is reversible, the characters of that area found
+// in valid UTF-8 are escaped too, octet by octet: any sequence of octets is decoded and encoded
+// back to the very same octets.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const ESCAPE_BASE: u32 = 0x0010_FF00;
+
+fn escape(octet: u8) -> char {
+    char::from_u32(ESCAPE_BASE + u32::from(octet)).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+fn unescape(c: char) -> Option<u8> {
+    u32::from(c)
+        .checked_sub(ESCAPE_BASE)
+        .and_then(|octet| u8::try_from(octet).ok())
+}
+
+// append some valid UTF-8 to a text, escaping the characters of the escape area
+fn push_valid(text: &mut String, valid: &str) {
+    let mut buf = [0u8; 4];
+    for c in valid.chars() {
+        if unescape(c).is_some() {
+            text.extend(c.encode_utf8(&mut buf).bytes().map(escape));
+        } else {
+            text.push(c);
+        }
+    }
+}
+
+// the text in some octets, with the octets that are not valid UTF-8 escaped
+pub fn decode(octets: Vec<u8>) -> String {
+    let octets = match String::from_utf8(octets) {
+        Ok(text) if !text.chars().any(|c| unescape(c).is_some()) => return text,
+        Ok(text) => text.into_bytes(),
+        Err(e) => e.into_bytes(),
+    };
+    let mut text = String::with_capacity(octets.len());
+    for chunk in octets.utf8_chunks() {
+        push_valid(&mut text, chunk.valid());
+        text.extend(chunk.invalid().iter().map(|octet| escape(*octet)));
+    }
+    text
+}
+
+// whether some text has octets that were not valid UTF-8
+pub fn is_escaped(text: &str) -> bool {
+    text.chars().any(|c| unescape(c).is_some()) && std::str::from_utf8(&encode(text)).is_err()
+}
+
+// the octets of some text, as originally received
+pub fn encode(text: &str) -> Cow<'_, [u8]> {
+    if !text.chars().any(|c| unescape(c).is_some()) {
+        return Cow::Borrowed(text.as_bytes());
+    }
+    let mut octets = Vec::with_capacity(text.len());
+    let mut buf = [0u8; 4];
+    for c in text.chars() {
+        match unescape(c) {
+            Some(octet) => octets.push(octet),
+            None => octets.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+        }
+    }
+    Cow::Owned(octets)
+}
+
+// read a config file, escaping the octets that are not valid UTF-8
+pub fn read(path: impl AsRef<Path>) -> io::Result<String> {
+    fs::read(path).map(decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(octets: &[u8]) -> String {
+        let text = decode(octets.to_vec());
+        assert_eq!(encode(&text).as_ref(), octets);
+        text
+    }
+
+    #[test]
+    fn utf8_is_kept_as_is() {
+        let config = "interface eth0\n description café ☕\n";
+        assert_eq!(round_trip(config.as_bytes()), config);
+        assert!(!is_escaped(config));
+    }
+
+    #[test]
+    fn invalid_octets_are_escaped() {
+        let config = b"interface eth0\n description caf\xe9\n";
+        let text = round_trip(config);
+        assert!(text.starts_with("interface eth0\n description caf"));
+        assert!(is_escaped(&text));
+    }
+
+    #[test]
+    fn escape_area_is_escaped_too() {
+        /* valid UTF-8 with characters of the escape area, e.g. U+10FF41 and U+10FFFF */
+        let config = "description \u{10FF41}\u{10FFFF} x";
+        let text = round_trip(config.as_bytes());
+        assert_ne!(text, config);
+        assert!(!is_escaped(&text));
+    }
+
+    #[test]
+    fn escape_area_mixed_with_invalid_octets() {
+        let mut config = b"a\xff".to_vec();
+        config.extend_from_slice("\u{10FF00}".as_bytes());
+        config.extend_from_slice(b"\xc3\xf4\x8f\xbf");
+        let text = round_trip(&config);
+        assert!(is_escaped(&text));
+    }
+
+    #[test]
+    fn any_octets_round_trip() {
+        let all: Vec<u8> = (0..=255).collect();
+        round_trip(&all);
+        round_trip(&all.iter().rev().copied().collect::<Vec<u8>>());
+        round_trip(b"");
+        round_trip(b"\xf4\x8f\xbc");
+        round_trip(b"\xf4\x8f\xbc\x80\xf4");
+    }
+
+    #[test]
+    fn encode_of_plain_text_borrows() {
+        assert!(matches!(encode("router bgp 65001\n"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Recent history of the agent, kept in memory and served with LOGS, so that a controller can fetch
+// it even when log collection on the node is broken. The last transcripts of the requests that ran
+// the reloader and the last significant events (those alerts are fired for) are kept in a bounded
+// ring buffer, the oldest entries being dropped first.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::GenId;
+use crate::alerting::AlertEvent;
+use crate::json::Json;
+use crate::reload::Transcript;
+use crate::shipping::runs_json;
+
+pub(crate) struct RecentLogs {
+    capacity: usize,
+    entries: Mutex<VecDeque<Json>>,
+}
+
+impl RecentLogs {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn push(&self, kind: &str, fields: Vec<(&'static str, Json)>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut entry = vec![("time", time.into()), ("type", kind.into())];
+        entry.extend(fields);
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() == self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(Json::object(entry));
+        }
+    }
+
+    // keep the transcript of a request that ran the reloader
+    pub(crate) fn transcript(
+        &self,
+        genid: GenId,
+        request: &str,
+        peer: &str,
+        result: &str,
+        transcript: &Transcript,
+    ) {
+        self.push(
+            "transcript",
+            vec![
+                ("genid", genid.into()),
+                ("request", request.into()),
+                ("peer", peer.into()),
+                ("result", result.into()),
+                ("runs", runs_json(transcript)),
+            ],
+        );
+    }
+
+    // keep a significant event
+    pub(crate) fn event(&self, event: AlertEvent, genid: Option<GenId>, message: &str) {
+        self.push(
+            "event",
+            vec![
+                ("event", event.to_string().into()),
+                ("genid", genid.into()),
+                ("message", message.into()),
+            ],
+        );
+    }
+
+    // the last entries kept (all of them if no count is given), oldest first
+    pub(crate) fn to_json(&self, count: Option<usize>) -> Json {
+        let Ok(entries) = self.entries.lock() else {
+            return Json::Array(vec![]);
+        };
+        let skipped = count.map_or(0, |count| entries.len().saturating_sub(count));
+        Json::Array(entries.iter().skip(skipped).cloned().collect())
+    }
+}
@@ -12,19 +12,35 @@
     clippy::panic
 )]
 
+use std::fmt::Write as _;
 use std::fs::OpenOptions;
 use std::fs::create_dir_all;
-use std::fs::read_to_string;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{OnceLock, RwLock};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[allow(unused)]
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
 use super::GenId;
+use crate::cancel::{TESTS, Test};
+use crate::hooks::{HookStage, run_hook};
+use crate::metrics::METRICS;
+use crate::native;
+use crate::netns;
+use crate::rawtext;
+use crate::retry::RetryPolicy;
+use crate::runas::RunAs;
+use crate::sha256::sha256_hex;
+use crate::tempfiles;
+use crate::vtysh::Vtysh;
+use crate::vtyshlock::{LockPolicy, lock_contention, steal_lock};
 
 #[derive(Error, Debug)]
 pub enum FrrErr {
@@ -34,56 +50,386 @@ pub enum FrrErr {
     CmdSpawnFailed(String),
     #[error("Failed to wait for reloader: {0}")]
     CmdWaitFailed(String),
+    #[error("Bad config: {0}")]
+    BadConfig(String),
     #[error("Reloading error")]
     ReloadErr,
+    #[error("Reloading error: the reloader timed out after {0:?} and was killed")]
+    ReloadTimeout(Duration),
+    #[error("Reloading error: vtysh configuration is locked by another session ({0})")]
+    ConfigLocked(String),
+    #[error("CANCELLED\nthe --test stage of generation {0} was cancelled")]
+    Cancelled(GenId),
+    #[error("Pre-hook failed: {0}")]
+    HookFailed(String),
+    #[error("Refusing to run the reloader: {0}")]
+    ReloaderIntegrity(String),
+    #[error("{1} (after {0} attempts)")]
+    GaveUp(u32, Box<FrrErr>),
     #[error("Internal failure: {0}")]
     Failure(&'static str),
 }
 
-fn execute(
-    reloader: &str,
-    reload_args: &Vec<&str>,
-    conf_file: &Path,
+// outcome of one run of the reloader
+#[derive(Clone, Debug)]
+pub struct ReloaderRun {
+    pub test: bool,
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+    pub timed_out: bool,
+    pub attempt: u32, /* of the stage, with transient failures retried */
+}
+
+// the runs of the reloader for a request
+pub type Transcript = Vec<ReloaderRun>;
+
+// outcome of the --test stage of a config, with the transcript of the run
+pub type Validation = (Result<PathBuf, FrrErr>, Transcript);
+
+// PATH of the reloader, unless given in its environment
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+// the reloader, the args it is called with and the credentials it is run with. The reloader does
+// not inherit the environment of the agent: it only gets the variables given (and a default PATH).
+pub struct Reloader<'a> {
+    pub binary: RwLock<ReloaderBinary>, /* may be changed while the agent runs (SIGHUP) */
+    pub rundir: &'a str,
+    pub args: Vec<&'a str>,
+    pub env: Vec<(&'a str, &'a str)>,
+    pub run_as: Option<RunAs>,
+    pub lock: LockPolicy,
+    pub retry: RetryPolicy,
+    pub timeout: Option<Duration>, /* runs taking longer are killed */
+    pub native: Option<Vtysh<'a>>, /* configs are applied natively, with vtysh (--native-reloader) */
+    pub pre_hook: Option<&'a str>, /* run before the --test stage of generations */
+    pub netns: Option<&'a str>,    /* network namespace the reloader is run in */
+}
+
+// the reloader executable and the checksum it must have, if pinned
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReloaderBinary {
+    pub path: String,
+    pub sha256: Option<String>,
+}
+
+impl Reloader<'_> {
+    // the reloader executable currently run
+    pub fn binary(&self) -> ReloaderBinary {
+        match self.binary.read() {
+            Ok(binary) => binary.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+    // change the reloader executable run from now on
+    pub fn set_binary(&self, binary: ReloaderBinary) {
+        match self.binary.write() {
+            Ok(mut current) => *current = binary,
+            Err(poisoned) => *poisoned.into_inner() = binary,
+        }
+    }
+}
+
+// check the reloader against the checksum it is pinned to, if any, so that a tampered reloader
+// is never run (as root, typically)
+pub(crate) fn verify(reloader: &ReloaderBinary) -> Result<(), FrrErr> {
+    let Some(expected) = &reloader.sha256 else {
+        return Ok(());
+    };
+    let content = std::fs::read(&reloader.path)
+        .map_err(|e| FrrErr::ReloaderIntegrity(format!("could not read {}: {e}", reloader.path)))?;
+    let checksum = sha256_hex(&content);
+    if checksum != *expected {
+        error!(
+            "Reloader {} has checksum {checksum} instead of {expected}: not running it",
+            reloader.path
+        );
+        return Err(FrrErr::ReloaderIntegrity(format!(
+            "{} has checksum {checksum} instead of {expected}",
+            reloader.path
+        )));
+    }
+    Ok(())
+}
+
+// how often a reloader with a timeout is checked for completion
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// kill a process group
+#[allow(unsafe_code)]
+fn kill_group(pgid: u32) {
+    let Ok(pgid) = i32::try_from(pgid) else {
+        return;
+    };
+    // SAFETY: kill has no memory safety requirements
+    if unsafe { libc::kill(-pgid, libc::SIGKILL) } != 0 {
+        error!(
+            "Failed to kill process group {pgid}: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+// why a run of the reloader was killed
+#[derive(Clone, Copy, PartialEq)]
+enum Killed {
+    TimedOut,
+    Cancelled,
+}
+
+// wait for the reloader to complete and collect its output. If it does not complete within the
+// timeout, or its --test stage is cancelled, it is killed along with its children. Returns the
+// output and why it was killed, if so.
+fn wait_bounded(
+    mut child: Child,
+    timeout: Option<Duration>,
+    test: Option<&Test>,
+) -> std::io::Result<(Output, Option<Killed>)> {
+    if timeout.is_none() && test.is_none() {
+        return child.wait_with_output().map(|output| (output, None));
+    }
+    /* the pipes are drained while waiting, lest the reloader blocks writing to them */
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut output = vec![];
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut output);
+            }
+            output
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr = drain(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+    let started = Instant::now();
+    let mut killed = None;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if let Some(timeout) = timeout
+            && started.elapsed() > timeout
+        {
+            error!("Reloader timed out after {timeout:?}. Killing it...");
+            killed = Some(Killed::TimedOut);
+        } else if let Some(test) = test.filter(|test| test.cancelled()) {
+            warn!(
+                "Generation {} was cancelled. Killing the reloader...",
+                test.genid
+            );
+            killed = Some(Killed::Cancelled);
+        }
+        if killed.is_some() {
+            kill_group(child.id());
+            break child.wait()?;
+        }
+        sleep(WAIT_POLL_INTERVAL);
+    };
+    let output = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    Ok((output, killed))
+}
+
+// run the reloader once on a config file. The --test stage of a generation may be cancelled.
+fn run(
+    reloader: &Reloader,
+    conf_file: &str,
     test: bool,
-) -> Result<(), FrrErr> {
+    cancellable: Option<&Test>,
+) -> Result<ReloaderRun, FrrErr> {
+    if let Some(cancelled) = cancellable.filter(|test| test.cancelled()) {
+        return Err(FrrErr::Cancelled(cancelled.genid));
+    }
+    if let Some(vtysh) = &reloader.native {
+        return Ok(run_native(vtysh, conf_file, test));
+    }
+    let binary = reloader.binary();
+    verify(&binary)?;
     let mut args = if test {
         vec!["--test"]
     } else {
         vec!["--reload"]
     };
-    args.extend_from_slice(reload_args);
-
-    /* convert config file path back to string */
-    let conf_file = conf_file.to_str().ok_or(FrrErr::Failure("Bad filename"))?;
+    args.extend_from_slice(&reloader.args);
 
     /* Build command */
-    let mut cmd = Command::new(reloader);
+    let mut cmd = Command::new(&binary.path);
     cmd.args(args.clone());
+    cmd.env_clear();
+    if !reloader.env.iter().any(|(name, _)| *name == "PATH") {
+        cmd.env("PATH", DEFAULT_PATH);
+    }
+    cmd.envs(reloader.env.iter().copied());
+    if let Some(netns) = reloader.netns {
+        netns::enter(&mut cmd, netns, reloader.run_as).map_err(|e| {
+            error!("{e}");
+            FrrErr::CmdSpawnFailed(e)
+        })?;
+    } else if let Some(run_as) = reloader.run_as {
+        run_as.apply(&mut cmd);
+    }
     cmd.arg(conf_file);
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
 
-    debug!("Executing: {reloader} {} {}", args.join(" "), conf_file);
+    debug!(
+        "Executing: {} {} {}",
+        binary.path,
+        args.join(" "),
+        conf_file
+    );
+
+    /* the reloader runs in a process group of its own, so that it can be killed with its children */
+    cmd.process_group(0);
 
     /* execute */
-    let output = cmd
-        .spawn()
-        .map_err(|e| {
-            error!("Cmd spawn failed: {e}");
-            FrrErr::CmdSpawnFailed(format!("{e}"))
-        })?
-        .wait_with_output()
-        .map_err(|e| {
-            error!("Cmd wait failed: {e}");
-            FrrErr::CmdWaitFailed(format!("{e}"))
-        })?;
+    let temp_files = tempfiles::snapshot(reloader.rundir);
+    let started = Instant::now();
+    let child = cmd.spawn().map_err(|e| {
+        error!("Cmd spawn failed: {e}");
+        FrrErr::CmdSpawnFailed(format!("{e}"))
+    })?;
+    let (output, killed) = wait_bounded(child, reloader.timeout, cancellable).map_err(|e| {
+        error!("Cmd wait failed: {e}");
+        FrrErr::CmdWaitFailed(format!("{e}"))
+    })?;
 
     debug!("Reload completed (test:{test})");
-    if !output.status.success() {
+    let run = ReloaderRun {
+        test,
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        duration: started.elapsed(),
+        timed_out: killed == Some(Killed::TimedOut),
+        attempt: 1,
+    };
+    METRICS.reloader_run(&run);
+    if let Some(cancelled) = cancellable.filter(|_| killed == Some(Killed::Cancelled)) {
+        tempfiles::clean_new(reloader.rundir, &temp_files);
+        return Err(FrrErr::Cancelled(cancelled.genid));
+    }
+    if !run.success {
+        error!(">>>> FRR Reload failed! <<<<");
+        error!("stderr: {}", run.stderr);
+        error!("stdout: {}", run.stdout);
+        tempfiles::clean_new(reloader.rundir, &temp_files);
+    }
+    Ok(run)
+}
+
+// test or apply a config file natively, with vtysh, instead of with the reloader
+fn run_native(vtysh: &Vtysh, conf_file: &str, test: bool) -> ReloaderRun {
+    debug!("Executing natively (test:{test}): {conf_file}");
+    let started = Instant::now();
+    let native = native::run(vtysh, conf_file, test);
+    let run = ReloaderRun {
+        test,
+        success: native.success,
+        stdout: native.stdout,
+        stderr: native.stderr,
+        duration: started.elapsed(),
+        timed_out: false,
+        attempt: 1,
+    };
+    METRICS.reloader_run(&run);
+    if !run.success {
         error!(">>>> FRR Reload failed! <<<<");
-        error!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-        error!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-        return Err(FrrErr::ReloadErr);
+        error!("stderr: {}", run.stderr);
+        error!("stdout: {}", run.stdout);
+    }
+    run
+}
+
+// run a stage of the reloader, retrying it while it fails for reasons deemed transient. The
+// --test stage of a generation may be cancelled.
+fn execute(
+    reloader: &Reloader,
+    conf_file: &Path,
+    test: bool,
+    cancellable: Option<&Test>,
+    transcript: &mut Transcript,
+) -> Result<(), FrrErr> {
+    /* convert config file path back to string */
+    let conf_file = conf_file.to_str().ok_or(FrrErr::Failure("Bad filename"))?;
+
+    let policy = &reloader.retry;
+    let mut attempt = 1;
+    loop {
+        let first = transcript.len();
+        let result = execute_once(reloader, conf_file, test, cancellable, transcript);
+        for run in &mut transcript[first..] {
+            run.attempt = attempt;
+        }
+        let Err(e) = result else {
+            return Ok(());
+        };
+        let Some(class) = policy.retryable(&e, transcript[first..].last()) else {
+            return Err(e);
+        };
+        if attempt >= policy.attempts {
+            error!("Reloader still failing after {attempt} attempts ({class} failure): {e}");
+            return Err(FrrErr::GaveUp(attempt, Box::new(e)));
+        }
+        attempt += 1;
+        let backoff = policy.backoff(attempt);
+        warn!(
+            "Reloader failed ({class} failure): {e}. Retrying in {backoff:?} ({attempt}/{})...",
+            policy.attempts
+        );
+        sleep(backoff);
+    }
+}
+
+// run a stage of the reloader once, bar retries due to vtysh configuration lock contention
+fn execute_once(
+    reloader: &Reloader,
+    conf_file: &str,
+    test: bool,
+    cancellable: Option<&Test>,
+    transcript: &mut Transcript,
+) -> Result<(), FrrErr> {
+    /* applies failing due to the vtysh configuration lock are retried, then the lock is stolen */
+    let policy = reloader.lock;
+    let mut retries = 0;
+    let mut stolen = None;
+    loop {
+        let run = run(reloader, conf_file, test, cancellable)?;
+        let success = run.success;
+        let timed_out = run.timed_out;
+        let locked = !test && lock_contention(&run);
+        transcript.push(run);
+        if success {
+            break;
+        }
+        if timed_out {
+            return Err(FrrErr::ReloadTimeout(reloader.timeout.unwrap_or_default()));
+        }
+        if !locked {
+            return Err(FrrErr::ReloadErr);
+        }
+        if retries < policy.retries {
+            retries += 1;
+            warn!(
+                "vtysh configuration is locked by another session. Retrying ({retries}/{})...",
+                policy.retries
+            );
+            sleep(policy.interval);
+        } else if policy.steal && stolen.is_none() {
+            warn!(
+                "vtysh configuration is still locked after {retries} retries. Stealing the lock..."
+            );
+            stolen = Some(steal_lock());
+        } else {
+            let mut reason = format!("gave up after {retries} retries");
+            if let Some(stolen) = stolen {
+                let _ = write!(reason, " and stealing the lock from vtysh pids {stolen:?}");
+            }
+            return Err(FrrErr::ConfigLocked(reason));
+        }
     }
 
     if test {
@@ -94,12 +440,63 @@ fn execute(
     Ok(())
 }
 
-fn write_config_file(genid: GenId, config: &str, outdir: &str) -> Result<PathBuf, FrrErr> {
-    /* file name to write the config into */
-    let mut conf_file = PathBuf::from(outdir);
+// directory the configs of generations are kept in instead of outdir, if any (e.g. on tmpfs, for
+// diskless nodes), so that only their metadata is persisted
+static CONFIG_DIR: OnceLock<String> = OnceLock::new();
+
+// keep the configs of generations in a directory other than outdir
+pub fn keep_configs_in(dir: &str) {
+    let _ = CONFIG_DIR.set(dir.to_string());
+}
+
+// the directory the configs of generations are kept in
+pub fn config_dir(outdir: &str) -> &str {
+    CONFIG_DIR.get().map_or(outdir, String::as_str)
+}
+
+// file name a generation is stored as
+pub fn config_file_path(genid: GenId, outdir: &str) -> PathBuf {
+    let mut conf_file = PathBuf::from(config_dir(outdir));
     conf_file.push(format!("frr-config-gen-{genid}"));
     conf_file.set_extension("conf");
+    conf_file
+}
+
+// file name the metadata of a generation is stored as
+pub fn metadata_file_path(genid: GenId, outdir: &str) -> PathBuf {
+    Path::new(outdir).join(format!("frr-config-gen-{genid}.json"))
+}
+
+// the generation a file in outdir belongs to, if it is a config or metadata file
+pub fn generation_of(file_name: &str) -> Option<GenId> {
+    let genid = file_name.strip_prefix("frr-config-gen-")?;
+    let genid = genid
+        .strip_suffix(".conf")
+        .or_else(|| genid.strip_suffix(".json"))?;
+    genid.parse().ok()
+}
+
+// the generations whose config is stored, in ascending order
+pub fn stored_generations(outdir: &str) -> Vec<GenId> {
+    let Ok(entries) = std::fs::read_dir(config_dir(outdir)) else {
+        return vec![];
+    };
+    let mut generations: Vec<GenId> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| Path::new(name).extension().is_some_and(|ext| ext == "conf"))
+        .filter_map(|name| generation_of(&name))
+        .collect();
+    generations.sort_unstable();
+    generations
+}
+
+pub fn write_config_file(genid: GenId, config: &str, outdir: &str) -> Result<PathBuf, FrrErr> {
+    /* file name to write the config into */
+    write_config(config_file_path(genid, outdir), config)
+}
 
+fn write_config(conf_file: PathBuf, config: &str) -> Result<PathBuf, FrrErr> {
     if let Some(parent) = conf_file.parent() {
         create_dir_all(parent)
             .map_err(|e| FrrErr::COnfigFileWriteFailed(format!("Could not create dir: {e:?}")))?;
@@ -117,12 +514,12 @@ fn write_config_file(genid: GenId, config: &str, outdir: &str) -> Result<PathBuf
     debug!("Successfully created config file at {conf_file:?}");
 
     /* write config to file */
-    file.write_all(config.as_bytes()).map_err(|e| {
+    file.write_all(&rawtext::encode(config)).map_err(|e| {
         FrrErr::COnfigFileWriteFailed(format!("Unable to write config file: {e:?}"))
     })?;
 
     /* read file back: fixme, this may not be needed */
-    let contents = read_to_string(&conf_file).map_err(|e| {
+    let contents = rawtext::read(&conf_file).map_err(|e| {
         FrrErr::COnfigFileWriteFailed(format!("Unable to read written file: {e:?}"))
     })?;
     debug!("Requested config is:\n{contents}");
@@ -130,31 +527,89 @@ fn write_config_file(genid: GenId, config: &str, outdir: &str) -> Result<PathBuf
     Ok(conf_file)
 }
 
-fn do_frr_reload(
-    reloader: &str,
+// write the config for a generation and run frr-reload --test on it. This is the first stage
+// of a reload and may run ahead of time (e.g. while another generation is being applied).
+pub fn frr_validate(
+    reloader: &Reloader,
     genid: GenId,
     config: &str,
     outdir: &str,
-    reload_args: &Vec<&str>,
-) -> Result<(), FrrErr> {
+    transcript: &mut Transcript,
+) -> Result<PathBuf, FrrErr> {
+    let cancellable = TESTS.begin(genid);
     let config_file = write_config_file(genid, config, outdir)?;
 
+    if let Some(hook) = reloader.pre_hook {
+        run_hook(hook, HookStage::Pre, genid, &config_file, None).map_err(|e| {
+            error!("Pre-hook failed for generation {genid}: {e}");
+            FrrErr::HookFailed(e)
+        })?;
+    }
+
     // call frr-reload with --test
-    execute(reloader, reload_args, &config_file, true)?;
+    execute(reloader, &config_file, true, Some(&cancellable), transcript)?;
+    /* the native reloader is not interrupted, but the generation is not applied */
+    if cancellable.cancelled() {
+        return Err(FrrErr::Cancelled(genid));
+    }
+    Ok(config_file)
+}
+
+fn do_frr_reload(
+    reloader: &Reloader,
+    genid: GenId,
+    config: &str,
+    outdir: &str,
+    transcript: &mut Transcript,
+) -> Result<(), FrrErr> {
+    let config_file = frr_validate(reloader, genid, config, outdir, transcript)?;
 
     // call with --reload
-    execute(reloader, reload_args, &config_file, false)?;
+    execute(reloader, &config_file, false, None, transcript)?;
     Ok(())
 }
 
 pub fn frr_reload(
-    reloader: &str,
+    reloader: &Reloader,
+    genid: GenId,
+    config: &str,
+    outdir: &str,
+    transcript: &mut Transcript,
+) -> String {
+    match do_frr_reload(reloader, genid, config, outdir, transcript) {
+        Ok(()) => "Ok".to_string(),
+        Err(e) => e.to_string(),
+    }
+}
+
+// run frr-reload --test on a candidate config without it becoming a generation. The change set
+// computed by the reloader is in the transcript.
+pub fn frr_preview(
+    reloader: &Reloader,
     genid: GenId,
     config: &str,
     outdir: &str,
-    reload_args: &Vec<&str>,
+    transcript: &mut Transcript,
+) -> Result<(), FrrErr> {
+    let mut conf_file = PathBuf::from(outdir);
+    conf_file.push(format!("frr-config-preview-{genid}.conf"));
+    let conf_file = write_config(conf_file, config)?;
+    let result = execute(reloader, &conf_file, true, None, transcript);
+    let _ = std::fs::remove_file(&conf_file);
+    result
+}
+
+// apply a generation whose --test stage was already run by frr_validate()
+pub fn frr_reload_validated(
+    reloader: &Reloader,
+    validation: Validation,
+    transcript: &mut Transcript,
 ) -> String {
-    match do_frr_reload(reloader, genid, config, outdir, reload_args) {
+    let (validation, validation_transcript) = validation;
+    transcript.extend(validation_transcript);
+    match validation
+        .and_then(|config_file| execute(reloader, &config_file, false, None, transcript))
+    {
         Ok(()) => "Ok".to_string(),
         Err(e) => e.to_string(),
     }
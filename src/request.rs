@@ -0,0 +1,454 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Requests understood by the agent. A request is either a keepalive, a command or a config.
+// Commands are recognized by their first line, which is a keyword, e.g.
+//
+//      UPLOAD\n<config>     store and validate a config without applying it
+//      PREVIEW\n<config>    get the changes that applying a config would make, without applying it
+//      PROMOTE              apply a previously uploaded config (genid taken from the frame)
+//      PREPARE\n<config>    validate a config and lock it in for a two-phase apply
+//      COMMIT\n<token>      apply the config prepared (genid taken from the frame)
+//      ABORT\n<token>       release the config prepared (genid taken from the frame)
+//      DAEMONS\n<daemons>   set the FRR daemons to be enabled (e.g. "bgpd,bfdd")
+//      SERVICE\n<action>    start, stop or restart the FRR service
+//      ROLLBACK             re-apply a previously applied generation (genid taken from the frame,
+//                           0 for the last-known-good generation)
+//      APPLY[\nconfirm=<token>] apply a stored generation (genid taken from the frame). Without the
+//                           confirmation, the changes are previewed along with the token to confirm
+//      BASELINE             store the running config of FRR as generation 0 (genid must be 0)
+//      STATUS               get the status of the agent
+//      VERSION              get the build information and uptime of the agent
+//      HELLO\n<keepalive>   negotiate keepalives (e.g. "keepalive=10 misses=3"), and possibly the
+//                           framing and compression of messages (e.g. "framing=4 compression=zstd")
+//      INTERFACES[\n<names>] get the operational state of (some) interfaces (e.g. "eth0,eth1")
+//      SUBSCRIBE\nlogs      turn the connection into a stream of FRR log lines
+//      EXPORT\n<path>       export the generation history to an archive at path
+//      IMPORT\n<path>       import the generation history from the archive at path
+//      BLAME\n<line>        which generation introduced a line in the config of a generation
+//                           (genid taken from the frame, 0 for the last generation applied)
+//      CHANGES\n<from> [to] the generations processed in a time window (seconds since the epoch)
+//      RESET                close the circuit breaker
+//      FORCE\n<config>      apply a config even though disruptions are predicted (see --require-force)
+//      VTYSH                turn the connection into a vtysh passthrough session
+//      SHOW\n<command>      get the output of a vtysh show command (e.g. "show bgp summary json")
+//      LOGS[\n<count>]      get the last (count) reload transcripts and significant events
+//      HISTORY[\n<count>]   get the last (count) requests that wrote a generation, and their result
+//      FORMAT\n<format>     get subsequent responses on the connection as text or json
+//      CANCEL               cancel the apply of a generation queued or being validated (genid taken
+//                           from the frame); its --reload stage is never interrupted
+//      CHUNKED\n<params>    open a chunked transfer of a config (e.g. "size=4194304"), its
+//                           chunks following in messages of their own (see chunked.rs)
+//
+// Any other message is considered a config to be applied. Any request may be prefixed with
+// "INSTANCE\n<name>\n" to target an FRR instance other than the default one (see --instance).
+
+use std::sync::Arc;
+
+use crate::chunked::{Spooled, Transfer};
+use crate::framing::{Compression, Framing};
+use crate::logstream::LogSource;
+use crate::rawtext;
+use crate::response::ResponseFormat;
+use crate::service::FrrAction;
+use crate::session::Keepalive;
+use crate::{Args, GenId};
+
+#[derive(Clone, Debug)]
+pub(crate) enum Request {
+    Keepalive,
+    Config(String, bool), /* the config and whether it is forced despite predicted disruptions */
+    Upload(String),
+    Preview(String),
+    Promote,
+    Prepare(String),
+    Commit(String),
+    Abort(String),
+    Daemons(Vec<String>),
+    Service(FrrAction),
+    Rollback,
+    Apply(Option<String>),
+    Baseline,
+    Status,
+    Version,
+    Hello(Keepalive, Option<Framing>, Option<Compression>), /* with those negotiated, if offered */
+    Interfaces(Vec<String>),
+    Subscribe(LogSource),
+    Export(String),
+    Import(String),
+    Blame(String),
+    Changes(u64, Option<u64>),
+    Reset,
+    Vtysh,
+    Show(String),
+    Logs(Option<usize>),
+    History(Option<usize>),
+    Format(ResponseFormat),
+    Chunked(Result<Transfer, String>), /* the transfer, or why its params are bad */
+    Spooled(Arc<Spooled>, bool),       /* a config received in chunks, and whether it is forced */
+    Cancel,
+    Invalid(String),
+}
+
+impl Request {
+    pub(crate) fn parse(message: String, args: &Args) -> Self {
+        if args.strict_utf8 && rawtext::is_escaped(&message) {
+            return Request::Invalid("Request is not valid UTF-8".to_string());
+        }
+        let (keyword, rest) = message.split_once('\n').unwrap_or((&message, ""));
+        match keyword.trim_end() {
+            "KEEPALIVE" => Request::Keepalive,
+            "UPLOAD" => Request::Upload(rest.to_string()),
+            "PREVIEW" => Request::Preview(rest.to_string()),
+            "PROMOTE" => Request::Promote,
+            "PREPARE" => Request::Prepare(rest.to_string()),
+            "COMMIT" | "ABORT" if rest.trim().is_empty() => {
+                Request::Invalid(format!("Missing token in {}", keyword.trim_end()))
+            }
+            "COMMIT" => Request::Commit(rest.trim().to_string()),
+            "ABORT" => Request::Abort(rest.trim().to_string()),
+            "DAEMONS" => Request::Daemons(parse_list(rest)),
+            "ROLLBACK" => Request::Rollback,
+            "APPLY" => match rest.trim() {
+                "" => Request::Apply(None),
+                confirm => match confirm.strip_prefix("confirm=") {
+                    Some(token) => Request::Apply(Some(token.to_string())),
+                    None => Request::Invalid(format!("Bad confirmation '{confirm}'")),
+                },
+            },
+            "BASELINE" => Request::Baseline,
+            "STATUS" => Request::Status,
+            "VERSION" => Request::Version,
+            "INTERFACES" => Request::Interfaces(parse_list(rest)),
+            "SUBSCRIBE" => match rest.trim() {
+                "logs" => Request::Subscribe(args.log_source()),
+                topic => Request::Invalid(format!("Unknown subscription topic '{topic}'")),
+            },
+            "EXPORT" | "IMPORT" if rest.trim().is_empty() => {
+                Request::Invalid(format!("Missing archive path in {}", keyword.trim_end()))
+            }
+            "EXPORT" => Request::Export(rest.trim().to_string()),
+            "IMPORT" => Request::Import(rest.trim().to_string()),
+            "BLAME" if rest.trim().is_empty() => Request::Invalid("Missing line to blame".into()),
+            "BLAME" => Request::Blame(rest.trim().to_string()),
+            "CHANGES" => match parse_window(rest) {
+                Ok((from, to)) => Request::Changes(from, to),
+                Err(e) => Request::Invalid(e),
+            },
+            "RESET" => Request::Reset,
+            "VTYSH" => Request::Vtysh,
+            "SHOW" if rest.trim().is_empty() => Request::Invalid("Missing show command".into()),
+            "SHOW" => Request::Show(rest.trim().to_string()),
+            "LOGS" => parse_count(rest).map_or_else(Request::Invalid, Request::Logs),
+            "HISTORY" => parse_count(rest).map_or_else(Request::Invalid, Request::History),
+            "FORMAT" => match ResponseFormat::parse(rest) {
+                Some(format) => Request::Format(format),
+                None => Request::Invalid(format!("Unknown response format '{}'", rest.trim())),
+            },
+            "HELLO" => parse_hello(rest).unwrap_or_else(Request::Invalid),
+            "CHUNKED" => Request::Chunked(Transfer::parse(rest)),
+            "CANCEL" => Request::Cancel,
+            "SERVICE" => match FrrAction::parse(rest) {
+                Some(action) => Request::Service(action),
+                None => Request::Invalid(format!("Unknown service action '{}'", rest.trim())),
+            },
+            "FORCE" => Request::Config(rest.to_string(), true),
+            _ => Request::Config(message, false),
+        }
+    }
+    // whether the response to the request is a JSON document
+    pub(crate) fn is_json_query(&self) -> bool {
+        matches!(
+            self,
+            Request::Keepalive
+                | Request::Status
+                | Request::Version
+                | Request::Interfaces(_)
+                | Request::Blame(_)
+                | Request::Changes(..)
+                | Request::Logs(_)
+                | Request::History(_)
+                | Request::Apply(None)
+                | Request::Preview(_)
+                | Request::Prepare(_)
+        )
+    }
+    // whether the request must not be processed concurrently with another such request (e.g.
+    // from another client), like those running the reloader. Queries are processed concurrently.
+    pub(crate) fn is_serialized(&self) -> bool {
+        !matches!(
+            self,
+            Request::Keepalive
+                | Request::Status
+                | Request::Version
+                | Request::Hello(..)
+                | Request::Interfaces(_)
+                | Request::Subscribe(_)
+                | Request::Vtysh
+                | Request::Show(_)
+                | Request::Logs(_)
+                | Request::History(_)
+                | Request::Format(_)
+                | Request::Blame(_)
+                | Request::Changes(..)
+                | Request::Apply(None)
+                | Request::Export(_)
+                | Request::Cancel
+                | Request::Invalid(_)
+        )
+    }
+    // whether the request is a config to be applied, whether it was received in chunks or not
+    pub(crate) fn is_config(&self) -> bool {
+        matches!(self, Request::Config(..) | Request::Spooled(..))
+    }
+    // whether the request writes a generation to outdir
+    pub(crate) fn writes_generation(&self) -> bool {
+        matches!(
+            self,
+            Request::Config(..)
+                | Request::Spooled(..)
+                | Request::Upload(_)
+                | Request::Prepare(_)
+                | Request::Promote
+                | Request::Commit(_)
+                | Request::Rollback
+                | Request::Apply(Some(_))
+                | Request::Baseline
+        )
+    }
+    // whether the request applies a generation
+    pub(crate) fn applies(&self) -> bool {
+        self.writes_generation()
+            && !matches!(
+                self,
+                Request::Upload(_) | Request::Prepare(_) | Request::Baseline
+            )
+    }
+    pub(crate) fn describe(&self, genid: GenId) -> String {
+        match self {
+            Request::Keepalive => "keepalive".to_string(),
+            Request::Config(..) | Request::Spooled(..) => {
+                format!("config request for generation {genid}")
+            }
+            Request::Upload(_) => format!("upload request for generation {genid}"),
+            Request::Preview(_) => format!("preview request for generation {genid}"),
+            Request::Promote => format!("promote request for generation {genid}"),
+            Request::Prepare(_) => format!("prepare request for generation {genid}"),
+            Request::Commit(_) => format!("commit request for generation {genid}"),
+            Request::Abort(_) => format!("abort request for generation {genid}"),
+            Request::Daemons(daemons) => format!("request to enable daemons {daemons:?}"),
+            Request::Service(action) => format!("request to {action} FRR"),
+            Request::Rollback => format!("rollback request to generation {genid}"),
+            Request::Apply(None) => format!("preview of generation {genid}"),
+            Request::Apply(Some(_)) => format!("request to apply generation {genid}"),
+            Request::Baseline => "baseline of the running config".to_string(),
+            Request::Status => "status request".to_string(),
+            Request::Version => "version request".to_string(),
+            Request::Hello(keepalive, ..) => format!("hello ({keepalive})"),
+            Request::Interfaces(_) => "interfaces request".to_string(),
+            Request::Subscribe(_) => "subscription to FRR logs".to_string(),
+            Request::Export(path) => format!("request to export history to {path}"),
+            Request::Import(path) => format!("request to import history from {path}"),
+            Request::Blame(line) => format!("blame of '{line}' in generation {genid}"),
+            Request::Changes(from, to) => format!("change log from {from} to {to:?}"),
+            Request::Reset => "request to reset the circuit breaker".to_string(),
+            Request::Vtysh => "vtysh passthrough request".to_string(),
+            Request::Show(command) => format!("show request '{command}'"),
+            Request::Logs(_) => "logs request".to_string(),
+            Request::History(_) => "history request".to_string(),
+            Request::Format(format) => format!("request for {format:?} responses"),
+            Request::Chunked(_) => format!("chunked transfer of generation {genid}"),
+            Request::Cancel => format!("cancellation of generation {genid}"),
+            Request::Invalid(_) => "invalid request".to_string(),
+        }
+    }
+}
+
+// parse an optional count of entries
+fn parse_count(count: &str) -> Result<Option<usize>, String> {
+    match count.trim() {
+        "" => Ok(None),
+        count => count
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Bad count '{count}': {e}")),
+    }
+}
+
+// parse the params of a HELLO: those of keepalives, and the framings and compressions offered, if
+// any (e.g. "keepalive=10 misses=3 framing=4,3 compression=zstd,none")
+fn parse_hello(params: &str) -> Result<Request, String> {
+    let (offered, params): (Vec<&str>, Vec<&str>) = params
+        .split_whitespace()
+        .partition(|param| param.starts_with("framing=") || param.starts_with("compression="));
+    let offered = |name: &str| {
+        offered
+            .iter()
+            .rev()
+            .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+    };
+    let framing = match offered("framing") {
+        Some(offered) => Some(
+            Framing::negotiate(offered).ok_or(format!("No supported framing in '{offered}'"))?,
+        ),
+        None => None,
+    };
+    let compression = match offered("compression") {
+        Some(offered) => Some(
+            Compression::negotiate(offered)
+                .ok_or(format!("No supported compression in '{offered}'"))?,
+        ),
+        None => None,
+    };
+    Ok(Request::Hello(
+        Keepalive::parse(&params.join(" "))?,
+        framing,
+        compression,
+    ))
+}
+
+// parse a list of items separated by commas, whitespace or newlines
+pub(crate) fn parse_list(list: &str) -> Vec<String> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// parse a time window like "<from> [to]", in seconds since the epoch
+fn parse_window(window: &str) -> Result<(u64, Option<u64>), String> {
+    let mut times = window
+        .split_whitespace()
+        .map(|time| time.parse().map_err(|e| format!("Bad time '{time}': {e}")));
+    let from = times.next().ok_or("Missing start of time window")??;
+    let to = times.next().transpose()?;
+    if times.next().is_some() {
+        return Err("Too many times in time window".to_string());
+    }
+    Ok((from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use super::*;
+
+    fn parse(message: &str) -> Request {
+        parse_with(message, &[])
+    }
+
+    fn parse_with(message: &str, options: &[&str]) -> Request {
+        let cmdline = ["frr-agent", "--sock-path", "/tmp/frr-agent.sock"];
+        let args = Args::try_parse_from(cmdline.iter().chain(options)).unwrap_or_else(|e| {
+            unreachable!("{e}");
+        });
+        Request::parse(message.to_string(), &args)
+    }
+
+    #[test]
+    fn configs() {
+        let config = "frr version 10.2\n!\nrouter bgp 65001\n";
+        assert!(matches!(parse(config), Request::Config(c, false) if c == config));
+        assert!(matches!(parse(""), Request::Config(c, false) if c.is_empty()));
+        /* keywords are only recognized on the first line */
+        assert!(matches!(parse("!\nSTATUS"), Request::Config(..)));
+        assert!(
+            matches!(parse("FORCE\nrouter bgp 1"), Request::Config(c, true) if c == "router bgp 1")
+        );
+    }
+
+    #[test]
+    fn keywords() {
+        assert!(matches!(parse("KEEPALIVE"), Request::Keepalive));
+        assert!(matches!(parse("STATUS\n"), Request::Status));
+        assert!(matches!(parse("STATUS \r"), Request::Status));
+        assert!(matches!(parse("UPLOAD\nrouter bgp 1"), Request::Upload(c) if c == "router bgp 1"));
+        assert!(matches!(parse("COMMIT\n t0k3n \n"), Request::Commit(t) if t == "t0k3n"));
+        assert!(matches!(parse("COMMIT"), Request::Invalid(_)));
+        assert!(matches!(parse("ABORT\n"), Request::Invalid(_)));
+        assert!(matches!(parse("CANCEL"), Request::Cancel));
+        assert!(matches!(parse("SHOW"), Request::Invalid(_)));
+        assert!(
+            matches!(parse("SHOW\nshow bgp summary"), Request::Show(c) if c == "show bgp summary")
+        );
+        assert!(matches!(parse("SUBSCRIBE\nmetrics"), Request::Invalid(_)));
+    }
+
+    #[test]
+    fn apply_confirmations() {
+        assert!(matches!(parse("APPLY"), Request::Apply(None)));
+        assert!(matches!(parse("APPLY\nconfirm=abc"), Request::Apply(Some(t)) if t == "abc"));
+        assert!(matches!(parse("APPLY\nabc"), Request::Invalid(_)));
+    }
+
+    #[test]
+    fn lists_and_counts() {
+        assert!(matches!(
+            parse("DAEMONS\nbgpd, bfdd\nstaticd"),
+            Request::Daemons(daemons) if daemons == ["bgpd", "bfdd", "staticd"]
+        ));
+        assert!(matches!(parse("INTERFACES"), Request::Interfaces(names) if names.is_empty()));
+        assert!(matches!(parse("LOGS"), Request::Logs(None)));
+        assert!(matches!(parse("LOGS\n5"), Request::Logs(Some(5))));
+        assert!(matches!(parse("HISTORY\n-1"), Request::Invalid(_)));
+        assert!(matches!(
+            parse("CHANGES\n10 20"),
+            Request::Changes(10, Some(20))
+        ));
+        assert!(matches!(parse("CHANGES\n10"), Request::Changes(10, None)));
+        assert!(matches!(parse("CHANGES"), Request::Invalid(_)));
+        assert!(matches!(parse("CHANGES\n1 2 3"), Request::Invalid(_)));
+    }
+
+    #[test]
+    fn hellos() {
+        assert!(matches!(
+            parse("HELLO\nkeepalive=10 misses=2"),
+            Request::Hello(k, None, None) if k.interval == Duration::from_secs(10) && k.misses == 2
+        ));
+        assert!(matches!(
+            parse("HELLO\nkeepalive=5 framing=5,3,2"),
+            Request::Hello(k, Some(Framing::V3), None) if k.misses == 3
+        ));
+        /* zstd is only agreed if the agent decompresses it, and HELLO fails otherwise */
+        let zstd = parse("HELLO\nkeepalive=5 framing=4 compression=zstd");
+        if cfg!(feature = "zstd") {
+            assert!(matches!(
+                zstd,
+                Request::Hello(_, Some(Framing::V4), Some(Compression::Zstd))
+            ));
+        } else {
+            assert!(matches!(zstd, Request::Invalid(_)));
+        }
+        assert!(matches!(
+            parse("HELLO\nkeepalive=5 compression=lz4,none"),
+            Request::Hello(_, None, Some(Compression::None))
+        ));
+        assert!(matches!(
+            parse("HELLO\nkeepalive=5 compression=lz4"),
+            Request::Invalid(_)
+        ));
+        assert!(matches!(
+            parse("HELLO\nkeepalive=5 framing=5"),
+            Request::Invalid(_)
+        ));
+        assert!(matches!(parse("HELLO\nmisses=2"), Request::Invalid(_)));
+        assert!(matches!(
+            parse("HELLO\nkeepalive=5 bogus=1"),
+            Request::Invalid(_)
+        ));
+    }
+
+    #[test]
+    fn strict_utf8() {
+        let message = rawtext::decode(b"router bgp 1\n description caf\xe9\n".to_vec());
+        assert!(matches!(parse(&message), Request::Config(..)));
+        assert!(matches!(
+            parse_with(&message, &["--strict-utf8"]),
+            Request::Invalid(_)
+        ));
+    }
+}
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Structured responses, so that controllers need not parse free text. A client sending
+// FORMAT\njson gets every subsequent response on the connection as a JSON object (version 1) like
+//
+//   {"version":1,"code":50,"category":"apply_failure","message":"Reloading error","runs":[...],...}
+//
+// with a numeric result code and its category:
+//    0 ok             the request succeeded (or was shadowed, see "shadowed")
+//   10 invalid        the request is not understood
+//   20 refused        the request was refused (throttled, breaker open, policy violation...)
+//   21 held           the request is held until FRR is reachable
+//   24 stale_generation the generation is not newer than the highest one applied (--monotonic-genid)
+//   25 cancelled      the apply of the generation was cancelled (CANCEL)
+//   30 write_failure  the config could not be written
+//   40 test_failure   the config did not pass validation (frr-reload --test)
+//   50 apply_failure  the config could not be applied
+//   51 locked         vtysh configuration is locked by another session
+//   60 timeout        the reloader timed out
+//   99 failure        any other failure
+// along with the response in the text format ("message"), the result of queries ("result"), the
+// checksum, complexity and predicted impact of the generations written (and the generation active
+// after a rollback), and the runs of the reloader (stage, success, duration, attempt, stdout and
+// stderr), the changes computed by the test stage in "diff".
+
+use crate::GenId;
+use crate::agent::{Outcome, SHADOWED};
+use crate::json::Json;
+use crate::request::Request;
+use crate::shipping::runs_json;
+
+const VERSION: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ResponseFormat {
+    Text,
+    Json,
+}
+impl ResponseFormat {
+    pub(crate) fn parse(format: &str) -> Option<Self> {
+        match format.trim() {
+            "text" => Some(ResponseFormat::Text),
+            "json" => Some(ResponseFormat::Json),
+            _ => None,
+        }
+    }
+
+    // the format of the responses following the response to a request
+    pub(crate) fn after(self, request: &Request, outcome: &Outcome) -> Self {
+        match request {
+            Request::Format(format) if outcome.response == "Ok" => *format,
+            _ => self,
+        }
+    }
+
+    // the response to a request, in this format
+    pub(crate) fn encode(self, request: &Request, genid: GenId, outcome: &Outcome) -> String {
+        match self {
+            ResponseFormat::Text => outcome.response.clone(),
+            ResponseFormat::Json => structured(request, genid, outcome).to_string(),
+        }
+    }
+}
+
+// prefixes of the responses to requests refused
+const REFUSALS: [&str; 9] = [
+    "THROTTLED",
+    "RATE_LIMITED",
+    "FRR_DOWN",
+    "POLICY_VIOLATION",
+    "DISRUPTIVE",
+    "GR_REQUIRED",
+    "BREAKER_OPEN",
+    "Locked:",
+    "Not authorized",
+];
+
+// the result of a request that wrote a generation, from a response like
+// "Ok\nsha256=<checksum>\ncomplexity=<json>" followed by optional lines like "impact=<json>",
+// "active=<genid>", "unchanged=<genid>", "diff=<json string>", "attempts=<n>",
+// "persist_error=<error>" or "verification=<json>"
+pub(crate) fn written(response: &str) -> Option<Json> {
+    let mut lines = response.lines();
+    let status = lines.next().filter(|s| *s == "Ok" || *s == SHADOWED)?;
+    let checksum = lines.next()?.strip_prefix("sha256=")?;
+    let complexity = lines.next()?.strip_prefix("complexity=")?;
+    let mut members = vec![
+        ("status", status.into()),
+        ("sha256", checksum.into()),
+        ("complexity", Json::parse(complexity).ok()?),
+        ("impact", Json::Array(vec![])),
+    ];
+    for line in lines {
+        if let Some(impact) = line.strip_prefix("impact=") {
+            members.push(("impact", Json::parse(impact).ok()?));
+        } else if let Some(active) = line.strip_prefix("active=") {
+            members.push(("active", active.parse::<GenId>().ok()?.into()));
+        } else if let Some(unchanged) = line.strip_prefix("unchanged=") {
+            members.push(("unchanged", unchanged.parse::<GenId>().ok()?.into()));
+        } else if let Some(diff) = line.strip_prefix("diff=") {
+            members.push(("diff", Json::parse(diff).ok()?));
+        } else if let Some(attempts) = line.strip_prefix("attempts=") {
+            members.push(("attempts", attempts.parse::<u64>().ok()?.into()));
+        } else if let Some(e) = line.strip_prefix("persist_error=") {
+            members.push(("persist_error", e.into()));
+        } else if let Some(verification) = line.strip_prefix("verification=") {
+            members.push(("verification", Json::parse(verification).ok()?));
+        }
+    }
+    Some(Json::object(members))
+}
+
+// the result code and category of the outcome of a request
+fn classify(request: &Request, outcome: &Outcome) -> (u64, &'static str) {
+    let response = outcome.response.as_str();
+    let status = response.lines().next().unwrap_or_default();
+    if status == "Ok"
+        || status == SHADOWED
+        || (request.is_json_query() && written(response).is_none() && Json::parse(response).is_ok())
+    {
+        return (0, "ok");
+    }
+    if matches!(request, Request::Invalid(_)) {
+        return (10, "invalid");
+    }
+    if REFUSALS.iter().any(|refusal| status.starts_with(refusal)) {
+        return (20, "refused");
+    }
+    if status == "HELD" {
+        return (21, "held");
+    }
+    if status == "QUEUED" {
+        return (22, "queued");
+    }
+    if status == "BUSY" {
+        return (23, "busy");
+    }
+    if status == "STALE_GENERATION" {
+        return (24, "stale_generation");
+    }
+    if status == "CANCELLED" {
+        return (25, "cancelled");
+    }
+    if response.starts_with("Failed to write config file") {
+        return (30, "write_failure");
+    }
+    if response.contains("vtysh configuration is locked") {
+        return (51, "locked");
+    }
+    if response.starts_with("Verification failed") {
+        return (52, "verification_failure");
+    }
+    match outcome.transcript.last() {
+        Some(run) if run.timed_out => (60, "timeout"),
+        Some(run) if !run.success && run.test => (40, "test_failure"),
+        Some(run) if !run.success => (50, "apply_failure"),
+        _ if response.starts_with("Bad config") => (40, "test_failure"),
+        _ if response.starts_with("Reloading error") => (50, "apply_failure"),
+        _ => (99, "failure"),
+    }
+}
+
+// the structured response to a request
+fn structured(request: &Request, genid: GenId, outcome: &Outcome) -> Json {
+    let response = outcome.response.as_str();
+    let (code, category) = classify(request, outcome);
+    let mut members = vec![
+        ("version", VERSION.into()),
+        ("genid", genid.into()),
+        ("code", code.into()),
+        ("category", category.into()),
+        ("message", response.into()),
+    ];
+    if let Some(Json::Object(written)) = written(response) {
+        members.extend([
+            (
+                "shadowed",
+                (written.get("status") == Some(&Json::from(SHADOWED))).into(),
+            ),
+            (
+                "sha256",
+                written.get("sha256").cloned().unwrap_or(Json::Null),
+            ),
+            (
+                "complexity",
+                written.get("complexity").cloned().unwrap_or(Json::Null),
+            ),
+            (
+                "impact",
+                written.get("impact").cloned().unwrap_or(Json::Null),
+            ),
+        ]);
+        for member in [
+            "active",
+            "unchanged",
+            "attempts",
+            "persist_error",
+            "verification",
+        ] {
+            if let Some(value) = written.get(member) {
+                members.push((member, value.clone()));
+            }
+        }
+    } else if request.is_json_query()
+        && let Ok(result) = Json::parse(response)
+    {
+        members.push(("result", result));
+    }
+    if !outcome.transcript.is_empty() {
+        let diff = outcome
+            .transcript
+            .iter()
+            .rev()
+            .find(|run| run.test)
+            .map(|run| run.stdout.as_str());
+        members.push(("diff", diff.into()));
+        members.push(("runs", runs_json(&outcome.transcript)));
+    }
+    Json::object(members)
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Retention of the generations stored (--keep-generations, --keep-days), so that outdir does not
+// accumulate the configs of every generation ever applied. After each successful apply, the
+// generations older than the one applied are pruned if they are not among the most recent ones
+// kept, or were stored longer ago than the days kept. The generation applied and the last-known-good
+// one are never pruned, nor are generations newer than the one applied (e.g. being validated).
+
+use std::fs;
+use std::io::ErrorKind;
+use std::time::{Duration, SystemTime};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::reload::{config_file_path, metadata_file_path, stored_generations};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Retention {
+    generations: Option<usize>, /* most recent generations kept */
+    age: Option<Duration>,      /* age past which generations are pruned */
+}
+impl Retention {
+    pub(crate) fn new(generations: Option<usize>, days: Option<u64>) -> Option<Self> {
+        if generations.is_none() && days.is_none() {
+            return None;
+        }
+        Some(Self {
+            generations,
+            age: days.map(|days| Duration::from_secs(days.saturating_mul(SECS_PER_DAY))),
+        })
+    }
+
+    // whether a generation stored is past retention
+    fn expired(&self, genid: GenId, rank: usize, outdir: &str) -> bool {
+        if self.generations.is_some_and(|kept| rank >= kept) {
+            return true;
+        }
+        let Some(age) = self.age else {
+            return false;
+        };
+        fs::metadata(config_file_path(genid, outdir))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|stored| SystemTime::now().duration_since(stored).ok())
+            .is_some_and(|stored_for| stored_for > age)
+    }
+
+    // prune the generations past retention once generation applied is applied, keeping those
+    // protected (e.g. the last-known-good one)
+    pub(crate) fn prune(&self, outdir: &str, applied: GenId, protected: &[GenId]) {
+        let mut generations = stored_generations(outdir);
+        generations.retain(|genid| *genid <= applied);
+        let mut pruned = 0;
+        /* the most recent generations are ranked first */
+        for (rank, genid) in generations.into_iter().rev().enumerate() {
+            if genid == applied || protected.contains(&genid) || !self.expired(genid, rank, outdir)
+            {
+                continue;
+            }
+            match remove_generation(genid, outdir) {
+                Ok(()) => pruned += 1,
+                Err(e) => warn!("Could not prune generation {genid}: {e}"),
+            }
+        }
+        if pruned > 0 {
+            info!("Pruned {pruned} generation(s) past retention");
+        }
+    }
+}
+
+// remove the config and metadata of a generation
+fn remove_generation(genid: GenId, outdir: &str) -> Result<(), String> {
+    for path in [
+        config_file_path(genid, outdir),
+        metadata_file_path(genid, outdir),
+    ] {
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Could not remove {}: {e}", path.display())),
+        }
+    }
+    debug!("Pruned generation {genid}");
+    Ok(())
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Retries of reloader runs failing for transient reasons, e.g. the reloader failing to reach a
+// daemon whose vty socket in rundir is not ready yet right after FRR restarts. Each stage (--test
+// and --reload) is attempted up to --reload-attempts times, with a backoff doubling from
+// --reload-retry-backoff between attempts, if its failure is of a class deemed retryable
+// (--reload-retry-on). Failures due to the vtysh configuration lock have a policy of their own.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::reload::{FrrErr, ReloaderRun};
+
+// what the reloader outputs when a daemon cannot be reached (yet)
+const SOCKET_MARKERS: [&str; 5] = [
+    "connection refused",
+    "failed to connect",
+    "resource temporarily unavailable",
+    "no such file or directory",
+    "is not running",
+];
+
+// the backoff between attempts is at most this
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// classes of failures that may be retried
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetryClass {
+    Socket,  /* the reloader could not reach a daemon */
+    Spawn,   /* the reloader could not be spawned */
+    Timeout, /* the reloader timed out */
+    Any,     /* the reloader failed, whatever the reason */
+}
+impl RetryClass {
+    pub fn parse(class: &str) -> Result<Self, String> {
+        match class {
+            "socket" => Ok(RetryClass::Socket),
+            "spawn" => Ok(RetryClass::Spawn),
+            "timeout" => Ok(RetryClass::Timeout),
+            "any" => Ok(RetryClass::Any),
+            _ => Err(format!(
+                "Bad retry class '{class}': expected socket, spawn, timeout or any"
+            )),
+        }
+    }
+}
+impl Display for RetryClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryClass::Socket => write!(f, "socket"),
+            RetryClass::Spawn => write!(f, "spawn"),
+            RetryClass::Timeout => write!(f, "timeout"),
+            RetryClass::Any => write!(f, "any"),
+        }
+    }
+}
+
+// whether a failed run of the reloader could not reach a daemon
+fn socket_failure(run: &ReloaderRun) -> bool {
+    let output = format!("{}\n{}", run.stdout, run.stderr).to_lowercase();
+    !run.success && SOCKET_MARKERS.iter().any(|marker| output.contains(marker))
+}
+
+// how transient failures of the reloader are retried
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u32, /* of each stage, at least 1 */
+    pub backoff: Duration,
+    pub classes: Vec<RetryClass>,
+}
+impl RetryPolicy {
+    // whether retries are enabled at all
+    pub fn enabled(&self) -> bool {
+        self.attempts > 1 && !self.classes.is_empty()
+    }
+
+    // the class of a failure that may be retried, given the last run of the reloader, if any
+    pub fn retryable(&self, e: &FrrErr, run: Option<&ReloaderRun>) -> Option<RetryClass> {
+        let class = match e {
+            FrrErr::CmdSpawnFailed(_) => RetryClass::Spawn,
+            FrrErr::ReloadTimeout(_) => RetryClass::Timeout,
+            FrrErr::ReloadErr if run.is_some_and(socket_failure) => RetryClass::Socket,
+            FrrErr::ReloadErr => RetryClass::Any,
+            _ => return None,
+        };
+        if self.classes.contains(&class) {
+            return Some(class);
+        }
+        /* any covers every failure of the reloader */
+        self.classes.contains(&RetryClass::Any).then_some(class)
+    }
+
+    // the backoff before an attempt (the second one being attempt 2)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(2).min(16);
+        self.backoff.saturating_mul(1 << doublings).min(MAX_BACKOFF)
+    }
+}
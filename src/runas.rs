@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Credentials the reloader is run with, so that it does not inherit the privileges of the agent,
+// and those the agent drops to once its listeners are bound (--run-as-user). Users and groups may
+// be given by name (looked up in /etc/passwd and /etc/group) or by id.
+
+use std::fmt::Display;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+const PASSWD: &str = "/etc/passwd";
+const GROUP: &str = "/etc/group";
+
+#[derive(Clone, Copy, Debug)]
+pub struct RunAs {
+    pub uid: Option<u32>, /* None to keep the uid of the agent */
+    pub gid: u32,
+}
+impl Display for RunAs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.uid {
+            Some(uid) => write!(f, "uid:{uid} gid:{}", self.gid),
+            None => write!(f, "gid:{}", self.gid),
+        }
+    }
+}
+
+// look up an entry by name in a file with the format of /etc/passwd or /etc/group, returning
+// its fields
+fn lookup(db: &str, name: &str) -> Result<Vec<String>, String> {
+    let contents = std::fs::read_to_string(db).map_err(|e| format!("Could not read {db}: {e}"))?;
+    contents
+        .lines()
+        .map(|line| line.split(':').map(str::to_string).collect::<Vec<_>>())
+        .find(|fields| fields.first().is_some_and(|field| field == name))
+        .ok_or(format!("No entry for '{name}' in {db}"))
+}
+
+fn id_field(fields: &[String], index: usize, db: &str) -> Result<u32, String> {
+    fields
+        .get(index)
+        .and_then(|id| id.parse().ok())
+        .ok_or(format!("Bad entry in {db}: {}", fields.join(":")))
+}
+
+// the uid and primary gid of a user
+fn user(user: &str) -> Result<(u32, Option<u32>), String> {
+    if let Ok(uid) = user.parse() {
+        return Ok((uid, None));
+    }
+    let fields = lookup(PASSWD, user)?;
+    Ok((
+        id_field(&fields, 2, PASSWD)?,
+        Some(id_field(&fields, 3, PASSWD)?),
+    ))
+}
+
+// the uid of a user, given by name or id
+pub fn user_id(name: &str) -> Result<u32, String> {
+    user(name).map(|(uid, _)| uid)
+}
+
+// the supplementary groups of a user, given by name or id: those listing it as a member in
+// /etc/group (e.g. frrvty, granting access to the vty sockets of the FRR daemons)
+pub fn supplementary_groups(user: &str) -> Result<Vec<u32>, String> {
+    let passwd =
+        std::fs::read_to_string(PASSWD).map_err(|e| format!("Could not read {PASSWD}: {e}"))?;
+    let name = passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&user) || fields.get(2) == Some(&user))
+        .and_then(|fields| fields.first().map(|name| (*name).to_string()));
+    let Some(name) = name else {
+        return Ok(vec![]); /* a uid without an entry is a member of no group */
+    };
+    let groups =
+        std::fs::read_to_string(GROUP).map_err(|e| format!("Could not read {GROUP}: {e}"))?;
+    groups
+        .lines()
+        .map(|line| line.split(':').map(str::to_string).collect::<Vec<_>>())
+        .filter(|fields| {
+            fields
+                .get(3)
+                .is_some_and(|members| members.split(',').any(|member| member == name))
+        })
+        .map(|fields| id_field(&fields, 2, GROUP))
+        .collect()
+}
+
+// the gid of a group, given by name or id
+pub fn group(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    id_field(&lookup(GROUP, group)?, 2, GROUP)
+}
+
+impl RunAs {
+    // the credentials to run as, from a user and/or group. Without a group, the primary group
+    // of the user is used; without a user, the uid of the agent is kept.
+    pub fn resolve(
+        user_name: Option<&str>,
+        group_name: Option<&str>,
+    ) -> Result<Option<Self>, String> {
+        let (uid, primary) = match user_name {
+            Some(name) => {
+                let (uid, primary) = user(name)?;
+                (Some(uid), primary)
+            }
+            None => (None, None),
+        };
+        let gid = match (group_name, primary) {
+            (Some(name), _) => group(name)?,
+            (None, Some(gid)) => gid,
+            (None, None) => {
+                return match uid {
+                    Some(uid) => Err(format!("No group given to run as uid {uid}")),
+                    None => Ok(None),
+                };
+            }
+        };
+        Ok(Some(Self { uid, gid }))
+    }
+
+    // make a command run with these credentials. When the uid is changed from root, the
+    // supplementary groups of the agent are dropped too.
+    pub fn apply(self, cmd: &mut Command) {
+        if let Some(uid) = self.uid {
+            cmd.uid(uid);
+        }
+        cmd.gid(self.gid);
+    }
+
+    // drop the privileges of the agent to these credentials (the uid being given) and the
+    // supplementary groups given, for good: real, effective and saved ids are all changed, so
+    // that root can not be regained
+    #[allow(unsafe_code)]
+    pub fn drop_privileges(self, groups: &[u32]) -> Result<(), String> {
+        let Some(uid) = self.uid else {
+            return Err("No user to drop privileges to".to_string());
+        };
+        let last_error = || std::io::Error::last_os_error();
+        // SAFETY: setgroups reads groups.len() gids from a valid slice
+        if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } != 0 {
+            return Err(format!("Could not set groups {groups:?}: {}", last_error()));
+        }
+        // SAFETY: setresgid has no memory safety requirements
+        if unsafe { libc::setresgid(self.gid, self.gid, self.gid) } != 0 {
+            return Err(format!("Could not set gid {}: {}", self.gid, last_error()));
+        }
+        // SAFETY: setresuid has no memory safety requirements
+        if unsafe { libc::setresuid(uid, uid, uid) } != 0 {
+            return Err(format!("Could not set uid {uid}: {}", last_error()));
+        }
+        // SAFETY: setuid has no memory safety requirements
+        if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err("Privileges could be regained after dropping them".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Control of the FRR service, either through systemd or FRR's init script (which drives watchfrr)
+
+use std::fmt::Display;
+use std::process::Command;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Copy, Debug)]
+pub enum FrrAction {
+    Start,
+    Stop,
+    Restart,
+}
+impl Display for FrrAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrrAction::Start => write!(f, "start"),
+            FrrAction::Stop => write!(f, "stop"),
+            FrrAction::Restart => write!(f, "restart"),
+        }
+    }
+}
+impl FrrAction {
+    pub fn parse(action: &str) -> Option<Self> {
+        match action.trim() {
+            "start" => Some(FrrAction::Start),
+            "stop" => Some(FrrAction::Stop),
+            "restart" => Some(FrrAction::Restart),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FrrService<'a> {
+    Systemd(&'a str), /* the unit */
+    Init(&'a str),    /* the init script */
+}
+
+impl FrrService<'_> {
+    // start, stop or restart FRR
+    pub fn control(&self, action: FrrAction) -> Result<(), String> {
+        let mut cmd = match self {
+            FrrService::Systemd(unit) => {
+                info!("Requesting FRR {action} to systemd (unit {unit})...");
+                let mut cmd = Command::new("systemctl");
+                cmd.arg(action.to_string()).arg(unit);
+                cmd
+            }
+            FrrService::Init(frrinit) => {
+                info!("Requesting FRR {action} with {frrinit}...");
+                let mut cmd = Command::new(frrinit);
+                cmd.arg(action.to_string());
+                cmd
+            }
+        };
+        let output = cmd
+            .output()
+            .map_err(|e| format!("Failed to execute {cmd:?}: {e}"))?;
+        if !output.status.success() {
+            error!("FRR {action} failed!");
+            error!("stderr: {}", String::from_utf8_lossy(&output.stderr));
+            error!("stdout: {}", String::from_utf8_lossy(&output.stdout));
+            return Err(format!("FRR {action} failed ({})", output.status));
+        }
+        info!("FRR {action} succeeded");
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Client sessions and their liveness. A client may negotiate a keepalive interval and a number
+// of keepalives it may miss with HELLO. The session is closed if no request is received from the
+// client within interval x misses.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::json::Json;
+
+const MAX_KEEPALIVE_MISSES: u32 = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Keepalive {
+    pub(crate) interval: Duration,
+    pub(crate) misses: u32,
+}
+impl Keepalive {
+    // time after which a session is considered dead
+    pub(crate) fn deadline(&self) -> Duration {
+        self.interval * self.misses
+    }
+    // parse keepalive parameters like "keepalive=10 misses=3"
+    pub(crate) fn parse(params: &str) -> Result<Self, String> {
+        let mut interval = None;
+        let mut misses = None;
+        for param in params.split_whitespace() {
+            match param.split_once('=') {
+                Some(("keepalive", value)) => {
+                    interval = Some(value.parse().map_err(|e| format!("Bad keepalive: {e}"))?);
+                }
+                Some(("misses", value)) => {
+                    misses = Some(value.parse().map_err(|e| format!("Bad misses: {e}"))?);
+                }
+                _ => return Err(format!("Unknown hello parameter '{param}'")),
+            }
+        }
+        Ok(Keepalive {
+            interval: Duration::from_secs(interval.ok_or("Missing keepalive interval")?),
+            misses: misses.unwrap_or(3),
+        })
+    }
+    // the parameters the agent agrees to for what a client requested
+    pub(crate) fn negotiate(&self, max_interval: Duration) -> Self {
+        Keepalive {
+            interval: self.interval.clamp(Duration::from_secs(1), max_interval),
+            misses: self.misses.clamp(1, MAX_KEEPALIVE_MISSES),
+        }
+    }
+}
+impl Display for Keepalive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "keepalive={} misses={}",
+            self.interval.as_secs(),
+            self.misses
+        )
+    }
+}
+
+struct Session {
+    peer: String,
+    keepalive: Option<Keepalive>,
+    last_seen: Instant,
+}
+
+// the sessions of the clients connected to the agent
+#[derive(Default)]
+pub(crate) struct Sessions {
+    next_id: AtomicU64,
+    sessions: Mutex<BTreeMap<u64, Session>>,
+}
+
+impl Sessions {
+    pub(crate) fn register(&self, peer: String) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.insert(
+                id,
+                Session {
+                    peer,
+                    keepalive: None,
+                    last_seen: Instant::now(),
+                },
+            );
+        }
+        id
+    }
+    pub(crate) fn unregister(&self, id: u64) {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(&id);
+        }
+    }
+    fn update(&self, id: u64, update: impl FnOnce(&mut Session)) {
+        if let Ok(mut sessions) = self.sessions.lock()
+            && let Some(session) = sessions.get_mut(&id)
+        {
+            update(session);
+        }
+    }
+    // record that a request was received in a session
+    pub(crate) fn seen(&self, id: u64) {
+        self.update(id, |session| session.last_seen = Instant::now());
+    }
+    pub(crate) fn set_keepalive(&self, id: u64, keepalive: Keepalive) {
+        self.update(id, |session| session.keepalive = Some(keepalive));
+    }
+    pub(crate) fn keepalive(&self, id: u64) -> Option<Keepalive> {
+        self.sessions.lock().ok()?.get(&id)?.keepalive
+    }
+    pub(crate) fn to_json(&self) -> Json {
+        let Ok(sessions) = self.sessions.lock() else {
+            return Json::Null;
+        };
+        let sessions = sessions.iter().map(|(id, session)| {
+            let idle = session.last_seen.elapsed();
+            Json::object([
+                ("id", (*id).into()),
+                ("peer", session.peer.as_str().into()),
+                (
+                    "keepalive",
+                    session.keepalive.map(|k| k.interval.as_secs()).into(),
+                ),
+                (
+                    "misses",
+                    session.keepalive.map(|k| u64::from(k.misses)).into(),
+                ),
+                ("idle", idle.as_secs().into()),
+                (
+                    "alive",
+                    session.keepalive.is_none_or(|k| idle < k.deadline()).into(),
+                ),
+            ])
+        });
+        Json::Array(sessions.collect())
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// SHA-256, to checksum the configs applied (computed by ring, the crypto provider of the TLS
+// listener)
+
+use std::fmt::Write;
+
+use ring::digest::{Context, SHA256, digest};
+
+// the SHA-256 digest of some data
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut octets = [0u8; 32];
+    octets.copy_from_slice(digest(&SHA256, data).as_ref());
+    octets
+}
+
+// the SHA-256 digest of some data, in hex
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    hex(&sha256(data))
+}
+
+fn hex(octets: &[u8]) -> String {
+    octets
+        .iter()
+        .fold(String::with_capacity(2 * octets.len()), |mut hex, octet| {
+            let _ = write!(hex, "{octet:02x}");
+            hex
+        })
+}
+
+// the SHA-256 digest of data fed in pieces (e.g. the chunks of a config)
+pub(crate) struct Sha256(Context);
+
+impl Sha256 {
+    pub(crate) fn new() -> Self {
+        Self(Context::new(&SHA256))
+    }
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+    // the digest of the data fed so far, in hex
+    pub(crate) fn hex(&self) -> String {
+        hex(self.0.clone().finish().as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_answers() {
+        /* FIPS 180-4 examples */
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256_hex(&vec![b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn incremental() {
+        let data = vec![b'a'; 1000];
+        let mut sha256 = Sha256::new();
+        for piece in data.chunks(63) {
+            sha256.update(piece);
+        }
+        assert_eq!(sha256.hex(), sha256_hex(&data));
+        assert_eq!(Sha256::new().hex(), sha256_hex(b""));
+    }
+
+    #[test]
+    fn block_boundaries() {
+        /* the padding of 55 octets fits in one block, that of 56 and 64 does not */
+        assert_eq!(
+            sha256_hex(&[b'a'; 55]),
+            "9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318"
+        );
+        assert_eq!(
+            sha256_hex(&[b'a'; 56]),
+            "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a"
+        );
+        assert_eq!(
+            sha256_hex(&[b'a'; 64]),
+            "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Shipping of reload transcripts to remote log collectors. Every request that runs the reloader
+// produces a transcript (request metadata, output of each reloader run and result) that can be
+// pushed to Loki (push API) and/or to an OpenTelemetry collector (OTLP/HTTP logs, JSON encoding).
+// Transcripts are shipped in the background so that they never delay the response to a request.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::node;
+use crate::reload::Transcript;
+
+const SHIPPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+// an http endpoint
+#[derive(Clone, Debug)]
+pub(crate) struct HttpUrl {
+    authority: String, /* host:port */
+    path: String,
+}
+impl HttpUrl {
+    pub(crate) fn parse(url: &str) -> Result<Self, String> {
+        let rest = url.strip_prefix("http://").ok_or(format!(
+            "Unsupported url '{url}': only http:// is supported"
+        ))?;
+        let (authority, path) = rest.find('/').map_or((rest, "/"), |n| rest.split_at(n));
+        if authority.is_empty() {
+            return Err(format!("Missing host in url '{url}'"));
+        }
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{authority}:80")
+        };
+        Ok(HttpUrl {
+            authority,
+            path: path.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Collector {
+    Loki(HttpUrl),
+    Otlp(HttpUrl),
+}
+
+// the runs of the reloader in a transcript
+pub(crate) fn runs_json(transcript: &Transcript) -> Json {
+    let runs = transcript.iter().map(|run| {
+        Json::object([
+            ("stage", if run.test { "test" } else { "reload" }.into()),
+            ("success", run.success.into()),
+            ("timed_out", run.timed_out.into()),
+            ("attempt", u64::from(run.attempt).into()),
+            (
+                "duration_ms",
+                u64::try_from(run.duration.as_millis())
+                    .unwrap_or(u64::MAX)
+                    .into(),
+            ),
+            ("stdout", run.stdout.as_str().into()),
+            ("stderr", run.stderr.as_str().into()),
+        ])
+    });
+    Json::Array(runs.collect())
+}
+
+// a transcript to ship
+struct Record {
+    genid: GenId,
+    request: String,
+    peer: String,
+    result: String,
+    transcript: Transcript,
+    time: Duration, /* since the epoch */
+}
+
+impl Record {
+    fn success(&self) -> bool {
+        self.result == "Ok"
+    }
+    // the transcript as a log line
+    fn to_json(&self) -> Json {
+        Json::object([
+            ("genid", self.genid.into()),
+            ("request", self.request.as_str().into()),
+            ("peer", self.peer.as_str().into()),
+            ("result", self.result.as_str().into()),
+            ("success", self.success().into()),
+            ("runs", runs_json(&self.transcript)),
+            ("node", node::identity().to_json()),
+        ])
+    }
+    fn to_loki(&self) -> Json {
+        let mut labels = vec![
+            ("job", "frr-agent".into()),
+            (
+                "result",
+                if self.success() { "ok" } else { "failed" }.into(),
+            ),
+        ];
+        labels.extend(
+            node::identity()
+                .labels()
+                .into_iter()
+                .map(|(name, value)| (name, value.into())),
+        );
+        let stream = Json::object(labels);
+        let value = Json::Array(vec![
+            self.time.as_nanos().to_string().into(),
+            self.to_json().to_string().into(),
+        ]);
+        Json::object([(
+            "streams",
+            Json::Array(vec![Json::object([
+                ("stream", stream),
+                ("values", Json::Array(vec![value])),
+            ])]),
+        )])
+    }
+    fn to_otlp(&self) -> Json {
+        let attribute =
+            |key: &'static str, value: Json| Json::object([("key", key.into()), ("value", value)]);
+        let string = |value: &str| Json::object([("stringValue", value.into())]);
+        let (severity, severity_text) = if self.success() {
+            (9, "INFO")
+        } else {
+            (17, "ERROR")
+        };
+        let record = Json::object([
+            ("timeUnixNano", self.time.as_nanos().to_string().into()),
+            ("severityNumber", i64::from(severity).into()),
+            ("severityText", severity_text.into()),
+            ("body", string(&self.to_json().to_string())),
+            (
+                "attributes",
+                Json::Array(vec![
+                    attribute(
+                        "genid",
+                        Json::object([("intValue", self.genid.to_string().into())]),
+                    ),
+                    attribute("request", string(&self.request)),
+                    attribute("peer", string(&self.peer)),
+                    attribute("result", string(&self.result)),
+                ]),
+            ),
+        ]);
+        let resource = Json::object([(
+            "attributes",
+            Json::Array(
+                [("service.name", "frr-agent")]
+                    .into_iter()
+                    .chain(node::identity().labels())
+                    .map(|(key, value)| attribute(key, string(value)))
+                    .collect(),
+            ),
+        )]);
+        let scope_logs = Json::object([
+            ("scope", Json::object([("name", "frr-agent".into())])),
+            ("logRecords", Json::Array(vec![record])),
+        ]);
+        Json::object([(
+            "resourceLogs",
+            Json::Array(vec![Json::object([
+                ("resource", resource),
+                ("scopeLogs", Json::Array(vec![scope_logs])),
+            ])]),
+        )])
+    }
+}
+
+// POST a JSON body to an http endpoint
+pub(crate) fn post(url: &HttpUrl, body: &str) -> Result<(), String> {
+    let addr = url
+        .authority
+        .to_socket_addrs()
+        .map_err(|e| format!("Could not resolve {}: {e}", url.authority))?
+        .next()
+        .ok_or(format!("Could not resolve {}", url.authority))?;
+    let mut stream = TcpStream::connect_timeout(&addr, SHIPPING_TIMEOUT)
+        .map_err(|e| format!("Could not connect to {}: {e}", url.authority))?;
+    stream
+        .set_read_timeout(Some(SHIPPING_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(SHIPPING_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        url.path,
+        url.authority,
+        body.len()
+    )
+    .map_err(|e| format!("Could not send to {}: {e}", url.authority))?;
+
+    /* only the status line matters */
+    let mut status = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status)
+        .map_err(|e| format!("No response from {}: {e}", url.authority))?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!(
+            "{} responded with '{}'",
+            url.authority,
+            status.trim_end()
+        )),
+    }
+}
+
+// ships reload transcripts to the collectors configured
+#[derive(Default)]
+pub(crate) struct TranscriptShipper {
+    collectors: Vec<Collector>,
+}
+
+impl TranscriptShipper {
+    pub(crate) fn new(loki_url: Option<&str>, otlp_url: Option<&str>) -> Result<Self, String> {
+        let mut collectors = vec![];
+        if let Some(url) = loki_url {
+            collectors.push(Collector::Loki(HttpUrl::parse(url)?));
+        }
+        if let Some(url) = otlp_url {
+            collectors.push(Collector::Otlp(HttpUrl::parse(url)?));
+        }
+        Ok(Self { collectors })
+    }
+
+    // ship the transcript of a request in the background
+    pub(crate) fn ship(
+        &self,
+        genid: GenId,
+        request: String,
+        peer: String,
+        result: &str,
+        transcript: Transcript,
+    ) {
+        if self.collectors.is_empty() {
+            return;
+        }
+        let record = Record {
+            genid,
+            request,
+            peer,
+            result: result.to_string(),
+            transcript,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        };
+        let collectors = self.collectors.clone();
+        thread::spawn(move || {
+            for collector in &collectors {
+                let (url, body) = match collector {
+                    Collector::Loki(url) => (url, record.to_loki()),
+                    Collector::Otlp(url) => (url, record.to_otlp()),
+                };
+                let endpoint = format!("http://{}{}", url.authority, url.path);
+                match post(url, &body.to_string()) {
+                    Ok(()) => debug!(
+                        "Shipped transcript of generation {} to {endpoint}",
+                        record.genid
+                    ),
+                    Err(e) => warn!(
+                        "Failed to ship transcript of generation {}: {e}",
+                        record.genid
+                    ),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Service level objective on the duration of reloads, so that systematic slowdowns (growing
+// configs, slow disks) are caught before they become outage-length. The reloader runs of a request
+// taking longer than the SLO in total are a breach: it is logged with the breakdown by stage,
+// counted and fires an alert.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::GenId;
+use crate::json::Json;
+use crate::reload::Transcript;
+
+#[derive(Default)]
+struct Breaches {
+    count: u64,
+    last: Option<Json>,
+}
+
+pub(crate) struct ReloadSlo {
+    threshold: Option<Duration>,
+    breaches: Mutex<Breaches>,
+}
+
+fn millis(duration: Duration) -> Json {
+    u64::try_from(duration.as_millis())
+        .unwrap_or(u64::MAX)
+        .into()
+}
+
+impl ReloadSlo {
+    // an SLO breached by reloads longer than threshold (never if None)
+    pub(crate) fn new(threshold: Option<Duration>) -> Self {
+        Self {
+            threshold,
+            breaches: Mutex::new(Breaches::default()),
+        }
+    }
+
+    // account for the reloader runs of a request. Returns the description of the breach, if the
+    // SLO was breached.
+    pub(crate) fn check(&self, genid: GenId, transcript: &Transcript) -> Option<String> {
+        let threshold = self.threshold?;
+        let total: Duration = transcript.iter().map(|run| run.duration).sum();
+        if total <= threshold {
+            return None;
+        }
+        let stage = |test: bool| if test { "test" } else { "reload" };
+        let breakdown = transcript
+            .iter()
+            .map(|run| format!("{} {:.1?}", stage(run.test), run.duration))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let message = format!(
+            "Reload of generation {genid} took {total:.1?}, above the SLO of {threshold:?} ({breakdown})"
+        );
+        warn!("{message}");
+        if let Ok(mut breaches) = self.breaches.lock() {
+            let stages = transcript.iter().map(|run| {
+                Json::object([
+                    ("stage", stage(run.test).into()),
+                    ("duration_ms", millis(run.duration)),
+                ])
+            });
+            breaches.count += 1;
+            breaches.last = Some(Json::object([
+                ("genid", genid.into()),
+                ("duration_ms", millis(total)),
+                ("stages", Json::Array(stages.collect())),
+            ]));
+        }
+        Some(message)
+    }
+
+    pub(crate) fn to_json(&self) -> Json {
+        let Some(threshold) = self.threshold else {
+            return Json::Null;
+        };
+        let Ok(breaches) = self.breaches.lock() else {
+            return Json::Null;
+        };
+        Json::object([
+            ("threshold_ms", millis(threshold)),
+            ("breaches", breaches.count.into()),
+            ("last_breach", breaches.last.clone().into()),
+        ])
+    }
+}
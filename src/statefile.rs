@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Periodic state file, so that node-local health checkers and other daemons can consume the state
+// of the agent without speaking the socket protocol. A small JSON object (last generation applied,
+// last result, timestamps and health flags) is written to a file periodically, atomically
+// replacing it so that readers never see a partial write.
+
+use std::fs;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::agent::Agent;
+
+// replace the state file with a new one
+fn write_state(path: &str, state: &str) -> Result<(), String> {
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, state)
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Could not write state file {path}: {e}")
+        })
+}
+
+// write the state of the agent to a file forever
+pub(crate) fn run(agent: &Agent, path: &str, interval: Duration) {
+    info!("Writing agent state to {path} every {interval:?}");
+    loop {
+        if let Err(e) = write_state(path, &agent.state().to_string()) {
+            warn!("{e}");
+        }
+        sleep(interval);
+    }
+}
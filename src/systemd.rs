@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// systemd integration (--systemd), for agents run as a Type=notify service: the agent tells systemd
+// that it is ready once it serves requests (READY=1), not merely once its socket is bound, and that
+// it is stopping on termination (STOPPING=1). If the watchdog is enabled (WatchdogSec=), it sends heartbeats (WATCHDOG=1) every
+// half watchdog period as long as it is not wedged, i.e. no apply has been running for longer
+// than the watchdog period (e.g. a reloader hanging), so that systemd restarts a wedged agent.
+
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::agent::Agent;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// enable notifications to systemd, if the agent runs as a Type=notify service
+pub(crate) fn enable() {
+    if env::var_os("NOTIFY_SOCKET").is_none() {
+        warn!("systemd integration is enabled but NOTIFY_SOCKET is not set: not notifying");
+        return;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+// send a notification (e.g. "READY=1") to systemd, if enabled
+pub(crate) fn notify(state: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    /* addresses starting with @ are in the abstract namespace */
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let sent = addr.and_then(|addr| {
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+        Ok(())
+    });
+    match sent {
+        Ok(()) => debug!("Notified systemd: {state}"),
+        Err(e) => warn!("Could not notify systemd of {state} at {path}: {e}"),
+    }
+}
+
+// the watchdog period set for the agent, if any
+pub(crate) fn watchdog() -> Option<Duration> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse() != Ok(std::process::id())
+    {
+        return None;
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+// send heartbeats to the systemd watchdog forever, unless the agent is wedged
+pub(crate) fn run_watchdog(agent: &Agent, period: Duration) {
+    info!(
+        "Sending heartbeats to the systemd watchdog every {:?}",
+        period / 2
+    );
+    loop {
+        match agent.longest_apply() {
+            Some(running) if running > period => {
+                warn!("An apply has been running for {running:.1?}: skipping watchdog heartbeat");
+            }
+            _ => notify("WATCHDOG=1"),
+        }
+        sleep(period / 2);
+    }
+}
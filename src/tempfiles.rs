@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Cleanup of the temp files the reloader leaves in rundir. frr-reload writes the commands it
+// applies to rundir/reload-<random>.txt and removes the file once done, so the file is left
+// behind if frr-reload is killed or crashes, slowly filling the (usually small) tmpfs rundir is
+// mounted on. The temp files that appear in rundir during a failed run are removed after it, and
+// at startup, those left by runs interrupted by the agent stopping and those that are stale.
+
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+// temp files older than this are left behind whoever created them
+const STALE_AGE: Duration = Duration::from_hours(1);
+
+fn is_temp_file(name: &OsString) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with("reload-") && name.ends_with(".txt")
+}
+
+// the temp files in rundir
+pub(crate) fn snapshot(rundir: &str) -> BTreeSet<OsString> {
+    let Ok(entries) = std::fs::read_dir(rundir) else {
+        return BTreeSet::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.file_name())
+        .filter(is_temp_file)
+        .collect()
+}
+
+fn remove(rundir: &str, name: &OsString) {
+    let path = Path::new(rundir).join(name);
+    match std::fs::remove_file(&path) {
+        Ok(()) => info!("Removed reloader temp file {}", path.display()),
+        Err(e) => warn!(
+            "Could not remove reloader temp file {}: {e}",
+            path.display()
+        ),
+    }
+}
+
+// remove the temp files that appeared in rundir since a snapshot
+pub(crate) fn clean_new(rundir: &str, before: &BTreeSet<OsString>) {
+    for name in snapshot(rundir).difference(before) {
+        remove(rundir, name);
+    }
+}
+
+// remove the temp files in rundir that are stale or were modified since a run interrupted at
+// the time given (seconds since the epoch), if any
+pub(crate) fn clean_stale(rundir: &str, interrupted: Option<u64>) {
+    let interrupted = interrupted.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+    for name in snapshot(rundir) {
+        let Ok(modified) = std::fs::metadata(Path::new(rundir).join(&name))
+            .and_then(|metadata| metadata.modified())
+        else {
+            continue;
+        };
+        let stale = modified.elapsed().is_ok_and(|age| age >= STALE_AGE);
+        if stale || interrupted.is_some_and(|interrupted| modified >= interrupted) {
+            remove(rundir, &name);
+        }
+    }
+}
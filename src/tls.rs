@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// TCP listener with mandatory mutual TLS (--tcp-addr), for controllers run on another host than
+// FRR. Connections speak the same protocol as those over the unix socket. Clients must present a
+// certificate issued by the CA given (--tls-ca): connections whose certificate is missing or does
+// not verify are closed during the handshake. Once the handshake completes, the TLS session is
+// terminated by a relay thread exchanging the plaintext with one end of a unix socket pair, the
+// other end being served as any unix stream.
+
+use std::fmt::Debug;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection};
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::sha256::sha256_hex;
+
+// time a client may take to complete the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// size of the buffers of plaintext relayed
+const RELAY_BUFFER: usize = 16 * 1024;
+
+// the address of a TLS peer, and the fingerprint of its certificate
+#[derive(Clone)]
+pub(crate) struct TlsAddr {
+    pub(crate) addr: SocketAddr,
+    pub(crate) fingerprint: String, /* SHA-256 of the certificate, in hex */
+}
+impl Debug for TlsAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tls:{} (cert sha256:{})", self.addr, self.fingerprint)
+    }
+}
+
+fn certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| format!("Could not read certificates from {path}: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {path}"));
+    }
+    Ok(certs)
+}
+
+// the TLS settings of the agent: its certificate and key, and the CA client certificates must be
+// issued by
+fn server_config(cert: &str, key: &str, ca: &str) -> Result<ServerConfig, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut roots = RootCertStore::empty();
+    for ca_cert in certificates(ca)? {
+        roots
+            .add(ca_cert)
+            .map_err(|e| format!("Bad CA certificate in {ca}: {e}"))?;
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| format!("Bad client certificate verifier: {e}"))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("Could not read private key from {key}: {e}"))?;
+    ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Bad TLS protocol versions: {e}"))?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certificates(cert)?, key)
+        .map_err(|e| format!("Bad certificate or key: {e}"))
+}
+
+pub(crate) struct TlsListener {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+}
+impl TlsListener {
+    // listen on a TCP address, with the certificate and key of the agent and the CA of clients
+    pub(crate) fn bind(addr: &str, cert: &str, key: &str, ca: &str) -> Result<Self, String> {
+        let config = server_config(cert, key, ca)?;
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("Could not bind {addr}: {e}"))?;
+        Ok(Self {
+            listener,
+            config: Arc::new(config),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    // accept a TCP connection, whose handshake is yet to be completed (see handshake)
+    pub(crate) fn accept(&self) -> Result<(TcpStream, SocketAddr), String> {
+        self.listener
+            .accept()
+            .map_err(|e| format!("Failed to accept TCP connection: {e}"))
+    }
+
+    // complete the handshake of a connection accepted, verifying the certificate of the client,
+    // and relay the session: the connection is served as the unix stream returned
+    pub(crate) fn handshake(
+        &self,
+        tcp: TcpStream,
+        addr: SocketAddr,
+    ) -> Result<(UnixStream, TlsAddr), String> {
+        let mut tcp = tcp;
+        let mut conn = ServerConnection::new(self.config.clone())
+            .map_err(|e| format!("Could not create TLS session: {e}"))?;
+        tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .and_then(|()| tcp.set_write_timeout(Some(HANDSHAKE_TIMEOUT)))
+            .map_err(|e| format!("Failed to set handshake timeout: {e}"))?;
+        while conn.is_handshaking() {
+            if let Err(e) = conn.complete_io(&mut tcp) {
+                let _ = tcp.shutdown(Shutdown::Both);
+                return Err(format!("TLS handshake with {addr} failed: {e}"));
+            }
+        }
+        /* the verifier requires a certificate: a session without one is not established */
+        let fingerprint = conn
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| sha256_hex(cert))
+            .ok_or(format!("No client certificate from {addr}"))?;
+        let (local, relayed) =
+            UnixStream::pair().map_err(|e| format!("Could not create socket pair: {e}"))?;
+        thread::spawn(move || {
+            if let Err(e) = relay(conn, &mut tcp, relayed) {
+                debug!("TLS session with {addr} ended: {e}");
+            }
+            let _ = tcp.shutdown(Shutdown::Both);
+        });
+        Ok((local, TlsAddr { addr, fingerprint }))
+    }
+}
+
+// wait for some of the events given on the TCP socket and on the unix stream
+#[allow(unsafe_code)]
+fn poll(tcp: &TcpStream, tcp_events: i16, local: &UnixStream, local_events: i16) -> (i16, i16) {
+    let mut fds = [
+        libc::pollfd {
+            fd: tcp.as_raw_fd(),
+            events: tcp_events,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: local.as_raw_fd(),
+            events: local_events,
+            revents: 0,
+        },
+    ];
+    // SAFETY: fds is a valid array of 2 pollfd
+    let ret = unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) };
+    if ret < 0 {
+        return (0, 0);
+    }
+    (fds[0].revents, fds[1].revents)
+}
+
+fn would_block(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted)
+}
+
+// relay a TLS session: the plaintext received is written to the unix stream, and what is read from
+// it is sent, until the agent closes its end
+fn relay(conn: ServerConnection, tcp: &mut TcpStream, local: UnixStream) -> Result<(), String> {
+    let mut conn = conn;
+    let mut local = local;
+    tcp.set_nonblocking(true)
+        .and_then(|()| local.set_nonblocking(true))
+        .map_err(|e| format!("Failed to set non-blocking: {e}"))?;
+    let mut inbound = Vec::with_capacity(RELAY_BUFFER); /* plaintext not written to local yet */
+    let mut buf = vec![0u8; RELAY_BUFFER];
+    let mut closed = false; /* the client closed the session */
+    loop {
+        while conn.wants_write() {
+            match conn.write_tls(tcp) {
+                Ok(_) => {}
+                Err(e) if would_block(&e) => break,
+                Err(e) => return Err(format!("Could not send: {e}")),
+            }
+        }
+        if !inbound.is_empty() {
+            match local.write(&inbound) {
+                Ok(written) => {
+                    inbound.drain(..written);
+                }
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(format!("Could not relay to the agent: {e}")),
+            }
+        }
+
+        /* received data is only read once the plaintext received before is relayed, and data to
+         * send only once what was sent before is flushed */
+        let mut tcp_events = 0;
+        if conn.wants_write() {
+            tcp_events |= libc::POLLOUT;
+        }
+        if inbound.is_empty() && !closed {
+            tcp_events |= libc::POLLIN;
+        }
+        let mut local_events = 0;
+        if !inbound.is_empty() {
+            local_events |= libc::POLLOUT;
+        }
+        if !conn.wants_write() {
+            local_events |= libc::POLLIN;
+        }
+        let (tcp_ready, local_ready) = poll(tcp, tcp_events, &local, local_events);
+
+        if tcp_ready & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match conn.read_tls(tcp) {
+                Ok(0) => closed = true,
+                Ok(_) => {}
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(format!("Could not receive: {e}")),
+            }
+            if let Err(e) = conn.process_new_packets() {
+                let _ = conn.write_tls(tcp);
+                return Err(format!("Bad TLS data: {e}"));
+            }
+            loop {
+                match conn.reader().read(&mut buf) {
+                    Ok(0) => {
+                        closed = true;
+                        break;
+                    }
+                    Ok(read) => inbound.extend_from_slice(&buf[..read]),
+                    Err(e) if would_block(&e) => break,
+                    Err(e) => return Err(format!("Could not receive: {e}")),
+                }
+            }
+            if closed && inbound.is_empty() {
+                let _ = local.shutdown(Shutdown::Write);
+            }
+        }
+        if local_ready & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0 {
+            match local.read(&mut buf) {
+                Ok(0) => {
+                    /* the agent closed the connection */
+                    conn.send_close_notify();
+                    let _ = tcp.set_nonblocking(false);
+                    while conn.wants_write() && conn.write_tls(tcp).is_ok() {}
+                    return Ok(());
+                }
+                Ok(read) => conn
+                    .writer()
+                    .write_all(&buf[..read])
+                    .map_err(|e| format!("Could not send: {e}"))?,
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(format!("Could not relay from the agent: {e}")),
+            }
+        }
+        if closed && inbound.is_empty() && tcp_ready & libc::POLLHUP != 0 && !conn.wants_write() {
+            /* nothing more can be exchanged with the client: the agent sees its end closed */
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,287 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Post-apply verification (--verify): checks on the JSON output of vtysh show commands that must
+// hold after a config is applied, e.g. that BGP sessions came up. Checks are given like
+//
+//      show bgp summary json => count(/ipv4Unicast/peers/*/state == Established) >= 2
+//      show bgp summary json => /ipv4Unicast/peerCount >= 2
+//      show ip route json => count(/10.0.0.0~124) == 1
+//
+// where paths are JSON pointers (with ~1 for / and ~0 for ~) in which * matches any member or
+// item. Without count, the condition must hold for every value at the path (and there must be
+// one); with count, the number of values at the path (that meet the condition, if any) is
+// compared. Numbers are compared as such, anything else as strings. Checks are polled until they
+// all hold or the verification times out (--verify-timeout).
+
+use std::fmt::Display;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::json::Json;
+use crate::vtysh::vtysh;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+impl Op {
+    fn parse(op: &str) -> Option<Self> {
+        match op {
+            "==" => Some(Op::Eq),
+            "!=" => Some(Op::Ne),
+            ">=" => Some(Op::Ge),
+            "<=" => Some(Op::Le),
+            ">" => Some(Op::Gt),
+            "<" => Some(Op::Lt),
+            _ => None,
+        }
+    }
+    fn holds<T: PartialOrd>(self, left: &T, right: &T) -> bool {
+        match self {
+            Op::Eq => left == right,
+            Op::Ne => left != right,
+            Op::Ge => left >= right,
+            Op::Le => left <= right,
+            Op::Gt => left > right,
+            Op::Lt => left < right,
+        }
+    }
+}
+
+// a condition on the values at a path
+#[derive(Clone, Debug)]
+struct Condition {
+    path: Vec<String>,
+    test: Option<(Op, String)>,
+}
+impl Condition {
+    fn parse(condition: &str) -> Result<Self, String> {
+        let mut words = condition.split_whitespace();
+        let path = words
+            .next()
+            .ok_or(format!("Missing path in '{condition}'"))?;
+        let path = pointer(path)?;
+        let test = match words.next() {
+            None => None,
+            Some(op) => {
+                let op = Op::parse(op).ok_or(format!("Bad operator '{op}' in '{condition}'"))?;
+                let value = words.collect::<Vec<_>>().join(" ");
+                if value.is_empty() {
+                    return Err(format!("Missing value in '{condition}'"));
+                }
+                Some((op, value))
+            }
+        };
+        Ok(Self { path, test })
+    }
+    // whether a value meets the condition
+    fn met_by(&self, value: &Json) -> bool {
+        let Some((op, expected)) = &self.test else {
+            return true;
+        };
+        if let (Json::Number(value), Ok(expected)) = (value, expected.parse::<f64>()) {
+            return op.holds(value, &expected);
+        }
+        let value = match value {
+            Json::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        match op {
+            Op::Eq | Op::Ne => op.holds(&value, expected),
+            _ => false,
+        }
+    }
+}
+
+// parse a JSON pointer
+fn pointer(path: &str) -> Result<Vec<String>, String> {
+    let Some(path) = path.strip_prefix('/') else {
+        return Err(format!(
+            "Path '{path}' is not a JSON pointer (e.g. /peers/*/state)"
+        ));
+    };
+    Ok(path
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+// the values at a path, * matching any member or item
+fn values<'a>(json: &'a Json, path: &[String]) -> Vec<&'a Json> {
+    let Some((token, rest)) = path.split_first() else {
+        return vec![json];
+    };
+    match (json, token.as_str()) {
+        (Json::Object(members), "*") => members.values().flat_map(|v| values(v, rest)).collect(),
+        (Json::Array(items), "*") => items.iter().flat_map(|v| values(v, rest)).collect(),
+        (Json::Object(members), key) => members
+            .get(key)
+            .map(|v| values(v, rest))
+            .unwrap_or_default(),
+        (Json::Array(items), index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get(index))
+            .map(|v| values(v, rest))
+            .unwrap_or_default(),
+        _ => vec![],
+    }
+}
+
+// a check on the output of a show command
+#[derive(Clone, Debug)]
+struct Check {
+    spec: String,
+    command: String,
+    condition: Condition,
+    count: Option<(Op, f64)>, /* the number of values meeting the condition is compared */
+}
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+impl Check {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (command, condition) = spec.split_once("=>").ok_or(format!(
+            "Bad check '{spec}': expected '<show command> => <condition>'"
+        ))?;
+        let command = command.trim();
+        if !command.starts_with("show ") {
+            return Err(format!(
+                "Bad check '{spec}': '{command}' is not a show command"
+            ));
+        }
+        let condition = condition.trim();
+        let (condition, count) = match condition.strip_prefix("count(") {
+            Some(counted) => {
+                let (inner, comparison) = counted
+                    .rsplit_once(')')
+                    .ok_or(format!("Bad check '{spec}': missing ')'"))?;
+                let mut words = comparison.split_whitespace();
+                let (Some(op), Some(n), None) = (words.next(), words.next(), words.next()) else {
+                    return Err(format!(
+                        "Bad check '{spec}': expected count(...) <op> <number>"
+                    ));
+                };
+                let op = Op::parse(op).ok_or(format!("Bad check '{spec}': bad operator '{op}'"))?;
+                let n = n
+                    .parse()
+                    .map_err(|e| format!("Bad check '{spec}': bad count '{n}': {e}"))?;
+                (inner, Some((op, n)))
+            }
+            None => (condition, None),
+        };
+        Ok(Self {
+            spec: spec.trim().to_string(),
+            command: command.to_string(),
+            condition: Condition::parse(condition)
+                .map_err(|e| format!("Bad check '{spec}': {e}"))?,
+            count,
+        })
+    }
+
+    // evaluate the check on the output of its command
+    fn evaluate(&self, output: &str) -> Result<(), String> {
+        let json =
+            Json::parse(output).map_err(|e| format!("bad JSON from '{}': {e}", self.command))?;
+        let values = values(&json, &self.condition.path);
+        let meeting = values.iter().filter(|v| self.condition.met_by(v)).count();
+        if let Some((op, n)) = self.count {
+            #[allow(clippy::cast_precision_loss)]
+            let meeting = meeting as f64;
+            if op.holds(&meeting, &n) {
+                return Ok(());
+            }
+            return Err(format!("{meeting} value(s) counted"));
+        }
+        if values.is_empty() {
+            return Err("no value at path".to_string());
+        }
+        if meeting == values.len() {
+            return Ok(());
+        }
+        let found: Vec<String> = values.iter().map(ToString::to_string).collect();
+        Err(format!("found {}", found.join(", ")))
+    }
+
+    fn run(&self, args: &Args) -> Result<(), String> {
+        let output = vtysh(args, &self.command)?;
+        self.evaluate(&output)
+    }
+}
+
+// outcome of the verification of a generation
+#[derive(Clone, Debug)]
+pub(crate) struct Verification {
+    pub(crate) passed: bool,
+    pub(crate) failures: Vec<String>, /* of the checks failing when the verification timed out */
+    pub(crate) duration: Duration,
+}
+impl Verification {
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object([
+            ("passed", self.passed.into()),
+            (
+                "failures",
+                Json::Array(self.failures.iter().map(|f| f.as_str().into()).collect()),
+            ),
+            (
+                "duration_ms",
+                u64::try_from(self.duration.as_millis())
+                    .unwrap_or(u64::MAX)
+                    .into(),
+            ),
+        ])
+    }
+}
+
+pub(crate) struct Verifier {
+    checks: Vec<Check>,
+    timeout: Duration,
+}
+impl Verifier {
+    pub(crate) fn new(specs: &[String], timeout: Duration) -> Result<Option<Self>, String> {
+        if specs.is_empty() {
+            return Ok(None);
+        }
+        let checks = specs
+            .iter()
+            .map(|spec| Check::parse(spec))
+            .collect::<Result<_, _>>()?;
+        Ok(Some(Self { checks, timeout }))
+    }
+
+    // poll the checks until they all hold or the verification times out
+    pub(crate) fn verify(&self, args: &Args) -> Verification {
+        let started = Instant::now();
+        loop {
+            let failures: Vec<String> = self
+                .checks
+                .iter()
+                .filter_map(|check| check.run(args).err().map(|e| format!("{check}: {e}")))
+                .collect();
+            if failures.is_empty() || started.elapsed() >= self.timeout {
+                return Verification {
+                    passed: failures.is_empty(),
+                    failures,
+                    duration: started.elapsed(),
+                };
+            }
+            debug!("{} check(s) do not hold yet", failures.len());
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// vsock listener (--vsock-port), for agents run in a VM (e.g. a microVM running FRR) to be reached
+// from the host without a shared filesystem. Connections speak the same protocol as those over the
+// unix socket: accepted vsock sockets are served as unix streams, since the operations used on
+// them (read, write, shutdown and timeouts) are those of any stream socket.
+
+use std::fmt::Debug;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+const BACKLOG: i32 = 128;
+
+// the address of a vsock peer
+#[derive(Clone, Copy)]
+pub(crate) struct VsockAddr {
+    pub(crate) cid: u32,
+    pub(crate) port: u32,
+}
+impl Debug for VsockAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "vsock:{}:{}", self.cid, self.port)
+    }
+}
+
+// whether a vsock peer may connect, given the CIDs allowed. Any peer may connect if none are set.
+// vsock peers have no credentials: --allowed-uid/--allowed-gid do not apply to them.
+pub(crate) fn allowed(addr: VsockAddr, cids: &[u32]) -> Result<(), String> {
+    if cids.is_empty() || cids.contains(&addr.cid) {
+        Ok(())
+    } else {
+        Err(format!("vsock CID {} is not allowed", addr.cid))
+    }
+}
+
+fn sockaddr(cid: u32, port: u32) -> libc::sockaddr_vm {
+    libc::sockaddr_vm {
+        svm_family: libc::sa_family_t::try_from(libc::AF_VSOCK).unwrap_or_default(),
+        svm_reserved1: 0,
+        svm_port: port,
+        svm_cid: cid,
+        svm_zero: [0; 4],
+    }
+}
+
+fn socklen() -> libc::socklen_t {
+    libc::socklen_t::try_from(size_of::<libc::sockaddr_vm>()).unwrap_or_default()
+}
+
+pub(crate) struct VsockListener {
+    fd: OwnedFd,
+    port: u32,
+}
+impl VsockListener {
+    // listen on a port for connections from any cid
+    #[allow(unsafe_code)]
+    pub(crate) fn bind(port: u32) -> Result<Self, String> {
+        // SAFETY: socket() has no memory safety requirements
+        let fd = unsafe { libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(format!(
+                "Could not create vsock socket: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: fd was just created and is owned by nothing else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let addr = sockaddr(libc::VMADDR_CID_ANY, port);
+        // SAFETY: addr is a valid sockaddr_vm and socklen() is its size
+        let ret = unsafe { libc::bind(fd.as_raw_fd(), (&raw const addr).cast(), socklen()) };
+        if ret != 0 {
+            return Err(format!(
+                "Could not bind vsock port {port}: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: listen() has no memory safety requirements
+        if unsafe { libc::listen(fd.as_raw_fd(), BACKLOG) } != 0 {
+            return Err(format!(
+                "Could not listen on vsock port {port}: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(Self { fd, port })
+    }
+
+    pub(crate) fn port(&self) -> u32 {
+        self.port
+    }
+
+    // accept a connection, served as a unix stream
+    #[allow(unsafe_code)]
+    pub(crate) fn accept(&self) -> Result<(UnixStream, VsockAddr), String> {
+        let mut addr = sockaddr(0, 0);
+        let mut len = socklen();
+        // SAFETY: addr and len are valid for writes and len is the size of addr
+        let fd = unsafe {
+            libc::accept4(
+                self.fd.as_raw_fd(),
+                (&raw mut addr).cast(),
+                &raw mut len,
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(format!(
+                "Failed to accept vsock connection: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: fd was just accepted and is owned by nothing else
+        let stream = unsafe { UnixStream::from_raw_fd(fd) };
+        Ok((
+            stream,
+            VsockAddr {
+                cid: addr.svm_cid,
+                port: addr.svm_port,
+            },
+        ))
+    }
+}
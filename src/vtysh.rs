@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Execution of vtysh commands
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::Args;
+use crate::json::Json;
+use crate::netns;
+use crate::rawtext;
+
+// run a vtysh command, returning its output
+pub fn vtysh(args: &Args, command: &str) -> Result<String, String> {
+    vtysh_commands(args, &[command])
+}
+
+// run config commands in vtysh (in configure mode), returning the output
+pub fn vtysh_config(args: &Args, commands: &[&str]) -> Result<String, String> {
+    let mut all = vec!["configure terminal"];
+    all.extend_from_slice(commands);
+    vtysh_commands(args, &all)
+}
+
+// run a sequence of vtysh commands, returning their output
+fn vtysh_commands(args: &Args, commands: &[&str]) -> Result<String, String> {
+    Vtysh::of(args).run(commands)
+}
+
+// get the running config of FRR, without the header vtysh prints before it
+pub fn running_config(args: &Args) -> Result<String, String> {
+    Vtysh::of(args).running_config()
+}
+
+// vtysh, as found in bindir, talking to the daemons through the sockets in vtysock (if set)
+#[derive(Clone, Copy, Debug)]
+pub struct Vtysh<'a> {
+    pub bindir: &'a str,
+    pub vtysock: Option<&'a str>,
+    pub pathspace: Option<&'a str>, /* of the FRR instance (-N), if not the default one */
+    pub netns: Option<&'a str>,     /* network namespace vtysh is run in */
+}
+
+impl<'a> Vtysh<'a> {
+    pub fn of(args: &'a Args) -> Self {
+        Self {
+            bindir: args.binddir(),
+            vtysock: args.vtysock.as_deref(),
+            pathspace: args.pathspace.as_deref(),
+            netns: args.netns.as_deref(),
+        }
+    }
+
+    fn command(&self) -> Result<(Command, PathBuf), String> {
+        let vtysh = Path::new(self.bindir).join("vtysh");
+        let mut cmd = Command::new(&vtysh);
+        if let Some(netns) = self.netns {
+            netns::enter(&mut cmd, netns, None)?;
+        }
+        if let Some(pathspace) = self.pathspace {
+            cmd.arg("-N").arg(pathspace);
+        }
+        if let Some(vtysock) = self.vtysock {
+            cmd.arg("--vty_socket").arg(vtysock);
+        }
+        Ok((cmd, vtysh))
+    }
+
+    // run a sequence of vtysh commands, returning their output. Commands (and their output) are
+    // raw text: the octets of a config that are not valid UTF-8 are passed on verbatim.
+    pub fn run(&self, commands: &[&str]) -> Result<String, String> {
+        let (mut cmd, vtysh) = self.command()?;
+        for command in commands {
+            cmd.arg("-c")
+                .arg(OsStr::from_bytes(&rawtext::encode(command)));
+        }
+        let command = commands.join("' -c '");
+
+        debug!("Executing: {} -c '{command}'", vtysh.display());
+        let output = cmd
+            .output()
+            .map_err(|e| format!("Failed to execute {}: {e}", vtysh.display()))?;
+        if !output.status.success() {
+            return Err(format!(
+                "vtysh command '{command}' failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(rawtext::decode(output.stdout))
+    }
+
+    // check a config file for validity without applying it (vtysh --dryrun)
+    pub fn check(&self, conf_file: &str) -> Result<String, String> {
+        let (mut cmd, vtysh) = self.command()?;
+        cmd.arg("--dryrun").arg("--inputfile").arg(conf_file);
+        debug!(
+            "Executing: {} --dryrun --inputfile {conf_file}",
+            vtysh.display()
+        );
+        let output = cmd
+            .output()
+            .map_err(|e| format!("Failed to execute {}: {e}", vtysh.display()))?;
+        let out = String::from_utf8_lossy(&output.stdout).into_owned()
+            + &String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            Ok(out)
+        } else {
+            Err(format!(
+                "{conf_file} is not valid ({}): {}",
+                output.status,
+                out.trim()
+            ))
+        }
+    }
+
+    // get the running config of FRR, without the header vtysh prints before it
+    pub fn running_config(&self) -> Result<String, String> {
+        let output = self.run(&["show running-config"])?;
+        let config: Vec<&str> = output
+            .lines()
+            .skip_while(|line| {
+                line.is_empty()
+                    || line.starts_with("Building configuration")
+                    || line.starts_with("Current configuration")
+            })
+            .collect();
+        if config.is_empty() {
+            return Err("Empty running config".to_string());
+        }
+        Ok(config.join("\n") + "\n")
+    }
+}
+
+// parse the output of "show watchfrr", where the state of each daemon is reported in
+// lines like "  bgpd                 Up".
+fn parse_watchfrr(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter(|line| line.starts_with("  ") && !line.contains(':'))
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let daemon = tokens.next()?;
+            let state = tokens.collect::<Vec<_>>().join(" ");
+            (!state.is_empty()).then(|| (daemon.to_string(), state.to_lowercase()))
+        })
+        .collect()
+}
+
+// get the state of the FRR daemons (e.g. up, down, restarting) as known by watchfrr
+pub fn watchfrr_status(args: &Args) -> Result<BTreeMap<String, String>, String> {
+    vtysh(args, "show watchfrr").map(|output| parse_watchfrr(&output))
+}
+
+// get the operational state of interfaces from "show interface json". If names are given,
+// only those interfaces are reported (as null if they do not exist).
+pub fn interface_status(args: &Args, names: &[String]) -> Result<Json, String> {
+    let output = vtysh(args, "show interface json")?;
+    let interfaces = Json::parse(&output).map_err(|e| format!("Bad interface json: {e}"))?;
+    if names.is_empty() {
+        return Ok(interfaces);
+    }
+    let selected = names.iter().map(|name| {
+        (
+            name.clone(),
+            interfaces.get(name).cloned().unwrap_or(Json::Null),
+        )
+    });
+    Ok(Json::Object(selected.collect()))
+}
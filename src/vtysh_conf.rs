@@ -0,0 +1,81 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Management of vtysh.conf. A config may carry the contents of vtysh.conf for the generation,
+// in a leading section as follows:
+//
+//      !! vtysh.conf
+//      service integrated-vtysh-config
+//      hostname router-1
+//      !! frr.conf
+//      <FRR config>
+//
+// The vtysh.conf is installed in confdir along with the config and restored if the config
+// fails to apply, so that both are applied atomically.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::rawtext;
+
+const VTYSH_CONF_MARKER: &str = "!! vtysh.conf\n";
+const FRR_CONF_MARKER: &str = "!! frr.conf\n";
+
+// split a config into its (optional) vtysh.conf section and the FRR config
+pub fn split_vtysh_conf(config: &str) -> Result<(Option<&str>, &str), String> {
+    let Some(bundle) = config.strip_prefix(VTYSH_CONF_MARKER) else {
+        return Ok((None, config));
+    };
+    let (vtysh_conf, frr_conf) = if let Some(frr_conf) = bundle.strip_prefix(FRR_CONF_MARKER) {
+        ("", frr_conf)
+    } else {
+        bundle
+            .split_once(&format!("\n{FRR_CONF_MARKER}"))
+            .ok_or("vtysh.conf section is not followed by a frr.conf section")?
+    };
+    Ok((Some(vtysh_conf), frr_conf))
+}
+
+// vtysh.conf as it was before installing the one of a generation
+pub struct VtyshConfBackup {
+    path: PathBuf,
+    contents: Option<String>,
+}
+
+// install a vtysh.conf in confdir, returning the means to restore the prior one
+pub fn install_vtysh_conf(confdir: &str, vtysh_conf: &str) -> Result<VtyshConfBackup, String> {
+    let path = Path::new(confdir).join("vtysh.conf");
+    let contents = match rawtext::read(&path) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(format!("Could not read {}: {e}", path.display())),
+    };
+    write_atomic(&path, vtysh_conf)?;
+    debug!("Installed vtysh.conf at {}", path.display());
+    Ok(VtyshConfBackup { path, contents })
+}
+
+impl VtyshConfBackup {
+    // restore the vtysh.conf there was before installing the new one
+    pub fn restore(self) {
+        let result = match &self.contents {
+            Some(contents) => write_atomic(&self.path, contents),
+            None => fs::remove_file(&self.path).map_err(|e| e.to_string()),
+        };
+        match result {
+            Ok(()) => info!("Restored prior vtysh.conf at {}", self.path.display()),
+            Err(e) => error!("Failed to restore vtysh.conf: {e}"),
+        }
+    }
+}
+
+fn write_atomic(path: &Path, contents: &str) -> Result<(), String> {
+    let tmp = path.with_extension("conf.tmp");
+    fs::write(&tmp, rawtext::encode(contents))
+        .map_err(|e| format!("Could not write {}: {e}", tmp.display()))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Could not update {}: {e}", path.display()))
+}
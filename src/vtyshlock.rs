@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright Open Network Fabric Authors
+
+// Handling of vtysh configuration lock contention. FRR lets a single vty at a time be in configure
+// mode, so the reloader fails to apply a config while another session (e.g. an operator in vtysh)
+// holds the lock. Such failures are told apart from the output of the reloader, so that the apply
+// is retried a bounded number of times and, optionally, the lock is stolen by terminating the
+// vtysh sessions holding it.
+
+use std::fs;
+use std::time::Duration;
+
+#[allow(unused)]
+use tracing::{debug, error, info, warn};
+
+use crate::reload::ReloaderRun;
+
+// what FRR outputs when the configuration is locked by another vty
+const LOCKED_MARKERS: [&str; 2] = ["configuration is locked", "config is locked"];
+
+// how contention on the vtysh configuration lock is handled
+#[derive(Clone, Copy, Debug)]
+pub struct LockPolicy {
+    pub retries: u32,
+    pub interval: Duration,
+    pub steal: bool,
+}
+
+// whether a reloader run failed because the vtysh configuration lock was held by another session
+pub fn lock_contention(run: &ReloaderRun) -> bool {
+    let output = format!("{}\n{}", run.stdout, run.stderr).to_lowercase();
+    !run.success && LOCKED_MARKERS.iter().any(|marker| output.contains(marker))
+}
+
+// the pids of the vtysh processes running, but ours
+fn vtysh_pids() -> Vec<i32> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return vec![];
+    };
+    let own = i32::try_from(std::process::id()).unwrap_or_default();
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            fs::read_to_string(format!("/proc/{pid}/comm")).is_ok_and(|comm| comm.trim() == "vtysh")
+        })
+        .collect()
+}
+
+// steal the vtysh configuration lock by terminating the vtysh sessions that may hold it. Their
+// vtys are closed by the daemons, which releases the lock. Returns the pids terminated.
+#[allow(unsafe_code)]
+pub fn steal_lock() -> Vec<i32> {
+    let mut stolen = vec![];
+    for pid in vtysh_pids() {
+        // SAFETY: kill has no memory safety requirements
+        if unsafe { libc::kill(pid, libc::SIGTERM) } == 0 {
+            warn!("Terminated vtysh session (pid {pid}) to steal the configuration lock");
+            stolen.push(pid);
+        } else {
+            error!(
+                "Failed to terminate vtysh session (pid {pid}): {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    stolen
+}